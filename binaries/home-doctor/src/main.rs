@@ -1,6 +1,41 @@
+use camino::Utf8PathBuf;
+use facet::Facet;
+use libdoctor::DoctorOptions;
+
+#[derive(Facet)]
+struct Args {
+    #[facet(long, default)]
+    /// Optional cub config file to check
+    pub config: Option<Utf8PathBuf>,
+
+    #[facet(long, default)]
+    /// Comma-separated tenant roots to check, as passed to `home serve`
+    pub roots: Option<String>,
+
+    #[facet(long, default)]
+    /// Optional mom-style tenant config file to check
+    pub tenant_config: Option<Utf8PathBuf>,
+}
+
 #[tokio::main]
 async fn main() {
     skelly::setup();
 
-    libdoctor::load().run().await;
+    let args: Args = match facet_args::from_std_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    let opts = DoctorOptions {
+        config: args.config,
+        roots: args
+            .roots
+            .map(|roots| roots.split(',').map(Utf8PathBuf::from).collect())
+            .unwrap_or_default(),
+        tenant_config: args.tenant_config,
+    };
+    libdoctor::load().run(opts).await;
 }
@@ -3,8 +3,10 @@
 // messages have a _little_ personality and some cheer through emojis, but not too much.
 
 use camino::Utf8Path;
+use owo_colors::OwoColorize;
+use similar::{ChangeTag, TextDiff};
 use skelly::eyre;
-use std::fmt;
+use std::fmt::{self, Write as _};
 use std::process::Command;
 
 // Steps to follow:
@@ -78,12 +80,55 @@ pub(crate) async fn perform_dev_setup(base_dir: &Utf8Path) -> eyre::Result<()> {
     Ok(())
 }
 
+/// What `perform_dev_setup` would change in `base_dir`, without changing it
+async fn pending_dev_setup(base_dir: &Utf8Path) -> eyre::Result<Vec<String>> {
+    let mut pending = Vec::new();
+
+    let package_json_path = base_dir.join("package.json");
+    if fs_err::tokio::metadata(&package_json_path).await.is_err() {
+        pending.push("run `pnpm init` to create package.json".to_string());
+        pending.push("set \"type\": \"module\" in package.json".to_string());
+    } else {
+        let package_json_content = fs_err::tokio::read_to_string(&package_json_path).await?;
+        match serde_json::from_str::<serde_json::Value>(&package_json_content) {
+            Ok(package_json) => {
+                if package_json["type"] != "module" {
+                    pending.push("set \"type\": \"module\" in package.json".to_string());
+                }
+            }
+            Err(e) => pending.push(format!("fix package.json, which isn't valid JSON ({e})")),
+        }
+    }
+
+    let gitignore_path = base_dir.join(".gitignore");
+    let gitignore_content = if fs_err::tokio::metadata(&gitignore_path).await.is_ok() {
+        fs_err::tokio::read_to_string(&gitignore_path).await?
+    } else {
+        String::new()
+    };
+    for entry in ["/node_modules", "/.home"] {
+        if !gitignore_content.contains(entry) {
+            pending.push(format!("add `{entry}` to .gitignore"));
+        }
+    }
+
+    Ok(pending)
+}
+
 #[derive(Debug)]
 struct FileInfo {
     path: String,
     content: String,
 }
 
+/// How a scaffold file compares to what's already in the project
+#[derive(Debug, PartialEq, Eq)]
+enum FileStatus {
+    Missing,
+    Unchanged,
+    Modified { existing: String },
+}
+
 #[derive(Debug)]
 struct ProjectChangeSet {
     files: Vec<FileInfo>,
@@ -132,6 +177,53 @@ impl ProjectChangeSet {
         Ok(existing_files)
     }
 
+    /// One status per file, in the same order
+    async fn file_statuses(&self, dir: &Utf8Path) -> eyre::Result<Vec<FileStatus>> {
+        let mut statuses = Vec::new();
+        for file in &self.files {
+            let full_path = dir.join(&file.path);
+            let status = if fs_err::tokio::metadata(&full_path).await.is_err() {
+                FileStatus::Missing
+            } else {
+                let existing = fs_err::tokio::read_to_string(&full_path).await?;
+                if existing == file.content {
+                    FileStatus::Unchanged
+                } else {
+                    FileStatus::Modified { existing }
+                }
+            };
+            statuses.push(status);
+        }
+        Ok(statuses)
+    }
+
+    /// Drops the files we shouldn't write: unchanged ones, and modified ones
+    /// `overwrite` says no to.
+    fn select(
+        &mut self,
+        statuses: Vec<FileStatus>,
+        mut overwrite: impl FnMut(&FileInfo, &str) -> eyre::Result<bool>,
+    ) -> eyre::Result<()> {
+        let mut selected = Vec::new();
+        for (file, status) in std::mem::take(&mut self.files).into_iter().zip(statuses) {
+            match status {
+                FileStatus::Missing => selected.push(file),
+                FileStatus::Unchanged => {
+                    eprintln!("⏭️ Skipping unchanged file: \x1b[36m{}\x1b[0m", file.path);
+                }
+                FileStatus::Modified { existing } => {
+                    if overwrite(&file, &existing)? {
+                        selected.push(file);
+                    } else {
+                        eprintln!("🛡️ Keeping your version of \x1b[36m{}\x1b[0m", file.path);
+                    }
+                }
+            }
+        }
+        self.files = selected;
+        Ok(())
+    }
+
     async fn commit(&self, dir: &Utf8Path) -> eyre::Result<()> {
         for file in &self.files {
             let full_path = dir.join(&file.path);
@@ -147,7 +239,7 @@ impl ProjectChangeSet {
 
 impl fmt::Display for ProjectChangeSet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "\x1b[34m📋 The following files will be created:\x1b[0m")?;
+        writeln!(f, "\x1b[34m📋 The following files will be written:\x1b[0m")?;
         for file in &self.files {
             writeln!(f, "  \x1b[36m{}\x1b[0m", file.path)?;
         }
@@ -155,7 +247,105 @@ impl fmt::Display for ProjectChangeSet {
     }
 }
 
-pub async fn init_project(dir: &camino::Utf8Path, force: bool) -> eyre::Result<()> {
+/// The state of a project directory, as `home-init --check` reports it
+#[derive(Debug)]
+pub(crate) struct ProjectCheck {
+    /// scaffold files that are there
+    present: Vec<String>,
+    /// scaffold files that aren't
+    missing: Vec<String>,
+    /// what the dev setup step would still change
+    pending_dev_setup: Vec<String>,
+}
+
+impl ProjectCheck {
+    /// A valid project has every scaffold file, and needs no dev setup
+    pub(crate) fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.pending_dev_setup.is_empty()
+    }
+}
+
+impl fmt::Display for ProjectCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\x1b[34m📋 Project files:\x1b[0m")?;
+        for path in &self.present {
+            writeln!(f, "  ✅ \x1b[36m{path}\x1b[0m")?;
+        }
+        for path in &self.missing {
+            writeln!(f, "  ❌ \x1b[36m{path}\x1b[0m \x1b[33m(missing)\x1b[0m")?;
+        }
+
+        if self.is_valid() {
+            return writeln!(
+                f,
+                "\x1b[32m✨ Nothing to do, this is a valid home project!\x1b[0m"
+            );
+        }
+
+        writeln!(f, "\x1b[34m🔧 Running home-init would:\x1b[0m")?;
+        for path in &self.present {
+            writeln!(
+                f,
+                "  ask before overwriting \x1b[36m{path}\x1b[0m if it differs from the template (unless run with \x1b[36m--force\x1b[0m)"
+            )?;
+        }
+        for path in &self.missing {
+            writeln!(f, "  create \x1b[36m{path}\x1b[0m")?;
+        }
+        for step in &self.pending_dev_setup {
+            writeln!(f, "  {step}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks at `dir` and reports what `init_project` would do there, without
+/// touching the filesystem
+pub(crate) async fn check_project(dir: &Utf8Path) -> eyre::Result<ProjectCheck> {
+    let change_set = ProjectChangeSet::new()?;
+    let present = change_set.check_existing_files(dir).await?;
+    let missing = change_set
+        .files
+        .iter()
+        .map(|file| file.path.clone())
+        .filter(|path| !present.contains(path))
+        .collect();
+
+    Ok(ProjectCheck {
+        present,
+        missing,
+        pending_dev_setup: pending_dev_setup(dir).await?,
+    })
+}
+
+/// A unified diff from `existing` to `template`, red and green
+fn colorized_diff(path: &str, existing: &str, template: &str) -> String {
+    let diff = TextDiff::from_lines(existing, template);
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", format!("--- {path} (yours)").red());
+    let _ = writeln!(out, "{}", format!("+++ {path} (template)").green());
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        let _ = writeln!(out, "{}", hunk.header().cyan());
+        for change in hunk.iter_changes() {
+            let line = change.value().trim_end_matches('\n');
+            let _ = match change.tag() {
+                ChangeTag::Delete => writeln!(out, "{}", format!("-{line}").red()),
+                ChangeTag::Insert => writeln!(out, "{}", format!("+{line}").green()),
+                ChangeTag::Equal => writeln!(out, " {line}"),
+            };
+        }
+    }
+    out
+}
+
+fn confirm(question: &str) -> eyre::Result<bool> {
+    eprint!("\x1b[32m{question} (y/N): \x1b[0m");
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+pub async fn init_project(dir: &camino::Utf8Path, force: bool, diff: bool) -> eyre::Result<()> {
     let absolute_dir = dir
         .canonicalize_utf8()
         .map_err(|e| eyre::eyre!("Failed to get absolute path: {}", e))?;
@@ -166,28 +356,26 @@ pub async fn init_project(dir: &camino::Utf8Path, force: bool) -> eyre::Result<(
             absolute_dir
         ))?;
 
-    let change_set = ProjectChangeSet::new()?;
-
-    let existing_files = change_set.check_existing_files(dir).await?;
+    let mut change_set = ProjectChangeSet::new()?;
 
-    if !existing_files.is_empty() && !force {
-        eprintln!("\x1b[33m⚠️ The following files already exist:\x1b[0m");
-        for file in &existing_files {
-            eprintln!("  \x1b[36m{file}\x1b[0m");
+    let statuses = change_set.file_statuses(dir).await?;
+    change_set.select(statuses, |file, existing| {
+        if force {
+            return Ok(true);
         }
-        eprintln!(
-            "\x1b[33mPlease remove these files or run again with --force to overwrite.\x1b[0m"
-        );
-        std::process::exit(1);
-    }
+        if diff {
+            eprint!("{}", colorized_diff(&file.path, existing, &file.content));
+        }
+        confirm(&format!(
+            "\x1b[33m⚠️ \x1b[36m{}\x1b[32m differs from the template, overwrite it?",
+            file.path
+        ))
+    })?;
 
     // Ask for user consent
-    if !force {
+    if !force && !change_set.files.is_empty() {
         println!("{change_set}");
-        eprint!("\x1b[32mDo you want to proceed? (y/N): \x1b[0m");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        if !input.trim().eq_ignore_ascii_case("y") {
+        if !confirm("Do you want to proceed?")? {
             eprintln!("\x1b[33mOperation cancelled by user.\x1b[0m");
             std::process::exit(1);
         }
@@ -207,3 +395,189 @@ pub async fn init_project(dir: &camino::Utf8Path, force: bool) -> eyre::Result<(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8PathBuf;
+
+    fn temp_dir() -> (tempfile::TempDir, Utf8PathBuf) {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = Utf8PathBuf::from_path_buf(temp.path().to_path_buf()).unwrap();
+        (temp, path)
+    }
+
+    async fn list_dir(dir: &Utf8Path) -> Vec<String> {
+        let mut entries = fs_err::tokio::read_dir(dir).await.unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_check_empty_dir() {
+        let (_temp, dir) = temp_dir();
+
+        let check = check_project(&dir).await.unwrap();
+        assert!(!check.is_valid());
+        assert!(check.present.is_empty());
+        assert_eq!(
+            check.missing.len(),
+            ProjectChangeSet::new().unwrap().files.len()
+        );
+        assert!(
+            check
+                .pending_dev_setup
+                .iter()
+                .any(|step| step.contains("pnpm init"))
+        );
+        assert!(check.to_string().contains("create"));
+
+        // and we didn't create anything
+        assert!(list_dir(&dir).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_complete_dir() {
+        let (_temp, dir) = temp_dir();
+        ProjectChangeSet::new().unwrap().commit(&dir).await.unwrap();
+        fs_err::tokio::write(
+            dir.join("package.json"),
+            r#"{"name": "blog", "type": "module"}"#,
+        )
+        .await
+        .unwrap();
+        fs_err::tokio::write(dir.join(".gitignore"), "/node_modules\n/.home\n")
+            .await
+            .unwrap();
+
+        let check = check_project(&dir).await.unwrap();
+        assert!(check.is_valid(), "{check}");
+        assert!(check.missing.is_empty());
+        assert!(check.pending_dev_setup.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_partial_dir() {
+        let (_temp, dir) = temp_dir();
+        fs_err::tokio::write(dir.join("home.json"), r#"{ "id": "example.org" }"#)
+            .await
+            .unwrap();
+        fs_err::tokio::write(dir.join("package.json"), r#"{"name": "blog"}"#)
+            .await
+            .unwrap();
+        fs_err::tokio::write(dir.join(".gitignore"), "/node_modules\n")
+            .await
+            .unwrap();
+        let before = list_dir(&dir).await;
+
+        let check = check_project(&dir).await.unwrap();
+        assert!(!check.is_valid());
+        assert_eq!(check.present, vec!["home.json".to_string()]);
+        assert!(check.missing.contains(&"content/_index.md".to_string()));
+        assert_eq!(
+            check.pending_dev_setup,
+            vec![
+                "set \"type\": \"module\" in package.json".to_string(),
+                "add `/.home` to .gitignore".to_string(),
+            ]
+        );
+        assert!(check.to_string().contains("--force"));
+
+        assert_eq!(list_dir(&dir).await, before);
+        assert_eq!(
+            fs_err::tokio::read_to_string(dir.join("package.json"))
+                .await
+                .unwrap(),
+            r#"{"name": "blog"}"#
+        );
+    }
+
+    /// Writes the whole scaffold, then changes the page title
+    async fn dir_with_modified_file() -> (tempfile::TempDir, Utf8PathBuf) {
+        let (temp, dir) = temp_dir();
+        ProjectChangeSet::new().unwrap().commit(&dir).await.unwrap();
+        let index = include_str!("scaffold/content/_index.md")
+            .replace("A new home site", "My very own site");
+        fs_err::tokio::write(dir.join("content/_index.md"), index)
+            .await
+            .unwrap();
+        fs_err::tokio::remove_file(dir.join("src/main.scss"))
+            .await
+            .unwrap();
+        (temp, dir)
+    }
+
+    #[tokio::test]
+    async fn test_file_statuses() {
+        let (_temp, dir) = dir_with_modified_file().await;
+
+        let change_set = ProjectChangeSet::new().unwrap();
+        let statuses = change_set.file_statuses(&dir).await.unwrap();
+        for (file, status) in change_set.files.iter().zip(&statuses) {
+            match file.path.as_str() {
+                "content/_index.md" => {
+                    assert!(
+                        matches!(status, FileStatus::Modified { existing } if existing.contains("My very own site"))
+                    )
+                }
+                "src/main.scss" => assert_eq!(status, &FileStatus::Missing),
+                _ => assert_eq!(status, &FileStatus::Unchanged, "{}", file.path),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_select_skips_unchanged_and_asks_about_modified() {
+        let (_temp, dir) = dir_with_modified_file().await;
+
+        for answer in [false, true] {
+            let mut change_set = ProjectChangeSet::new().unwrap();
+            let statuses = change_set.file_statuses(&dir).await.unwrap();
+            let mut asked = Vec::new();
+            change_set
+                .select(statuses, |file, _existing| {
+                    asked.push(file.path.clone());
+                    Ok(answer)
+                })
+                .unwrap();
+
+            assert_eq!(asked, vec!["content/_index.md".to_string()]);
+            let selected = change_set
+                .files
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>();
+            if answer {
+                assert_eq!(selected, vec!["content/_index.md", "src/main.scss"]);
+            } else {
+                assert_eq!(selected, vec!["src/main.scss"]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_colorized_diff() {
+        let template = include_str!("scaffold/content/_index.md");
+        let existing = template.replace("A new home site", "My very own site");
+
+        let diff = colorized_diff("content/_index.md", &existing, template);
+        assert!(diff.contains(&format!("{}", "-title: \"My very own site\"".red())));
+        assert!(diff.contains(&format!("{}", "+title: \"A new home site\"".green())));
+        // unchanged lines are context, not changes
+        assert!(diff.contains("\n date: \"1969-07-20T20:17:00Z\"\n"));
+        assert!(!diff.contains("It's empty in here"));
+
+        assert_eq!(
+            colorized_diff("content/_index.md", template, template),
+            format!(
+                "{}\n{}\n",
+                "--- content/_index.md (yours)".red(),
+                "+++ content/_index.md (template)".green()
+            )
+        );
+    }
+}
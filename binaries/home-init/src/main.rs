@@ -14,6 +14,16 @@ pub struct Args {
     #[facet(long, default = false)]
     /// overwrite existing files without asking
     pub force: bool,
+
+    #[facet(long, default = false)]
+    /// show how files you've changed differ from the template before asking
+    /// whether to overwrite them
+    pub diff: bool,
+
+    #[facet(long, default = false)]
+    /// report what's missing and what init would do, without writing anything.
+    /// exits non-zero if the directory isn't a valid home project
+    pub check: bool,
 }
 
 #[tokio::main]
@@ -26,7 +36,18 @@ async fn real_main() -> eyre::Result<()> {
 
     let args: Args = facet_args::from_std_args()?;
 
-    dev_setup::init_project(&args.dir, args.force)
+    if args.check {
+        let check = dev_setup::check_project(&args.dir)
+            .await
+            .map_err(|err| eyre::eyre!(err.to_string()))?;
+        eprint!("{check}");
+        if !check.is_valid() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    dev_setup::init_project(&args.dir, args.force, args.diff)
         .await
         .map_err(|err| eyre::eyre!(err.to_string()))
 }
@@ -21,6 +21,11 @@ struct Args {
     #[facet(long, default)]
     /// Unix socket file descriptor for receiving the TCP listener
     pub socket_fd: Option<i32>,
+
+    #[facet(long, default)]
+    /// Address to bind when no listener is passed via `--socket-fd`
+    /// (defaults to `[::]:$WEB_PORT`)
+    pub listen_addr: Option<String>,
 }
 
 #[tokio::main]
@@ -29,7 +34,12 @@ async fn main() -> eyre::Result<()> {
 }
 
 async fn real_main() -> eyre::Result<()> {
-    skelly::setup();
+    // when home-serve spawns us and goes away, let in-flight requests drain
+    #[cfg(unix)]
+    let on_parent_exit = skelly::OnParentExit::sigterm(std::time::Duration::from_secs(10));
+    #[cfg(not(unix))]
+    let on_parent_exit = skelly::OnParentExit::Exit;
+    skelly::setup_with(skelly::SetupOptions { on_parent_exit });
     let _sentry_guard = sentrywrap::install();
 
     let args: Args = facet_args::from_std_args()?;
@@ -46,35 +56,35 @@ async fn real_main() -> eyre::Result<()> {
         .into_iter()
         .map(|mut tc| -> eyre::Result<(TenantDomain, TenantInfo)> {
             log::info!("Processing tenant: {}", tc.name);
+            if tc.derive_concurrency == Some(0) {
+                eyre::bail!(
+                    "tenant {}: derive_concurrency must be at least 1, or every derivation is refused",
+                    tc.name
+                );
+            }
 
-            // Derive cookie sauce if not already set and secrets exist
-            if let Some(ref mut secrets) = tc.secrets {
+            // Tenants without a cookie sauce get one derived from the global
+            // cookie sauce: that happens when we send them to cubs, since it
+            // depends on how many times it was rotated.
+            if let Some(ref secrets) = tc.secrets {
                 log::info!(
                     "Found secrets for tenant {}. Checking cookie_sauce...",
                     tc.name
                 );
                 if secrets.cookie_sauce.is_none() {
                     log::info!(
-                        "No cookie_sauce set for tenant {}. Deriving from global cookie_sauce...",
+                        "No cookie_sauce set for tenant {}. It'll be derived from the global cookie_sauce.",
                         tc.name
                     );
-                    let global_cookie_sauce = &config.secrets.cookie_sauce;
-                    let derived_sauce =
-                        mom_types::derive_cookie_sauce(global_cookie_sauce, &tc.name);
-                    secrets.cookie_sauce = Some(derived_sauce);
-                    log::info!("Set derived cookie_sauce for tenant {}.", tc.name);
                 } else {
                     log::info!("Tenant {} already has a cookie_sauce set.", tc.name);
                 }
             } else if Environment::default() == Environment::Development {
-                // In development, create dummy secrets with derived cookie sauce
+                // In development, create dummy secrets (the cookie sauce gets derived)
                 log::info!(
                     "No secrets found for tenant {} in development. Creating dev secrets.",
                     tc.name
                 );
-                let global_cookie_sauce = &config.secrets.cookie_sauce;
-                let derived_sauce = mom_types::derive_cookie_sauce(global_cookie_sauce, &tc.name);
-
                 // Check for git credentials in environment variables
                 let git_credentials = match (
                     std::env::var("HOME_GIT_USERNAME"),
@@ -99,7 +109,8 @@ async fn real_main() -> eyre::Result<()> {
                         log::info!("Found Patreon secrets in environment variables for tenant {}", tc.name);
                         Some(config_types::PatreonSecrets {
                             oauth_client_id: client_id,
-                            oauth_client_secret: client_secret
+                            oauth_client_secret: client_secret,
+                            webhook_secret: std::env::var("HOME_PATREON_WEBHOOK_SECRET").ok(),
                         })
                     }
                     _ => {
@@ -156,7 +167,7 @@ async fn real_main() -> eyre::Result<()> {
                     discord: discord_secrets,
                     stripe: None,
                     git: git_credentials,
-                    cookie_sauce: Some(derived_sauce),
+                    cookie_sauce: None,
                 });
                 log::info!("Dev secrets created for tenant {}.", tc.name);
             } else {
@@ -199,38 +210,24 @@ async fn real_main() -> eyre::Result<()> {
         1118
     };
 
-    let listener = if let Some(socket_fd) = args.socket_fd {
-        // Receive the TCP listener via Unix socket
-        log::info!("Receiving TCP listener from socket fd {socket_fd}");
-
-        use sendfd::RecvWithFd;
-        use std::os::unix::io::{FromRawFd, RawFd};
-        use std::os::unix::net::UnixStream;
-
-        // Convert the fd to a UnixStream
-        let unix_stream = unsafe { UnixStream::from_raw_fd(socket_fd) };
-
-        // Receive the TCP listener fd
-        let mut buf = [0u8; 1];
-        let mut fds = [0 as RawFd; 1];
-        let (_, fd_count) = unix_stream
-            .recv_with_fd(&mut buf, &mut fds)
-            .map_err(|e| eyre::eyre!("Failed to receive TCP listener fd: {}", e))?;
-
-        if fd_count == 0 {
-            return Err(eyre::eyre!("No file descriptor received"));
+    let listener = match args.socket_fd {
+        #[cfg(unix)]
+        Some(socket_fd) => receive_listener(socket_fd)?,
+        #[cfg(not(unix))]
+        Some(_) => {
+            return Err(eyre::eyre!(
+                "--socket-fd is only supported on Unix, pass --listen-addr instead"
+            ));
+        }
+        None => {
+            // Fallback to binding directly
+            let addr = args
+                .listen_addr
+                .clone()
+                .unwrap_or_else(|| format!("[::]:{port}"));
+            log::info!("Binding our own listener on {addr}");
+            TcpListener::bind(addr).await?
         }
-
-        let tcp_fd = fds[0];
-
-        // Convert to std TcpListener
-        let std_listener = unsafe { std::net::TcpListener::from_raw_fd(tcp_fd) };
-
-        // Convert to tokio TcpListener
-        TcpListener::from_std(std_listener)?
-    } else {
-        // Fallback to binding directly
-        TcpListener::bind(format!("[::]:{port}")).await?
     };
 
     libmom::load()
@@ -246,3 +243,35 @@ async fn real_main() -> eyre::Result<()> {
         .await
         .map_err(|err| eyre::eyre!(err.to_string()))
 }
+
+/// Receives the TCP listener home-serve bound for us, via a Unix socket
+#[cfg(unix)]
+fn receive_listener(socket_fd: i32) -> eyre::Result<TcpListener> {
+    log::info!("Receiving TCP listener from socket fd {socket_fd}");
+
+    use sendfd::RecvWithFd;
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use std::os::unix::net::UnixStream;
+
+    // Convert the fd to a UnixStream
+    let unix_stream = unsafe { UnixStream::from_raw_fd(socket_fd) };
+
+    // Receive the TCP listener fd
+    let mut buf = [0u8; 1];
+    let mut fds = [0 as RawFd; 1];
+    let (_, fd_count) = unix_stream
+        .recv_with_fd(&mut buf, &mut fds)
+        .map_err(|e| eyre::eyre!("Failed to receive TCP listener fd: {}", e))?;
+
+    if fd_count == 0 {
+        return Err(eyre::eyre!("No file descriptor received"));
+    }
+
+    let tcp_fd = fds[0];
+
+    // Convert to std TcpListener
+    let std_listener = unsafe { std::net::TcpListener::from_raw_fd(tcp_fd) };
+
+    // Convert to tokio TcpListener
+    Ok(TcpListener::from_std(std_listener)?)
+}
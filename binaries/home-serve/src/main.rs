@@ -1,6 +1,7 @@
 use camino::Utf8PathBuf;
 use config_types::{
-    CubConfigBundle, Environment, MOM_DEV_API_KEY, MomConfig, MomSecrets, TenantConfig, WebConfig,
+    CubConfigBundle, Environment, ListenerConfig, MOM_DEV_API_KEY, MomConfig, MomSecrets,
+    TenantConfig, WebConfig,
 };
 use facet::Facet;
 use facet_pretty::FacetPretty;
@@ -10,7 +11,12 @@ use skelly::{
     log,
     owo_colors::OwoColorize,
 };
-use tokio::net::TcpListener;
+use std::{
+    ffi::OsString,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use tokio::net::{TcpListener, TcpSocket};
 
 #[derive(Facet)]
 struct Args {
@@ -25,6 +31,11 @@ struct Args {
     #[facet(long, default)]
     /// Open the site in the default browser
     pub open: bool,
+
+    #[facet(long, default)]
+    /// Open this URL in the default browser instead (a specific tenant, a
+    /// draft...)
+    pub open_url: Option<String>,
 }
 
 #[tokio::main]
@@ -40,6 +51,12 @@ async fn real_main() -> eyre::Result<()> {
 
     log::info!("Args: {}", args.pretty());
 
+    let open_behavior = match args.open_url.as_deref() {
+        Some(url) => OpenBehavior::open_url(url)?,
+        None if args.open => OpenBehavior::OpenOnStart,
+        None => OpenBehavior::DontOpen,
+    };
+
     let CubConfigBundle { mut cc, tenants } = libconfig::load()
         .load_cub_config(
             args.config.as_ref().map(|p| p.as_path()),
@@ -57,7 +74,7 @@ async fn real_main() -> eyre::Result<()> {
     let cub_ln;
 
     // Try to bind exactly as specified in cc.address first.
-    match TcpListener::bind(&addr).await {
+    match bind_listener(addr, cc.listener) {
         Ok(listener) => {
             let ln_addr = listener.local_addr().unwrap();
             cc.address = ln_addr;
@@ -73,7 +90,7 @@ async fn real_main() -> eyre::Result<()> {
             // Otherwise, bind to any available port (port 0)
             let mut random_addr = addr;
             random_addr.set_port(0);
-            let listener = tokio::net::TcpListener::bind(&random_addr).await.map_err(|e| {
+            let listener = bind_listener(random_addr, cc.listener).map_err(|e| {
                 eyre::eyre!(
                     "Failed to bind to random port (fallback after failing to bind to {addr}): {e}"
                 )
@@ -157,6 +174,9 @@ async fn real_main() -> eyre::Result<()> {
                 cookie_sauce: "dev_global_cookie_sauce_secret".to_owned(),
                 email: email_config,
             },
+            profile_cache_ttl_secs: None,
+            max_sponsor_page_size: None,
+            discord_request_limits: Default::default(),
         };
 
         // Write mom config to temp file
@@ -193,25 +213,17 @@ async fn real_main() -> eyre::Result<()> {
         eprintln!("Mom is listening on {}", mom_addr.blue());
         cc.mom_base_url = format!("http://{mom_addr}");
 
-        // Create a Unix socket pair for passing the TCP listener
-        use sendfd::SendWithFd;
-        use std::os::unix::io::AsRawFd;
-        use std::os::unix::net::UnixStream;
-
-        let (parent_sock, child_sock) = UnixStream::pair()?;
-
         // Find the home-mom binary
         let current_exe = std::env::current_exe()?;
         let exe_dir = current_exe
             .parent()
             .ok_or_else(|| eyre::eyre!("Failed to get exe dir"))?;
-        let mom_exe = exe_dir.join("home-mom");
-
-        if !mom_exe.exists() {
-            return Err(eyre::eyre!("home-mom binary not found at {:?}", mom_exe));
-        }
+        let mom_exe = find_mom_exe(&mom_exe_candidates(
+            exe_dir,
+            std::env::var_os(MOM_EXE_ENV),
+            std::env::var_os("PATH"),
+        ))?;
 
-        // Spawn home-mom process using skelly::spawn
         let mut cmd = tokio::process::Command::new(&mom_exe);
         cmd.arg("--mom-config")
             .arg(&mom_config_path)
@@ -220,31 +232,7 @@ async fn real_main() -> eyre::Result<()> {
             .env("HOME_ENV", "development")
             .env("WEB_PORT", cub_addr.port().to_string());
 
-        // Duplicate the child socket fd to pass it safely
-        let child_fd = child_sock.as_raw_fd();
-        let dup_fd = unsafe { libc::dup(child_fd) };
-        if dup_fd < 0 {
-            return Err(eyre::eyre!("Failed to duplicate socket fd"));
-        }
-        cmd.arg("--socket-fd").arg(dup_fd.to_string());
-
-        // Set close-on-exec for the original fd but not the duplicate
-        unsafe {
-            libc::fcntl(child_fd, libc::F_SETFD, libc::FD_CLOEXEC);
-        }
-
-        let mut cmd = skelly::spawn(cmd);
-
-        let mut child = cmd.spawn()?;
-
-        // Send the TCP listener file descriptor through the Unix socket
-        let tcp_fd = mom_ln.as_raw_fd();
-        parent_sock
-            .send_with_fd(&[1], &[tcp_fd])
-            .map_err(|e| eyre::eyre!("Failed to send TCP listener fd: {}", e))?;
-
-        // Don't close the listener - it needs to stay open
-        std::mem::forget(mom_ln);
+        let mut child = spawn_mom(cmd, MomListener::new(mom_ln)?)?;
 
         // Clean up temp directory on exit
         let temp_dir_clone = temp_dir.clone();
@@ -280,15 +268,7 @@ async fn real_main() -> eyre::Result<()> {
         cc.mom_base_url.blue()
     );
     if let Err(e) = libcub::load()
-        .serve(
-            cc,
-            cub_ln,
-            if args.open {
-                OpenBehavior::OpenOnStart
-            } else {
-                OpenBehavior::DontOpen
-            },
-        )
+        .serve(cc, args.config.clone(), cub_ln, open_behavior)
         .await
         .map_err(|err| eyre::eyre!(err.to_string()))
     {
@@ -299,3 +279,253 @@ async fn real_main() -> eyre::Result<()> {
     eprintln!("cub is done, exiting");
     std::process::exit(0);
 }
+
+/// Set this to point home-serve at a specific home-mom build. It's named
+/// after the variable cargo sets when building integration tests, but cargo
+/// never sets it when running binaries: it's up to you.
+const MOM_EXE_ENV: &str = "CARGO_BIN_EXE_home-mom";
+
+/// Where we look for home-mom, in order: next to us (where cargo puts
+/// sibling binaries), wherever [`MOM_EXE_ENV`] says, then every directory in
+/// `PATH`.
+fn mom_exe_candidates(
+    exe_dir: &Path,
+    mom_exe_env: Option<OsString>,
+    path_var: Option<OsString>,
+) -> Vec<PathBuf> {
+    let file_name = format!("home-mom{}", std::env::consts::EXE_SUFFIX);
+
+    let mut candidates = vec![exe_dir.join(&file_name)];
+    candidates.extend(mom_exe_env.filter(|v| !v.is_empty()).map(PathBuf::from));
+    if let Some(path_var) = path_var {
+        candidates.extend(std::env::split_paths(&path_var).map(|dir| dir.join(&file_name)));
+    }
+    candidates
+}
+
+/// The first candidate that exists, or an error listing them all
+fn find_mom_exe(candidates: &[PathBuf]) -> eyre::Result<PathBuf> {
+    if let Some(found) = candidates.iter().find(|candidate| candidate.is_file()) {
+        return Ok(found.clone());
+    }
+
+    let looked_at = candidates
+        .iter()
+        .map(|candidate| format!("  - {}", candidate.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Err(eyre::eyre!(
+        "home-mom binary not found. Looked at:\n{looked_at}\n\
+         Build it with `cargo build -p home-mom`, or set {MOM_EXE_ENV} to its path."
+    ))
+}
+
+/// How mom gets her listener
+enum MomListener {
+    /// We pass the bound socket itself, so nothing can grab the port in between
+    #[cfg(unix)]
+    PassFd(TcpListener),
+
+    /// We let go of the port and mom binds it again. Only used where fd
+    /// passing isn't available (and in tests).
+    #[cfg_attr(unix, allow(dead_code))]
+    BindAddr(SocketAddr),
+}
+
+impl MomListener {
+    #[cfg(unix)]
+    fn new(ln: TcpListener) -> eyre::Result<Self> {
+        Ok(Self::PassFd(ln))
+    }
+
+    #[cfg(not(unix))]
+    fn new(ln: TcpListener) -> eyre::Result<Self> {
+        Ok(Self::BindAddr(ln.local_addr()?))
+    }
+}
+
+/// Spawns home-mom (via `skelly::spawn`), handing her the listener
+fn spawn_mom(
+    mut cmd: tokio::process::Command,
+    listener: MomListener,
+) -> eyre::Result<tokio::process::Child> {
+    match listener {
+        #[cfg(unix)]
+        MomListener::PassFd(ln) => spawn_mom_with_fd(cmd, ln),
+        MomListener::BindAddr(addr) => {
+            cmd.arg("--listen-addr").arg(addr.to_string());
+            Ok(skelly::spawn(cmd).spawn()?)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn spawn_mom_with_fd(
+    mut cmd: tokio::process::Command,
+    mom_ln: TcpListener,
+) -> eyre::Result<tokio::process::Child> {
+    // Create a Unix socket pair for passing the TCP listener
+    use sendfd::SendWithFd;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    let (parent_sock, child_sock) = UnixStream::pair()?;
+
+    // Duplicate the child socket fd to pass it safely
+    let child_fd = child_sock.as_raw_fd();
+    let dup_fd = unsafe { libc::dup(child_fd) };
+    if dup_fd < 0 {
+        return Err(eyre::eyre!("Failed to duplicate socket fd"));
+    }
+    cmd.arg("--socket-fd").arg(dup_fd.to_string());
+
+    // Set close-on-exec for the original fd but not the duplicate
+    unsafe {
+        libc::fcntl(child_fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    let child = skelly::spawn(cmd).spawn()?;
+
+    // Send the TCP listener file descriptor through the Unix socket
+    let tcp_fd = mom_ln.as_raw_fd();
+    parent_sock
+        .send_with_fd(&[1], &[tcp_fd])
+        .map_err(|e| eyre::eyre!("Failed to send TCP listener fd: {}", e))?;
+
+    // Don't close the listener - it needs to stay open
+    std::mem::forget(mom_ln);
+
+    Ok(child)
+}
+
+/// Binds the cub listener, with the socket options from the config
+fn bind_listener(addr: SocketAddr, opts: ListenerConfig) -> std::io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(opts.reuseaddr)?;
+    socket.set_keepalive(opts.keepalive)?;
+    socket.bind(addr)?;
+    socket.listen(opts.backlog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn sockopt(ln: &TcpListener, name: libc::c_int) -> libc::c_int {
+        use std::os::fd::AsRawFd as _;
+
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                ln.as_raw_fd(),
+                libc::SOL_SOCKET,
+                name,
+                &mut value as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(res, 0, "{}", std::io::Error::last_os_error());
+        value
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_listener_options_are_applied() {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+        let ln = bind_listener(addr, ListenerConfig::default()).unwrap();
+        assert_ne!(sockopt(&ln, libc::SO_REUSEADDR), 0);
+        assert_ne!(sockopt(&ln, libc::SO_KEEPALIVE), 0);
+
+        let opts = ListenerConfig {
+            reuseaddr: false,
+            backlog: 16,
+            keepalive: false,
+        };
+        let ln = bind_listener(addr, opts).unwrap();
+        assert_eq!(sockopt(&ln, libc::SO_REUSEADDR), 0);
+        assert_eq!(sockopt(&ln, libc::SO_KEEPALIVE), 0);
+
+        // and it does accept connections
+        let ln_addr = ln.local_addr().unwrap();
+        let (client, accepted) = tokio::join!(tokio::net::TcpStream::connect(ln_addr), ln.accept());
+        client.unwrap();
+        accepted.unwrap();
+    }
+
+    #[test]
+    fn test_mom_exe_resolution_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = |name: &str| {
+            let dir = tmp.path().join(name);
+            fs_err::create_dir_all(&dir).unwrap();
+            dir
+        };
+        let (exe_dir, path_a, path_b) = (dir("target"), dir("path-a"), dir("path-b"));
+        let file_name = format!("home-mom{}", std::env::consts::EXE_SUFFIX);
+        let from_env = tmp.path().join("custom-mom");
+
+        let path_var = std::env::join_paths([&path_a, &path_b]).unwrap();
+        let candidates = mom_exe_candidates(
+            &exe_dir,
+            Some(from_env.clone().into()),
+            Some(path_var.clone()),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                exe_dir.join(&file_name),
+                from_env.clone(),
+                path_a.join(&file_name),
+                path_b.join(&file_name),
+            ]
+        );
+
+        // nothing there yet: the error lists every place we looked
+        let err = find_mom_exe(&candidates).unwrap_err().to_string();
+        for candidate in &candidates {
+            assert!(err.contains(&candidate.display().to_string()), "{err}");
+        }
+
+        // the later a candidate, the lower its priority
+        for candidate in candidates.iter().rev() {
+            fs_err::write(candidate, "").unwrap();
+            assert_eq!(&find_mom_exe(&candidates).unwrap(), candidate);
+        }
+
+        // an empty env var is as good as none
+        let candidates = mom_exe_candidates(&exe_dir, Some("".into()), None);
+        assert_eq!(candidates, vec![exe_dir.join(&file_name)]);
+    }
+
+    // `sh` stands in for home-mom and echoes back the arguments she'd get
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_spawn_mom_without_fd_passes_listen_addr() {
+        let ln = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = ln.local_addr().unwrap();
+        drop(ln);
+
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c")
+            .arg(r#"echo "$SKELLY_PARENT_PID $@""#)
+            .arg("sh")
+            .stdout(std::process::Stdio::piped());
+        let child = spawn_mom(cmd, MomListener::BindAddr(addr)).unwrap();
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().trim(),
+            format!("{} --listen-addr {addr}", std::process::id())
+        );
+
+        // and the port is free for mom to bind herself
+        TcpListener::bind(addr).await.unwrap();
+    }
+}
@@ -1,12 +1,8 @@
+use std::path::{Path, PathBuf};
+
 fn main() {
     let mut args = std::env::args().skip(1);
-    let subcommand = match args.next() {
-        Some(s) => s,
-        None => {
-            eprintln!("Missing subcommand");
-            std::process::exit(1);
-        }
-    };
+    let subcommand = args.next();
     let args: Vec<String> = args.collect();
 
     let exe_path = match std::env::current_exe() {
@@ -25,7 +21,16 @@ fn main() {
         }
     };
 
-    let sub_exe_path = exe_dir.join(format!("home-{subcommand}"));
+    let sub_exe_path = subcommand
+        .as_deref()
+        .and_then(|subcommand| resolve_subcommand(exe_dir, std::env::var_os("PATH"), subcommand));
+    let Some(sub_exe_path) = sub_exe_path else {
+        eprint!(
+            "{}",
+            unknown_subcommand_message(subcommand.as_deref(), &available_subcommands(exe_dir))
+        );
+        std::process::exit(1);
+    };
 
     let status = match std::process::Command::new(sub_exe_path)
         .args(&args)
@@ -42,3 +47,135 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+/// Finds `home-<subcommand>`: next to our own executable first (so invoking
+/// `home` by absolute path works from anywhere), then in `PATH`.
+fn resolve_subcommand(
+    exe_dir: &Path,
+    path_var: Option<std::ffi::OsString>,
+    subcommand: &str,
+) -> Option<PathBuf> {
+    let name = format!("home-{subcommand}{}", std::env::consts::EXE_SUFFIX);
+    let sibling = exe_dir.join(&name);
+    if sibling.is_file() {
+        return Some(sibling);
+    }
+
+    path_var
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(&name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Names of the `home-*` binaries next to our own executable, sorted
+fn available_subcommands(exe_dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs_err::read_dir(exe_dir) else {
+        return Vec::new();
+    };
+    let mut subcommands = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let name = name.strip_suffix(std::env::consts::EXE_SUFFIX)?;
+            let subcommand = name.strip_prefix("home-")?;
+            // skip cargo's `home-<hash>.d` and friends
+            (!subcommand.is_empty() && !subcommand.contains('.')).then(|| subcommand.to_string())
+        })
+        .collect::<Vec<_>>();
+    subcommands.sort();
+    subcommands.dedup();
+    subcommands
+}
+
+fn unknown_subcommand_message(subcommand: Option<&str>, available: &[String]) -> String {
+    let mut msg = String::new();
+    match subcommand {
+        Some(subcommand) => msg.push_str(&format!("Unknown subcommand: {subcommand}\n")),
+        None => msg.push_str("Missing subcommand\n"),
+    }
+    msg.push_str("\nUsage: home <subcommand> [args...]\n\n");
+    if available.is_empty() {
+        msg.push_str("No home-* binaries found next to this executable.\n");
+    } else {
+        msg.push_str("Available subcommands:\n");
+        for subcommand in available {
+            msg.push_str(&format!("  {subcommand}\n"));
+        }
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("home-dispatch-{}-{name}", std::process::id()));
+        let _ = fs_err::remove_dir_all(&dir);
+        fs_err::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, name: &str) {
+        fs_err::write(
+            dir.join(format!("{name}{}", std::env::consts::EXE_SUFFIX)),
+            "",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_siblings_win_over_path() {
+        let exe_dir = scratch_dir("exe");
+        let path_dir = scratch_dir("path");
+        touch(&exe_dir, "home-serve");
+        touch(&path_dir, "home-serve");
+        touch(&path_dir, "home-term");
+
+        let path_var = Some(std::env::join_paths([&path_dir]).unwrap());
+
+        // siblings win over PATH
+        assert_eq!(
+            resolve_subcommand(&exe_dir, path_var.clone(), "serve"),
+            Some(exe_dir.join(format!("home-serve{}", std::env::consts::EXE_SUFFIX)))
+        );
+        // PATH is the fallback
+        assert_eq!(
+            resolve_subcommand(&exe_dir, path_var.clone(), "term"),
+            Some(path_dir.join(format!("home-term{}", std::env::consts::EXE_SUFFIX)))
+        );
+        assert_eq!(resolve_subcommand(&exe_dir, path_var, "nope"), None);
+        assert_eq!(resolve_subcommand(&exe_dir, None, "term"), None);
+
+        fs_err::remove_dir_all(exe_dir).unwrap();
+        fs_err::remove_dir_all(path_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_subcommand_lists_siblings() {
+        let exe_dir = scratch_dir("list");
+        touch(&exe_dir, "home-serve");
+        touch(&exe_dir, "home-doctor");
+        touch(&exe_dir, "not-home");
+        fs_err::write(exe_dir.join("home-serve.d"), "").unwrap();
+        fs_err::create_dir(exe_dir.join("home-dir")).unwrap();
+
+        let available = available_subcommands(&exe_dir);
+        assert_eq!(available, vec!["doctor", "serve"]);
+
+        assert_eq!(
+            unknown_subcommand_message(Some("srve"), &available),
+            "Unknown subcommand: srve\n\
+             \n\
+             Usage: home <subcommand> [args...]\n\
+             \n\
+             Available subcommands:\n  doctor\n  serve\n"
+        );
+        assert!(unknown_subcommand_message(None, &available).starts_with("Missing subcommand\n"));
+        assert!(unknown_subcommand_message(Some("srve"), &[]).contains("No home-* binaries found"));
+
+        fs_err::remove_dir_all(exe_dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+
+/// How long to wait between attempts at something that can fail (an HTTP
+/// request, an object store read, a websocket connect, asking mom for a
+/// derivation...), and when to give up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Give up after this many failures in a row, `None` to keep going
+    pub max_attempts: Option<u32>,
+    /// Wait after the first failure, doubled after every one
+    pub initial: Duration,
+    /// Upper bound for the wait between attempts
+    pub max: Duration,
+    /// Up to this much random time is added to every wait
+    pub jitter: Duration,
+    /// Give up rather than wait past this, counting from the first attempt
+    pub max_total: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Three tries over about a second, for calls a user is waiting on
+    pub const QUICK: Self = Self {
+        max_attempts: Some(3),
+        initial: Duration::from_millis(300),
+        max: Duration::from_secs(1),
+        jitter: Duration::ZERO,
+        max_total: None,
+    };
+
+    /// Starts counting failures
+    pub fn backoff(self) -> Backoff {
+        Backoff {
+            policy: self,
+            failures: 0,
+            delay: Duration::ZERO,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Where we are in a [`RetryPolicy`]: how many times things failed in a row,
+/// and how long we waited last time.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    policy: RetryPolicy,
+    failures: u32,
+    delay: Duration,
+    started: Instant,
+}
+
+impl Backoff {
+    /// How many times things failed since we started (or last reset)
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Call after every failure: returns how long to wait before trying
+    /// again, or `None` once the policy says to give up.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.failures += 1;
+        if self
+            .policy
+            .max_attempts
+            .is_some_and(|max| self.failures >= max)
+        {
+            return None;
+        }
+
+        self.delay = if self.delay.is_zero() {
+            self.policy.initial
+        } else {
+            (self.delay * 2).min(self.policy.max)
+        };
+        let wait = self.delay + self.policy.jitter.mul_f64(rand::random::<f64>());
+        if self
+            .policy
+            .max_total
+            .is_some_and(|max_total| self.started.elapsed() + wait > max_total)
+        {
+            return None;
+        }
+        Some(wait)
+    }
+
+    /// Call once things work again: the next failure waits `initial` again
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.delay = Duration::ZERO;
+        self.started = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: Some(6),
+        initial: Duration::from_millis(100),
+        max: Duration::from_millis(350),
+        jitter: Duration::ZERO,
+        max_total: None,
+    };
+
+    fn delays(backoff: &mut Backoff) -> Vec<Option<u128>> {
+        (0..6)
+            .map(|_| backoff.next_delay().map(|d| d.as_millis()))
+            .collect()
+    }
+
+    #[test]
+    fn test_doubles_up_to_max_then_gives_up() {
+        let mut backoff = POLICY.backoff();
+        assert_eq!(
+            delays(&mut backoff),
+            vec![Some(100), Some(200), Some(350), Some(350), Some(350), None]
+        );
+        assert_eq!(backoff.failures(), 6);
+    }
+
+    #[test]
+    fn test_reset_starts_over() {
+        let mut backoff = POLICY.backoff();
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_gives_up_past_max_total() {
+        let mut backoff = RetryPolicy {
+            max_attempts: None,
+            max_total: Some(Duration::from_millis(150)),
+            ..POLICY
+        }
+        .backoff();
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+        // waiting 200ms would take us past 150ms
+        assert_eq!(backoff.next_delay(), None);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let policy = RetryPolicy {
+            jitter: Duration::from_millis(50),
+            ..POLICY
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff().next_delay().unwrap();
+            assert!(delay >= Duration::from_millis(100), "{delay:?}");
+            assert!(delay <= Duration::from_millis(150), "{delay:?}");
+        }
+    }
+}
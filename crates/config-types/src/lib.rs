@@ -1,5 +1,5 @@
 use camino::Utf8PathBuf;
-use credentials::{GithubUserId, PatreonUserId};
+use credentials::{GithubUserId, PatreonUserId, TierMapping};
 use facet::Facet;
 use serde::{Deserialize, Serialize};
 
@@ -43,6 +43,36 @@ impl TenantDomain {
     pub fn into_pretty(self) -> PrettyTenantDomain {
         PrettyTenantDomain(self)
     }
+
+    /// Parses a hostname (without port) into a tenant domain: lowercases it,
+    /// strips a trailing dot, and rejects anything that isn't a valid
+    /// DNS name (labels of 1 to 63 alphanumerics or hyphens, 253 bytes total).
+    pub fn parse(host: &str) -> Result<TenantDomain, String> {
+        let host = host.strip_suffix('.').unwrap_or(host);
+        if host.is_empty() {
+            return Err("Empty domain".to_string());
+        }
+        if host.len() > 253 {
+            return Err(format!("Domain is too long ({} bytes)", host.len()));
+        }
+
+        for label in host.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(format!("Invalid label length in '{host}'"));
+            }
+            if !label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            {
+                return Err(format!("Invalid character in '{host}'"));
+            }
+            if label.starts_with('-') || label.ends_with('-') {
+                return Err(format!("Label starts or ends with '-' in '{host}'"));
+            }
+        }
+
+        Ok(TenantDomain::new(host.to_ascii_lowercase()))
+    }
 }
 #[derive(Facet)]
 pub struct PrettyTenantDomain(TenantDomain);
@@ -61,6 +91,11 @@ pub struct CubConfig {
     #[serde(default = "serde_defaults::default_disk_cache_size")]
     pub disk_cache_size: ByteSize,
 
+    /// size the in-memory cache of derivations (served by the CDN) is
+    /// allowed to use, zero disables it
+    #[serde(default = "serde_defaults::default_mem_cache_size")]
+    pub mem_cache_size: ByteSize,
+
     /// Listen address without http, something like "127.0.0.1:1111"
     #[serde(default = "serde_defaults::cub_address")]
     pub address: SocketAddr,
@@ -87,6 +122,162 @@ pub struct CubConfig {
 
     /// Honeycomb-specific secrets
     pub honeycomb_secrets: Option<HoneycombSecrets>,
+
+    /// Log directives, `RUST_LOG`-style (e.g. `info,libcub=debug`), overriding
+    /// `RUST_LOG` when set
+    #[serde(default)]
+    pub log_level: Option<String>,
+
+    /// How patiently the CDN waits for mom to run derivations
+    #[serde(default)]
+    pub derive_retry: DeriveRetryConfig,
+
+    /// How many derivations a single tenant may have waiting on mom at once.
+    /// Past that, the CDN answers 503 instead of queuing. Tenants can
+    /// override it with their own `derive_concurrency`.
+    #[serde(default = "serde_defaults::derive_concurrency")]
+    pub derive_concurrency: usize,
+
+    /// Where to write the structured access log (one JSON object per
+    /// request): a file path, or `-` for stdout. Off when unset.
+    #[serde(default)]
+    pub access_log: Option<String>,
+
+    /// How the listening socket is set up
+    #[serde(default)]
+    pub listener: ListenerConfig,
+
+    /// In development, send revision and asset uploads to the production mom
+    /// rather than `mom_base_url`. `FORCE_LOCAL_MOM=1` overrides this.
+    #[serde(default = "serde_defaults::use_prod_mom_in_dev")]
+    pub use_prod_mom_in_dev: bool,
+
+    /// How `/extras` requests are proxied to the git host
+    #[serde(default)]
+    pub git_proxy: GitProxyConfig,
+
+    /// How big a request body each group of routes accepts
+    #[serde(default)]
+    pub body_limits: BodyLimitsConfig,
+
+    /// At startup, check that every tenant's object store is reachable with
+    /// the configured credentials. A failure stops cub in production, and is
+    /// only logged in development.
+    #[serde(default)]
+    pub probe_object_stores: bool,
+
+    /// The header a request's id comes in, as set by the load balancer in
+    /// front of us. Requests without one get a fresh id. Either way, the id
+    /// is sent back in that same header, shows up in logs and traces, and is
+    /// forwarded to mom.
+    #[serde(default = "serde_defaults::request_id_header")]
+    pub request_id_header: String,
+}
+
+/// Socket options for the listener cub accepts connections on. Every field
+/// is optional in the config file.
+#[derive(Facet, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Set `SO_REUSEADDR`, so a restarted cub can bind while connections
+    /// from the previous one linger in `TIME_WAIT`
+    pub reuseaddr: bool,
+
+    /// How many connections may wait to be accepted
+    pub backlog: u32,
+
+    /// Set `SO_KEEPALIVE`, so dead peers eventually get noticed. Accepted
+    /// connections inherit it.
+    pub keepalive: bool,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            reuseaddr: true,
+            backlog: 1024,
+            keepalive: true,
+        }
+    }
+}
+
+/// How cub talks to the git host it proxies `/extras` to. Every field is
+/// optional in the config file.
+#[derive(Facet, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitProxyConfig {
+    /// How long we wait to connect to the git host, in milliseconds
+    pub connect_timeout_ms: u64,
+
+    /// How long the git host may go without sending us anything, in
+    /// milliseconds. Past that, the client gets a 504.
+    pub read_timeout_ms: u64,
+
+    /// Sent instead of the git client's own user agent
+    pub user_agent: String,
+}
+
+impl Default for GitProxyConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 10_000,
+            read_timeout_ms: 60_000,
+            user_agent: "home-git-proxy/1.0 (+https://github.com/bearcove/home)".to_string(),
+        }
+    }
+}
+
+/// How big a request body cub accepts, per group of routes. Every field is
+/// optional in the config file.
+#[derive(Facet, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyLimitsConfig {
+    /// Pages, login, the public API and the CDN: these get forms and small
+    /// JSON payloads at most
+    pub web: ByteSize,
+
+    /// The (development-only) internal API, which edits and uploads assets
+    pub uploads: ByteSize,
+
+    /// `git push` through `/extras`
+    pub extras: ByteSize,
+}
+
+impl Default for BodyLimitsConfig {
+    fn default() -> Self {
+        Self {
+            web: ByteSize::new(64 * 1024),
+            uploads: ByteSize::mib(256),
+            extras: ByteSize::mib(512),
+        }
+    }
+}
+
+/// How many times (and how slowly) the CDN asks mom for a derivation before
+/// giving up. Every field is optional in the config file.
+#[derive(Facet, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeriveRetryConfig {
+    /// How many times we ask mom before giving up
+    pub max_tries: u32,
+
+    /// How long we wait after the first try, in milliseconds. The wait
+    /// doubles after every try, whether the derivation is already in
+    /// progress or mom is too busy to start it...
+    pub initial_sleep_ms: u64,
+
+    /// ...up to this, in milliseconds
+    pub max_sleep_ms: u64,
+}
+
+impl Default for DeriveRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_tries: 20,
+            initial_sleep_ms: 200,
+            max_sleep_ms: 2000,
+        }
+    }
 }
 
 #[derive(Facet, Serialize, Deserialize)]
@@ -97,6 +288,33 @@ pub struct MomConfig {
 
     /// Mom-specific secrets
     pub secrets: MomSecrets,
+
+    /// How long GitHub/Patreon/Discord profile fetches are cached for, per
+    /// access token. Not cached at all when unset.
+    #[serde(default)]
+    pub profile_cache_ttl_secs: Option<u64>,
+
+    /// How big a single page of GitHub/Patreon sponsors can get before the
+    /// sponsor sync gives up on it. 16MiB when unset.
+    #[serde(default)]
+    pub max_sponsor_page_size: Option<ByteSize>,
+
+    /// Bounds on every call to Discord's API
+    #[serde(default)]
+    pub discord_request_limits: DiscordRequestLimitsConfig,
+}
+
+/// How long, and how big, a response from Discord's API can get. Every field
+/// is optional in the config file.
+#[derive(Facet, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordRequestLimitsConfig {
+    /// How long we wait for the whole response, body included, in seconds.
+    /// 30 when unset.
+    pub timeout_secs: Option<u64>,
+
+    /// Responses with a bigger body are an error. 8MiB when unset.
+    pub max_body_size: Option<ByteSize>,
 }
 
 /// Just enough information to build web/cdn URLs
@@ -117,6 +335,33 @@ impl CubConfig {
             port: self.address.port(),
         }
     }
+
+    /// A config with every setting at its default (and the dev mom API key,
+    /// without warning about it), for tests to override with struct-update
+    /// syntax.
+    pub fn for_tests() -> Self {
+        Self {
+            disk_cache_size: serde_defaults::default_disk_cache_size(),
+            mem_cache_size: serde_defaults::default_mem_cache_size(),
+            address: serde_defaults::cub_address(),
+            random_port_fallback: serde_defaults::random_port_fallback(),
+            mom_base_url: serde_defaults::mom_base_url(),
+            mom_api_key: MOM_DEV_API_KEY.to_owned(),
+            tenant_data_dir: None,
+            reddit_secrets: None,
+            honeycomb_secrets: None,
+            log_level: None,
+            derive_retry: Default::default(),
+            derive_concurrency: serde_defaults::derive_concurrency(),
+            access_log: None,
+            listener: Default::default(),
+            use_prod_mom_in_dev: serde_defaults::use_prod_mom_in_dev(),
+            git_proxy: Default::default(),
+            body_limits: Default::default(),
+            probe_object_stores: false,
+            request_id_header: serde_defaults::request_id_header(),
+        }
+    }
 }
 
 /// tenant-specific configuration that's common betweeen mom and cub
@@ -142,6 +387,20 @@ pub struct TenantConfig {
 
     /// the first RevisionConfig we read, specified by cub for the dev mom
     pub rc_for_dev: Option<RevisionConfig>,
+
+    /// who upstream APIs (Patreon, GitHub...) can reach about this tenant's
+    /// requests, mentioned in our user agent
+    #[serde(default)]
+    pub contact_email: Option<String>,
+
+    /// overrides cub's `derive_concurrency` for this tenant
+    #[serde(default)]
+    pub derive_concurrency: Option<usize>,
+
+    /// fail OAuth logins when the provider grants fewer scopes than we
+    /// asked for, instead of letting them through with a warning
+    #[serde(default)]
+    pub strict_oauth_scopes: bool,
 }
 
 impl TenantConfig {
@@ -154,6 +413,60 @@ impl TenantConfig {
             secrets: None,
             base_dir_for_dev: None,
             rc_for_dev: None,
+            contact_email: None,
+            derive_concurrency: None,
+            strict_oauth_scopes: false,
+        }
+    }
+
+    /// Checks for mistakes that would only show up once serving: malformed
+    /// domains, missing object storage or secrets in production, and a
+    /// `derive_concurrency` that would refuse every derivation.
+    pub fn validate(&self, env: Environment) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        match TenantDomain::parse(self.name.as_str()) {
+            Ok(parsed) if parsed != self.name => problems.push(format!(
+                "tenant name '{}' should be written '{parsed}'",
+                self.name
+            )),
+            Ok(_) => {}
+            Err(e) => problems.push(format!("invalid tenant name: {e}")),
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for alias in &self.domain_aliases {
+            if let Err(e) = TenantDomain::parse(alias.as_str()) {
+                problems.push(format!("invalid domain alias: {e}"));
+            }
+            if *alias == self.name {
+                problems.push(format!("'{alias}' is listed as an alias of itself"));
+            }
+            if !seen.insert(alias) {
+                problems.push(format!("domain alias '{alias}' is listed twice"));
+            }
+        }
+
+        if env.is_prod() {
+            if self.object_storage.is_none() {
+                problems.push("object_storage must be set in production".to_string());
+            }
+            if self.secrets.is_none() {
+                problems.push("secrets must be set in production".to_string());
+            }
+        } else if self.object_storage.is_some() && self.secrets.is_none() {
+            problems
+                .push("object_storage is set, but there are no secrets to access it".to_string());
+        }
+
+        if self.derive_concurrency == Some(0) {
+            problems.push("derive_concurrency must be at least 1".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
         }
     }
 
@@ -262,6 +575,16 @@ impl TenantConfig {
         })
     }
 
+    pub fn stripe_secrets(&self) -> eyre::Result<&StripeSecrets> {
+        self.secrets().and_then(|secrets| {
+            if let Some(ref stripe) = secrets.stripe {
+                Ok(stripe)
+            } else {
+                eyre::bail!("Stripe secrets not specified for tenant {}", self.name)
+            }
+        })
+    }
+
     pub fn object_storage(&self) -> eyre::Result<&ObjectStorageConfig> {
         if let Some(object_storage) = &self.object_storage {
             Ok(object_storage)
@@ -333,6 +656,58 @@ pub struct RevisionConfig {
     /// SVG font face collection
     #[serde(default)]
     pub svg_fonts: Vec<SvgFontSpec>,
+
+    /// How sponsorships map to tiers (defaults to fasterthanlime's tiers)
+    #[serde(default)]
+    pub tier_mapping: TierMapping,
+
+    /// How long clients may cache what we serve
+    #[serde(default)]
+    pub cache_policy: CachePolicyConfig,
+}
+
+/// What a response carries, as far as caching goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCategory {
+    /// Served from a cache-busted URL (it includes a hash of the content),
+    /// like derived assets: it never changes
+    Immutable,
+
+    /// Can change under the same URL, like extra files
+    Mutable,
+}
+
+/// How long clients may cache each category of content, in seconds. Every
+/// field is optional in the config file, and defaults to a year.
+#[derive(Facet, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[facet(default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CachePolicyConfig {
+    pub immutable_max_age_secs: u64,
+    pub mutable_max_age_secs: u64,
+}
+
+impl Default for CachePolicyConfig {
+    fn default() -> Self {
+        Self {
+            immutable_max_age_secs: 31_536_000,
+            mutable_max_age_secs: 31_536_000,
+        }
+    }
+}
+
+impl CachePolicyConfig {
+    pub fn max_age_secs(&self, category: CacheCategory) -> u64 {
+        match category {
+            CacheCategory::Immutable => self.immutable_max_age_secs,
+            CacheCategory::Mutable => self.mutable_max_age_secs,
+        }
+    }
+
+    /// Value of the `Cache-Control` header for `category`
+    pub fn cache_control(&self, category: CacheCategory) -> String {
+        format!("max-age={}", self.max_age_secs(category))
+    }
 }
 
 #[derive(Facet, Clone, Serialize, Deserialize)]
@@ -436,6 +811,18 @@ mod serde_defaults {
         super::ByteSize::mib(200)
     }
 
+    pub(super) fn default_mem_cache_size() -> super::ByteSize {
+        super::ByteSize::mib(64)
+    }
+
+    pub(super) fn derive_concurrency() -> usize {
+        8
+    }
+
+    pub(super) fn request_id_header() -> String {
+        "x-request-id".to_string()
+    }
+
     pub(super) fn mom_base_url() -> String {
         "http://localhost:1118".to_string()
     }
@@ -450,6 +837,10 @@ mod serde_defaults {
     pub(super) fn random_port_fallback() -> bool {
         true
     }
+
+    pub(super) fn use_prod_mom_in_dev() -> bool {
+        true
+    }
 }
 
 #[derive(Facet, Serialize, Deserialize)]
@@ -551,6 +942,9 @@ impl std::fmt::Display for Environment {
 pub struct PatreonSecrets {
     pub oauth_client_id: String,
     pub oauth_client_secret: String,
+    /// Secret used to sign webhook payloads (`X-Patreon-Signature`)
+    #[facet(optional)]
+    pub webhook_secret: Option<String>,
 }
 
 #[derive(Facet, Clone, Serialize, Deserialize)]
@@ -684,21 +1078,34 @@ impl std::str::FromStr for ByteSize {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.trim().to_lowercase();
-        if let Some(size) = s.strip_suffix(" gib") {
-            size.trim()
-                .parse::<u64>()
-                .map(|v| ByteSize(v * 1024 * 1024 * 1024))
-                .map_err(|_| format!("Invalid number in '{s}'"))
-        } else if let Some(size) = s.strip_suffix(" mib") {
-            size.trim()
-                .parse::<u64>()
-                .map(|v| ByteSize(v * 1024 * 1024))
-                .map_err(|_| format!("Invalid number in '{s}'"))
+        let (size, multiplier) = if let Some(size) = s.strip_suffix("gib") {
+            (size, 1024 * 1024 * 1024)
+        } else if let Some(size) = s.strip_suffix("mib") {
+            (size, 1024 * 1024)
+        } else if let Some(size) = s.strip_suffix("kib") {
+            (size, 1024)
+        } else if let Some(size) = s.strip_suffix("bytes") {
+            (size, 1)
         } else {
-            s.parse::<u64>()
-                .map(ByteSize)
-                .map_err(|_| format!("Invalid number in '{s}'"))
-        }
+            (s.as_str(), 1)
+        };
+
+        let size = size
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid number in '{s}'"))?;
+        size.checked_mul(multiplier)
+            .map(ByteSize)
+            .ok_or_else(|| format!("Size is too large in '{s}'"))
+    }
+}
+
+impl serde::Serialize for ByteSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
     }
 }
 
@@ -733,6 +1140,7 @@ mod bytesize_tests {
     use std::str::FromStr;
 
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_display() {
@@ -749,5 +1157,132 @@ mod bytesize_tests {
         );
         assert_eq!(ByteSize::from_str("1 MiB").unwrap(), ByteSize(1024 * 1024));
         assert_eq!(ByteSize::from_str("1024").unwrap(), ByteSize(1024));
+        assert_eq!(ByteSize::from_str("1024 bytes").unwrap(), ByteSize(1024));
+        assert_eq!(ByteSize::from_str("4 KiB").unwrap(), ByteSize(4096));
+        assert_eq!(ByteSize::from_str("2gib").unwrap(), ByteSize(2 << 30));
+        assert!(ByteSize::from_str("17179869184 GiB").is_err());
+        assert!(ByteSize::from_str("GiB").is_err());
+        assert!(ByteSize::from_str("-1 MiB").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_display_round_trips(size in any::<u64>()) {
+            let size = ByteSize(size);
+            prop_assert_eq!(ByteSize::from_str(&size.to_string()), Ok(size));
+        }
+
+        #[test]
+        fn prop_unit_round_trips(
+            n in 0..=(u64::MAX >> 30),
+            unit in prop::sample::select(vec!["GiB", "MiB", "KiB", "bytes", ""]),
+        ) {
+            let multiplier = match unit {
+                "GiB" => 1 << 30,
+                "MiB" => 1 << 20,
+                "KiB" => 1 << 10,
+                _ => 1,
+            };
+            prop_assert_eq!(
+                ByteSize::from_str(&format!("{n} {unit}")),
+                Ok(ByteSize(n * multiplier))
+            );
+        }
+
+        #[test]
+        fn prop_from_str_never_panics(s in "\\PC*") {
+            let _ = ByteSize::from_str(&s);
+        }
+
+        #[test]
+        fn prop_from_str_never_panics_on_big_numbers(s in "[0-9]{1,30} ?(gib|MiB|kib|bytes)?") {
+            let _ = ByteSize::from_str(&s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tenant_config_tests {
+    use super::*;
+
+    fn td(s: &str) -> TenantDomain {
+        TenantDomain::new(s.to_string())
+    }
+
+    #[test]
+    fn test_validate() {
+        let tc = TenantConfig::new(td("fasterthanli.me"));
+        assert_eq!(tc.validate(Environment::Development), Ok(()));
+
+        let problems = tc.validate(Environment::Production).unwrap_err();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+
+        let mut tc = TenantConfig::new(td("FTL.example"));
+        tc.domain_aliases = vec![td("ftl.example"), td("old.example"), td("old.example")];
+        let problems = tc.validate(Environment::Development).unwrap_err();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("should be written 'ftl.example'"));
+        assert!(problems[1].contains("listed twice"));
+
+        let tc = TenantConfig::new(td("not a domain"));
+        assert!(tc.validate(Environment::Development).is_err());
+
+        let mut tc = TenantConfig::new(td("fasterthanli.me"));
+        tc.derive_concurrency = Some(0);
+        let problems = tc.validate(Environment::Development).unwrap_err();
+        assert!(problems[0].contains("derive_concurrency"), "{problems:?}");
+    }
+}
+
+#[cfg(test)]
+mod tenant_domain_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |s: &str| TenantDomain::parse(s).map(|d| d.to_string());
+        assert_eq!(parse("fasterthanli.me").as_deref(), Ok("fasterthanli.me"));
+        assert_eq!(parse("FTL.localhost.").as_deref(), Ok("ftl.localhost"));
+        assert_eq!(parse("192.168.1.1").as_deref(), Ok("192.168.1.1"));
+        assert_eq!(parse("my-site.example").as_deref(), Ok("my-site.example"));
+
+        assert!(parse("").is_err());
+        assert!(parse(".").is_err());
+        assert!(parse("a..b").is_err());
+        assert!(parse("-a.example").is_err());
+        assert!(parse("a-.example").is_err());
+        assert!(parse("a_b.example").is_err());
+        assert!(parse("example.com:8080").is_err());
+        assert!(parse("café.example").is_err());
+        assert!(parse(&"a".repeat(64)).is_err());
+    }
+
+    fn label() -> impl Strategy<Value = String> {
+        "[a-z0-9]([a-z0-9-]{0,20}[a-z0-9])?"
+    }
+
+    proptest! {
+        #[test]
+        fn prop_valid_domains_round_trip(labels in prop::collection::vec(label(), 1..6)) {
+            let raw = labels.join(".");
+            let domain = TenantDomain::parse(&raw).unwrap();
+            prop_assert_eq!(domain.as_str(), raw.as_str());
+            prop_assert_eq!(TenantDomain::parse(&domain.to_string()), Ok(domain.clone()));
+            prop_assert_eq!(TenantDomain::parse(&raw.to_ascii_uppercase()), Ok(domain.clone()));
+            prop_assert_eq!(TenantDomain::parse(&format!("{raw}.")), Ok(domain));
+        }
+
+        #[test]
+        fn prop_parse_never_panics_and_is_idempotent(s in any::<String>()) {
+            if let Ok(domain) = TenantDomain::parse(&s) {
+                prop_assert_eq!(TenantDomain::parse(domain.as_str()), Ok(domain.clone()));
+            }
+        }
+
+        #[test]
+        fn prop_parse_never_panics_on_hostish_input(s in "[a-zA-Z0-9.:_-]{0,300}") {
+            let _ = TenantDomain::parse(&s);
+        }
     }
 }
@@ -41,6 +41,7 @@ impl std::fmt::Display for VContainer {
 #[allow(clippy::upper_case_acronyms)]
 pub enum VCodec {
     AVC,
+    HEVC,
     VP9,
     AV1,
 }
@@ -82,6 +83,7 @@ macro_rules! impl_codec_conversions {
 
 impl_codec_conversions!(VCodec,
     AVC => "h264",
+    HEVC => "hevc",
     VP9 => "vp9",
     AV1 => "av1"
 );
@@ -102,6 +104,13 @@ impl VCodec {
             // 34 = level (5.0)
             VCodec::AVC => ContentTypeCodecRef::from_static("avc1.640034"),
 
+            // hvc1 = H.265/HEVC codec, parameters out-of-band (what Safari wants)
+            // 1 = profile (main)
+            // 6 = profile compatibility flags
+            // L150 = main tier, level (5.0)
+            // B0 = constraint flags
+            VCodec::HEVC => ContentTypeCodecRef::from_static("hvc1.1.6.L150.B0"),
+
             // vp09 = VP9 codec
             // 00 = profile (0)
             // 41 = level (4.1)
@@ -22,7 +22,9 @@ use objectstore_types::ObjectStoreKey;
 
 use closest::{GetOrHelp, ResourceKind};
 use config_types::{FontStyle, FontWeight, RevisionConfig, TenantConfig, TenantInfo, WebConfig};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_encode};
 use plait::plait;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
 
 mod av;
 pub use av::*;
@@ -373,7 +375,7 @@ impl Viewer {
                 }
             }
 
-            if let Some((tier, cause)) = user_info.get_fasterthanlime_tier() {
+            if let Some((tier, cause)) = user_info.tier(&rc.tier_mapping) {
                 v.has_bronze = tier.has_bronze();
                 v.has_silver = tier.has_silver();
                 v.has_gold = tier.has_gold();
@@ -830,7 +832,7 @@ impl RouteRef {
         // e.g. base = `https://fasterthanli.me`
         // e.g. self = `/articles/10-months-of-itch`
         let base = tc.web_base_url(web);
-        AbsoluteUrl::new(format!("{base}{self}"))
+        AbsoluteUrl::new(format!("{base}{}", self.to_url_path()))
     }
 
     /// Build an absolute CDN URL from this route path (e.g. <https://cdn.fasterthanli.me/blah>)
@@ -838,7 +840,7 @@ impl RouteRef {
         // e.g. base = `https://cdn.fasterthanli.me`
         // e.g. self = `/articles/10-months-of-itch`
         let base = tc.cdn_base_url(web);
-        AbsoluteUrl::new(format!("{base}{self}"))
+        AbsoluteUrl::new(format!("{base}{}", self.to_url_path()))
     }
 
     /// Build an absolute web URL from this route path as a `url::Url`
@@ -861,6 +863,299 @@ impl RouteRef {
             self.to_owned()
         }
     }
+
+    /// Returns the canonical form of this (already decoded) route, see [`Route::parse`]
+    pub fn canonicalize(&self) -> Result<Route, RouteError> {
+        Route::parse(self.as_str())
+    }
+
+    /// Percent-encodes this route so it can be used as the path of a URL
+    /// (e.g. `/café` => `/caf%C3%A9`). This is the inverse of [`Route::from_url_path`].
+    pub fn to_url_path(&self) -> String {
+        percent_encode(self.as_str().as_bytes(), ROUTE_PATH_ENCODE_SET).to_string()
+    }
+}
+
+impl Route {
+    /// Parses a route, and returns it in canonical form, which is what
+    /// routes are stored as (in `page_routes`, `assets`, etc.) and looked up with:
+    ///
+    /// ```text
+    /// /articles/foo/          => /articles/foo    (no trailing slash)
+    /// //articles///foo        => /articles/foo    (slashes collapsed)
+    /// /                       => /
+    /// ```
+    ///
+    /// Routes are stored decoded (`/café`, not `/caf%C3%A9`): this doesn't
+    /// do any percent-decoding, use [`Route::from_url_path`] for paths that
+    /// come from a URL. Non-ASCII routes are normalized to NFC, so that
+    /// `/café` matches no matter how the `é` was composed.
+    ///
+    /// Routes that don't start with `/`, contain control characters, or
+    /// contain `.` or `..` segments are rejected.
+    pub fn parse(raw: &str) -> Result<Route, RouteError> {
+        if !raw.starts_with('/') {
+            return Err(RouteError::NotAbsolute);
+        }
+        if raw.chars().any(|c| c.is_control()) {
+            return Err(RouteError::ControlCharacter);
+        }
+
+        let mut canonical = String::with_capacity(raw.len());
+        for segment in raw.split('/').filter(|s| !s.is_empty()) {
+            if segment == "." || segment == ".." {
+                return Err(RouteError::DotSegment);
+            }
+            canonical.push('/');
+            canonical.push_str(segment);
+        }
+        if canonical.is_empty() {
+            canonical.push('/');
+        }
+        if !canonical.is_ascii() && !is_nfc(&canonical) {
+            canonical = canonical.nfc().collect();
+        }
+
+        Ok(Route::new(canonical))
+    }
+
+    /// Percent-decodes the path of a URL (e.g. `/caf%C3%A9/`), then parses
+    /// it with [`Route::parse`] (e.g. `/café`). Malformed percent-escapes and
+    /// escapes that decode to invalid UTF-8 are rejected.
+    pub fn from_url_path(path: &str) -> Result<Route, RouteError> {
+        Route::parse(&percent_decode(path)?)
+    }
+}
+
+/// Everything but unreserved characters, `/`, and the sub-delimiters that
+/// are allowed as-is in a path segment (RFC 3986, section 3.3)
+const ROUTE_PATH_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/')
+    .remove(b'!')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=')
+    .remove(b':')
+    .remove(b'@');
+
+fn percent_decode(raw: &str) -> Result<String, RouteError> {
+    if !raw.contains('%') {
+        return Ok(raw.to_owned());
+    }
+
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or(RouteError::InvalidPercentEncoding)?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| RouteError::InvalidUtf8)
+}
+
+/// Why a route was rejected by [`Route::parse`] or [`Route::from_url_path`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    /// routes must start with `/`
+    NotAbsolute,
+    /// a `%` wasn't followed by two hex digits
+    InvalidPercentEncoding,
+    /// percent-decoding yielded invalid UTF-8
+    InvalidUtf8,
+    /// routes can't contain control characters
+    ControlCharacter,
+    /// routes can't contain `.` or `..` segments
+    DotSegment,
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::NotAbsolute => write!(f, "route must start with '/'"),
+            RouteError::InvalidPercentEncoding => write!(f, "route has malformed percent-encoding"),
+            RouteError::InvalidUtf8 => write!(f, "route percent-decodes to invalid UTF-8"),
+            RouteError::ControlCharacter => write!(f, "route contains control characters"),
+            RouteError::DotSegment => write!(f, "route contains '.' or '..' segments"),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[test]
+fn test_route_parse_slashes() {
+    let parse = |s: &str| Route::parse(s).map(|r| r.to_string());
+    assert_eq!(parse("/").as_deref(), Ok("/"));
+    assert_eq!(parse("//").as_deref(), Ok("/"));
+    assert_eq!(parse("/about").as_deref(), Ok("/about"));
+    assert_eq!(parse("/about/").as_deref(), Ok("/about"));
+    assert_eq!(parse("/about//").as_deref(), Ok("/about"));
+    assert_eq!(
+        parse("//articles///10-months-of-itch/").as_deref(),
+        Ok("/articles/10-months-of-itch")
+    );
+    assert_eq!(
+        parse("/articles/foo/bar~hash.png").as_deref(),
+        Ok("/articles/foo/bar~hash.png")
+    );
+    // stored routes are already decoded, `%` is just a character
+    assert_eq!(parse("/100%").as_deref(), Ok("/100%"));
+}
+
+#[test]
+fn test_route_from_url_path() {
+    let parse = |s: &str| Route::from_url_path(s).map(|r| r.to_string());
+    assert_eq!(parse("/caf%C3%A9").as_deref(), Ok("/café"));
+    assert_eq!(parse("/caf%c3%a9/").as_deref(), Ok("/café"));
+    assert_eq!(parse("/café").as_deref(), Ok("/café"));
+    assert_eq!(parse("/hello%20world").as_deref(), Ok("/hello world"));
+    assert_eq!(parse("/100%25").as_deref(), Ok("/100%"));
+    // decoding happens once: `%2525` is a literal `%25`
+    assert_eq!(parse("/100%2525").as_deref(), Ok("/100%25"));
+}
+
+#[test]
+fn test_route_rejects_malformed() {
+    assert_eq!(Route::parse(""), Err(RouteError::NotAbsolute));
+    assert_eq!(Route::parse("about"), Err(RouteError::NotAbsolute));
+    assert_eq!(Route::parse("/a\nb"), Err(RouteError::ControlCharacter));
+    assert_eq!(Route::parse("/a/../b"), Err(RouteError::DotSegment));
+    assert_eq!(Route::parse("/./a"), Err(RouteError::DotSegment));
+
+    assert_eq!(
+        Route::from_url_path("/100%"),
+        Err(RouteError::InvalidPercentEncoding)
+    );
+    assert_eq!(
+        Route::from_url_path("/%zz"),
+        Err(RouteError::InvalidPercentEncoding)
+    );
+    assert_eq!(
+        Route::from_url_path("/%C3%A"),
+        Err(RouteError::InvalidPercentEncoding)
+    );
+    assert_eq!(
+        Route::from_url_path("/%+1"),
+        Err(RouteError::InvalidPercentEncoding)
+    );
+    assert_eq!(Route::from_url_path("/%C3"), Err(RouteError::InvalidUtf8));
+    assert_eq!(
+        Route::from_url_path("/a%00b"),
+        Err(RouteError::ControlCharacter)
+    );
+    assert_eq!(
+        Route::from_url_path("/a/%2E%2E/b"),
+        Err(RouteError::DotSegment)
+    );
+}
+
+#[test]
+fn test_route_unicode_and_spaces() {
+    let nfc = Route::parse("/articles/café").unwrap();
+    // `e` followed by a combining acute accent
+    let nfd = Route::parse("/articles/cafe\u{301}").unwrap();
+    assert_eq!(nfc, nfd);
+    assert_eq!(Route::from_url_path("/articles/caf%C3%A9").unwrap(), nfc);
+    assert_eq!(Route::from_url_path("/articles/cafe%CC%81").unwrap(), nfc);
+    assert_eq!(Route::from_url_path("/articles/café").unwrap(), nfc);
+
+    let spaced = Route::parse("/articles/hello world").unwrap();
+    assert_eq!(
+        Route::from_url_path("/articles/hello%20world").unwrap(),
+        spaced
+    );
+    assert_eq!(
+        Route::from_url_path("/articles/hello world/").unwrap(),
+        spaced
+    );
+}
+
+#[test]
+fn test_route_to_url_path() {
+    let enc = |s: &str| Route::parse(s).unwrap().to_url_path();
+    assert_eq!(enc("/"), "/");
+    assert_eq!(
+        enc("/articles/10-months-of-itch"),
+        "/articles/10-months-of-itch"
+    );
+    assert_eq!(enc("/articles/bar~hash.png"), "/articles/bar~hash.png");
+    assert_eq!(enc("/café"), "/caf%C3%A9");
+    assert_eq!(enc("/hello world"), "/hello%20world");
+    assert_eq!(enc("/100%"), "/100%25");
+    assert_eq!(enc("/what?#"), "/what%3F%23");
+
+    for raw in [
+        "/café",
+        "/hello world",
+        "/100%",
+        "/a+b=c",
+        "/what?#",
+        "/日本語",
+    ] {
+        let route = Route::parse(raw).unwrap();
+        assert_eq!(Route::from_url_path(&route.to_url_path()).unwrap(), route);
+    }
+}
+
+#[test]
+fn test_route_canonicalize_is_idempotent() {
+    for raw in ["/", "/about/", "//a//b/", "/café", "/100%", "/hello world"] {
+        let once = Route::parse(raw).unwrap();
+        assert_eq!(once.canonicalize().unwrap(), once);
+    }
+}
+
+#[cfg(test)]
+mod route_proptests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_parse_never_panics_and_is_idempotent(raw in any::<String>()) {
+            if let Ok(route) = Route::parse(&raw) {
+                prop_assert_eq!(route.canonicalize(), Ok(route.clone()));
+            }
+        }
+
+        #[test]
+        fn prop_from_url_path_never_panics(raw in "/[a-zA-Z0-9%/. é]{0,40}") {
+            if let Ok(route) = Route::from_url_path(&raw) {
+                prop_assert_eq!(route.canonicalize(), Ok(route.clone()));
+            }
+        }
+
+        #[test]
+        fn prop_url_path_round_trips(raw in "/\\PC{0,40}") {
+            if let Ok(route) = Route::parse(&raw) {
+                let url_path = route.to_url_path();
+                prop_assert!(url_path.is_ascii());
+                prop_assert_eq!(Route::from_url_path(&url_path), Ok(route));
+            }
+        }
+    }
 }
 
 /// A revision, fully loaded — with tags indexed, series
@@ -1365,6 +1660,9 @@ mod tests {
                 secrets: None,
                 base_dir_for_dev: None,
                 rc_for_dev: None,
+                contact_email: None,
+                derive_concurrency: None,
+                strict_oauth_scopes: false,
             },
         };
 
@@ -74,3 +74,50 @@ content_types! {
     OctetStream => { ext: "bin", mime: "application/octet-stream", serial: "octet-stream" },
     Jinja => { ext: "jinja", mime: "application/x-jinja", serial: "jinja" }
 }
+
+impl ContentType {
+    /// How many leading bytes [`ContentType::sniff`] looks at, at most
+    pub const SNIFF_LEN: usize = 16;
+
+    /// Guesses the type from the first bytes of a file, for when the
+    /// extension doesn't tell. Only recognizes binary media and fonts, never
+    /// something a browser would run or render as a document (HTML, SVG, JS).
+    pub fn sniff(prefix: &[u8]) -> Option<Self> {
+        let at =
+            |offset: usize, magic: &[u8]| prefix.get(offset..offset + magic.len()) == Some(magic);
+
+        if at(0, b"\x89PNG\r\n\x1a\n") {
+            Some(Self::PNG)
+        } else if at(0, b"\xff\xd8\xff") {
+            Some(Self::JPG)
+        } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+            Some(Self::GIF)
+        } else if at(0, b"RIFF") && at(8, b"WEBP") {
+            Some(Self::WEBP)
+        } else if at(0, b"\xff\x0a") || at(0, b"\x00\x00\x00\x0cJXL \r\n\x87\n") {
+            Some(Self::JXL)
+        } else if at(4, b"ftyp") {
+            match prefix.get(8..12) {
+                Some(b"avif" | b"avis") => Some(Self::AVIF),
+                Some(b"heic" | b"heix") => Some(Self::HEIC),
+                Some(b"M4A ") => Some(Self::M4A),
+                Some(_) => Some(Self::MP4),
+                None => None,
+            }
+        } else if at(0, b"\x1a\x45\xdf\xa3") {
+            Some(Self::WebM)
+        } else if at(0, b"OggS") {
+            Some(Self::OGG)
+        } else if at(0, b"fLaC") {
+            Some(Self::FLAC)
+        } else if at(0, b"ID3") || at(0, b"\xff\xfb") || at(0, b"\xff\xf3") || at(0, b"\xff\xf2") {
+            Some(Self::MP3)
+        } else if at(0, b"\xff\xf1") || at(0, b"\xff\xf9") {
+            Some(Self::AAC)
+        } else if at(0, b"wOF2") {
+            Some(Self::WOFF2)
+        } else {
+            None
+        }
+    }
+}
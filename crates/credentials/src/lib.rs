@@ -1,10 +1,13 @@
 use facet::Facet;
 use plait::plait;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 pub use eyre::{Result, eyre};
 use time::OffsetDateTime;
 
+mod profile_cache;
+pub use profile_cache::{PROFILE_CACHE_CAPACITY, ProfileCache};
+
 plait! {
     with crates {
         serde
@@ -43,19 +46,138 @@ plait! {
     pub struct TierCause => &TierCauseRef;
 }
 
-/// An auth bundle, stored in a confidential cookie
-#[derive(Debug, Clone, Serialize, Facet)]
+/// How long before they expire we refresh OAuth credentials
+pub const CREDENTIALS_REFRESH_THRESHOLD: time::Duration = time::Duration::hours(1);
+
+/// OAuth credentials for a third-party provider (GitHub, Patreon, Discord)
+pub trait Credentials {
+    /// When the access token stops being valid
+    fn expires_at(&self) -> OffsetDateTime;
+
+    /// Whether the access token expires less than `threshold` from now
+    /// (or has already expired)
+    fn expire_soon(&self, threshold: time::Duration) -> bool {
+        self.expires_within(OffsetDateTime::now_utc(), threshold)
+    }
+
+    /// Whether the access token expires less than `threshold` after `now`
+    fn expires_within(&self, now: OffsetDateTime, threshold: time::Duration) -> bool {
+        self.expires_at() - now < threshold
+    }
+}
+
+/// The provider granted fewer OAuth scopes than we asked for, e.g. because
+/// a GitHub org restricts third-party access. The login still works, but
+/// anything that needs the missing scopes will fail later on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeDowngrade {
+    /// "github", "patreon", "discord"
+    pub provider: &'static str,
+    pub requested: Vec<String>,
+    pub granted: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl ScopeDowngrade {
+    /// Compares scope lists as they appear in OAuth requests and token
+    /// responses: separated by commas (GitHub) or spaces (everyone else).
+    /// Returns `None` if every requested scope was granted.
+    pub fn detect(provider: &'static str, requested: &str, granted: &str) -> Option<Self> {
+        let requested = split_scopes(requested);
+        let granted = split_scopes(granted);
+        let missing: Vec<String> = requested
+            .iter()
+            .filter(|scope| !granted.contains(scope))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            provider,
+            requested,
+            granted,
+            missing,
+        })
+    }
+
+    /// Like [`Self::detect`], but in strict mode a downgrade is an error
+    /// instead of a warning for the caller to deal with
+    pub fn check(
+        provider: &'static str,
+        requested: &str,
+        granted: &str,
+        strict: bool,
+    ) -> Result<Option<Self>> {
+        match Self::detect(provider, requested, granted) {
+            Some(downgrade) if strict => Err(downgrade.into()),
+            downgrade => Ok(downgrade),
+        }
+    }
+}
+
+impl std::fmt::Display for ScopeDowngrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "OAuth scope downgrade: provider={} missing={} requested={} granted={}",
+            self.provider,
+            self.missing.join(","),
+            self.requested.join(","),
+            self.granted.join(",")
+        )
+    }
+}
+
+impl std::error::Error for ScopeDowngrade {}
+
+fn split_scopes(scopes: &str) -> Vec<String> {
+    scopes
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|scope| !scope.is_empty())
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
+/// What a provider's OAuth callback hands back once the user went through
+/// with the flow
+#[derive(Debug, Clone)]
+pub struct OAuthGrant<C> {
+    pub creds: C,
+    /// set if the provider granted fewer scopes than we asked for
+    pub scope_downgrade: Option<ScopeDowngrade>,
+}
+
+/// An auth bundle, stored in a confidential cookie (as `facet_json`)
+#[derive(Debug, Clone, Serialize, Deserialize, Facet)]
 pub struct AuthBundle {
     pub user_info: UserInfo,
 }
 
-#[derive(Debug, Clone, Serialize, Facet)]
+#[derive(Clone, Serialize, Facet)]
 pub struct Profile {
     pub name: String,
     pub avatar_url: Option<String>,
+
+    /// Contact email, from whichever provider gave us one
+    #[facet(default)]
+    pub email: Option<String>,
+
+    #[facet(default)]
+    pub patreon_id: Option<PatreonUserId>,
+
+    #[facet(default)]
+    pub github_id: Option<GithubUserId>,
+
+    #[facet(default)]
+    pub discord_id: Option<DiscordUserId>,
 }
 
-#[derive(Debug, Clone, Serialize, Facet)]
+/// Goes through `facet_json` between mom and cub and in the auth cookie, and
+/// through serde when handed to templates. Both write `fetched_at` as RFC3339:
+/// serde via `time::serde::rfc3339`, facet via its `time` feature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Facet)]
 pub struct UserInfo {
     /// tenant-specific user ID
     pub id: UserId,
@@ -78,6 +200,7 @@ pub struct UserInfo {
 
     /// Is that user a member of the Discord server?
     #[facet(default)]
+    #[serde(default)]
     pub in_discord: bool,
 
     /// gifted tier (if any)
@@ -85,7 +208,7 @@ pub struct UserInfo {
     pub gifted_tier: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Facet)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Facet)]
 pub struct GithubProfile {
     /// Github user ID
     pub id: GithubUserId,
@@ -106,9 +229,13 @@ pub struct GithubProfile {
 
     /// Avatar URL
     pub avatar_url: Option<String>,
+
+    /// Public profile email, if the user set one
+    #[facet(default)]
+    pub email: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Facet)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Facet)]
 pub struct PatreonProfile {
     /// Patreon user ID
     pub id: PatreonUserId,
@@ -121,9 +248,18 @@ pub struct PatreonProfile {
 
     /// Avatar URL
     pub avatar_url: Option<String>,
+
+    /// Email, only present if we were granted the `identity[email]` scope
+    #[facet(default)]
+    pub email: Option<String>,
+
+    /// What the member is currently entitled to, in cents, as of the last
+    /// time we listed the campaign's members
+    #[facet(default)]
+    pub entitled_cents: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Facet)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Facet)]
 pub struct DiscordProfile {
     /// Discord user ID
     pub id: DiscordUserId,
@@ -138,11 +274,120 @@ pub struct DiscordProfile {
     /// Base URL is https://cdn.discordapp.com/
     /// For avatars you want 'avatars/user_id/user_avatar.png'
     pub avatar_hash: Option<String>,
+
+    /// Legacy discriminator (the `1234` in `name#1234`), "0" or absent for
+    /// users who migrated to the new username system
+    #[facet(default)]
+    pub discriminator: Option<String>,
+
+    /// Email, only present if we were granted the `email` scope
+    #[facet(default)]
+    pub email: Option<String>,
+}
+
+/// Profiles end up in logs, so their `Debug` says whether there's an email
+/// but not what it is. Each impl destructures its struct, so that new fields
+/// aren't left out.
+struct RedactedEmail<'a>(&'a Option<String>);
+
+impl std::fmt::Debug for RedactedEmail<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(<redacted>)"),
+            None => f.write_str("None"),
+        }
+    }
+}
+
+impl std::fmt::Debug for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Profile {
+            name,
+            avatar_url,
+            email,
+            patreon_id,
+            github_id,
+            discord_id,
+        } = self;
+        f.debug_struct("Profile")
+            .field("name", name)
+            .field("avatar_url", avatar_url)
+            .field("email", &RedactedEmail(email))
+            .field("patreon_id", patreon_id)
+            .field("github_id", github_id)
+            .field("discord_id", discord_id)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for GithubProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let GithubProfile {
+            id,
+            monthly_usd,
+            sponsorship_privacy_level,
+            name,
+            login,
+            avatar_url,
+            email,
+        } = self;
+        f.debug_struct("GithubProfile")
+            .field("id", id)
+            .field("monthly_usd", monthly_usd)
+            .field("sponsorship_privacy_level", sponsorship_privacy_level)
+            .field("name", name)
+            .field("login", login)
+            .field("avatar_url", avatar_url)
+            .field("email", &RedactedEmail(email))
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for PatreonProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let PatreonProfile {
+            id,
+            tier,
+            full_name,
+            avatar_url,
+            email,
+            entitled_cents,
+        } = self;
+        f.debug_struct("PatreonProfile")
+            .field("id", id)
+            .field("tier", tier)
+            .field("full_name", full_name)
+            .field("avatar_url", avatar_url)
+            .field("email", &RedactedEmail(email))
+            .field("entitled_cents", entitled_cents)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for DiscordProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let DiscordProfile {
+            id,
+            username,
+            global_name,
+            avatar_hash,
+            discriminator,
+            email,
+        } = self;
+        f.debug_struct("DiscordProfile")
+            .field("id", id)
+            .field("username", username)
+            .field("global_name", global_name)
+            .field("avatar_hash", avatar_hash)
+            .field("discriminator", discriminator)
+            .field("email", &RedactedEmail(email))
+            .finish()
+    }
 }
 
 /// hardcoded stuff for fasterthanlime
 
-#[derive(Facet, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[derive(Facet, Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum FasterthanlimeTier {
     None = 0,
@@ -163,62 +408,160 @@ impl Ord for FasterthanlimeTier {
     }
 }
 
+/// How a tenant turns sponsorships (and gifts) into tiers
+#[derive(Facet, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TierMapping {
+    /// Patreon tier titles (matched case-insensitively)
+    #[serde(default)]
+    #[facet(default)]
+    pub patreon: Vec<NamedTier>,
+
+    /// GitHub monthly sponsorship amounts: the highest threshold met wins
+    #[serde(default)]
+    #[facet(default)]
+    pub github: Vec<GithubTierThreshold>,
+
+    /// Gifted tier names (matched case-insensitively)
+    #[serde(default)]
+    #[facet(default)]
+    pub gifted: Vec<NamedTier>,
+}
+
+#[derive(Facet, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamedTier {
+    pub name: String,
+    pub tier: FasterthanlimeTier,
+}
+
+#[derive(Facet, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GithubTierThreshold {
+    pub min_monthly_usd: u64,
+    pub tier: FasterthanlimeTier,
+}
+
+impl Default for TierMapping {
+    /// fasterthanlime's tiers
+    fn default() -> Self {
+        let named = |name: &str, tier| NamedTier {
+            name: name.to_string(),
+            tier,
+        };
+        let names = vec![
+            named("bronze", FasterthanlimeTier::Bronze),
+            named("silver", FasterthanlimeTier::Silver),
+            named("gold", FasterthanlimeTier::Gold),
+            named("creator", FasterthanlimeTier::Gold),
+        ];
+        let threshold = |min_monthly_usd, tier| GithubTierThreshold {
+            min_monthly_usd,
+            tier,
+        };
+
+        Self {
+            patreon: names.clone(),
+            github: vec![
+                threshold(50, FasterthanlimeTier::Gold),
+                threshold(10, FasterthanlimeTier::Silver),
+                threshold(5, FasterthanlimeTier::Bronze),
+            ],
+            gifted: names,
+        }
+    }
+}
+
+impl TierMapping {
+    fn by_name(names: &[NamedTier], name: &str) -> FasterthanlimeTier {
+        names
+            .iter()
+            .find(|nt| nt.name.eq_ignore_ascii_case(name))
+            .map(|nt| nt.tier)
+            .unwrap_or(FasterthanlimeTier::None)
+    }
+
+    pub fn patreon_tier(&self, title: &str) -> FasterthanlimeTier {
+        Self::by_name(&self.patreon, title)
+    }
+
+    pub fn github_tier(&self, monthly_usd: u64) -> FasterthanlimeTier {
+        self.github
+            .iter()
+            .filter(|t| monthly_usd >= t.min_monthly_usd)
+            .map(|t| t.tier)
+            .max()
+            .unwrap_or(FasterthanlimeTier::None)
+    }
+
+    pub fn gifted_tier(&self, name: &str) -> FasterthanlimeTier {
+        Self::by_name(&self.gifted, name)
+    }
+}
+
 impl UserInfo {
+    /// Clears the email of every linked profile. Emails stay with mom: cubs
+    /// get user infos to figure out tiers, they have no use for contact info.
+    pub fn strip_emails(&mut self) {
+        if let Some(github) = self.github.as_mut() {
+            github.email = None;
+        }
+        if let Some(patreon) = self.patreon.as_mut() {
+            patreon.email = None;
+        }
+        if let Some(discord) = self.discord.as_mut() {
+            discord.email = None;
+        }
+    }
+
+    /// Tier with the default (fasterthanlime) mapping, see [`UserInfo::tier`]
     pub fn get_fasterthanlime_tier(&self) -> Option<(FasterthanlimeTier, TierCause)> {
-        // Check Patreon tier
-        let patreon_tier = self
-            .patreon
-            .as_ref()
-            .and_then(|p| p.tier.as_deref())
-            .map(|tier| match tier.to_lowercase().as_str() {
-                "bronze" => FasterthanlimeTier::Bronze,
-                "silver" => FasterthanlimeTier::Silver,
-                "gold" | "creator" => FasterthanlimeTier::Gold,
-                _ => FasterthanlimeTier::None,
-            })
-            .unwrap_or(FasterthanlimeTier::None);
+        self.tier(&TierMapping::default())
+    }
 
-        // Check GitHub sponsorship tier
-        let github_tier = self
-            .github
-            .as_ref()
-            .and_then(|g| g.monthly_usd)
-            .map(|amount| match amount {
-                amount if amount >= 50 => FasterthanlimeTier::Gold,
-                amount if amount >= 10 => FasterthanlimeTier::Silver,
-                amount if amount >= 5 => FasterthanlimeTier::Bronze,
-                _ => FasterthanlimeTier::None,
-            })
-            .unwrap_or(FasterthanlimeTier::None);
-
-        // Check gifted tier
-        let gifted_tier = self
-            .gifted_tier
-            .as_deref()
-            .map(|tier| match tier.to_lowercase().as_str() {
-                "bronze" => FasterthanlimeTier::Bronze,
-                "silver" => FasterthanlimeTier::Silver,
-                "gold" | "creator" => FasterthanlimeTier::Gold,
-                _ => FasterthanlimeTier::None,
-            })
-            .unwrap_or(FasterthanlimeTier::None);
-
-        // Return the highest tier from any platform
-        let highest_tier = patreon_tier.max(github_tier).max(gifted_tier);
-
-        match highest_tier {
-            FasterthanlimeTier::None => None,
-            tier => {
-                let cause = if tier == gifted_tier && tier != FasterthanlimeTier::None {
-                    TierCause::from("gift")
-                } else if tier == patreon_tier && tier != FasterthanlimeTier::None {
-                    TierCause::from("patreon")
-                } else {
-                    TierCause::from("github")
-                };
-                Some((tier, cause))
+    /// The highest tier granted by any source, and which source granted it
+    /// (gifts win ties, then Patreon, then GitHub).
+    pub fn tier(&self, mapping: &TierMapping) -> Option<(FasterthanlimeTier, TierCause)> {
+        let mut highest: Option<(FasterthanlimeTier, TierCause)> = None;
+        for (cause, tier) in self.tier_breakdown(mapping) {
+            if tier == FasterthanlimeTier::None {
+                continue;
+            }
+            if highest.as_ref().is_none_or(|(best, _)| tier > *best) {
+                highest = Some((tier, cause));
             }
         }
+        highest
+    }
+
+    /// The tier each linked source grants on its own, in tie-breaking order
+    /// (gift, Patreon, GitHub). Sources that grant nothing are listed with
+    /// [`FasterthanlimeTier::None`], unlinked ones are left out.
+    pub fn tier_breakdown(&self, mapping: &TierMapping) -> Vec<(TierCause, FasterthanlimeTier)> {
+        let mut breakdown = Vec::new();
+
+        if let Some(tier) = self.gifted_tier.as_deref() {
+            breakdown.push((TierCause::from("gift"), mapping.gifted_tier(tier)));
+        }
+
+        if let Some(patreon) = self.patreon.as_ref() {
+            let tier = patreon
+                .tier
+                .as_deref()
+                .map(|tier| mapping.patreon_tier(tier))
+                .unwrap_or(FasterthanlimeTier::None);
+            breakdown.push((TierCause::from("patreon"), tier));
+        }
+
+        if let Some(github) = self.github.as_ref() {
+            let tier = github
+                .monthly_usd
+                .map(|amount| mapping.github_tier(amount))
+                .unwrap_or(FasterthanlimeTier::None);
+            breakdown.push((TierCause::from("github"), tier));
+        }
+
+        breakdown
     }
 
     pub fn name(&self) -> String {
@@ -250,18 +593,30 @@ impl UserInfo {
             .and_then(|g| g.avatar_url.clone())
             .or_else(|| self.patreon.as_ref().and_then(|p| p.avatar_url.clone()))
             .or_else(|| {
-                self.discord.as_ref().and_then(|d| {
-                    d.avatar_hash
-                        .as_ref()
-                        .map(|hash| build_discord_avatar_url(&d.id, hash))
+                self.discord.as_ref().map(|d| match d.avatar_hash.as_ref() {
+                    Some(hash) => build_discord_avatar_url(&d.id, hash),
+                    None => build_discord_default_avatar_url(&d.id, d.discriminator.as_deref()),
                 })
             })
     }
 
+    pub fn email(&self) -> Option<String> {
+        self.github
+            .as_ref()
+            .and_then(|g| g.email.clone())
+            .or_else(|| self.patreon.as_ref().and_then(|p| p.email.clone()))
+            .or_else(|| self.discord.as_ref().and_then(|d| d.email.clone()))
+            .filter(|email| !email.trim().is_empty())
+    }
+
     pub fn get_profile(&self) -> Profile {
         Profile {
             name: self.name(),
             avatar_url: self.avatar_url(),
+            email: self.email(),
+            patreon_id: self.patreon.as_ref().map(|p| p.id.clone()),
+            github_id: self.github.as_ref().map(|g| g.id.clone()),
+            discord_id: self.discord.as_ref().map(|d| d.id.clone()),
         }
     }
 
@@ -274,6 +629,28 @@ fn build_discord_avatar_url(user_id: &DiscordUserIdRef, avatar_hash: &str) -> St
     format!("https://cdn.discordapp.com/avatars/{user_id}/{avatar_hash}.png")
 }
 
+/// The avatar Discord shows for users who never uploaded one. Users on the new
+/// username system get `(user_id >> 22) % 6`, legacy users `discriminator % 5`.
+fn build_discord_default_avatar_url(
+    user_id: &DiscordUserIdRef,
+    discriminator: Option<&str>,
+) -> String {
+    let legacy_index = discriminator
+        .and_then(|d| d.parse::<u64>().ok())
+        .filter(|&d| d != 0)
+        .map(|d| d % 5);
+    let index = legacy_index
+        .or_else(|| {
+            user_id
+                .as_str()
+                .parse::<u64>()
+                .ok()
+                .map(|id| (id >> 22) % 6)
+        })
+        .unwrap_or(0);
+    format!("https://cdn.discordapp.com/embed/avatars/{index}.png")
+}
+
 impl FasterthanlimeTier {
     pub fn has_bronze(self) -> bool {
         self >= FasterthanlimeTier::Bronze
@@ -287,3 +664,393 @@ impl FasterthanlimeTier {
         self >= FasterthanlimeTier::Gold
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All providers' credentials only say when they expire, the rest is up
+    /// to the trait
+    struct ExpiresAt(OffsetDateTime);
+
+    impl Credentials for ExpiresAt {
+        fn expires_at(&self) -> OffsetDateTime {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_expire_soon_threshold() {
+        use time::Duration;
+
+        let now = OffsetDateTime::now_utc();
+        let threshold = CREDENTIALS_REFRESH_THRESHOLD;
+        let creds = ExpiresAt;
+
+        // fresh tokens last hours (GitHub) to a month (Patreon)
+        assert!(!creds(now + Duration::hours(8)).expires_within(now, threshold));
+        assert!(!creds(now + Duration::days(31)).expires_within(now, threshold));
+        assert!(!creds(now + threshold).expires_within(now, threshold));
+        assert!(creds(now + threshold - Duration::seconds(1)).expires_within(now, threshold));
+        assert!(creds(now - Duration::minutes(5)).expires_within(now, threshold));
+
+        // the threshold is up to the caller
+        assert!(creds(now + Duration::hours(8)).expires_within(now, Duration::hours(24)));
+        assert!(!creds(now + Duration::minutes(30)).expires_within(now, Duration::minutes(10)));
+    }
+
+    fn user(patreon: Option<&str>, github_usd: Option<u64>, gifted: Option<&str>) -> UserInfo {
+        UserInfo {
+            id: UserId::new("1".to_string()),
+            fetched_at: OffsetDateTime::now_utc(),
+            patreon: patreon.map(|tier| PatreonProfile {
+                id: PatreonUserId::new("p1".to_string()),
+                tier: Some(tier.to_string()),
+                full_name: "Patron".to_string(),
+                avatar_url: None,
+                email: None,
+                entitled_cents: None,
+            }),
+            github: github_usd.map(|usd| GithubProfile {
+                id: GithubUserId::new("g1".to_string()),
+                monthly_usd: Some(usd),
+                sponsorship_privacy_level: None,
+                name: None,
+                login: "sponsor".to_string(),
+                avatar_url: None,
+                email: None,
+            }),
+            discord: None,
+            in_discord: false,
+            gifted_tier: gifted.map(|s| s.to_string()),
+        }
+    }
+
+    fn tier(user: &UserInfo, mapping: &TierMapping) -> Option<(FasterthanlimeTier, String)> {
+        user.tier(mapping)
+            .map(|(tier, cause)| (tier, cause.as_str().to_string()))
+    }
+
+    fn custom_mapping() -> TierMapping {
+        TierMapping {
+            patreon: vec![NamedTier {
+                name: "Supporter".to_string(),
+                tier: FasterthanlimeTier::Silver,
+            }],
+            github: vec![GithubTierThreshold {
+                min_monthly_usd: 100,
+                tier: FasterthanlimeTier::Gold,
+            }],
+            gifted: vec![NamedTier {
+                name: "friend".to_string(),
+                tier: FasterthanlimeTier::Bronze,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_default_mapping_matches_fasterthanlime() {
+        let mapping = TierMapping::default();
+        assert_eq!(tier(&user(None, None, None), &mapping), None);
+        assert_eq!(
+            tier(&user(Some("Creator"), None, None), &mapping),
+            Some((FasterthanlimeTier::Gold, "patreon".to_string()))
+        );
+        assert_eq!(
+            tier(&user(None, Some(9), None), &mapping),
+            Some((FasterthanlimeTier::Bronze, "github".to_string()))
+        );
+        assert_eq!(tier(&user(None, Some(4), None), &mapping), None);
+
+        let u = user(Some("silver"), Some(50), None);
+        assert_eq!(
+            u.get_fasterthanlime_tier().map(|(tier, _)| tier),
+            Some(FasterthanlimeTier::Gold)
+        );
+    }
+
+    #[test]
+    fn test_each_source_can_win() {
+        let mapping = custom_mapping();
+
+        // patreon wins
+        assert_eq!(
+            tier(&user(Some("supporter"), Some(99), Some("friend")), &mapping),
+            Some((FasterthanlimeTier::Silver, "patreon".to_string()))
+        );
+        // github wins
+        assert_eq!(
+            tier(
+                &user(Some("supporter"), Some(100), Some("friend")),
+                &mapping
+            ),
+            Some((FasterthanlimeTier::Gold, "github".to_string()))
+        );
+        // gift wins
+        assert_eq!(
+            tier(&user(Some("Gold"), None, Some("Friend")), &mapping),
+            Some((FasterthanlimeTier::Bronze, "gift".to_string()))
+        );
+        // names from the default mapping mean nothing here
+        assert_eq!(tier(&user(Some("Gold"), Some(50), None), &mapping), None);
+    }
+
+    #[test]
+    fn test_github_thresholds_order_does_not_matter() {
+        let mut mapping = TierMapping::default();
+        mapping.github.reverse();
+        assert_eq!(mapping.github_tier(5), FasterthanlimeTier::Bronze);
+        assert_eq!(mapping.github_tier(10), FasterthanlimeTier::Silver);
+        assert_eq!(mapping.github_tier(1000), FasterthanlimeTier::Gold);
+        assert_eq!(mapping.github_tier(0), FasterthanlimeTier::None);
+    }
+
+    #[test]
+    fn test_partial_mapping_leaves_other_sources_empty() {
+        let json = r#"{ "patreon": [{ "name": "Supporter", "tier": "Silver" }] }"#;
+        let expected = TierMapping {
+            patreon: vec![NamedTier {
+                name: "Supporter".to_string(),
+                tier: FasterthanlimeTier::Silver,
+            }],
+            github: vec![],
+            gifted: vec![],
+        };
+
+        let mapping: TierMapping = serde_json::from_str(json).unwrap();
+        assert_eq!(mapping, expected);
+        let mapping: TierMapping = facet_json::from_str(json).unwrap();
+        assert_eq!(mapping, expected);
+    }
+
+    fn discord_user(avatar_hash: Option<&str>, discriminator: Option<&str>) -> UserInfo {
+        let mut user = user(None, None, None);
+        user.discord = Some(DiscordProfile {
+            id: DiscordUserId::new("80351110224678912".to_string()),
+            username: "nelly".to_string(),
+            global_name: None,
+            avatar_hash: avatar_hash.map(|s| s.to_string()),
+            discriminator: discriminator.map(|s| s.to_string()),
+            email: None,
+        });
+        user
+    }
+
+    #[test]
+    fn test_discord_avatar_with_custom_hash() {
+        let user = discord_user(Some("8342729096ea3675442027381ff50dfe"), Some("1337"));
+        assert_eq!(
+            user.avatar_url().as_deref(),
+            Some(
+                "https://cdn.discordapp.com/avatars/80351110224678912/8342729096ea3675442027381ff50dfe.png"
+            )
+        );
+    }
+
+    #[test]
+    fn test_discord_avatar_default_fallback() {
+        // new username system: (80351110224678912 >> 22) % 6 == 5
+        for discriminator in [None, Some("0")] {
+            let user = discord_user(None, discriminator);
+            assert_eq!(
+                user.avatar_url().as_deref(),
+                Some("https://cdn.discordapp.com/embed/avatars/5.png")
+            );
+        }
+
+        // legacy discriminator: 1337 % 5 == 2
+        let user = discord_user(None, Some("1337"));
+        assert_eq!(
+            user.avatar_url().as_deref(),
+            Some("https://cdn.discordapp.com/embed/avatars/2.png")
+        );
+    }
+
+    #[test]
+    fn test_profile_from_each_provider() {
+        let mut patreon = user(Some("Gold"), None, None);
+        patreon.patreon.as_mut().unwrap().email = Some("patron@example.org".to_string());
+        let profile = patreon.get_profile();
+        assert_eq!(profile.name, "Patron");
+        assert_eq!(profile.email.as_deref(), Some("patron@example.org"));
+        assert_eq!(
+            profile.patreon_id.as_ref().map(|id| id.as_str()),
+            Some("p1")
+        );
+        assert!(profile.github_id.is_none());
+        assert!(profile.discord_id.is_none());
+
+        let mut github = user(None, Some(10), None);
+        github.github.as_mut().unwrap().email = Some("sponsor@example.org".to_string());
+        let profile = github.get_profile();
+        assert_eq!(profile.name, "sponsor");
+        assert_eq!(profile.email.as_deref(), Some("sponsor@example.org"));
+        assert_eq!(profile.github_id.as_ref().map(|id| id.as_str()), Some("g1"));
+        assert!(profile.patreon_id.is_none());
+
+        let mut discord = discord_user(None, None);
+        discord.discord.as_mut().unwrap().email = Some("nelly@example.org".to_string());
+        let profile = discord.get_profile();
+        assert_eq!(profile.email.as_deref(), Some("nelly@example.org"));
+        assert_eq!(
+            profile.discord_id.as_ref().map(|id| id.as_str()),
+            Some("80351110224678912")
+        );
+        assert!(profile.patreon_id.is_none());
+        assert!(profile.github_id.is_none());
+
+        // no scope for email: still a profile, just without contact info
+        let profile = user(Some("Gold"), Some(10), None).get_profile();
+        assert_eq!(profile.email, None);
+        assert!(profile.patreon_id.is_some());
+        assert!(profile.github_id.is_some());
+    }
+
+    #[test]
+    fn test_debug_redacts_emails() {
+        let mut u = user(Some("Gold"), Some(10), None);
+        u.patreon.as_mut().unwrap().email = Some("patron@example.org".to_string());
+        u.github.as_mut().unwrap().email = Some("sponsor@example.org".to_string());
+        let mut d = discord_user(None, None);
+        d.discord.as_mut().unwrap().email = Some("nelly@example.org".to_string());
+        u.discord = d.discord;
+
+        for formatted in [
+            format!("{u:?}"),
+            format!("{u:#?}"),
+            format!("{:#?}", u.get_profile()),
+        ] {
+            assert!(!formatted.contains("@example.org"), "{formatted}");
+            assert!(formatted.contains("Some(<redacted>)"), "{formatted}");
+        }
+    }
+
+    #[test]
+    fn test_tier_breakdown_lists_every_source() {
+        let mapping = TierMapping::default();
+        let breakdown = |user: &UserInfo| {
+            user.tier_breakdown(&mapping)
+                .into_iter()
+                .map(|(cause, tier)| (cause.as_str().to_string(), tier))
+                .collect::<Vec<_>>()
+        };
+
+        let u = user(Some("Silver"), Some(50), Some("Bronze"));
+        assert_eq!(
+            breakdown(&u),
+            vec![
+                ("gift".to_string(), FasterthanlimeTier::Bronze),
+                ("patreon".to_string(), FasterthanlimeTier::Silver),
+                ("github".to_string(), FasterthanlimeTier::Gold),
+            ]
+        );
+        assert_eq!(
+            tier(&u, &mapping),
+            Some((FasterthanlimeTier::Gold, "github".to_string()))
+        );
+
+        // linked but not sponsoring still shows up, unlinked sources don't
+        let u = user(Some("Free"), Some(0), None);
+        assert_eq!(
+            breakdown(&u),
+            vec![
+                ("patreon".to_string(), FasterthanlimeTier::None),
+                ("github".to_string(), FasterthanlimeTier::None),
+            ]
+        );
+        assert_eq!(tier(&u, &mapping), None);
+
+        assert!(breakdown(&user(None, None, None)).is_empty());
+    }
+
+    fn sample_user_info() -> UserInfo {
+        let mut u = user(Some("Gold"), Some(10), Some("Bronze"));
+        // sub-second precision and a non-UTC offset, to catch lossy formats
+        u.fetched_at = OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789)
+            .unwrap()
+            .to_offset(time::UtcOffset::from_hms(2, 0, 0).unwrap());
+        u.discord = discord_user(None, Some("1337")).discord;
+        u.in_discord = true;
+        u
+    }
+
+    #[test]
+    fn test_fetched_at_is_rfc3339_in_both_serializers() {
+        let u = sample_user_info();
+        let expected = "2023-11-15T00:13:20.123456789+02:00";
+
+        let serde_value = serde_json::to_value(&u).unwrap();
+        assert_eq!(serde_value["fetched_at"], expected);
+
+        let facet_value: serde_json::Value =
+            serde_json::from_str(&facet_json::to_string(&u)).unwrap();
+        assert_eq!(facet_value["fetched_at"], expected);
+    }
+
+    #[test]
+    fn test_user_info_round_trips() {
+        let u = sample_user_info();
+
+        // facet -> facet (mom <-> cub)
+        let back: UserInfo = facet_json::from_str(&facet_json::to_string(&u)).unwrap();
+        assert_eq!(back, u);
+
+        // serde -> serde
+        let back: UserInfo = serde_json::from_str(&serde_json::to_string(&u).unwrap()).unwrap();
+        assert_eq!(back, u);
+
+        // across the two
+        let back: UserInfo = serde_json::from_str(&facet_json::to_string(&u)).unwrap();
+        assert_eq!(back, u);
+        let back: UserInfo = facet_json::from_str(&serde_json::to_string(&u).unwrap()).unwrap();
+        assert_eq!(back, u);
+    }
+
+    #[test]
+    fn test_auth_bundle_round_trips() {
+        let ab = AuthBundle {
+            user_info: sample_user_info(),
+        };
+
+        let back: AuthBundle = facet_json::from_str(&facet_json::to_string(&ab)).unwrap();
+        assert_eq!(back.user_info, ab.user_info);
+
+        let back: AuthBundle = serde_json::from_str(&facet_json::to_string(&ab)).unwrap();
+        assert_eq!(back.user_info, ab.user_info);
+
+        let back: AuthBundle = facet_json::from_str(&serde_json::to_string(&ab).unwrap()).unwrap();
+        assert_eq!(back.user_info, ab.user_info);
+    }
+
+    #[test]
+    fn test_scope_downgrade() {
+        // github separates with commas, patreon and discord with spaces
+        assert_eq!(
+            ScopeDowngrade::detect("github", "read:user,read:org", "read:org,read:user"),
+            None
+        );
+        assert_eq!(
+            ScopeDowngrade::detect("patreon", "identity identity.memberships", "identity"),
+            Some(ScopeDowngrade {
+                provider: "patreon",
+                requested: vec!["identity".to_string(), "identity.memberships".to_string()],
+                granted: vec!["identity".to_string()],
+                missing: vec!["identity.memberships".to_string()],
+            })
+        );
+
+        // granting more than we asked for is fine
+        assert!(ScopeDowngrade::detect("discord", "identify", "identify email").is_none());
+
+        // strict mode turns it into an error
+        assert!(
+            ScopeDowngrade::check("github", "read:user,read:org", "read:user", false)
+                .unwrap()
+                .is_some()
+        );
+        let err =
+            ScopeDowngrade::check("github", "read:user,read:org", "read:user", true).unwrap_err();
+        let downgrade = err.downcast_ref::<ScopeDowngrade>().unwrap();
+        assert_eq!(downgrade.missing, vec!["read:org".to_string()]);
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+use lrucache::LruCache;
+use sha2::{Digest, Sha256};
+
+/// How many profiles a [`ProfileCache`] holds before it evicts the least
+/// recently used one
+pub const PROFILE_CACHE_CAPACITY: usize = 1024;
+
+/// Remembers profile fetches for a little while, so a burst of requests
+/// validating the same user's cookie doesn't turn into a burst of calls to
+/// GitHub/Patreon/Discord. Entries are keyed by a hash of the access token:
+/// the token itself is never kept around.
+pub struct ProfileCache<V> {
+    /// `None` means caching is disabled
+    ttl: Option<Duration>,
+    entries: LruCache<[u8; 32], CacheEntry<V>>,
+}
+
+#[derive(Clone)]
+struct CacheEntry<V> {
+    value: V,
+    fetched_at: Instant,
+}
+
+impl<V: Clone> Default for ProfileCache<V> {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl<V: Clone> ProfileCache<V> {
+    /// A cache that never caches: every fetch goes to the network
+    pub fn disabled() -> Self {
+        Self::new(None, PROFILE_CACHE_CAPACITY)
+    }
+
+    pub fn new(ttl: Option<Duration>, capacity: usize) -> Self {
+        Self {
+            ttl,
+            // every profile counts the same
+            entries: LruCache::new(capacity as u64, |_| 1),
+        }
+    }
+
+    fn key(access_token: &str) -> [u8; 32] {
+        Sha256::digest(access_token.as_bytes()).into()
+    }
+
+    /// What we got for `access_token` less than a TTL ago, if anything
+    pub fn get(&self, access_token: &str) -> Option<V> {
+        self.get_at(access_token, Instant::now())
+    }
+
+    /// Remembers what we got for `access_token`. Don't call this with
+    /// errors: those should be retried.
+    pub fn insert(&self, access_token: &str, value: V) {
+        self.insert_at(access_token, value, Instant::now())
+    }
+
+    fn get_at(&self, access_token: &str, now: Instant) -> Option<V> {
+        let ttl = self.ttl?;
+        let key = Self::key(access_token);
+
+        let entry = self.entries.get(&key)?;
+        if now.saturating_duration_since(entry.fetched_at) >= ttl {
+            self.entries.remove(&key);
+            return None;
+        }
+        Some(entry.value)
+    }
+
+    fn insert_at(&self, access_token: &str, value: V, now: Instant) {
+        if self.ttl.is_none() {
+            return;
+        }
+        self.entries.insert(
+            Self::key(access_token),
+            CacheEntry {
+                value,
+                fetched_at: now,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_within_ttl_and_expiry() {
+        let cache = ProfileCache::new(Some(Duration::from_secs(30)), 8);
+        let t0 = Instant::now();
+
+        assert_eq!(cache.get_at("token-a", t0), None);
+        cache.insert_at("token-a", "alice", t0);
+        assert_eq!(
+            cache.get_at("token-a", t0 + Duration::from_secs(29)),
+            Some("alice")
+        );
+        assert_eq!(cache.get_at("token-b", t0), None);
+
+        // reads don't extend the TTL
+        assert_eq!(cache.get_at("token-a", t0 + Duration::from_secs(30)), None);
+        assert_eq!(cache.get_at("token-a", t0), None);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = ProfileCache::new(Some(Duration::from_secs(30)), 2);
+        let t0 = Instant::now();
+
+        cache.insert_at("a", 1, t0);
+        cache.insert_at("b", 2, t0 + Duration::from_secs(1));
+        assert_eq!(cache.get_at("a", t0 + Duration::from_secs(2)), Some(1));
+
+        cache.insert_at("c", 3, t0 + Duration::from_secs(3));
+        assert_eq!(cache.get_at("b", t0 + Duration::from_secs(4)), None);
+        assert_eq!(cache.get_at("a", t0 + Duration::from_secs(4)), Some(1));
+        assert_eq!(cache.get_at("c", t0 + Duration::from_secs(4)), Some(3));
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let cache = ProfileCache::default();
+        let t0 = Instant::now();
+        cache.insert_at("a", 1, t0);
+        assert_eq!(cache.get_at("a", t0), None);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+/// Upper bounds (in seconds) of the buckets for everything we time: from a
+/// quick round-trip to mom, up to a video transcode.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Everything cub counts, for `/metrics`. Traces say what happened to one
+/// request, these say how things are going overall.
+pub struct CubMetrics {
+    /// HTTP requests served, by tenant and status class
+    pub http_requests: CounterVec,
+    /// Where derivations were found: `memory`, `store`, or `miss`
+    pub derive_cache: CounterVec,
+    /// How long it took mom to make a derivation, retries included
+    pub derive_duration: Histogram,
+    /// How long a single derive request to mom took
+    pub mom_rtt: Histogram,
+}
+
+static METRICS: LazyLock<CubMetrics> = LazyLock::new(|| CubMetrics {
+    http_requests: CounterVec::new(
+        "cub_http_requests_total",
+        "HTTP requests served, by tenant and status class",
+        &["tenant", "status"],
+    ),
+    derive_cache: CounterVec::new(
+        "cub_derive_cache_total",
+        "Derivation lookups, by where the output was found",
+        &["result"],
+    ),
+    derive_duration: Histogram::new(
+        "cub_derive_duration_seconds",
+        "Time spent waiting for mom to make a derivation",
+        DURATION_BUCKETS,
+    ),
+    mom_rtt: Histogram::new(
+        "cub_mom_request_duration_seconds",
+        "Round-trip time of derive requests to mom",
+        DURATION_BUCKETS,
+    ),
+});
+
+pub fn metrics() -> &'static CubMetrics {
+    &METRICS
+}
+
+impl CubMetrics {
+    /// All metrics, in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.http_requests.render(&mut out);
+        self.derive_cache.render(&mut out);
+        self.derive_duration.render(&mut out);
+        self.mom_rtt.render(&mut out);
+        out
+    }
+}
+
+/// A counter with labels: one count per combination of label values
+pub struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    counts: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    pub fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            counts: Default::default(),
+        }
+    }
+
+    /// Adds one to the count for `values`, given in the same order as the
+    /// label names.
+    pub fn inc(&self, values: &[&str]) {
+        assert_eq!(
+            values.len(),
+            self.labels.len(),
+            "wrong number of label values for {}",
+            self.name
+        );
+        let key = values.iter().map(|v| v.to_string()).collect();
+        *self.counts.lock().entry(key).or_default() += 1;
+    }
+
+    pub fn get(&self, values: &[&str]) -> u64 {
+        let key = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        self.counts.lock().get(&key).copied().unwrap_or_default()
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+        for (values, count) in self.counts.lock().iter() {
+            let labels = self
+                .labels
+                .iter()
+                .zip(values)
+                .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(out, "{}{{{labels}}} {count}", self.name).unwrap();
+        }
+    }
+}
+
+/// Counts observations into buckets, Prometheus-style
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    buckets: &'static [f64],
+    /// one per bucket, plus one for `+Inf`. Not cumulative: that's done when
+    /// rendering.
+    counts: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(name: &'static str, help: &'static str, buckets: &'static [f64]) -> Self {
+        Self {
+            name,
+            help,
+            buckets,
+            counts: (0..=buckets.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|le| secs <= *le)
+            .unwrap_or(self.buckets.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+        let mut cumulative = 0;
+        let les = self
+            .buckets
+            .iter()
+            .map(|le| le.to_string())
+            .chain(["+Inf".to_string()]);
+        for (le, count) in les.zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            writeln!(out, "{}_bucket{{le=\"{le}\"}} {cumulative}", self.name).unwrap();
+        }
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        writeln!(out, "{}_sum {sum}", self.name).unwrap();
+        writeln!(out, "{}_count {cumulative}", self.name).unwrap();
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_vec_render() {
+        let counter = CounterVec::new("requests_total", "Requests", &["tenant", "status"]);
+        counter.inc(&["b.example", "2xx"]);
+        counter.inc(&["a.example", "4xx"]);
+        counter.inc(&["a.example", "4xx"]);
+        counter.inc(&["we\"ird", "2xx"]);
+        assert_eq!(counter.get(&["a.example", "4xx"]), 2);
+
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            r#"# HELP requests_total Requests
+# TYPE requests_total counter
+requests_total{tenant="a.example",status="4xx"} 2
+requests_total{tenant="b.example",status="2xx"} 1
+requests_total{tenant="we\"ird",status="2xx"} 1
+"#
+        );
+    }
+
+    #[test]
+    fn test_histogram_render() {
+        let histogram = Histogram::new("rtt_seconds", "RTT", &[0.1, 1.0]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(100));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(3));
+        assert_eq!(histogram.count(), 4);
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+        assert_eq!(
+            out,
+            r#"# HELP rtt_seconds RTT
+# TYPE rtt_seconds histogram
+rtt_seconds_bucket{le="0.1"} 2
+rtt_seconds_bucket{le="1"} 3
+rtt_seconds_bucket{le="+Inf"} 4
+rtt_seconds_sum 3.65
+rtt_seconds_count 4
+"#
+        );
+    }
+}
@@ -2,19 +2,35 @@ use mom_types::AllUsers;
 use std::{collections::HashMap, sync::Arc};
 use time::OffsetDateTime;
 
-use config_types::{RedditSecrets, RevisionConfig, TenantConfig, TenantInfo, WebConfig};
+use config_types::{
+    ByteSize, DeriveRetryConfig, RedditSecrets, RevisionConfig, TenantConfig, TenantDomain,
+    TenantInfo, WebConfig,
+};
 use conflux::{Revision, RevisionError, RouteRef};
 use futures_core::future::BoxFuture;
 use hattip::{
     HReply,
+    bytes::Bytes,
     http::{Uri, request::Parts},
 };
 use libmomclient::MomTenantClient;
 use libobjectstore::ObjectStore;
 use libsearch::Index;
 use libwebsock::WebSocketStream;
+use lrucache::LruCache;
+use objectstore_types::ObjectStoreKey;
 use template_types::TemplateCollection;
 
+/// Recently served derivations, kept in memory so hot assets don't need a
+/// round-trip to the object store. Shared by all tenants: keys are
+/// content-addressed, so they only need the tenant to stay apart.
+pub type DerivationCache = LruCache<(TenantDomain, ObjectStoreKey), Bytes>;
+
+/// A derivation cache that holds up to `size` worth of derivations
+pub fn derivation_cache(size: ByteSize) -> DerivationCache {
+    LruCache::new(size.as_u64(), |bytes| bytes.len() as u64)
+}
+
 /// An indexed revision (with a template collection, a search index, etc.)
 #[derive(Clone)]
 pub struct IndexedRevision {
@@ -53,6 +69,15 @@ pub trait CubReq: Send + Sync + 'static {
     /// Returns reddit secrets (if any)
     fn reddit_secrets(&self) -> eyre::Result<&RedditSecrets>;
 
+    /// Returns how patiently we wait for mom to run derivations
+    fn derive_retry(&self) -> DeriveRetryConfig;
+
+    /// Returns the derivations the CDN keeps in memory
+    fn mem_cache(&self) -> &DerivationCache;
+
+    /// Returns how many derivations this tenant may have waiting on mom at once
+    fn derive_concurrency(&self) -> usize;
+
     /// Returns true if the request has a websocket upgrade
     fn has_ws(&self) -> bool;
 
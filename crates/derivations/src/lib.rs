@@ -227,6 +227,7 @@ impl HasPipelineHash for VCodec {
             VCodec::AV1 => "av1-pipeline-2025-01-26",
             VCodec::VP9 => "vp9-pipeline-2025-01-26",
             VCodec::AVC => "avc-pipeline-2025-04-28",
+            VCodec::HEVC => "hevc-pipeline-2026-10-16",
         });
     }
 }
@@ -0,0 +1,132 @@
+use std::{collections::HashMap, hash::Hash, sync::Arc};
+
+use parking_lot::Mutex;
+
+/// Caps how many derivations each tenant can have waiting on mom at once, so
+/// a spike on one tenant's uncached media doesn't starve everyone else.
+pub(crate) struct DeriveLimiter<K> {
+    in_flight: Arc<Mutex<HashMap<K, usize>>>,
+}
+
+impl<K> Default for DeriveLimiter<K> {
+    fn default() -> Self {
+        Self {
+            in_flight: Default::default(),
+        }
+    }
+}
+
+/// Returned when a tenant already has as many derivations in flight as it's
+/// allowed to. Not worth queuing for: the client should come back later.
+#[derive(Debug, Clone)]
+pub(crate) struct DeriveSaturated {
+    pub(crate) limit: usize,
+}
+
+impl std::fmt::Display for DeriveSaturated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "too many derivations in progress for this tenant (limit is {})",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for DeriveSaturated {}
+
+/// One of a tenant's slots, given back when dropped
+pub(crate) struct DerivePermit<K: Eq + Hash> {
+    in_flight: Arc<Mutex<HashMap<K, usize>>>,
+    key: K,
+}
+
+impl<K: Eq + Hash> Drop for DerivePermit<K> {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock();
+        if let Some(count) = in_flight.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl<K> DeriveLimiter<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Takes one of `key`'s `limit` slots, which frees up when the permit is
+    /// dropped. `limit` can change from one call to the next (the tenant's
+    /// config got reloaded, say): if it went down, derivations already in
+    /// flight finish, but no new ones start until we're under it again.
+    pub(crate) fn try_acquire(
+        &self,
+        key: &K,
+        limit: usize,
+    ) -> Result<DerivePermit<K>, DeriveSaturated> {
+        let mut in_flight = self.in_flight.lock();
+        let count = in_flight.entry(key.clone()).or_default();
+        if *count >= limit {
+            if *count == 0 {
+                in_flight.remove(key);
+            }
+            return Err(DeriveSaturated { limit });
+        }
+        *count += 1;
+        Ok(DerivePermit {
+            in_flight: self.in_flight.clone(),
+            key: key.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_is_per_key_and_frees_up() {
+        let limiter = DeriveLimiter::<&str>::default();
+
+        let first = limiter.try_acquire(&"a", 2).unwrap();
+        let _second = limiter.try_acquire(&"a", 2).unwrap();
+        let err = limiter.try_acquire(&"a", 2).unwrap_err();
+        assert_eq!(err.limit, 2);
+
+        // other tenants aren't affected
+        let _other = limiter.try_acquire(&"b", 2).unwrap();
+
+        // once a derivation is done, the next one can go
+        drop(first);
+        let _third = limiter.try_acquire(&"a", 2).unwrap();
+        assert!(limiter.try_acquire(&"a", 2).is_err());
+    }
+
+    #[test]
+    fn test_limit_changes_apply_right_away() {
+        let limiter = DeriveLimiter::<&str>::default();
+
+        let first = limiter.try_acquire(&"a", 1).unwrap();
+        assert!(limiter.try_acquire(&"a", 1).is_err());
+
+        // the limit went up
+        let second = limiter.try_acquire(&"a", 2).unwrap();
+
+        // ...then back down: nothing new starts until both are done
+        drop(first);
+        assert!(limiter.try_acquire(&"a", 1).is_err());
+        drop(second);
+        let _third = limiter.try_acquire(&"a", 1).unwrap();
+    }
+
+    #[test]
+    fn test_released_keys_are_forgotten() {
+        let limiter = DeriveLimiter::<&str>::default();
+
+        drop(limiter.try_acquire(&"a", 1).unwrap());
+        assert!(limiter.try_acquire(&"b", 0).is_err());
+        assert!(limiter.in_flight.lock().is_empty());
+    }
+}
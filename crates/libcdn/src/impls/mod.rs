@@ -1,22 +1,39 @@
+mod derive_limit;
+mod singleflight;
+mod spans;
+
 use std::sync::LazyLock;
 use std::time::{Duration, Instant};
 
 use bytesize::ByteSize;
-use config_types::{TenantConfig, WebConfig};
+use config_types::{
+    CacheCategory, CachePolicyConfig, DeriveRetryConfig, TenantConfig, TenantDomain, WebConfig,
+};
 use conflux::{Asset, PathMappings, Route};
 use content_type::ContentType;
 use cub_types::CubReq;
 use derivations::DerivationInfo;
+use derive_limit::{DeriveLimiter, DeriveSaturated};
 use eyre::bail;
-use hattip::http::Uri;
-use libhttpclient::HttpClient;
+use hattip::http::{HeaderName, HeaderValue, Uri};
+use libhttpclient::{HttpClient, RetryPolicy};
+use libobjectstore::ObjectStore;
 use mom_types::{DeriveParams, DeriveResponse};
+use objectstore_types::{ObjectStoreKey, ObjectStoreKeyRef};
+use opentelemetry::KeyValue;
+use singleflight::{SharedReport, Singleflight};
+use spans::{in_span, record, record_error};
 
 use hattip::prelude::*;
 use hattip::to_herror;
-use libwebsock::{Message, WebSocketStream};
+use libwebsock::{CloseCode, CloseFrame, Message, WebSocketStream};
 
 pub(crate) async fn serve_asset(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HReply {
+    let attributes = vec![KeyValue::new("asset.route", rcx.route().to_string())];
+    in_span("serve_asset", attributes, serve_asset_inner(rcx, headers)).await
+}
+
+async fn serve_asset_inner(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HReply {
     let tenant = rcx.tenant_owned();
 
     let web = rcx.web();
@@ -25,11 +42,13 @@ pub(crate) async fn serve_asset(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HRe
     log::debug!("Serving asset \x1b[1;32m{route}\x1b[0m");
 
     if env.is_dev() && route.as_str().starts_with("/dist") {
+        record(KeyValue::new("asset.kind", "vite"));
         return proxy_to_vite(rcx).await;
     }
 
     let lrev = tenant.rev().map_err(to_herror)?;
     let rev = &lrev.rev;
+    let cache_policy = &rev.pak.rc.cache_policy;
     let asset = rev
         .assets
         .get(route)
@@ -41,13 +60,15 @@ pub(crate) async fn serve_asset(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HRe
             content_type,
         } => {
             log::trace!("Found inline asset route");
+            record(KeyValue::new("asset.kind", "inline"));
             let body = HBody::from(content.clone());
-            asset_response_builder(tenant.tc(), web, *content_type)
+            asset_response_builder(tenant.tc(), web, *content_type, cache_policy)
                 .body(body)
                 .into_reply()
         }
         Asset::Derivation(derivation) => {
             log::trace!("Found derivation asset route");
+            record(KeyValue::new("asset.kind", "derivation"));
             let input = rev.pak.inputs.get(&derivation.input).ok_or_else(|| {
                 log::warn!("Input not found for path: {:?}", &derivation.input);
                 HError::with_status(StatusCode::NOT_FOUND, "input not found for path")
@@ -56,38 +77,55 @@ pub(crate) async fn serve_asset(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HRe
 
             let di = DerivationInfo::new(input, derivation);
             let content_type = di.content_type();
-            let bytes = derive(rcx.as_ref(), di).await.map_err(to_herror)?;
+            // the derivation hash covers the input and how it's derived, so
+            // it's a strong validator for the output
+            let etag = format!("\"{}\"", di.hash());
+            let bytes = match derive(rcx.as_ref(), di).await {
+                Ok(bytes) => bytes,
+                Err(e) => match e.downcast_ref::<DeriveSaturated>() {
+                    Some(saturated) => {
+                        log::warn!("Turning away derivation of {route}: {saturated}");
+                        return saturated_response(saturated);
+                    }
+                    None => return Err(to_herror(e)),
+                },
+            };
 
             // Build base response with common headers
-            let mut res = asset_response_builder(tenant.tc(), web, content_type);
+            let mut res = asset_response_builder(tenant.tc(), web, content_type, cache_policy)
+                .header(header::ETAG, etag.as_str());
 
             // Handle range requests
-            if let Some(range_header) = headers.get(header::RANGE) {
-                if let Ok(ranges) = http_range::HttpRange::parse(
-                    range_header.to_str().unwrap_or(""),
-                    bytes.len() as _,
-                ) {
-                    // For now just handle the first range
-                    let range = &ranges[0];
-                    let content_length = range.length;
-                    let range_header = format!(
-                        "bytes {}-{}/{}",
-                        range.start,
-                        range.start + content_length - 1,
-                        bytes.len()
-                    );
+            let range = match requested_range(&headers, Some(&etag), bytes.len() as _) {
+                RequestedRange::Full => None,
+                RequestedRange::Partial(range) => Some(range),
+                RequestedRange::Unsatisfiable => {
+                    return res
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header(header::CONTENT_RANGE, format!("bytes */{}", bytes.len()))
+                        .body(HBody::empty())
+                        .into_reply();
+                }
+            };
+            if let Some(range) = range {
+                let content_length = range.length;
+                let range_header = format!(
+                    "bytes {}-{}/{}",
+                    range.start,
+                    range.start + content_length - 1,
+                    bytes.len()
+                );
 
-                    res = res
-                        .status(StatusCode::PARTIAL_CONTENT)
-                        .header(header::CONTENT_LENGTH, content_length.to_string())
-                        .header(header::CONTENT_RANGE, range_header)
-                        .header(header::ACCEPT_RANGES, "bytes");
+                res = res
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(header::CONTENT_LENGTH, content_length.to_string())
+                    .header(header::CONTENT_RANGE, range_header)
+                    .header(header::ACCEPT_RANGES, "bytes");
 
-                    let start = range.start as usize;
-                    let end = (range.start + content_length) as usize;
-                    let body = HBody::from(bytes.slice(start..end));
-                    return res.body(body).unwrap().into_reply();
-                }
+                let start = range.start as usize;
+                let end = (range.start + content_length) as usize;
+                let body = HBody::from(bytes.slice(start..end));
+                return res.body(body).unwrap().into_reply();
             }
 
             // Return full response if no range or invalid range
@@ -97,6 +135,7 @@ pub(crate) async fn serve_asset(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HRe
                 .into_reply()
         }
         Asset::AcceptBasedRedirect { options } => {
+            record(KeyValue::new("asset.kind", "redirect"));
             if options.is_empty() {
                 log::error!("No options available for accept-based redirect");
                 return Err(HError::with_status(
@@ -142,34 +181,178 @@ pub(crate) async fn serve_asset(rcx: Box<dyn CubReq>, headers: HeaderMap) -> HRe
     }
 }
 
+/// What to answer to a request's `Range` header
+#[derive(Debug, Clone, Copy)]
+pub enum RequestedRange {
+    /// No usable range (none asked for, a malformed one, or a stale
+    /// `If-Range`): send the whole body
+    Full,
+    /// The first range asked for
+    Partial(http_range::HttpRange),
+    /// None of the ranges overlap the body: that's a 416
+    Unsatisfiable,
+}
+
+/// The range to send back, if the client asked for a valid one. With
+/// `If-Range`, a range only makes sense if the client's copy is the one we
+/// have (`etag`): otherwise it gets the full body, so it doesn't stitch
+/// together bytes from two different versions.
+pub fn requested_range(headers: &HeaderMap, etag: Option<&str>, len: u64) -> RequestedRange {
+    let Some(range_header) = headers.get(header::RANGE).and_then(|h| h.to_str().ok()) else {
+        return RequestedRange::Full;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE) {
+        // we don't send Last-Modified, so dates never match. Weak validators
+        // can't be used with If-Range either.
+        let if_range_str = if_range.to_str().ok().map(str::trim);
+        if etag.is_none() || if_range_str != etag {
+            log::debug!("If-Range {if_range:?} doesn't match {etag:?}, sending the full body");
+            return RequestedRange::Full;
+        }
+    }
+
+    // For now just handle the first range
+    match http_range::HttpRange::parse(range_header, len) {
+        Ok(ranges) => match ranges.into_iter().next() {
+            Some(range) => RequestedRange::Partial(range),
+            None => RequestedRange::Full,
+        },
+        Err(http_range::HttpRangeParseError::NoOverlap) => RequestedRange::Unsatisfiable,
+        Err(http_range::HttpRangeParseError::InvalidRange) => RequestedRange::Full,
+    }
+}
+
 fn asset_response_builder(
     tc: &TenantConfig,
     web: WebConfig,
     content_type: ContentType,
+    cache_policy: &CachePolicyConfig,
 ) -> response::Builder {
+    // asset routes are cache-busted
     Response::builder()
         .header(header::CONTENT_TYPE, content_type.as_str())
         .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, tc.web_base_url(web))
         .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
-        .header(header::CACHE_CONTROL, "max-age=31536000")
+        .header(
+            header::CACHE_CONTROL,
+            cache_policy.cache_control(CacheCategory::Immutable),
+        )
+}
+
+/// What we tell clients turned away because their tenant has too many
+/// derivations in progress
+const DERIVE_RETRY_AFTER_SECS: u64 = 5;
+
+fn saturated_response(saturated: &DeriveSaturated) -> HReply {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::RETRY_AFTER, DERIVE_RETRY_AFTER_SECS.to_string())
+        .body(HBody::from(saturated.to_string()))
+        .into_reply()
 }
 
 async fn derive(rcx: &dyn CubReq, di: DerivationInfo<'_>) -> eyre::Result<Bytes> {
     let env = rcx.web().env;
-    let tenant = rcx.tenant_ref();
+    let cache_key = di.key(env);
 
+    in_derive_span(&di.route(), di.input.size, async {
+        // cache keys are content-addressed, so whatever we have in memory is good
+        let mem_key = (rcx.tc().name.clone(), cache_key.clone());
+        if let Some(bytes) = rcx.mem_cache().get(&mem_key) {
+            log::debug!("Found derivation in memory: {cache_key}");
+            record_derive_cache("memory");
+            return Ok(bytes);
+        }
+
+        let bytes = derive_uncached(rcx, &di, &cache_key).await?;
+        rcx.mem_cache().insert(mem_key, bytes.clone());
+        Ok(bytes)
+    })
+    .await
+}
+
+/// Runs `work` in a `derive` span, which says where the output came from
+/// (`derive.cache`: memory, store or miss), how big it is, and how many times
+/// we had to ask mom for it.
+async fn in_derive_span(
+    route: &Route,
+    input_size: u64,
+    work: impl Future<Output = eyre::Result<Bytes>>,
+) -> eyre::Result<Bytes> {
+    let attributes = vec![
+        KeyValue::new("derive.route", route.to_string()),
+        KeyValue::new("derive.input_size", input_size as i64),
+    ];
+    in_span("derive", attributes, async {
+        let res = work.await;
+        match &res {
+            Ok(bytes) => record(KeyValue::new("derive.output_size", bytes.len() as i64)),
+            Err(e) => record_error(e),
+        }
+        res
+    })
+    .await
+}
+
+/// Says where a derivation was found, on the current `derive` span and in
+/// the cache metrics
+fn record_derive_cache(result: &'static str) {
+    record(KeyValue::new("derive.cache", result));
+    cub_metrics::metrics().derive_cache.inc(&[result]);
+}
+
+async fn derive_uncached(
+    rcx: &dyn CubReq,
+    di: &DerivationInfo<'_>,
+    cache_key: &ObjectStoreKey,
+) -> eyre::Result<Bytes> {
+    let make = derive_shared(
+        &rcx.tc().name,
+        rcx.derive_concurrency(),
+        cache_key,
+        ask_mom_to_derive(rcx, di, cache_key),
+    );
+    fetch_or_derive(rcx.tenant_ref().store().as_ref(), cache_key, make).await
+}
+
+/// Runs `make`, unless the same derivation is already in flight, in which
+/// case this waits for it. Concurrent requests for the same derivation share
+/// a single conversation with mom, which counts once towards the tenant's
+/// `limit` (read on every call, so config reloads apply right away).
+async fn derive_shared(
+    tn: &TenantDomain,
+    limit: usize,
+    cache_key: &ObjectStoreKey,
+    make: impl Future<Output = eyre::Result<()>>,
+) -> eyre::Result<()> {
+    static IN_FLIGHT: LazyLock<Singleflight<DerivationKey, ()>> = LazyLock::new(Default::default);
+    static LIMITER: LazyLock<DeriveLimiter<TenantDomain>> = LazyLock::new(Default::default);
+    IN_FLIGHT
+        .run((tn.clone(), cache_key.clone()), async {
+            let _permit = LIMITER.try_acquire(tn, limit)?;
+            make.await
+        })
+        .await
+        .map_err(|e| match e.downcast_ref::<DeriveSaturated>() {
+            Some(saturated) => eyre::Report::new(saturated.clone()),
+            None => eyre::Report::new(SharedReport(e)),
+        })
+}
+
+/// Gets a derivation from the object store, having `make` put it there first
+/// if it's not there yet
+async fn fetch_or_derive(
+    store: &dyn ObjectStore,
+    cache_key: &ObjectStoreKey,
+    make: impl Future<Output = eyre::Result<()>>,
+) -> eyre::Result<Bytes> {
     // has the derivation already been made? if so, return it
-    let cache_key = di.key(env);
-    match tenant.store().get(&cache_key).await {
-        Ok(res) => {
+    match get_bytes_traced(store, cache_key).await {
+        Ok(bytes) => {
             log::debug!("Found derivation in cache: {cache_key:?}");
-            return res.bytes().await.map_err(|e| {
-                eyre::eyre!(
-                    "failed to fetch bytes from upstream for cache key '{}': {}",
-                    cache_key,
-                    e
-                )
-            });
+            record_derive_cache("store");
+            return Ok(bytes);
         }
         Err(e) => {
             if e.is_not_found() {
@@ -180,6 +363,53 @@ async fn derive(rcx: &dyn CubReq, di: DerivationInfo<'_>) -> eyre::Result<Bytes>
             }
         }
     }
+    record_derive_cache("miss");
+
+    make.await?;
+
+    // according to mom, it's now available in the object store, fetch it
+    get_bytes_traced(store, cache_key).await.map_err(|e| {
+        eyre::eyre!(
+            "failed to fetch bytes from upstream for cache key '{}': {}",
+            cache_key,
+            e
+        )
+    })
+}
+
+/// Gets a whole object (retrying transient errors), in an `object_store.get`
+/// span
+async fn get_bytes_traced(
+    store: &dyn ObjectStore,
+    key: &ObjectStoreKeyRef,
+) -> libobjectstore::Result<Bytes> {
+    let attributes = vec![
+        KeyValue::new("object_store.key", key.to_string()),
+        KeyValue::new("object_store.desc", store.desc()),
+    ];
+    in_span("object_store.get", attributes, async {
+        let res = store.get_bytes_retrying(key).await;
+        match &res {
+            Ok(bytes) => {
+                record(KeyValue::new("object_store.found", true));
+                record(KeyValue::new("object_store.size", bytes.len() as i64));
+            }
+            Err(e) if e.is_not_found() => record(KeyValue::new("object_store.found", false)),
+            Err(e) => record_error(e),
+        }
+        res
+    })
+    .await
+}
+
+/// Asks mom to run the derivation until it's done (and in the object store)
+async fn ask_mom_to_derive(
+    rcx: &dyn CubReq,
+    di: &DerivationInfo<'_>,
+    cache_key: &ObjectStoreKey,
+) -> eyre::Result<()> {
+    let env = rcx.web().env;
+    let tenant = rcx.tenant_ref();
 
     // kindly ask mom to run the derivation
     let tcli = tenant.tcli();
@@ -191,9 +421,7 @@ async fn derive(rcx: &dyn CubReq, di: DerivationInfo<'_>) -> eyre::Result<Bytes>
             log::info!("Uploading input to object storage in development mode: {input_key}");
             let mappings = PathMappings::from_ti(tenant.ti());
             let disk_path = mappings.to_disk_path(&di.input.path)?;
-            // TODO: don't buffer the whole file in memory
-            let bytes = fs_err::tokio::read(&disk_path).await?;
-            tenant.store().put(&input_key, bytes.into()).await?;
+            tcli.put_asset_from_path(&input_key, &disk_path).await?;
         } else {
             log::info!(
                 "Input is already in object storage: {}, object_store = {}",
@@ -206,26 +434,22 @@ async fn derive(rcx: &dyn CubReq, di: DerivationInfo<'_>) -> eyre::Result<Bytes>
     let start = Instant::now();
     let route = di.route();
 
-    let mut tries = 0;
-    let mut sleep_ms = 200;
-    let max_tries = 20;
+    let derive_retry = rcx.derive_retry();
+    let mut backoff = derive_retry_policy(derive_retry).backoff();
     loop {
-        tries += 1;
-        if tries > max_tries {
-            bail!("max retries ({}) exceeded waiting for derivation", tries);
-        }
-
         log::info!("Asking mom to derive (input_key: {input_key}, route: {route})");
+        let asked_at = Instant::now();
         let res = tcli
             .derive(DeriveParams {
                 input: di.input.clone(),
                 derivation: di.derivation.clone(),
             })
             .await?;
+        cub_metrics::metrics().mom_rtt.observe(asked_at.elapsed());
         match res {
             DeriveResponse::Done(donezo) => {
                 let written_to = donezo.dest;
-                if written_to != cache_key {
+                if written_to != *cache_key {
                     bail!(
                         "derivation output key ({}) does not match expected key ({})",
                         written_to,
@@ -242,36 +466,132 @@ async fn derive(rcx: &dyn CubReq, di: DerivationInfo<'_>) -> eyre::Result<Bytes>
                     donezo.output_size as f64 / di.input.size as f64,
                     route
                 );
-                break;
+                record(KeyValue::new(
+                    "derive.tries",
+                    (backoff.failures() + 1) as i64,
+                ));
+                cub_metrics::metrics()
+                    .derive_duration
+                    .observe(start.elapsed());
+                return Ok(());
             }
             DeriveResponse::AlreadyInProgress(inprog) => {
                 log::info!("Derivation {route} is already in progress: {inprog:?}");
-
-                sleep_ms = std::cmp::min(2000, sleep_ms + 100);
-                tokio::time::sleep(Duration::from_millis(sleep_ms)).await;
             }
             DeriveResponse::TooManyRequests(_) => {
                 log::warn!("Too many requests for derivation {route}");
-                sleep_ms = std::cmp::min(5000, sleep_ms * 2);
-                tokio::time::sleep(Duration::from_millis(sleep_ms)).await;
             }
         }
+
+        let Some(delay) = backoff.next_delay() else {
+            bail!(
+                "max retries ({}) exceeded waiting for derivation",
+                derive_retry.max_tries
+            );
+        };
+        tokio::time::sleep(delay).await;
     }
+}
 
-    // according to mom, it's now available in the object store, fetch it
-    let res = tenant.store().get(&cache_key).await?;
-    return res.bytes().await.map_err(|e| {
-        eyre::eyre!(
-            "failed to fetch bytes from upstream for cache key '{}': {}",
-            cache_key,
-            e
-        )
-    });
+/// How many times (and how slowly) we ask mom for a derivation
+fn derive_retry_policy(cfg: DeriveRetryConfig) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: Some(cfg.max_tries),
+        initial: Duration::from_millis(cfg.initial_sleep_ms),
+        max: Duration::from_millis(cfg.max_sleep_ms),
+        jitter: Duration::ZERO,
+        max_total: None,
+    }
 }
 
 static VITE_HTTP_CLIENT: LazyLock<Arc<dyn HttpClient>> =
     LazyLock::new(|| Arc::from(libhttpclient::load().client()));
 
+/// Dev bundles (with inline source maps) are well past the HTTP client's default
+const MAX_VITE_RESPONSE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Browser request headers we pass on to vite. Our HTTP client doesn't
+/// decompress bodies, so whatever encoding vite picks is passed through as-is:
+/// let it pick one the browser accepts. The conditional ones let vite answer
+/// 304 for bundles the browser already has.
+static VITE_FORWARDED_HEADERS: &[HeaderName] = &[
+    header::ACCEPT_ENCODING,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+];
+
+/// A GET to vite: identical ones that are in flight at the same time share
+/// a single upstream request.
+type ViteRequestKey = (Uri, Vec<(HeaderName, HeaderValue)>);
+
+/// vite's reply, buffered so it can be handed to every coalesced request
+#[derive(Clone)]
+struct ViteResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl ViteResponse {
+    fn into_response(self) -> Response<HBody> {
+        let mut headers = vite_response_headers(&self.headers, self.body.len());
+        if self.status == StatusCode::NOT_MODIFIED {
+            // a 304 describes the cached representation, not its own (empty) body
+            headers.remove(header::CONTENT_LENGTH);
+        }
+        let mut response = Response::new(HBody::from(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = headers;
+        response
+    }
+}
+
+fn vite_request_key(dst_uri: Uri, src_headers: &HeaderMap) -> ViteRequestKey {
+    let headers = VITE_FORWARDED_HEADERS
+        .iter()
+        .flat_map(|name| {
+            src_headers
+                .get_all(name)
+                .iter()
+                .map(move |value| (name.clone(), value.clone()))
+        })
+        .collect();
+    (dst_uri, headers)
+}
+
+async fn fetch_from_vite(
+    in_flight: &Singleflight<ViteRequestKey, ViteResponse>,
+    client: &dyn HttpClient,
+    key: ViteRequestKey,
+) -> eyre::Result<ViteResponse> {
+    let work = async {
+        let (dst_uri, headers) = key.clone();
+        let mut req = client.get(dst_uri);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let response = req
+            .send()
+            .await
+            .map_err(|e| eyre::eyre!("failed to proxy to vite dev server: {e}"))?;
+        let status = response.status();
+        let headers = response.headers();
+        let body = response
+            .bytes_limited(MAX_VITE_RESPONSE_SIZE)
+            .await
+            .map_err(|e| eyre::eyre!("failed to read response from vite dev server: {e}"))?;
+        Ok(ViteResponse {
+            status,
+            headers,
+            body,
+        })
+    };
+    in_flight
+        .run(key.clone(), work)
+        .await
+        .map_err(|e| eyre::Report::new(SharedReport(e)))
+}
+
 async fn proxy_to_vite(rcx: Box<dyn CubReq>) -> HReply {
     let port = rcx.tenant_ref().vite_port().await.map_err(|e| {
         HError::with_status(
@@ -356,36 +676,48 @@ async fn proxy_to_vite(rcx: Box<dyn CubReq>) -> HReply {
 
         Ok(res)
     } else {
-        let client = VITE_HTTP_CLIENT.clone();
-        let response = client.get(dst_uri).send().await.map_err(|e| {
-            HError::with_status(
-                StatusCode::BAD_GATEWAY,
-                format!("failed to proxy to vite dev server: {e}"),
-            )
-        })?;
-        let status = response.status();
-        let headers = response.headers_only_string_safe().clone();
-        let bytes = response.bytes().await.map_err(|e| {
-            HError::with_status(
-                StatusCode::BAD_GATEWAY,
-                format!("failed to read response from vite dev server: {e}"),
-            )
-        })?;
+        static IN_FLIGHT: LazyLock<Singleflight<ViteRequestKey, ViteResponse>> =
+            LazyLock::new(Default::default);
+
+        let key = vite_request_key(dst_uri, &src_headers);
+        let response = fetch_from_vite(&IN_FLIGHT, VITE_HTTP_CLIENT.as_ref(), key)
+            .await
+            .map_err(|e| HError::with_status(StatusCode::BAD_GATEWAY, e.to_string()))?;
+        response.into_response().into_reply()
+    }
+}
 
-        let mut builder = Response::builder().status(status);
-        for (name, value) in headers.iter() {
-            builder = builder.header(name, value);
+/// Headers that only make sense for a single connection, and must not be
+/// forwarded by a proxy.
+static HOP_BY_HOP_HEADERS: &[HeaderName] = &[
+    header::CONNECTION,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Copies vite's response headers verbatim (including non-UTF-8 values and
+/// `Content-Encoding`), minus hop-by-hop headers. Since we buffer the body,
+/// `Content-Length` is set to the length of what we actually send.
+fn vite_response_headers(upstream: &HeaderMap, body_len: usize) -> HeaderMap {
+    let mut headers = HeaderMap::with_capacity(upstream.len());
+    for (name, value) in upstream.iter() {
+        if HOP_BY_HOP_HEADERS.contains(name) || name.as_str() == "keep-alive" {
+            continue;
         }
-        let response = builder.body(HBody::from(bytes)).map_err(|e| {
-            HError::with_status(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("failed to build response: {e}"),
-            )
-        })?;
-        response.into_reply()
+        headers.append(name.clone(), value.clone());
     }
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body_len));
+    headers
 }
 
+/// How long we keep reading from a peer after sending it a close frame,
+/// giving it a chance to complete the close handshake.
+const WS_CLOSE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
 async fn do_ws_proxy(
     mut upstream: Box<dyn WebSocketStream>,
     mut downstream: Box<dyn WebSocketStream>,
@@ -406,10 +738,26 @@ async fn do_ws_proxy(
         };
 
         match ev {
+            Event::FromUpstream(Some(Ok(Message::Close(frame)))) => {
+                log::trace!("[WS_PROXY] Upstream sent close frame {frame:?}, forwarding");
+                close_and_drain(downstream.as_mut(), frame, "downstream").await;
+                break;
+            }
+            Event::FromDownstream(Some(Ok(Message::Close(frame)))) => {
+                log::trace!("[WS_PROXY] Downstream sent close frame {frame:?}, forwarding");
+                close_and_drain(upstream.as_mut(), frame, "upstream").await;
+                break;
+            }
             Event::FromUpstream(Some(Ok(msg))) => {
                 log::trace!("[WS_PROXY] Upstream → Downstream: forwarding message: {msg:?}");
                 if let Err(e) = downstream.send(msg).await {
                     log::error!("[WS_PROXY] Error forwarding message to downstream: {e}");
+                    close_and_drain(
+                        upstream.as_mut(),
+                        going_away("client went away"),
+                        "upstream",
+                    )
+                    .await;
                     break;
                 }
                 log::trace!("[WS_PROXY] forwarded to downstream!");
@@ -418,24 +766,54 @@ async fn do_ws_proxy(
                 log::trace!("[WS_PROXY] Downstream → Upstream: forwarding message: {msg:?}");
                 if let Err(e) = upstream.send(msg).await {
                     log::error!("[WS_PROXY] Error forwarding message to upstream: {e}");
+                    close_and_drain(
+                        downstream.as_mut(),
+                        going_away("vite went away"),
+                        "downstream",
+                    )
+                    .await;
                     break;
                 }
                 log::trace!("[WS_PROXY] forwarded to upstream!");
             }
             Event::FromUpstream(None) => {
                 log::trace!("[WS_PROXY] Received None from upstream, closing connection");
+                close_and_drain(
+                    downstream.as_mut(),
+                    going_away("vite went away"),
+                    "downstream",
+                )
+                .await;
                 break;
             }
             Event::FromDownstream(None) => {
                 log::trace!("[WS_PROXY] Received None from downstream, closing connection");
+                close_and_drain(
+                    upstream.as_mut(),
+                    going_away("client went away"),
+                    "upstream",
+                )
+                .await;
                 break;
             }
             Event::FromUpstream(Some(Err(e))) => {
                 log::error!("[WS_PROXY] Error receiving message from upstream: {e}");
+                close_and_drain(
+                    downstream.as_mut(),
+                    going_away("vite went away"),
+                    "downstream",
+                )
+                .await;
                 break;
             }
             Event::FromDownstream(Some(Err(e))) => {
                 log::error!("[WS_PROXY] Error receiving message from downstream: {e}");
+                close_and_drain(
+                    upstream.as_mut(),
+                    going_away("client went away"),
+                    "upstream",
+                )
+                .await;
                 break;
             }
         }
@@ -443,3 +821,439 @@ async fn do_ws_proxy(
     log::trace!("[WS_PROXY] Stopping websocket connection");
     Ok(())
 }
+
+fn going_away(reason: &str) -> Option<CloseFrame> {
+    Some(CloseFrame {
+        code: CloseCode::Away,
+        reason: reason.into(),
+    })
+}
+
+/// Sends a close frame to `peer`, then reads (and discards) whatever it sends
+/// until it acknowledges the close, or until [`WS_CLOSE_DRAIN_TIMEOUT`].
+async fn close_and_drain(peer: &mut dyn WebSocketStream, frame: Option<CloseFrame>, name: &str) {
+    if let Err(e) = peer.send(Message::Close(frame)).await {
+        log::debug!("[WS_PROXY] Could not send close frame to {name}: {e}");
+        return;
+    }
+
+    let drain = async {
+        while let Some(Ok(msg)) = peer.receive().await {
+            if let Message::Close(_) = msg {
+                log::trace!("[WS_PROXY] {name} acknowledged close");
+                break;
+            }
+            log::trace!("[WS_PROXY] Discarding message from {name} while closing: {msg:?}");
+        }
+    };
+    if tokio::time::timeout(WS_CLOSE_DRAIN_TIMEOUT, drain)
+        .await
+        .is_err()
+    {
+        log::debug!("[WS_PROXY] Timed out waiting for {name} to acknowledge close");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_types::Environment;
+    use libhttpclient::test_server::{Reply, TestServer};
+    use libwebsock::test_ws::Peer;
+
+    #[test]
+    fn test_asset_cache_control_follows_policy() {
+        let tc = TenantConfig::new(TenantDomain::new("example.org".to_string()));
+        let web = WebConfig {
+            env: Environment::Production,
+            port: 1111,
+        };
+        let cache_control = |policy: &CachePolicyConfig| {
+            let res = asset_response_builder(&tc, web, ContentType::AVIF, policy)
+                .body(())
+                .unwrap();
+            res.headers()[header::CACHE_CONTROL].clone()
+        };
+
+        assert_eq!(
+            cache_control(&CachePolicyConfig::default()),
+            "max-age=31536000"
+        );
+        assert_eq!(
+            cache_control(&CachePolicyConfig {
+                immutable_max_age_secs: 600,
+                mutable_max_age_secs: 60,
+            }),
+            "max-age=600"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_saturated_tenant_gets_a_503() {
+        let tn = TenantDomain::new("saturated.example".to_string());
+        let key = |name: &str| ObjectStoreKey::new(format!("derivations/{name}"));
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+
+        // the first derivation takes the tenant's only slot, and holds on to it
+        // until the second one has been turned away
+        let first = derive_shared(&tn, 1, &key("first"), async {
+            done_rx.await.ok();
+            Ok(())
+        });
+        let second = async {
+            let res = derive_shared(&tn, 1, &key("second"), async { Ok(()) }).await;
+            done_tx.send(()).unwrap();
+            res
+        };
+        let (first, second) = tokio::join!(first, second);
+        first.unwrap();
+
+        let err = second.unwrap_err();
+        let saturated = err.downcast_ref::<DeriveSaturated>().unwrap();
+        assert_eq!(saturated.limit, 1);
+
+        let res = saturated_response(saturated).unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.headers()[header::RETRY_AFTER],
+            DERIVE_RETRY_AFTER_SECS.to_string()
+        );
+
+        // the slot is free again
+        derive_shared(&tn, 1, &key("third"), async { Ok(()) })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cache_miss_derive_is_traced() {
+        use opentelemetry::Value;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let misses = || cub_metrics::metrics().derive_cache.get(&["miss"]);
+        let misses_before = misses();
+
+        let store = libobjectstore::load().in_memory();
+        let key = ObjectStoreKey::new("derivations/ab/abcdef.avif".to_string());
+        let route = Route::new("/content/cat~abcdef.avif".to_string());
+        // stands in for mom, who writes the output to the object store
+        let make = async {
+            store.put(&key, Bytes::from_static(b"avif!")).await?;
+            Ok::<_, eyre::Report>(())
+        };
+        let bytes = spans::TEST_TRACER_PROVIDER
+            .scope(
+                provider.clone(),
+                in_derive_span(&route, 1234, fetch_or_derive(&store, &key, make)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"avif!");
+        assert!(misses() > misses_before);
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |span: &SpanData, key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+
+        let derive = spans.iter().find(|span| span.name == "derive").unwrap();
+        assert_eq!(
+            attr(derive, "derive.route"),
+            Some(Value::from("/content/cat~abcdef.avif"))
+        );
+        assert_eq!(attr(derive, "derive.cache"), Some(Value::from("miss")));
+        assert_eq!(attr(derive, "derive.input_size"), Some(Value::I64(1234)));
+        assert_eq!(attr(derive, "derive.output_size"), Some(Value::I64(5)));
+
+        // one miss, and one fetch once it's been made
+        let gets = spans
+            .iter()
+            .filter(|span| span.name == "object_store.get")
+            .collect::<Vec<_>>();
+        assert_eq!(gets.len(), 2);
+        for get in &gets {
+            assert_eq!(get.parent_span_id, derive.span_context.span_id());
+        }
+        assert_eq!(
+            attr(gets[0], "object_store.found"),
+            Some(Value::Bool(false))
+        );
+        assert_eq!(attr(gets[1], "object_store.found"), Some(Value::Bool(true)));
+        assert_eq!(attr(gets[1], "object_store.size"), Some(Value::I64(5)));
+    }
+
+    fn derive_delays(cfg: DeriveRetryConfig) -> Vec<Option<u128>> {
+        let mut backoff = derive_retry_policy(cfg).backoff();
+        (0..cfg.max_tries)
+            .map(|_| backoff.next_delay().map(|d| d.as_millis()))
+            .collect()
+    }
+
+    #[test]
+    fn test_derive_retry_honors_try_count_and_cap() {
+        let delays = derive_delays(DeriveRetryConfig {
+            max_tries: 6,
+            initial_sleep_ms: 100,
+            max_sleep_ms: 250,
+        });
+        // we ask 6 times, so there are 5 waits in between
+        assert_eq!(
+            delays,
+            vec![Some(100), Some(200), Some(250), Some(250), Some(250), None]
+        );
+    }
+
+    #[test]
+    fn test_derive_retry_defaults() {
+        let delays = derive_delays(DeriveRetryConfig::default());
+        assert_eq!(delays.len(), 20);
+        assert_eq!(
+            &delays[..5],
+            &[Some(200), Some(400), Some(800), Some(1600), Some(2000)]
+        );
+        assert_eq!(delays[18], Some(2000));
+        assert_eq!(delays[19], None);
+    }
+
+    #[test]
+    fn test_vite_response_headers_gzipped_asset() {
+        // what vite replies with for a gzipped asset, served chunked
+        let gzipped_body = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        let mut upstream = HeaderMap::new();
+        upstream.insert(header::CONTENT_TYPE, "text/javascript".parse().unwrap());
+        upstream.insert(header::CONTENT_ENCODING, "gzip".parse().unwrap());
+        upstream.insert(header::TRANSFER_ENCODING, "chunked".parse().unwrap());
+        upstream.insert(header::CONNECTION, "keep-alive".parse().unwrap());
+        upstream.insert(header::VARY, "Accept-Encoding".parse().unwrap());
+        upstream.insert(
+            HeaderName::from_static("x-binary"),
+            HeaderValue::from_bytes(&[0xff, 0xfe]).unwrap(),
+        );
+        upstream.append(header::SET_COOKIE, "a=1".parse().unwrap());
+        upstream.append(header::SET_COOKIE, "b=2".parse().unwrap());
+
+        let headers = vite_response_headers(&upstream, gzipped_body.len());
+
+        assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(headers[header::CONTENT_TYPE], "text/javascript");
+        assert_eq!(headers[header::VARY], "Accept-Encoding");
+        assert_eq!(
+            headers[header::CONTENT_LENGTH],
+            gzipped_body.len().to_string().as_str()
+        );
+        assert!(!headers.contains_key(header::TRANSFER_ENCODING));
+        assert!(!headers.contains_key(header::CONNECTION));
+        assert_eq!(headers["x-binary"].as_bytes(), &[0xff, 0xfe]);
+        assert_eq!(headers.get_all(header::SET_COOKIE).iter().count(), 2);
+    }
+
+    #[test]
+    fn test_vite_response_headers_fixes_stale_content_length() {
+        let mut upstream = HeaderMap::new();
+        upstream.insert(header::CONTENT_LENGTH, "9999".parse().unwrap());
+
+        let headers = vite_response_headers(&upstream, 42);
+        assert_eq!(headers[header::CONTENT_LENGTH], "42");
+        assert_eq!(headers.get_all(header::CONTENT_LENGTH).iter().count(), 1);
+    }
+
+    /// A fake vite: answers 304 to requests carrying `If-None-Match: "v1"`,
+    /// and a (slow) 200 otherwise.
+    async fn fake_vite() -> TestServer {
+        TestServer::start(|req| {
+            if req.header("if-none-match") == Some("\"v1\"") {
+                Reply::new("304 Not Modified").header("etag", "\"v1\"")
+            } else {
+                Reply::new("200 OK")
+                    .header("etag", "\"v1\"")
+                    .body("export {};\n\n")
+                    .delay(Duration::from_millis(100))
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_vite_proxy_passes_304_through() {
+        let vite = fake_vite().await;
+        let in_flight = Singleflight::default();
+        let client = libhttpclient::load().client();
+        let uri = vite.uri("/dist/main.js");
+
+        let mut src_headers = HeaderMap::new();
+        src_headers.insert(header::IF_NONE_MATCH, "\"v1\"".parse().unwrap());
+        src_headers.insert(header::COOKIE, "not=forwarded".parse().unwrap());
+        let key = vite_request_key(uri, &src_headers);
+        assert_eq!(
+            key.1,
+            vec![(header::IF_NONE_MATCH, HeaderValue::from_static("\"v1\""))]
+        );
+
+        let response = fetch_from_vite(&in_flight, client.as_ref(), key)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], "\"v1\"");
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        assert_eq!(vite.requests().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_vite_proxy_coalesces_concurrent_requests() {
+        let vite = fake_vite().await;
+        let in_flight = Arc::new(Singleflight::default());
+        let client: Arc<dyn HttpClient> = Arc::from(libhttpclient::load().client());
+        let uri = vite.uri("/dist/main.js");
+
+        let tasks = (0..8)
+            .map(|_| {
+                let (in_flight, client, uri) = (in_flight.clone(), client.clone(), uri.clone());
+                tokio::spawn(async move {
+                    let key = vite_request_key(uri, &HeaderMap::new());
+                    fetch_from_vite(&in_flight, client.as_ref(), key).await
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            let response = task.await.unwrap().unwrap();
+            assert_eq!(response.status, StatusCode::OK);
+            assert_eq!(&response.body[..], b"export {};\n\n");
+        }
+        assert_eq!(vite.requests().len(), 1);
+
+        // once it's landed, the next request goes upstream again
+        let key = vite_request_key(uri, &HeaderMap::new());
+        fetch_from_vite(&in_flight, client.as_ref(), key)
+            .await
+            .unwrap();
+        assert_eq!(vite.requests().len(), 2);
+    }
+
+    fn channel_ws() -> (Box<dyn WebSocketStream>, Peer) {
+        let (ws, peer) = libwebsock::test_ws::channel_ws();
+        (Box::new(ws), peer)
+    }
+
+    #[tokio::test]
+    async fn test_ws_proxy_forwards_upstream_close() {
+        let (upstream, upstream_peer) = channel_ws();
+        let (downstream, mut downstream_peer) = channel_ws();
+        let proxy = tokio::spawn(do_ws_proxy(upstream, downstream));
+
+        upstream_peer
+            .incoming
+            .send(Message::Text("hello".into()))
+            .unwrap();
+        upstream_peer
+            .incoming
+            .send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: "vite restarting".into(),
+            })))
+            .unwrap();
+
+        assert_eq!(
+            downstream_peer.sent.recv().await.unwrap(),
+            Message::Text("hello".into())
+        );
+        match downstream_peer.sent.recv().await.unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Normal);
+                assert_eq!(frame.reason.as_str(), "vite restarting");
+            }
+            other => panic!("expected close frame, got {other:?}"),
+        }
+
+        // the browser acknowledges, the proxy wraps up
+        downstream_peer.incoming.send(Message::Close(None)).unwrap();
+        proxy.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ws_proxy_closes_downstream_when_upstream_drops() {
+        let (upstream, upstream_peer) = channel_ws();
+        let (downstream, mut downstream_peer) = channel_ws();
+        let proxy = tokio::spawn(do_ws_proxy(upstream, downstream));
+
+        drop(upstream_peer.incoming);
+
+        match downstream_peer.sent.recv().await.unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+            other => panic!("expected close frame, got {other:?}"),
+        }
+
+        // downstream never acknowledges: the proxy gives up after draining
+        proxy.await.unwrap().unwrap();
+    }
+
+    fn range_headers(range: &str, if_range: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, range.parse().unwrap());
+        if let Some(if_range) = if_range {
+            headers.insert(header::IF_RANGE, if_range.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_range_honors_matching_if_range() {
+        let etag = "\"abc123\"";
+
+        let RequestedRange::Partial(range) =
+            requested_range(&range_headers("bytes=10-19", None), Some(etag), 100)
+        else {
+            panic!("expected a partial range");
+        };
+        assert_eq!((range.start, range.length), (10, 10));
+
+        let headers = range_headers("bytes=10-19", Some("\"abc123\""));
+        let RequestedRange::Partial(range) = requested_range(&headers, Some(etag), 100) else {
+            panic!("expected a partial range");
+        };
+        assert_eq!((range.start, range.length), (10, 10));
+    }
+
+    #[test]
+    fn test_range_ignored_for_stale_if_range() {
+        let etag = "\"abc123\"";
+        for if_range in ["\"older\"", "W/\"abc123\"", "Wed, 21 Oct 2015 07:28:00 GMT"] {
+            let headers = range_headers("bytes=10-19", Some(if_range));
+            assert!(
+                matches!(
+                    requested_range(&headers, Some(etag), 100),
+                    RequestedRange::Full
+                ),
+                "{if_range}"
+            );
+            // without an etag of our own, nothing matches
+            assert!(
+                matches!(requested_range(&headers, None, 100), RequestedRange::Full),
+                "{if_range}"
+            );
+        }
+
+        // no range at all, or a malformed one: full body too
+        assert!(matches!(
+            requested_range(&HeaderMap::new(), Some(etag), 100),
+            RequestedRange::Full
+        ));
+        assert!(matches!(
+            requested_range(&range_headers("lines=1-2", None), Some(etag), 100),
+            RequestedRange::Full
+        ));
+        // ...but an unsatisfiable one is an error
+        assert!(matches!(
+            requested_range(&range_headers("bytes=200-300", None), Some(etag), 100),
+            RequestedRange::Unsatisfiable
+        ));
+    }
+}
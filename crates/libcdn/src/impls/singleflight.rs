@@ -0,0 +1,202 @@
+use std::{collections::HashMap, fmt, future::Future, hash::Hash, sync::Arc};
+
+use parking_lot::Mutex;
+use tokio::sync::OnceCell;
+
+type Flight<T> = Arc<OnceCell<Result<T, Arc<eyre::Report>>>>;
+
+/// Lets concurrent callers doing the same work (as identified by a key) share
+/// a single run of it.
+pub(crate) struct Singleflight<K, T> {
+    flights: Mutex<HashMap<K, Flight<T>>>,
+}
+
+impl<K, T> Default for Singleflight<K, T> {
+    fn default() -> Self {
+        Self {
+            flights: Default::default(),
+        }
+    }
+}
+
+impl<K, T> Singleflight<K, T>
+where
+    K: Eq + Hash + Clone,
+    T: Clone,
+{
+    /// Runs `work`, unless work for `key` is already in flight, in which case
+    /// this waits for it and returns its result (errors included). If the
+    /// caller running the work goes away, one of the waiters takes over.
+    pub(crate) async fn run<F>(&self, key: K, work: F) -> Result<T, Arc<eyre::Report>>
+    where
+        F: Future<Output = eyre::Result<T>>,
+    {
+        let flight = self.flights.lock().entry(key.clone()).or_default().clone();
+        let guard = FlightGuard {
+            flights: &self.flights,
+            key,
+            flight,
+        };
+        guard
+            .flight
+            .get_or_init(|| async { work.await.map_err(Arc::new) })
+            .await
+            .clone()
+    }
+
+    #[cfg(test)]
+    fn in_flight(&self) -> usize {
+        self.flights.lock().len()
+    }
+}
+
+/// Takes a flight off the map once it's over, or once its last caller gives
+/// up on it (the future got dropped), so the key doesn't stay stuck.
+struct FlightGuard<'a, K: Eq + Hash, T> {
+    flights: &'a Mutex<HashMap<K, Flight<T>>>,
+    key: K,
+    flight: Flight<T>,
+}
+
+impl<K: Eq + Hash, T> Drop for FlightGuard<'_, K, T> {
+    fn drop(&mut self) {
+        let mut flights = self.flights.lock();
+        let is_current = flights
+            .get(&self.key)
+            .is_some_and(|current| Arc::ptr_eq(current, &self.flight));
+        // callers only clone the flight with the lock held: if the map and we
+        // are the only ones left holding it, nobody is waiting on it
+        let abandoned = Arc::strong_count(&self.flight) == 2;
+        if is_current && (self.flight.initialized() || abandoned) {
+            flights.remove(&self.key);
+        }
+    }
+}
+
+/// The error of a flight, shared by all its callers. Turning it back into
+/// an [`eyre::Report`] keeps the whole chain of causes.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedReport(pub(crate) Arc<eyre::Report>);
+
+impl fmt::Display for SharedReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self.0, f)
+    }
+}
+
+impl std::error::Error for SharedReport {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    const N: usize = 16;
+
+    async fn run_concurrently(
+        sf: Arc<Singleflight<&'static str, u64>>,
+        runs: Arc<AtomicUsize>,
+        fail: bool,
+    ) -> Vec<Result<u64, Arc<eyre::Report>>> {
+        let barrier = Arc::new(tokio::sync::Barrier::new(N));
+        let tasks = (0..N)
+            .map(|_| {
+                let (sf, runs, barrier) = (sf.clone(), runs.clone(), barrier.clone());
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    sf.run("derivations/some-key", async {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        if fail {
+                            eyre::bail!("mom is on fire");
+                        }
+                        Ok(42)
+                    })
+                    .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+        results
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_calls_share_one_run() {
+        let sf = Arc::new(Singleflight::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let results = run_concurrently(sf.clone(), runs.clone(), false).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| matches!(r, Ok(42))));
+        assert_eq!(sf.in_flight(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_failures_reach_every_waiter_and_clear_the_key() {
+        let sf = Arc::new(Singleflight::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let results = run_concurrently(sf.clone(), runs.clone(), true).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        for res in results {
+            let err = res.unwrap_err();
+            assert!(err.to_string().contains("mom is on fire"), "{err}");
+        }
+        assert_eq!(sf.in_flight(), 0);
+
+        // the next caller gets a fresh try
+        let res = sf.run("derivations/some-key", async { Ok(7) }).await;
+        assert_eq!(res.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_flight_clears_the_key() {
+        let sf = Singleflight::<&'static str, u64>::default();
+
+        let res = tokio::time::timeout(
+            Duration::from_millis(10),
+            sf.run("derivations/some-key", std::future::pending()),
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(sf.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_waiter_takes_over_an_abandoned_flight() {
+        let sf = Arc::new(Singleflight::<&'static str, u64>::default());
+
+        let leader = tokio::spawn({
+            let sf = sf.clone();
+            async move { sf.run("derivations/some-key", std::future::pending()).await }
+        });
+        while sf.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let waiter = tokio::spawn({
+            let sf = sf.clone();
+            async move { sf.run("derivations/some-key", async { Ok(7) }).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        leader.abort();
+        assert_eq!(waiter.await.unwrap().unwrap(), 7);
+        assert_eq!(sf.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_shared_report_keeps_the_causes() {
+        let err = eyre::eyre!("mom is on fire").wrap_err("deriving some-key");
+        let err = eyre::Report::new(SharedReport(Arc::new(err)));
+        assert_eq!(format!("{err:#}"), "deriving some-key: mom is on fire");
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt::Display;
+
+use opentelemetry::{
+    Context, KeyValue, global,
+    trace::{FutureExt as _, Status, TraceContextExt as _, Tracer as _},
+};
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Lets a test collect its own spans, without installing a global tracer
+    /// provider that every other test running in parallel would report to
+    pub(crate) static TEST_TRACER_PROVIDER: opentelemetry_sdk::trace::SdkTracerProvider;
+}
+
+/// Runs `work` in a new span, a child of whatever span is current (normally
+/// the one cub opened for the HTTP request). This goes through the global
+/// tracer provider: unless cub installed an exporter, spans go nowhere.
+pub(crate) async fn in_span<F: Future>(
+    name: &'static str,
+    attributes: Vec<KeyValue>,
+    work: F,
+) -> F::Output {
+    #[cfg(test)]
+    if let Ok(tracer) = TEST_TRACER_PROVIDER.try_with(|provider| {
+        use opentelemetry::trace::TracerProvider as _;
+        provider.tracer("cdn")
+    }) {
+        let span = tracer
+            .span_builder(name)
+            .with_attributes(attributes)
+            .start(&tracer);
+        return work.with_context(Context::current_with_span(span)).await;
+    }
+
+    let tracer = global::tracer("cdn");
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start(&tracer);
+    work.with_context(Context::current_with_span(span)).await
+}
+
+/// Adds an attribute to the current span
+pub(crate) fn record(attribute: KeyValue) {
+    Context::current().span().set_attribute(attribute);
+}
+
+/// Marks the current span as failed
+pub(crate) fn record_error(e: &dyn Display) {
+    Context::current()
+        .span()
+        .set_status(Status::error(e.to_string()));
+}
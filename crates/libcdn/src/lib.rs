@@ -10,6 +10,7 @@ pub fn load() -> &'static dyn Mod {
 }
 
 mod impls;
+pub use impls::{RequestedRange, requested_range};
 
 #[autotrait]
 impl Mod for ModImpl {
@@ -44,6 +44,11 @@ impl Mod for ModImpl {
             let file_contents = fs_err::read_to_string(config_path)?;
             let mut config: CubConfig = serde_json::from_str(&file_contents)?;
             apply_env_overrides(&mut config);
+            if config.derive_concurrency == 0 {
+                eyre::bail!(
+                    "derive_concurrency must be at least 1, or every derivation is refused"
+                );
+            }
 
             return Ok(CubConfigBundle {
                 cc: config,
@@ -102,6 +107,9 @@ impl Mod for ModImpl {
                 secrets: None,
                 base_dir_for_dev: None,
                 rc_for_dev: Some(rc),
+                contact_email: None,
+                derive_concurrency: None,
+                strict_oauth_scopes: false,
             };
             let ti = TenantInfo { base_dir, tc };
             bundle.tenants.insert(tenant, ti);
@@ -0,0 +1,196 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{SyncSender, TrySendError},
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use eyre::Context as _;
+use facet::Facet;
+
+/// One served request, as written to the access log
+#[derive(Debug, Clone, PartialEq, Facet)]
+pub(crate) struct AccessEvent {
+    /// Milliseconds since the unix epoch, when the response was ready
+    pub(crate) timestamp_ms: u64,
+    pub(crate) method: String,
+    /// The host the request was for, if it had one
+    pub(crate) host: Option<String>,
+    /// The path, without the query string
+    pub(crate) path: String,
+    pub(crate) status: u16,
+    /// How long it took to produce the response headers, in milliseconds
+    pub(crate) duration_ms: f64,
+    /// Size of the response body before compression, when known up front
+    pub(crate) bytes: Option<u64>,
+    /// The tenant that served the request, if we could resolve one
+    pub(crate) tenant: Option<String>,
+    /// See `request_id_header` in the cub config
+    #[facet(default)]
+    pub(crate) request_id: Option<String>,
+}
+
+impl AccessEvent {
+    pub(crate) fn now(
+        method: String,
+        host: Option<String>,
+        path: String,
+        status: u16,
+        duration: Duration,
+        bytes: Option<u64>,
+        tenant: Option<String>,
+    ) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Self {
+            timestamp_ms,
+            method,
+            host,
+            path,
+            status,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            bytes,
+            tenant,
+            request_id: None,
+        }
+    }
+
+    pub(crate) fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+}
+
+/// How many lines may wait for the writer before we start dropping them
+const ACCESS_LOG_BACKLOG: usize = 4096;
+
+/// Machine-readable access log: one JSON object per line, for analytics.
+/// The colorful log lines are for humans and stay as they are.
+///
+/// Lines are written by a dedicated thread, so a slow disk never holds up
+/// a response. If that thread falls too far behind, lines are dropped.
+pub(crate) struct AccessLog {
+    tx: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+impl AccessLog {
+    /// `-` logs to stdout, anything else is a file we append to
+    pub(crate) fn open(target: &str) -> eyre::Result<Self> {
+        if target == "-" {
+            return Self::to_writer(Box::new(std::io::stdout()));
+        }
+
+        let file = fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(target)
+            .wrap_err_with(|| format!("opening access log {target}"))?;
+        Self::to_writer(Box::new(std::io::LineWriter::new(file)))
+    }
+
+    pub(crate) fn to_writer(mut out: Box<dyn Write + Send>) -> eyre::Result<Self> {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<String>(ACCESS_LOG_BACKLOG);
+        let writer = std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for line in rx {
+                    if let Err(e) = out.write_all(line.as_bytes()) {
+                        log::warn!("Could not write to access log: {e}");
+                    }
+                }
+                if let Err(e) = out.flush() {
+                    log::warn!("Could not flush access log: {e}");
+                }
+            })
+            .wrap_err("spawning access log writer")?;
+        Ok(Self {
+            tx: Some(tx),
+            writer: Some(writer),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn record(&self, event: &AccessEvent) {
+        let Some(tx) = self.tx.as_ref() else {
+            return;
+        };
+        let mut line = facet_json::to_string(event);
+        line.push('\n');
+        if let Err(TrySendError::Full(_)) = tx.try_send(line) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                log::warn!("Access log writer can't keep up, dropped {dropped} lines so far");
+            }
+        }
+    }
+}
+
+impl Drop for AccessLog {
+    /// Lets the writer finish what's queued, so we don't lose the last
+    /// requests on shutdown
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_access_event_is_one_json_line() {
+        let buf = SharedBuf::default();
+        let log = AccessLog::to_writer(Box::new(buf.clone())).unwrap();
+
+        let event = AccessEvent::now(
+            "GET".to_string(),
+            Some("fasterthanli.me".to_string()),
+            "/articles/foo".to_string(),
+            200,
+            Duration::from_micros(12_500),
+            Some(4096),
+            Some("fasterthanli.me".to_string()),
+        )
+        .with_request_id(Some("lb-4f2a".to_string()));
+        log.record(&event);
+        // waits for the writer to drain the queue
+        drop(log);
+
+        let written = String::from_utf8(buf.0.lock().clone()).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        assert!(written.ends_with('\n'));
+
+        let parsed: AccessEvent = facet_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed, event);
+        assert_eq!(parsed.duration_ms, 12.5);
+        assert_eq!(parsed.request_id.as_deref(), Some("lb-4f2a"));
+        assert!(parsed.timestamp_ms > 0);
+    }
+}
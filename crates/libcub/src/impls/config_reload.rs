@@ -0,0 +1,287 @@
+use camino::Utf8PathBuf;
+use config_types::{ByteSize, CubConfig, HoneycombSecrets, RedditSecrets};
+use cub_types::DerivationCache;
+use log::{info, warn};
+use tokio::signal::unix::SignalKind;
+
+use super::{global_state::global_state, install_honeycomb_tracer, node_metadata::NodeMetadata};
+
+/// The part of [`CubConfig`] that can change without a restart
+#[derive(Clone)]
+pub struct ReloadableConfig {
+    pub log_level: Option<String>,
+    pub honeycomb_secrets: Option<HoneycombSecrets>,
+    pub mem_cache_size: ByteSize,
+}
+
+impl ReloadableConfig {
+    pub(crate) fn from_config(cc: &CubConfig) -> Self {
+        Self {
+            log_level: cc.log_level.clone(),
+            honeycomb_secrets: cc.honeycomb_secrets.clone(),
+            mem_cache_size: cc.mem_cache_size,
+        }
+    }
+}
+
+/// Which fields changed in the config file
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ReloadReport {
+    /// changed, and applied to the running cub
+    pub(crate) applied: Vec<&'static str>,
+    /// changed, but only taken into account after a restart
+    pub(crate) needs_restart: Vec<&'static str>,
+}
+
+/// Applies the reloadable fields of `new` to `live` (resizing `mem_cache` if
+/// needed), and lists the fields that differ from `startup` but can't be
+/// changed at runtime.
+pub(crate) fn apply_reload(
+    startup: &CubConfig,
+    live: &mut ReloadableConfig,
+    mem_cache: &DerivationCache,
+    new: CubConfig,
+) -> ReloadReport {
+    // no `..` here: a new field won't build until it's either applied or
+    // listed as fixed below
+    let CubConfig {
+        log_level,
+        honeycomb_secrets,
+        mem_cache_size,
+        disk_cache_size,
+        address,
+        random_port_fallback,
+        mom_base_url,
+        mom_api_key,
+        tenant_data_dir,
+        reddit_secrets,
+        derive_retry,
+        derive_concurrency,
+        access_log,
+        listener,
+        use_prod_mom_in_dev,
+        git_proxy,
+        body_limits,
+        probe_object_stores,
+        request_id_header,
+    } = new;
+    let mut report = ReloadReport::default();
+
+    if live.log_level != log_level {
+        live.log_level = log_level;
+        report.applied.push("log_level");
+    }
+
+    let api_key = |hs: &Option<HoneycombSecrets>| hs.as_ref().map(|hs| hs.api_key.clone());
+    match (
+        api_key(&live.honeycomb_secrets),
+        api_key(&honeycomb_secrets),
+    ) {
+        (old, new_key) if old == new_key => {}
+        // we can swap the exporter, but not go back to not having one
+        (_, None) => report.needs_restart.push("honeycomb_secrets"),
+        (_, Some(_)) => {
+            live.honeycomb_secrets = honeycomb_secrets;
+            report.applied.push("honeycomb_secrets");
+        }
+    }
+
+    if live.mem_cache_size != mem_cache_size {
+        live.mem_cache_size = mem_cache_size;
+        mem_cache.set_capacity(mem_cache_size.as_u64());
+        report.applied.push("mem_cache_size");
+    }
+
+    let reddit = |rs: &Option<RedditSecrets>| {
+        rs.as_ref()
+            .map(|rs| (rs.oauth_client_id.clone(), rs.oauth_client_secret.clone()))
+    };
+    let fixed = [
+        // nothing caches to disk (yet), so there's nothing to resize
+        (
+            "disk_cache_size",
+            startup.disk_cache_size != disk_cache_size,
+        ),
+        ("address", startup.address != address),
+        (
+            "random_port_fallback",
+            startup.random_port_fallback != random_port_fallback,
+        ),
+        ("mom_base_url", startup.mom_base_url != mom_base_url),
+        ("mom_api_key", startup.mom_api_key != mom_api_key),
+        (
+            "tenant_data_dir",
+            startup.tenant_data_dir != tenant_data_dir,
+        ),
+        (
+            "reddit_secrets",
+            reddit(&startup.reddit_secrets) != reddit(&reddit_secrets),
+        ),
+        ("derive_retry", startup.derive_retry != derive_retry),
+        (
+            "derive_concurrency",
+            startup.derive_concurrency != derive_concurrency,
+        ),
+        ("access_log", startup.access_log != access_log),
+        ("listener", startup.listener != listener),
+        (
+            "use_prod_mom_in_dev",
+            startup.use_prod_mom_in_dev != use_prod_mom_in_dev,
+        ),
+        ("git_proxy", startup.git_proxy != git_proxy),
+        ("body_limits", startup.body_limits != body_limits),
+        (
+            "probe_object_stores",
+            startup.probe_object_stores != probe_object_stores,
+        ),
+        (
+            "request_id_header",
+            startup.request_id_header != request_id_header,
+        ),
+    ];
+    report.needs_restart.extend(
+        fixed
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| field),
+    );
+
+    report
+}
+
+/// Re-reads the config file every time we get a SIGHUP. Only call this once
+/// the global state is set.
+pub(crate) async fn reload_on_sighup(config_path: Option<Utf8PathBuf>, metadata: NodeMetadata) {
+    let mut sighup = match tokio::signal::unix::signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(e) => {
+            warn!("Could not listen for SIGHUP, config won't be reloadable: {e}");
+            return;
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        let Some(config_path) = config_path.as_ref() else {
+            warn!("Received SIGHUP, but we weren't started with a config file: nothing to reload");
+            continue;
+        };
+        info!("Received SIGHUP, reloading config from {config_path}");
+        if let Err(e) = reload(config_path, &metadata) {
+            warn!("Could not reload config, keeping the current one: {e}");
+        }
+    }
+}
+
+fn reload(config_path: &Utf8PathBuf, metadata: &NodeMetadata) -> eyre::Result<()> {
+    let new = libconfig::load()
+        .load_cub_config(Some(config_path.as_path()), vec![])?
+        .cc;
+
+    let gs = global_state();
+    let (report, live) = {
+        let mut live = gs.reloadable.write();
+        let report = apply_reload(&gs.config, &mut live, &gs.mem_cache, new);
+        (report, live.clone())
+    };
+
+    for field in &report.applied {
+        match *field {
+            "log_level" => {
+                if !skelly::set_log_directives(live.log_level.as_deref().unwrap_or_default()) {
+                    warn!("skelly's logger isn't installed, log level left as is");
+                }
+            }
+            "honeycomb_secrets" => {
+                if let Some(hs) = live.honeycomb_secrets.as_ref() {
+                    install_honeycomb_tracer(hs, metadata)?;
+                }
+            }
+            _ => {}
+        }
+        info!("Reloaded {field}");
+    }
+    if report.applied.is_empty() {
+        info!("No reloadable settings changed");
+    }
+    if !report.needs_restart.is_empty() {
+        warn!(
+            "These settings changed but need a restart to take effect: {}",
+            report.needs_restart.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_types::TenantDomain;
+    use hattip::bytes::Bytes;
+    use objectstore_types::ObjectStoreKey;
+
+    fn config(mem_cache_size: ByteSize) -> CubConfig {
+        CubConfig {
+            mem_cache_size,
+            ..CubConfig::for_tests()
+        }
+    }
+
+    fn derivation(name: &str) -> (TenantDomain, ObjectStoreKey) {
+        (
+            TenantDomain::new("example.org".to_string()),
+            ObjectStoreKey::new(format!("derivations/{name}")),
+        )
+    }
+
+    #[test]
+    fn test_reload_resizes_mem_cache() {
+        let startup = config(ByteSize::kib(3));
+        let mut live = ReloadableConfig::from_config(&startup);
+        let mem_cache = cub_types::derivation_cache(startup.mem_cache_size);
+        for name in ["a", "b", "c"] {
+            mem_cache.insert(derivation(name), Bytes::from(vec![0u8; 1024]));
+        }
+
+        let report = apply_reload(&startup, &mut live, &mem_cache, config(ByteSize::kib(2)));
+        assert_eq!(report.applied, vec!["mem_cache_size"]);
+        assert!(report.needs_restart.is_empty());
+        assert_eq!(live.mem_cache_size, ByteSize::kib(2));
+        assert_eq!(mem_cache.capacity(), 2048);
+        assert_eq!(mem_cache.weight(), 2048);
+        assert!(mem_cache.get(&derivation("a")).is_none());
+
+        // reloading the same file again is a no-op
+        let report = apply_reload(&startup, &mut live, &mem_cache, config(ByteSize::kib(2)));
+        assert_eq!(report, ReloadReport::default());
+    }
+
+    #[test]
+    fn test_reload_reports_but_skips_fixed_settings() {
+        let startup = config(ByteSize::mib(64));
+        let mut live = ReloadableConfig::from_config(&startup);
+        let mem_cache = cub_types::derivation_cache(startup.mem_cache_size);
+
+        let mut new = config(ByteSize::mib(64));
+        new.mom_base_url = "http://mom.svc.cluster.local:1118".to_string();
+        new.disk_cache_size = ByteSize::mib(500);
+        new.log_level = Some("info,libcub=debug".to_string());
+        new.honeycomb_secrets = Some(HoneycombSecrets {
+            api_key: "hc".to_string(),
+        });
+
+        let report = apply_reload(&startup, &mut live, &mem_cache, new);
+        assert_eq!(report.applied, vec!["log_level", "honeycomb_secrets"]);
+        assert_eq!(
+            report.needs_restart,
+            vec!["disk_cache_size", "mom_base_url"]
+        );
+        assert_eq!(live.log_level.as_deref(), Some("info,libcub=debug"));
+
+        // dropping the honeycomb key can't be applied live
+        let report = apply_reload(&startup, &mut live, &mem_cache, config(ByteSize::mib(64)));
+        assert_eq!(report.applied, vec!["log_level"]);
+        assert_eq!(report.needs_restart, vec!["honeycomb_secrets"]);
+        assert!(live.honeycomb_secrets.is_some());
+    }
+}
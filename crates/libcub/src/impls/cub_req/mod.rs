@@ -297,6 +297,21 @@ impl CubReq for CubReqImpl {
             .as_ref()
             .ok_or_else(|| eyre::eyre!("reddit secrets not found"))
     }
+
+    fn derive_retry(&self) -> config_types::DeriveRetryConfig {
+        global_state().config.derive_retry
+    }
+
+    fn mem_cache(&self) -> &cub_types::DerivationCache {
+        &global_state().mem_cache
+    }
+
+    fn derive_concurrency(&self) -> usize {
+        self.tenant
+            .tc()
+            .derive_concurrency
+            .unwrap_or(global_state().config.derive_concurrency)
+    }
 }
 
 /// Compatibility wrapper between axum and libwebsock (tungstenite)
@@ -13,3 +13,44 @@ pub fn global_state() -> &'static CubGlobalState {
 pub fn set_global_state(gs: &'static CubGlobalState) -> Result<(), &'static CubGlobalState> {
     GLOBAL_STATE.set(gs)
 }
+
+/// Sets a global state without any tenants (once per process, whichever test
+/// gets there first), for tests that go through the whole app.
+#[cfg(test)]
+pub(crate) async fn global_state_for_tests() -> &'static CubGlobalState {
+    use std::sync::Arc;
+
+    use config_types::CubConfig;
+    use libmomclient::{MomClient, MomClientConfig};
+
+    static INIT: tokio::sync::OnceCell<()> = tokio::sync::OnceCell::const_new();
+    INIT.get_or_init(|| async {
+        let config = CubConfig::for_tests();
+        // never actually called: there are no tenants to talk to mom about
+        let mom_client: Arc<dyn MomClient> = Arc::from(
+            libmomclient::load()
+                .client(MomClientConfig {
+                    base_url: config.mom_base_url.clone(),
+                    api_key: Some(config.mom_api_key.clone()),
+                    use_prod_mom_in_dev: false,
+                })
+                .await
+                .unwrap(),
+        );
+        let gs = crate::impls::build_global_state(
+            config.clone(),
+            config.web_config(),
+            mom_client.clone(),
+            mom_client,
+            &Default::default(),
+            &mut Default::default(),
+            &mut Default::default(),
+        )
+        .await
+        .unwrap();
+        set_global_state(Box::leak(Box::new(gs)))
+            .unwrap_or_else(|_| panic!("GLOBAL_STATE must be set only once"));
+    })
+    .await;
+    global_state()
+}
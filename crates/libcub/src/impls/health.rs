@@ -0,0 +1,206 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{Router, body::Body, extract::State, http::StatusCode, routing::get};
+use config_types::TenantDomain;
+use futures_core::future::BoxFuture;
+use itertools::Itertools;
+use parking_lot::Mutex;
+use tower::{Service, ServiceExt as _, util::BoxCloneService};
+
+use super::global_state::global_state;
+
+pub(crate) type AppService =
+    BoxCloneService<axum::extract::Request, axum::response::Response, Infallible>;
+
+/// Lets us accept connections before mom's good morning: until the app is
+/// installed, only health probes get a real answer, everything else gets a 503.
+#[derive(Clone, Default)]
+pub(crate) struct StartupGate {
+    app: Arc<Mutex<Option<AppService>>>,
+}
+
+impl StartupGate {
+    /// Starts routing requests to `app`. Only call this once the global state is set.
+    pub(crate) fn install(&self, app: AppService) {
+        *self.app.lock() = Some(app);
+    }
+
+    fn is_open(&self) -> bool {
+        self.app.lock().is_some()
+    }
+}
+
+impl Service<axum::extract::Request> for StartupGate {
+    type Response = axum::response::Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: axum::extract::Request) -> Self::Future {
+        let app = self.app.lock().clone();
+        Box::pin(async move {
+            match app {
+                Some(app) => app.oneshot(req).await,
+                None => Ok(axum::response::Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from("cub is starting up"))
+                    .unwrap()),
+            }
+        })
+    }
+}
+
+/// `/health/live`, `/health/ready` and `/metrics`, with everything else going
+/// through the gate
+pub(crate) fn routes(gate: StartupGate) -> Router {
+    Router::new()
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+        .route("/metrics", get(super::metrics::scrape))
+        .with_state(gate.clone())
+        .fallback_service(gate)
+}
+
+async fn live() -> &'static str {
+    "ok"
+}
+
+async fn ready(State(gate): State<StartupGate>) -> (StatusCode, String) {
+    if !gate.is_open() {
+        return readiness_reply(Readiness::WaitingForGoodMorning);
+    }
+
+    let tenants = global_state()
+        .dynamic
+        .read()
+        .tenants_by_name
+        .iter()
+        .map(|(tn, ts)| (tn.clone(), ts.rev_state.read().rev.is_some()))
+        .collect::<Vec<_>>();
+    readiness_reply(readiness(tenants))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Readiness {
+    /// mom hasn't sent us tenants yet
+    WaitingForGoodMorning,
+    /// these tenants don't have a loaded revision
+    MissingRevisions(Vec<TenantDomain>),
+    Ready,
+}
+
+/// Given each tenant and whether it has a loaded revision, are we ready?
+/// (Only meaningful once the good morning has been processed.)
+fn readiness(tenants: impl IntoIterator<Item = (TenantDomain, bool)>) -> Readiness {
+    let missing = tenants
+        .into_iter()
+        .filter(|(_, has_rev)| !has_rev)
+        .map(|(tn, _)| tn)
+        .sorted_by(|a, b| a.as_str().cmp(b.as_str()))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        Readiness::Ready
+    } else {
+        Readiness::MissingRevisions(missing)
+    }
+}
+
+fn readiness_reply(readiness: Readiness) -> (StatusCode, String) {
+    match readiness {
+        Readiness::Ready => (StatusCode::OK, "ready".to_string()),
+        Readiness::WaitingForGoodMorning => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "waiting for mom's good morning".to_string(),
+        ),
+        Readiness::MissingRevisions(tenants) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("no revision loaded for: {}", tenants.iter().join(", ")),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt as _;
+
+    fn tn(s: &str) -> TenantDomain {
+        TenantDomain::new(s.to_string())
+    }
+
+    async fn get_status(router: Router, path: &str) -> StatusCode {
+        let req = axum::extract::Request::builder()
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_gate_before_and_after_install() {
+        let gate = StartupGate::default();
+        let router = routes(gate.clone());
+
+        assert_eq!(
+            get_status(router.clone(), "/health/live").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_status(router.clone(), "/health/ready").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            get_status(router.clone(), "/articles/foo").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let app = Router::new()
+            .route("/articles/foo", get(|| async { "hi" }))
+            .boxed_clone();
+        gate.install(app);
+
+        assert_eq!(
+            get_status(router.clone(), "/health/live").await,
+            StatusCode::OK
+        );
+        assert_eq!(get_status(router, "/articles/foo").await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_readiness_transition() {
+        assert_eq!(readiness(vec![]), Readiness::Ready);
+
+        let tenants = vec![(tn("b.example"), false), (tn("a.example"), false)];
+        assert_eq!(
+            readiness(tenants),
+            Readiness::MissingRevisions(vec![tn("a.example"), tn("b.example")])
+        );
+
+        let tenants = vec![(tn("a.example"), true), (tn("b.example"), false)];
+        assert_eq!(
+            readiness(tenants),
+            Readiness::MissingRevisions(vec![tn("b.example")])
+        );
+
+        let tenants = vec![(tn("a.example"), true), (tn("b.example"), true)];
+        assert_eq!(readiness(tenants), Readiness::Ready);
+    }
+
+    #[test]
+    fn test_readiness_reply_statuses() {
+        assert_eq!(readiness_reply(Readiness::Ready).0, StatusCode::OK);
+        assert_eq!(
+            readiness_reply(Readiness::WaitingForGoodMorning).0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let (status, body) = readiness_reply(Readiness::MissingRevisions(vec![tn("a.example")]));
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("a.example"));
+    }
+}
@@ -5,6 +5,7 @@ use axum::http::{
 
 use super::{DomainResolution, global_state};
 use config_types::TenantDomain;
+use std::collections::HashMap;
 
 const X_FORWARDED_HOST_HEADER_KEY: &str = "X-Forwarded-Host";
 
@@ -52,15 +53,19 @@ impl ExtractedHost {
         self.0.split(':').next().unwrap_or_default()
     }
 
-    /// Get domain resolution for this domain
+    /// Get domain resolution for this domain (hosts that aren't valid domain
+    /// names never resolve)
     pub fn resolve_domain(&self) -> Option<DomainResolution> {
-        let domain = TenantDomain::new(self.domain().to_string());
-        global_state()
-            .dynamic
-            .read()
-            .domain_resolution
-            .get(&domain)
-            .cloned()
+        self.resolve_in(&global_state().dynamic.read().domain_resolution)
+    }
+
+    /// Like [`Self::resolve_domain`], against a given set of resolutions
+    pub fn resolve_in(
+        &self,
+        resolutions: &HashMap<TenantDomain, DomainResolution>,
+    ) -> Option<DomainResolution> {
+        let domain = TenantDomain::parse(self.domain()).ok()?;
+        resolutions.get(&domain).cloned()
     }
 }
 
@@ -0,0 +1,47 @@
+use axum::{body::Body, extract::Request, response::Response};
+use config_types::is_development;
+use futures_core::future::BoxFuture;
+use skelly::LogCapture;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// In development, records the logs emitted while handling each request, so
+/// error pages can show them (see `LegacyHttpError`). Does nothing in production.
+#[derive(Clone)]
+pub(crate) struct CaptureLogsLayer;
+
+impl<S> Layer<S> for CaptureLogsLayer {
+    type Service = CaptureLogsService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        CaptureLogsService { inner: service }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CaptureLogsService<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for CaptureLogsService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let future = self.inner.call(req);
+        if is_development() {
+            Box::pin(skelly::capture_logs(LogCapture::default(), future))
+        } else {
+            Box::pin(future)
+        }
+    }
+}
@@ -92,6 +92,10 @@ impl CompressContext {
         }
 
         let (mut parts, body) = self.res.into_parts();
+        // from here on, what we send depends on what the client accepts:
+        // caches must not hand a compressed body to a client that didn't ask for it
+        add_vary_accept_encoding(&mut parts.headers);
+
         let accept_encoding = match self
             .accept_encoding
             .and_then(|v| v.to_str().ok().map(|s| s.to_string()))
@@ -159,6 +163,24 @@ impl CompressContext {
     }
 }
 
+fn add_vary_accept_encoding(headers: &mut HeaderMap) {
+    let already_there = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| {
+            let v = v.trim();
+            v == "*" || v.eq_ignore_ascii_case("accept-encoding")
+        });
+    if !already_there {
+        headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+/// Only text-like formats are worth compressing: video, audio, most images
+/// (avif, webp, jpeg, png...) and woff2 fonts are already compressed, so
+/// compressing them again burns CPU for nothing.
 fn should_compress(headers: &HeaderMap) -> bool {
     // Check if the response is already compressed
     if headers.get(http::header::CONTENT_ENCODING).is_some() {
@@ -205,3 +227,87 @@ fn should_compress(headers: &HeaderMap) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt as _;
+
+    fn asset_body() -> String {
+        "body { color: hotpink; }\n".repeat(100)
+    }
+
+    async fn serve(content_type: &'static str, accept_encoding: Option<&str>) -> Response<Body> {
+        let service = CompressionLayer::default().layer(tower::service_fn(
+            move |_req: Request<Body>| async move {
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header(header::CONTENT_TYPE, content_type)
+                        .body(Body::from(asset_body()))
+                        .unwrap(),
+                )
+            },
+        ));
+
+        let mut req = Request::builder().uri("/dist/asset");
+        if let Some(accept_encoding) = accept_encoding {
+            req = req.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+        service
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_bytes(res: Response<Body>) -> Vec<u8> {
+        axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_text_asset_is_gzipped_with_vary() {
+        let res = serve("text/css; charset=utf-8", Some("gzip")).await;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[header::VARY], "accept-encoding");
+        let body = body_bytes(res).await;
+        assert!(body.len() < asset_body().len());
+
+        // not compressed, but a cache must still not reuse it for gzip clients
+        let res = serve("text/css; charset=utf-8", None).await;
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(res.headers()[header::VARY], "accept-encoding");
+        assert_eq!(body_bytes(res).await, asset_body().as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_media_is_passed_through() {
+        for content_type in ["video/mp4", "image/avif", "font/woff2"] {
+            let res = serve(content_type, Some("gzip, br")).await;
+            assert!(
+                res.headers().get(header::CONTENT_ENCODING).is_none(),
+                "{content_type}"
+            );
+            assert!(res.headers().get(header::VARY).is_none(), "{content_type}");
+            assert_eq!(body_bytes(res).await, asset_body().as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_vary_is_not_duplicated() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::VARY,
+            HeaderValue::from_static("Origin, Accept-Encoding"),
+        );
+        add_vary_accept_encoding(&mut headers);
+        assert_eq!(headers.get_all(header::VARY).iter().count(), 1);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        add_vary_accept_encoding(&mut headers);
+        assert_eq!(headers.get_all(header::VARY).iter().count(), 2);
+    }
+}
@@ -1,4 +1,7 @@
-use std::task::{Context, Poll};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
 
 use axum::{
     body::Body,
@@ -15,16 +18,18 @@ use tower::{Layer, Service};
 use tower_cookies::Cookies;
 use url::form_urlencoded;
 
+use super::request_id::RequestId;
 use crate::impls::{
     credentials::authbundle_load_from_cookies,
     cub_req::CubReqImpl,
     global_state::global_state,
     host_extract,
+    metrics::ServedTenant,
     reply::{IntoLegacyReply, LegacyReply},
-    types::DomainResolution,
+    types::{CubDynamicState, CubTenantImpl, DomainResolution},
 };
 use axum::extract::FromRequestParts;
-use config_types::Environment;
+use config_types::WebConfig;
 use conflux::Route;
 use credentials::{AuthBundle, UserApiKey};
 use cub_types::CubTenant;
@@ -71,6 +76,8 @@ where
             // Try to create CubReqImpl from the request parts
             match create_cub_req_impl(&mut parts).await {
                 Ok(cub_req) => {
+                    let tenant = ServedTenant(cub_req.tenant.tc().name.to_string());
+
                     // Insert CubReqImpl as an extension
                     parts.extensions.insert(cub_req);
 
@@ -125,6 +132,10 @@ where
                         }
                     }
 
+                    if let Some(RequestId(id)) = parts.extensions.get::<RequestId>() {
+                        attributes.push(opentelemetry::KeyValue::new("request.id", id.clone()));
+                    }
+
                     // User agent (recommended)
                     if let Some(user_agent) = parts.headers.get("user-agent") {
                         if let Ok(ua_str) = user_agent.to_str() {
@@ -228,7 +239,10 @@ where
                             // muffin
                         }
                     }
-                    res
+                    res.map(|mut http_res| {
+                        http_res.extensions_mut().insert(tenant);
+                        http_res
+                    })
                 }
                 Err(legacy_reply) => {
                     // Convert the legacy reply error into a response and return early
@@ -242,8 +256,93 @@ where
     }
 }
 
+/// The tenant a request for `host` is for, redirected or not
+fn tenant_for_host(
+    host: &host_extract::ExtractedHost,
+    dynamic: &CubDynamicState,
+) -> Option<Arc<CubTenantImpl>> {
+    match host.resolve_in(&dynamic.domain_resolution)? {
+        DomainResolution::Tenant(ts) => Some(ts),
+        DomainResolution::Redirect { tenant, .. } => Some(tenant),
+    }
+}
+
+/// What we reply to requests for hosts we don't serve. 421 is "you've got
+/// the wrong server", which is what happened. In development, `listed`
+/// tenants are linked to, to help find the right URL.
+fn unknown_host_response(
+    domain: &str,
+    listed: Option<&CubDynamicState>,
+    web: WebConfig,
+) -> Response<Body> {
+    let domain = html_escape::encode_text(domain);
+    let available_tenants = match listed {
+        Some(dynamic) => {
+            let mut tenants = dynamic
+                .tenants_by_name
+                .values()
+                .map(|ts| {
+                    let tc = ts.tc();
+                    format!(
+                        "<li><a href=\"{}\">{}</a></li>",
+                        html_escape::encode_double_quoted_attribute(&tc.web_base_url(web)),
+                        html_escape::encode_text(tc.name.as_str())
+                    )
+                })
+                .collect::<Vec<_>>();
+            tenants.sort();
+            format!(
+                "<p>Available tenants:</p>\n<ul>\n{}\n</ul>",
+                tenants.join("\n")
+            )
+        }
+        None => String::new(),
+    };
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Unknown host</title>
+    <style>
+    body {{
+        font-family: system-ui, -apple-system, sans-serif;
+        max-width: 800px;
+        margin: 2rem auto;
+        line-height: 1.5;
+    }}
+    code {{
+        background: #eee;
+        padding: 0.2em 0.4em;
+        border-radius: 3px;
+    }}
+    </style>
+</head>
+<body>
+    <h1>Unknown host: <code>{domain}</code></h1>
+    <p>There's no home site here, check the address?</p>
+    {available_tenants}
+</body>
+</html>
+"#
+    );
+
+    (
+        StatusCode::MISDIRECTED_REQUEST,
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
+
 async fn create_cub_req_impl(parts: &mut Parts) -> Result<CubReqImpl, LegacyReply> {
-    let path = Route::new(parts.uri.path().to_string()).trim_trailing_slash();
+    let path = match Route::from_url_path(parts.uri.path()) {
+        Ok(path) => path,
+        Err(e) => {
+            log::debug!("Rejecting request for {}: {e}", parts.uri.path());
+            return Err((StatusCode::BAD_REQUEST, "Invalid request path").into_legacy_reply());
+        }
+    };
 
     let host = match host_extract::ExtractedHost::from_headers(&parts.uri, &parts.headers) {
         Some(host) => host,
@@ -256,68 +355,21 @@ async fn create_cub_req_impl(parts: &mut Parts) -> Result<CubReqImpl, LegacyRepl
             return Err((StatusCode::BAD_REQUEST, "No host found in request").into_legacy_reply());
         }
     };
-    let domain = host.domain();
-    let tenant = match host.resolve_domain() {
-        Some(DomainResolution::Tenant(ts)) => ts.clone(),
-        Some(DomainResolution::Redirect { tenant, .. }) => tenant.clone(),
-        None => {
-            log::warn!("No tenant found for domain {domain}");
-            let msg = if Environment::default().is_dev() {
-                let global_state = global_state();
-                let available_tenants = global_state
-                    .dynamic
-                    .read()
-                    .tenants_by_name
-                    .values()
-                    .map(|ts| {
-                        let tc = ts.tc();
-                        format!(
-                            "<li><a href=\"{}\">{}</a></li>",
-                            tc.web_base_url(global_state.web),
-                            tc.name
-                        )
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n");
-
-                format!(
-                    r#"
-                    <html>
-                    <head>
-                        <style>
-                        body {{
-                            font-family: system-ui, -apple-system, sans-serif;
-                            max-width: 800px;
-                            margin: 2rem auto;
-                            line-height: 1.5;
-                        }}
-                        code {{
-                            background: #eee;
-                            padding: 0.2em 0.4em;
-                            border-radius: 3px;
-                        }}
-                        </style>
-                    </head>
-                    <body>
-                        <h1>No tenant found for domain <code>{domain}</code></h1>
-                        <p>Available tenants:</p>
-                        <ul>
-                            {available_tenants}</ul>
-                    </body>
-                    </html>
-                    "#
-                )
-            } else {
-                "tenant_not_found".to_string()
-            };
-
-            let resp = (
-                StatusCode::BAD_REQUEST,
-                [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
-                msg,
-            );
-            // lol
-            return Err(Ok(resp.into_response()));
+    let tenant = {
+        let gs = global_state();
+        let dynamic = gs.dynamic.read();
+        match tenant_for_host(&host, &dynamic) {
+            Some(tenant) => tenant,
+            None => {
+                log::warn!(
+                    "No tenant found for host {}, turning away {} {}",
+                    host.domain(),
+                    parts.method,
+                    parts.uri.path()
+                );
+                let listed = gs.web.env.is_dev().then_some(&*dynamic);
+                return Err(Ok(unknown_host_response(host.domain(), listed, gs.web)));
+            }
         }
     };
 
@@ -332,7 +384,7 @@ async fn create_cub_req_impl(parts: &mut Parts) -> Result<CubReqImpl, LegacyRepl
         };
 
     let mut auth_bundle =
-        authbundle_load_from_cookies(&public_cookies.private(&tenant.cookie_key)).await;
+        authbundle_load_from_cookies(&public_cookies.private(&tenant.cookie_key())).await;
 
     if let Some(query) = parts.uri.query() {
         let params: std::collections::HashMap<String, String> =
@@ -374,7 +426,7 @@ async fn create_cub_req_impl(parts: &mut Parts) -> Result<CubReqImpl, LegacyRepl
     );
 
     let cub_req = CubReqImpl {
-        cookie_key: tenant.cookie_key.clone(),
+        cookie_key: tenant.cookie_key(),
         public_cookies,
         tenant,
         path,
@@ -385,3 +437,75 @@ async fn create_cub_req_impl(parts: &mut Parts) -> Result<CubReqImpl, LegacyRepl
 
     Ok(cub_req)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_types::{Environment, TenantConfig, TenantDomain};
+    use host_extract::ExtractedHost;
+
+    fn dynamic_state(env: Environment) -> CubDynamicState {
+        let ts = CubTenantImpl::for_tests(TenantConfig::new(TenantDomain::new(
+            "example.org".to_string(),
+        )));
+        CubDynamicState {
+            tenants_by_name: [(ts.tc().name.clone(), ts.clone())].into(),
+            domain_resolution: crate::impls::domain_resolutions(&ts, env)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    async fn body_string(res: Response<Body>) -> String {
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_host() {
+        let web = WebConfig {
+            env: Environment::Production,
+            port: 1111,
+        };
+        let dynamic = dynamic_state(web.env);
+        let host = |h: &str| ExtractedHost(h.to_string());
+
+        assert!(tenant_for_host(&host("example.org"), &dynamic).is_some());
+        assert!(tenant_for_host(&host("cdn.example.org:443"), &dynamic).is_some());
+        assert!(tenant_for_host(&host("example.com"), &dynamic).is_none());
+
+        let res = unknown_host_response(host("example.com").domain(), None, web);
+        assert_eq!(res.status(), StatusCode::MISDIRECTED_REQUEST);
+        let body = body_string(res).await;
+        assert!(
+            body.contains("Unknown host: <code>example.com</code>"),
+            "{body}"
+        );
+        // we don't tell strangers what else we serve
+        assert!(!body.contains("example.org"), "{body}");
+
+        // hosts are attacker-controlled
+        assert!(tenant_for_host(&host("<script>"), &dynamic).is_none());
+        let body = body_string(unknown_host_response("<script>", None, web)).await;
+        assert!(body.contains("<code>&lt;script&gt;</code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_host_lists_tenants_in_development() {
+        let web = WebConfig {
+            env: Environment::Development,
+            port: 1111,
+        };
+        let dynamic = dynamic_state(web.env);
+
+        let res = unknown_host_response("example.com.localhost", Some(&dynamic), web);
+        assert_eq!(res.status(), StatusCode::MISDIRECTED_REQUEST);
+        let body = body_string(res).await;
+        assert!(
+            body.contains(r#".localhost:1111">example.org</a></li>"#),
+            "{body}"
+        );
+    }
+}
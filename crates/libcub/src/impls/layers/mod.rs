@@ -1,5 +1,7 @@
+pub(crate) mod capture_logs;
 pub(crate) mod compression;
 pub(crate) mod cub_req;
 pub(crate) mod domain_redirect;
+pub(crate) mod request_id;
 pub(crate) mod set_response_header;
 pub(crate) mod strip_slash_if_404;
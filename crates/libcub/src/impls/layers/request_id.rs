@@ -0,0 +1,152 @@
+use axum::{body::Body, extract::Request, response::Response};
+use futures_core::future::BoxFuture;
+use http::{HeaderName, HeaderValue};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Incoming ids longer than this are replaced with one of ours
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id of the request being served, inserted as a request extension
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RequestId(pub(crate) String);
+
+/// Gives every request an id: the one it came with in `header`, or a fresh
+/// one. The id is echoed back in the same header, and is the current request
+/// id (see [`libhttpclient::with_request_id`]) while the request is served,
+/// so calls to mom carry it too.
+#[derive(Clone)]
+pub(crate) struct RequestIdLayer {
+    header: HeaderName,
+}
+
+impl RequestIdLayer {
+    pub(crate) fn new(header: HeaderName) -> Self {
+        Self { header }
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RequestIdService {
+            inner: service,
+            header: self.header.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RequestIdService<S> {
+    inner: S,
+    header: HeaderName,
+}
+
+impl<S> Service<Request> for RequestIdService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let id = req
+            .headers()
+            .get(&self.header)
+            .and_then(incoming_request_id)
+            .unwrap_or_else(new_request_id);
+        req.extensions_mut().insert(RequestId(id.clone()));
+        let header = self.header.clone();
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response = libhttpclient::with_request_id(id.clone(), future).await?;
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                response.headers_mut().insert(header, value);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// We only trust ids that are safe to put in log lines and headers as-is
+fn incoming_request_id(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| id.to_string())
+}
+
+fn new_request_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::ServiceExt as _;
+
+    /// Answers with the id it saw in the extensions, and the current request
+    /// id, separated by a space
+    async fn serve(incoming: Option<&str>) -> (Option<String>, String) {
+        let service = RequestIdLayer::new(HeaderName::from_static("x-request-id")).layer(
+            tower::service_fn(|req: Request| async move {
+                let RequestId(id) = req.extensions().get::<RequestId>().unwrap().clone();
+                let current = libhttpclient::current_request_id().unwrap();
+                Ok::<_, Infallible>(Response::new(Body::from(format!("{id} {current}"))))
+            }),
+        );
+
+        let mut req = Request::builder().uri("/articles");
+        if let Some(incoming) = incoming {
+            req = req.header("x-request-id", incoming);
+        }
+        let res = service
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let echoed = res
+            .headers()
+            .get("x-request-id")
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (echoed, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_incoming_id_is_echoed() {
+        let (echoed, body) = serve(Some("lb-4f2a")).await;
+        assert_eq!(echoed.as_deref(), Some("lb-4f2a"));
+        assert_eq!(body, "lb-4f2a lb-4f2a");
+    }
+
+    #[tokio::test]
+    async fn test_id_is_generated_when_absent() {
+        let (echoed, body) = serve(None).await;
+        let echoed = echoed.unwrap();
+        assert_eq!(echoed.len(), 32);
+        assert!(echoed.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(body, format!("{echoed} {echoed}"));
+
+        // every request gets its own
+        let (other, _) = serve(None).await;
+        assert_ne!(other.unwrap(), echoed);
+
+        // ids we wouldn't want in our logs are replaced too
+        let (echoed, _) = serve(Some("has spaces in it")).await;
+        assert_ne!(echoed.as_deref(), Some("has spaces in it"));
+        let (echoed, _) = serve(Some(&"a".repeat(MAX_REQUEST_ID_LEN + 1))).await;
+        assert_eq!(echoed.unwrap().len(), 32);
+    }
+}
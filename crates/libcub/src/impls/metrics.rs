@@ -0,0 +1,209 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// What Prometheus expects for its text exposition format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Headers a proxy adds: if we see one, the request didn't come from the
+/// scraper directly.
+const FORWARDING_HEADERS: &[&str] = &["forwarded", "x-forwarded-for", "x-real-ip"];
+
+/// `/metrics`, in the Prometheus text format. Only answered for internal
+/// scrapers: everyone else gets a 404, as if the route didn't exist.
+pub(crate) async fn scrape(req: Request) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    if !is_internal(peer, req.headers()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+        cub_metrics::metrics().render(),
+    )
+        .into_response()
+}
+
+/// Whether the peer is on localhost or a private network, and talking to us
+/// directly. Requests that went through a proxy (like the ingress) don't
+/// count, even though the proxy itself is on a private network. Without a
+/// peer address (when serving HTTPS), nothing is internal.
+fn is_internal(peer: Option<SocketAddr>, headers: &HeaderMap) -> bool {
+    if FORWARDING_HEADERS.iter().any(|h| headers.contains_key(*h)) {
+        return false;
+    }
+    match peer.map(|addr| addr.ip().to_canonical()) {
+        Some(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private(),
+        Some(IpAddr::V6(ip)) => ip.is_loopback() || ip.is_unique_local(),
+        None => false,
+    }
+}
+
+/// Which tenant a response is for: `CubReqLayer` attaches it to everything
+/// that made it past host resolution.
+#[derive(Clone)]
+pub(crate) struct ServedTenant(pub(crate) String);
+
+/// Counts every request the app answers. Sits outside of `CubReqLayer`, so
+/// the requests it turns away (unknown hosts, bad paths) are counted too.
+pub(crate) async fn record_requests(req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    let tenant = response
+        .extensions()
+        .get::<ServedTenant>()
+        .map(|ServedTenant(tenant)| tenant.as_str());
+    record_request(tenant, response.status());
+    response
+}
+
+/// Counts a request the app served, by tenant (if the host resolved to one)
+/// and status class.
+fn record_request(tenant: Option<&str>, status: StatusCode) {
+    let status_class = format!("{}xx", status.as_u16() / 100);
+    cub_metrics::metrics()
+        .http_requests
+        .inc(&[tenant.unwrap_or("none"), &status_class]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::impls::{
+        global_state::global_state_for_tests,
+        health::{self, StartupGate},
+        node_metadata::NodeMetadata,
+        setup_app_routes,
+    };
+    use axum::{Router, body::Body, routing::get};
+    use tower::ServiceExt as _;
+
+    async fn scrape_from(peer: Option<&str>, forwarded_for: Option<&str>) -> (StatusCode, String) {
+        let router = Router::new().route("/metrics", get(scrape));
+        scrape_router(router, peer, forwarded_for).await
+    }
+
+    async fn scrape_router(
+        router: Router,
+        peer: Option<&str>,
+        forwarded_for: Option<&str>,
+    ) -> (StatusCode, String) {
+        let mut req = Request::builder().uri("/metrics");
+        if let Some(forwarded_for) = forwarded_for {
+            req = req.header("x-forwarded-for", forwarded_for);
+        }
+        let mut req = req.body(Body::empty()).unwrap();
+        if let Some(peer) = peer {
+            req.extensions_mut()
+                .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        }
+
+        let res = router.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_scrape_metrics() {
+        record_request(Some("example.org"), StatusCode::OK);
+        cub_metrics::metrics().derive_cache.inc(&["memory"]);
+        cub_metrics::metrics()
+            .mom_rtt
+            .observe(Duration::from_millis(30));
+
+        let (status, body) = scrape_from(Some("127.0.0.1:51234"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        for line in [
+            "# TYPE cub_http_requests_total counter",
+            r#"cub_http_requests_total{tenant="example.org",status="2xx"}"#,
+            r#"cub_derive_cache_total{result="memory"}"#,
+            "# TYPE cub_derive_duration_seconds histogram",
+            r#"cub_derive_duration_seconds_bucket{le="+Inf"}"#,
+            r#"cub_mom_request_duration_seconds_bucket{le="0.05"}"#,
+            "cub_mom_request_duration_seconds_count",
+        ] {
+            assert!(body.contains(line), "missing {line:?} in:\n{body}");
+        }
+
+        // pod-to-pod is fine too
+        let (status, _) = scrape_from(Some("10.42.0.17:40000"), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    /// How many requests for hosts we don't know, according to `/metrics`
+    async fn unknown_host_count(router: Router) -> u64 {
+        let (_, body) = scrape_router(router, Some("127.0.0.1:51234"), None).await;
+        body.lines()
+            .find_map(|line| {
+                line.strip_prefix(r#"cub_http_requests_total{tenant="none",status="4xx"} "#)
+            })
+            .map_or(0, |count| count.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_hosts_are_counted() {
+        global_state_for_tests().await;
+        let gate = StartupGate::default();
+        gate.install(
+            setup_app_routes(&NodeMetadata {
+                node_type: "leader".into(),
+                region: "test".into(),
+            })
+            .await
+            .unwrap(),
+        );
+        let router = health::routes(gate);
+        let before = unknown_host_count(router.clone()).await;
+
+        let req = Request::builder()
+            .uri("/articles/foo")
+            .header(header::HOST, "nobody.example.org")
+            .body(Body::empty())
+            .unwrap();
+        let res = router.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::MISDIRECTED_REQUEST);
+
+        assert_eq!(unknown_host_count(router).await, before + 1);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_is_internal_only() {
+        for (peer, forwarded_for) in [
+            (Some("203.0.113.7:443"), None),
+            (Some("10.42.0.3:40000"), Some("203.0.113.7")),
+            (None, None),
+        ] {
+            let (status, _) = scrape_from(peer, forwarded_for).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{peer:?} {forwarded_for:?}");
+        }
+    }
+
+    #[test]
+    fn test_ipv6_peers() {
+        let headers = HeaderMap::new();
+        for (peer, internal) in [
+            ("[::1]:1111", true),
+            ("[::ffff:127.0.0.1]:1111", true),
+            ("[fd00::5]:1111", true),
+            ("[2001:db8::1]:1111", false),
+        ] {
+            assert_eq!(
+                is_internal(Some(peer.parse().unwrap()), &headers),
+                internal,
+                "{peer}"
+            );
+        }
+    }
+}
@@ -5,18 +5,23 @@ use graceful_shutdown::setup_graceful_shutdown;
 use hattip::{HBody, HError, HReply};
 use libc as _;
 
-use axum::{Router, ServiceExt as _, body::Body, extract::DefaultBodyLimit};
+use axum::{Router, body::Body};
+use camino::Utf8PathBuf;
 use config_types::{
-    CubConfig, Environment, MOM_DEV_API_KEY, MomApiKey, TenantDomain, TenantInfo, WebConfig,
-    is_development, is_production,
+    CubConfig, Environment, HoneycombSecrets, MOM_DEV_API_KEY, MomApiKey, TenantDomain, TenantInfo,
+    WebConfig, is_development, is_production,
 };
 use futures_core::future::BoxFuture;
 use itertools::Itertools;
 use layers::{
-    compression::CompressionLayer, cub_req::CubReqLayer, domain_redirect::DomainRedirectLayer,
+    capture_logs::CaptureLogsLayer,
+    compression::CompressionLayer,
+    cub_req::CubReqLayer,
+    domain_redirect::DomainRedirectLayer,
+    request_id::{RequestId, RequestIdLayer},
     strip_slash_if_404::StripSlashIf404Layer,
 };
-use libmomclient::{MomClient, MomClientConfig, MomEventListener};
+use libmomclient::{MomClient, MomClientConfig, MomEventListener, ProdMomChoice};
 use librevision::{RevisionKind, RevisionSpec};
 use log::{info, warn};
 use mom_event_handler::spawn_mom_event_handler;
@@ -39,91 +44,78 @@ use tower_cookies::CookieManagerLayer;
 use types::CubDynamicState;
 
 pub mod access_control;
+mod access_log;
 pub mod cdn;
+pub mod config_reload;
 pub mod credentials;
 pub mod cub_req;
 pub mod global_state;
 mod graceful_shutdown;
+mod health;
 pub mod host_extract;
 pub mod layers;
+mod metrics;
 mod node_metadata;
 pub mod path_metadata;
 pub mod reply;
+mod store_probe;
 pub mod types;
 pub mod vite;
 pub mod web;
 
 use crate::OpenBehavior;
 
+use self::config_reload::ReloadableConfig;
 use self::types::{CubGlobalState, CubTenantImpl, DomainResolution};
 
 pub(crate) async fn serve(
     cc: CubConfig,
+    config_path: Option<Utf8PathBuf>,
     ln: TcpListener,
     open_behavior: OpenBehavior,
 ) -> eyre::Result<()> {
     let metadata = load_node_metadata().await?;
 
-    let mut valid_otlp = true;
-    let mut otlp_headers: HashMap<String, String> = Default::default();
-    match cc.honeycomb_secrets.as_ref() {
-        Some(hs) => {
-            otlp_headers.insert("x-honeycomb-team".to_string(), hs.api_key.to_string());
+    let dr = &cc.derive_retry;
+    info!(
+        "Derive retry: max_tries={}, initial_sleep={}ms, max_sleep={}ms",
+        dr.max_tries, dr.initial_sleep_ms, dr.max_sleep_ms
+    );
+
+    if let Some(log_level) = cc.log_level.as_deref() {
+        if !skelly::set_log_directives(log_level) {
+            warn!("skelly's logger isn't installed, ignoring log_level from config");
         }
+    }
+
+    match cc.honeycomb_secrets.as_ref() {
+        Some(hs) => install_honeycomb_tracer(hs, &metadata)?,
         None => {
             log::warn!("No honeycomb API key set! Traces won't be sent anywhere.");
             if is_production() {
                 panic!("No honeycomb API key set, bailing out");
             }
-            valid_otlp = false;
         }
     }
 
-    // Initialize OTLP exporter using the GRPC protocol
-    let otlp_exporter = opentelemetry_otlp::SpanExporter::builder()
-        .with_http()
-        .with_protocol(Protocol::HttpBinary)
-        .with_endpoint("https://api.eu1.honeycomb.io/v1/traces")
-        .with_headers(otlp_headers)
-        .build()?;
-
-    // Create a tracer provider with the exporter
-    let tracer_provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
-        .with_batch_exporter(otlp_exporter)
-        .with_resource(
-            Resource::builder()
-                .with_service_name("cub")
-                .with_attribute(KeyValue::new(
-                    "host.name",
-                    gethostname::gethostname().to_string_lossy().to_string(),
-                ))
-                .with_attribute(KeyValue::new(
-                    "deployment.environment",
-                    if is_development() {
-                        "development".to_string()
-                    } else {
-                        "production".to_string()
-                    },
-                ))
-                .with_attribute(KeyValue::new("host.type", metadata.node_type.clone()))
-                .with_attribute(KeyValue::new("cloud.region", metadata.region.clone()))
-                .build(),
-        )
-        .build();
-
-    // Set it as the global provider (only if valid)
-    if valid_otlp {
-        opentelemetry::global::set_tracer_provider(tracer_provider);
-    }
-
     let web = WebConfig {
         env: Environment::default(),
         port: cc.address.port(),
     };
 
+    // start accepting connections right away, so health probes work while
+    // we wait for mom: everything else gets a 503 until the gate is opened.
+    let gate = health::StartupGate::default();
+    let server = tokio::spawn(serve_listener(
+        ln,
+        health::routes(gate.clone()),
+        setup_graceful_shutdown(),
+    ));
+
     let mom_client_config = MomClientConfig {
         base_url: cc.mom_base_url.clone(),
         api_key: Some(cc.mom_api_key.clone()),
+        use_prod_mom_in_dev: cc.use_prod_mom_in_dev,
     };
     let (mom_client, mut mev_rx) = setup_mom_client(mom_client_config.clone()).await?;
 
@@ -134,11 +126,22 @@ pub(crate) async fn serve(
         mom_client.clone()
     } else {
         {
-            let force_local = std::env::var("FORCE_LOCAL_MOM")
-                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
-                .unwrap_or(false);
+            let choice = ProdMomChoice::resolve(
+                web.env.is_dev(),
+                cc.use_prod_mom_in_dev,
+                libmomclient::force_local_mom(),
+            );
+            info!(
+                "Deploys go to the {} mom: {}",
+                if choice.uses_production_mom() {
+                    "production"
+                } else {
+                    "configured"
+                },
+                choice.reason()
+            );
 
-            let mom_client_config = if force_local {
+            let mom_client_config = if !choice.uses_production_mom() {
                 mom_client_config
             } else {
                 let base_url = "https://mom.bearcove.cloud".to_string();
@@ -149,6 +152,7 @@ pub(crate) async fn serve(
                 MomClientConfig {
                     base_url,
                     api_key: Some(api_key),
+                    use_prod_mom_in_dev: cc.use_prod_mom_in_dev,
                 }
             };
 
@@ -178,19 +182,94 @@ pub(crate) async fn serve(
     }
 
     let app = setup_app_routes(&metadata).await?;
-    let quit_sig = setup_graceful_shutdown();
+    gate.install(app);
+    tokio::spawn(config_reload::reload_on_sighup(config_path, metadata));
     log_tenant_urls(&cc);
 
-    if matches!(open_behavior, OpenBehavior::OpenOnStart) {
-        let web = cc.web_config();
-        if let Some(ti) = tenant_infos.values().next() {
-            let url = ti.tc.web_base_url(web);
-            if let Err(e) = open::that(url) {
-                warn!("Failed to open browser: {e}");
-            }
-        }
+    open_on_start(
+        &open_behavior,
+        || {
+            let ti = tenant_infos.values().next()?;
+            Some(ti.tc.web_base_url(cc.web_config()))
+        },
+        |url| open::that(url),
+    );
+
+    server
+        .await
+        .map_err(|e| eyre::eyre!("Server task failed: {e}"))?
+}
+
+/// Opens whatever `open_behavior` asks for with `opener` (`open::that`,
+/// outside of tests)
+fn open_on_start(
+    open_behavior: &OpenBehavior,
+    first_tenant_url: impl FnOnce() -> Option<String>,
+    opener: impl FnOnce(&str) -> std::io::Result<()>,
+) {
+    let url = match open_behavior {
+        OpenBehavior::OpenOnStart => first_tenant_url(),
+        OpenBehavior::OpenUrl(url) => Some(url.clone()),
+        OpenBehavior::DontOpen => None,
+    };
+    let Some(url) = url else {
+        return;
+    };
+    info!("Opening {url} in the browser");
+    if let Err(e) = opener(&url) {
+        warn!("Failed to open browser: {e}");
     }
+}
 
+/// Sends our traces to honeycomb, replacing the previous tracer provider if any
+pub(crate) fn install_honeycomb_tracer(
+    hs: &HoneycombSecrets,
+    metadata: &NodeMetadata,
+) -> eyre::Result<()> {
+    let mut otlp_headers: HashMap<String, String> = Default::default();
+    otlp_headers.insert("x-honeycomb-team".to_string(), hs.api_key.to_string());
+
+    // Initialize OTLP exporter using the GRPC protocol
+    let otlp_exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint("https://api.eu1.honeycomb.io/v1/traces")
+        .with_headers(otlp_headers)
+        .build()?;
+
+    // Create a tracer provider with the exporter
+    let tracer_provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(otlp_exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name("cub")
+                .with_attribute(KeyValue::new(
+                    "host.name",
+                    gethostname::gethostname().to_string_lossy().to_string(),
+                ))
+                .with_attribute(KeyValue::new(
+                    "deployment.environment",
+                    if is_development() {
+                        "development".to_string()
+                    } else {
+                        "production".to_string()
+                    },
+                ))
+                .with_attribute(KeyValue::new("host.type", metadata.node_type.clone()))
+                .with_attribute(KeyValue::new("cloud.region", metadata.region.clone()))
+                .build(),
+        )
+        .build();
+
+    opentelemetry::global::set_tracer_provider(tracer_provider);
+    Ok(())
+}
+
+async fn serve_listener(
+    ln: TcpListener,
+    app: Router,
+    quit_sig: impl Future<Output = ()> + Send + 'static,
+) -> eyre::Result<()> {
     if let Ok(_var) = std::env::var("CUB_HTTPS") {
         // Create a self-signed certificate for HTTPS
         let config = rustls::ServerConfig::builder().with_no_client_auth();
@@ -415,7 +494,8 @@ async fn process_mom_good_morning(
 /// tenants, setting up domain resolutions, and preparing the necessary components for each
 /// tenant. It's crucial because it:
 /// 1. Creates the central data structure (CubGlobalState) that holds all tenant information
-/// 2. Sets up object stores, cookie keys, and revision states for each tenant
+/// 2. Sets up object stores (probing them if configured), cookie keys, and revision states
+///    for each tenant
 /// 3. Configures domain resolutions for web and CDN domains
 /// 4. Initializes Vite for development environments
 /// 5. Prepares the application to handle requests for multiple tenants efficiently
@@ -429,6 +509,8 @@ async fn build_global_state(
     users_per_ts: &mut HashMap<TenantDomain, Arc<AllUsers>>,
 ) -> eyre::Result<CubGlobalState> {
     let mut gs = CubGlobalState {
+        reloadable: RwLock::new(ReloadableConfig::from_config(&config)),
+        mem_cache: cub_types::derivation_cache(config.mem_cache_size),
         config,
         web,
         mom_client,
@@ -439,19 +521,19 @@ async fn build_global_state(
         })),
     };
 
+    let mut stores = Vec::new();
     for (tn, ti) in tenant_infos {
         let (bx_rev, _) = broadcast::channel(128);
         let object_store = derivations::objectstore_for_tenant(ti, Environment::default())
             .await
             .map_err(|e| eyre::eyre!("Failed to get object store: {}", e))?;
+        stores.push((tn.clone(), object_store.clone()));
         let cookie_sauce = ti.tc.cookie_sauce();
         assert!(
             !cookie_sauce.is_empty(),
             "[{tn}] cookie sauce cannot be empty"
         );
-        let sauce_repetitions = (32 / cookie_sauce.len()) + 1;
-        let cookie_master_key = cookie_sauce.into_bytes().repeat(sauce_repetitions);
-        let cookie_key = tower_cookies::Key::derive_from(&cookie_master_key);
+        let cookie_key = types::cookie_key_from_sauce(&cookie_sauce);
 
         let rs = revs_per_ts.remove(tn).unwrap().clone();
         let users = users_per_ts.remove(tn).unwrap_or_default();
@@ -460,7 +542,7 @@ async fn build_global_state(
             rev_state: RwLock::new(rs),
             bx_rev,
             store: object_store,
-            cookie_key,
+            cookie_key: RwLock::new(cookie_key),
             users: RwLock::new(users),
             vite_port: Default::default(),
         };
@@ -473,42 +555,52 @@ async fn build_global_state(
         setup_domain_resolution(&mut gs, &ts, web);
     }
 
+    if gs.config.probe_object_stores {
+        store_probe::probe_object_stores(&stores, web.env).await?;
+    }
+
     Ok(gs)
 }
 
 fn setup_domain_resolution(gs: &mut CubGlobalState, ts: &Arc<CubTenantImpl>, web: WebConfig) {
-    let web_domain = ts.ti.tc.web_domain(web.env).to_owned();
-    let cdn_domain = ts.ti.tc.cdn_domain(web.env);
-
-    {
-        let mut dynamic = gs.dynamic.write();
-
-        dynamic
-            .domain_resolution
-            .insert(web_domain.clone(), DomainResolution::Tenant(ts.clone()));
-        dynamic
-            .domain_resolution
-            .insert(cdn_domain.clone(), DomainResolution::Tenant(ts.clone()));
-
-        for alias in &ts.tc().domain_aliases {
-            dynamic.domain_resolution.insert(
-                alias.clone(),
-                DomainResolution::Redirect {
-                    target_domain: web_domain.clone(),
-                    tenant: ts.clone(),
-                },
-            );
+    gs.dynamic
+        .write()
+        .domain_resolution
+        .extend(domain_resolutions(ts, web.env));
+}
 
-            let cdn_alias = TenantDomain::new(format!("cdn.{alias}"));
-            dynamic.domain_resolution.insert(
-                cdn_alias,
-                DomainResolution::Redirect {
-                    target_domain: cdn_domain.clone(),
-                    tenant: ts.clone(),
-                },
-            );
-        }
+/// Which domains lead to `ts`, and how:
+///
+///   - its web and CDN domains serve it
+///   - web aliases redirect to the web domain, so pages have one canonical URL
+///   - CDN aliases (`cdn.{alias}`) serve assets too: asset URLs are
+///     cache-busted and identical on every domain, so a redirect would only
+///     cost every asset an extra round-trip
+fn domain_resolutions(
+    ts: &Arc<CubTenantImpl>,
+    env: Environment,
+) -> Vec<(TenantDomain, DomainResolution)> {
+    let web_domain = ts.ti.tc.web_domain(env);
+    let cdn_domain = ts.ti.tc.cdn_domain(env);
+
+    let mut resolutions = vec![
+        (web_domain.clone(), DomainResolution::Tenant(ts.clone())),
+        (cdn_domain, DomainResolution::Tenant(ts.clone())),
+    ];
+    for alias in &ts.tc().domain_aliases {
+        resolutions.push((
+            alias.clone(),
+            DomainResolution::Redirect {
+                target_domain: web_domain.clone(),
+                tenant: ts.clone(),
+            },
+        ));
+        resolutions.push((
+            TenantDomain::new(format!("cdn.{alias}")),
+            DomainResolution::Tenant(ts.clone()),
+        ));
     }
+    resolutions
 }
 
 mod mom_event_handler;
@@ -554,37 +646,81 @@ async fn setup_app_routes(
         http::HeaderValue::try_from(source_value).unwrap(),
     );
 
+    let request_id_header = &global_state().config.request_id_header;
+    let request_id_layer = RequestIdLayer::new(
+        http::HeaderName::try_from(request_id_header.as_str())
+            .map_err(|e| eyre::eyre!("invalid request_id_header {request_id_header:?}: {e}"))?,
+    );
+
+    let access_log = match global_state().config.access_log.as_deref() {
+        Some(target) => Some(Arc::new(access_log::AccessLog::open(target)?)),
+        None => None,
+    };
+
     let common_layers = ServiceBuilder::new()
+        .layer(axum::middleware::from_fn(metrics::record_requests))
+        .layer(request_id_layer)
         .layer(CookieManagerLayer::new())
         .layer(source_layer.clone())
         .layer(CompressionLayer::default())
         .layer(StripSlashIf404Layer)
         .layer(CubReqLayer)
         .layer(DomainRedirectLayer)
-        .layer(DefaultBodyLimit::max(32 * 1024 * 1024))
+        .layer(web::body_limit(global_state().config.body_limits.web))
+        .layer(CaptureLogsLayer)
         .layer(
             axum::middleware::from_fn(
-                |req: axum::http::Request<axum::body::Body>, next: axum::middleware::Next| async move {
-                    let path = req.uri().path().to_owned();
-                    let query = req.uri().query().map(|q| q.to_owned());
-                    let method = req.method().clone();
-                    let start = std::time::Instant::now();
-                    let response = next.run(req).await;
-                    let duration = start.elapsed();
-                    let status = response.status();
-                    if !(path.starts_with("/health")  || (path.starts_with("/dist") && is_development())) {
-                        if let Some(q) = query {
-                            log::info!("\x1b[36m{}\x1b[0m \x1b[33m{}\x1b[0m\x1b[90m?\x1b[0m\x1b[32m{}\x1b[0m -> \x1b[35m{}\x1b[0m (took {:?})", method, path, q, status.as_u16(), duration);
-                        } else {
-                            log::info!("\x1b[36m{}\x1b[0m \x1b[33m{}\x1b[0m -> \x1b[35m{}\x1b[0m (took {:?})", method, path, status.as_u16(), duration);
+                move |req: axum::http::Request<axum::body::Body>, next: axum::middleware::Next| {
+                    let access_log = access_log.clone();
+                    async move {
+                        let path = req.uri().path().to_owned();
+                        let query = req.uri().query().map(|q| q.to_owned());
+                        let method = req.method().clone();
+                        let host = host_extract::ExtractedHost::from_headers(req.uri(), req.headers())
+                            .map(|h| h.domain().to_owned());
+                        let tenant = req
+                            .extensions()
+                            .get::<cub_req::CubReqImpl>()
+                            .map(|rcx| rcx.tenant.tc().name.to_string());
+                        let request_id = req
+                            .extensions()
+                            .get::<RequestId>()
+                            .map(|RequestId(id)| id.clone());
+                        let start = std::time::Instant::now();
+                        let response = next.run(req).await;
+                        let duration = start.elapsed();
+                        let status = response.status();
+                        if !(path.starts_with("/health")  || (path.starts_with("/dist") && is_development())) {
+                            let rid = request_id.as_deref().unwrap_or("-");
+                            if let Some(q) = query {
+                                log::info!("\x1b[36m{}\x1b[0m \x1b[33m{}\x1b[0m\x1b[90m?\x1b[0m\x1b[32m{}\x1b[0m -> \x1b[35m{}\x1b[0m (took {:?}) \x1b[90m{}\x1b[0m", method, path, q, status.as_u16(), duration, rid);
+                            } else {
+                                log::info!("\x1b[36m{}\x1b[0m \x1b[33m{}\x1b[0m -> \x1b[35m{}\x1b[0m (took {:?}) \x1b[90m{}\x1b[0m", method, path, status.as_u16(), duration, rid);
+                            }
+                            if let Some(access_log) = access_log.as_ref() {
+                                let bytes = response
+                                    .headers()
+                                    .get(http::header::CONTENT_LENGTH)
+                                    .and_then(|v| v.to_str().ok())
+                                    .and_then(|v| v.parse().ok());
+                                access_log.record(&access_log::AccessEvent::now(
+                                    method.to_string(),
+                                    host,
+                                    path,
+                                    status.as_u16(),
+                                    duration,
+                                    bytes,
+                                    tenant,
+                                ).with_request_id(request_id));
+                            }
                         }
+                        response
                     }
-                    response
                 }
             )
         );
 
-    let web_routes = web::web_routes().layer(common_layers.clone());
+    let web_routes = web::web_routes(&global_state().config)?.layer(common_layers.clone());
     let cdn_routes = cdn::routes().layer(common_layers.clone());
 
     let app = {
@@ -640,3 +776,119 @@ pub fn h_to_axum(hrep: HReply) -> LegacyReply {
         HError::Internal { err } => LegacyHttpError::Internal { err },
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_types::TenantConfig;
+    use host_extract::ExtractedHost;
+
+    fn opened(open_behavior: &OpenBehavior) -> Vec<String> {
+        let mut opened = Vec::new();
+        open_on_start(
+            open_behavior,
+            || Some("http://a.example.localhost:1111".to_string()),
+            |url| {
+                opened.push(url.to_string());
+                Ok(())
+            },
+        );
+        opened
+    }
+
+    #[test]
+    fn test_open_behavior() {
+        assert_eq!(
+            opened(&OpenBehavior::OpenOnStart),
+            vec!["http://a.example.localhost:1111"]
+        );
+        assert!(opened(&OpenBehavior::DontOpen).is_empty());
+
+        let draft = "http://b.example.localhost:1111/articles/draft?preview=1#intro";
+        let behavior = OpenBehavior::open_url(draft).unwrap();
+        assert_eq!(opened(&behavior), vec![draft]);
+
+        for bad in [
+            "b.example.localhost:1111/articles",
+            "/articles",
+            "file:///etc/passwd",
+        ] {
+            assert!(OpenBehavior::open_url(bad).is_err(), "{bad}");
+        }
+    }
+
+    fn tenant(name: &str, aliases: &[&str]) -> Arc<CubTenantImpl> {
+        let mut tc = TenantConfig::new(TenantDomain::new(name.to_string()));
+        tc.domain_aliases = aliases
+            .iter()
+            .map(|alias| TenantDomain::new(alias.to_string()))
+            .collect();
+        CubTenantImpl::for_tests(tc)
+    }
+
+    fn resolve(
+        resolutions: &HashMap<TenantDomain, DomainResolution>,
+        host: &str,
+    ) -> Option<DomainResolution> {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::HOST, host.parse().unwrap());
+        ExtractedHost::from_headers(&"/".parse().unwrap(), &headers)
+            .unwrap()
+            .resolve_in(resolutions)
+    }
+
+    #[test]
+    fn test_cdn_alias_serves_assets() {
+        let ts = tenant("fasterthanli.me", &["ftl.me"]);
+        let resolutions = domain_resolutions(&ts, Environment::Production)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        assert_eq!(resolutions.len(), 4);
+
+        for host in [
+            "fasterthanli.me",
+            "cdn.fasterthanli.me",
+            "cdn.ftl.me",
+            "cdn.ftl.me:443",
+        ] {
+            match resolve(&resolutions, host) {
+                Some(DomainResolution::Tenant(resolved)) => assert!(Arc::ptr_eq(&resolved, &ts)),
+                _ => panic!("{host} should serve the tenant"),
+            }
+        }
+
+        match resolve(&resolutions, "ftl.me") {
+            Some(DomainResolution::Redirect {
+                target_domain,
+                tenant,
+            }) => {
+                assert_eq!(target_domain.as_str(), "fasterthanli.me");
+                assert!(Arc::ptr_eq(&tenant, &ts));
+            }
+            _ => panic!("web aliases should redirect"),
+        }
+
+        assert!(resolve(&resolutions, "cdn.cdn.ftl.me").is_none());
+    }
+
+    #[test]
+    fn test_cdn_alias_in_development() {
+        let ts = tenant("fasterthanli.me", &["ftl.me"]);
+        let resolutions = domain_resolutions(&ts, Environment::Development)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert!(matches!(
+            resolve(&resolutions, "cdn.fasterthanli.me.localhost:1111"),
+            Some(DomainResolution::Tenant(_))
+        ));
+        assert!(matches!(
+            resolve(&resolutions, "cdn.ftl.me:1111"),
+            Some(DomainResolution::Tenant(_))
+        ));
+        assert!(matches!(
+            resolve(&resolutions, "ftl.me:1111"),
+            Some(DomainResolution::Redirect { target_domain, .. }) if target_domain.as_str() == "fasterthanli.me.localhost"
+        ));
+    }
+}
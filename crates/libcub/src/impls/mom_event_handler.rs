@@ -1,22 +1,53 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use config_types::{WebConfig, is_development};
+use config_types::{TenantDomain, WebConfig, is_development};
 use conflux::{Pak, PathMappings};
 use cub_types::CubTenant;
-use mom_types::{AllUsers, MomEvent, TenantEventPayload};
-use tokio::sync::mpsc;
+use mom_types::{AllUsers, GoodMorning, MomEvent, TenantEventPayload, UsersDelta};
+use tokio::{sync::mpsc, time::Instant};
 
 use super::{global_state, types::CubTenantImpl};
 
+/// How long we hold on to users updates before swapping them in, so that
+/// a burst of updates only takes the write lock once.
+const USERS_COALESCE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Past this many users, we log a warning on every update
+const USERS_SOFT_LIMIT: usize = 50_000;
+
+/// Past this many users, we refuse the update and keep the previous users
+const USERS_HARD_LIMIT: usize = 500_000;
+
 pub(crate) fn spawn_mom_event_handler(mut mev_rx: mpsc::Receiver<MomEvent>, web: WebConfig) {
     tokio::spawn(async move {
+        let mut pending_users: HashMap<TenantDomain, (Arc<CubTenantImpl>, UsersCoalescer)> =
+            Default::default();
+
         loop {
-            let ev = mev_rx.recv().await.unwrap();
+            let flush_deadline = pending_users
+                .values()
+                .filter_map(|(_, coalescer)| coalescer.flush_deadline())
+                .min();
+
+            let ev = tokio::select! {
+                ev = mev_rx.recv() => ev.unwrap(),
+                _ = sleep_until_deadline(flush_deadline) => {
+                    flush_users(&mut pending_users, Instant::now());
+                    continue;
+                }
+            };
+
             match ev {
-                MomEvent::GoodMorning(_gm) => {
+                MomEvent::GoodMorning(gm) => {
                     log::warn!(
                         "Received a good morning later than expected. Probably we got reconnected."
                     );
+                    let tenants = global_state::global_state()
+                        .dynamic
+                        .read()
+                        .tenants_by_name
+                        .clone();
+                    apply_missed_cookie_sauces(&tenants, &gm);
                 }
                 MomEvent::TenantEvent(ev) => {
                     let tn = &ev.tenant_name;
@@ -34,30 +65,163 @@ pub(crate) fn spawn_mom_event_handler(mut mev_rx: mpsc::Receiver<MomEvent>, web:
                         }
                     };
 
-                    handle_tenant_event(ts, ev.payload, web).await;
+                    match ev.payload {
+                        TenantEventPayload::UsersUpdated(users) => {
+                            let (_, coalescer) = pending_users
+                                .entry(tn.clone())
+                                .or_insert_with(|| (ts, Default::default()));
+                            coalescer.push_snapshot(users, Instant::now());
+                        }
+                        TenantEventPayload::UsersDelta(delta) => {
+                            let (ts, coalescer) = pending_users
+                                .entry(tn.clone())
+                                .or_insert_with(|| (ts, Default::default()));
+                            let current = ts.users();
+                            coalescer.push_delta(&current, delta, Instant::now());
+                        }
+                        TenantEventPayload::RevisionChanged(pak) => {
+                            handle_revision_changed(ts, pak, web).await;
+                        }
+                        TenantEventPayload::CookieSauceRotated(cookie_sauce) => {
+                            log::warn!("[{tn}] Cookie sauce rotated, everyone is logged out");
+                            ts.rotate_cookie_key(&cookie_sauce);
+                        }
+                    }
                 }
             }
         }
     });
 }
 
-async fn handle_tenant_event(
-    ts: Arc<CubTenantImpl>,
-    payload: mom_types::TenantEventPayload,
-    web: WebConfig,
+/// A cookie sauce rotation that happened while we were disconnected only
+/// shows up in the good morning we get on reconnect
+fn apply_missed_cookie_sauces(
+    tenants: &HashMap<TenantDomain, Arc<CubTenantImpl>>,
+    gm: &GoodMorning,
+) {
+    for (tn, state) in &gm.initial_states {
+        let Some(ts) = tenants.get(tn) else {
+            continue;
+        };
+        let Some(cookie_sauce) = state
+            .tc
+            .secrets
+            .as_ref()
+            .and_then(|secrets| secrets.cookie_sauce.as_deref())
+        else {
+            continue;
+        };
+        if ts.rotate_cookie_key_if_changed(cookie_sauce) {
+            log::warn!(
+                "[{tn}] Cookie sauce rotated while we were disconnected, everyone is logged out"
+            );
+        }
+    }
+}
+
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Swaps in users for every tenant whose coalescing window has elapsed
+fn flush_users(
+    pending_users: &mut HashMap<TenantDomain, (Arc<CubTenantImpl>, UsersCoalescer)>,
+    now: Instant,
 ) {
-    match payload {
-        TenantEventPayload::UsersUpdated(users) => {
-            handle_users_updated(ts, users);
+    for (ts, coalescer) in pending_users.values_mut() {
+        let due = coalescer
+            .flush_deadline()
+            .is_some_and(|deadline| deadline <= now);
+        if !due {
+            continue;
+        }
+        if let Some(users) = coalescer.take(&ts.ti.tc.name) {
+            *ts.users.write() = users;
         }
-        TenantEventPayload::RevisionChanged(pak) => {
-            handle_revision_changed(ts, pak, web).await;
+    }
+}
+
+/// Accumulates users snapshots and deltas for a single tenant, so they can be
+/// applied with a single write.
+struct UsersCoalescer {
+    /// users with all pending updates applied
+    pending: Option<AllUsers>,
+
+    /// when the first of the pending updates was received
+    first_pending_at: Option<Instant>,
+
+    /// how many updates were folded into `pending`
+    pending_count: usize,
+
+    /// updates with more users than this are refused
+    hard_limit: usize,
+}
+
+impl Default for UsersCoalescer {
+    fn default() -> Self {
+        Self {
+            pending: None,
+            first_pending_at: None,
+            pending_count: 0,
+            hard_limit: USERS_HARD_LIMIT,
         }
     }
 }
 
-fn handle_users_updated(ts: Arc<CubTenantImpl>, users: Arc<AllUsers>) {
-    *ts.users.write() = users;
+impl UsersCoalescer {
+    fn push_snapshot(&mut self, users: Arc<AllUsers>, now: Instant) {
+        self.pending = Some(Arc::unwrap_or_clone(users));
+        self.mark_pending(now);
+    }
+
+    /// `current` is what's currently swapped in — deltas apply on top of it,
+    /// unless there are updates pending already.
+    fn push_delta(&mut self, current: &AllUsers, delta: UsersDelta, now: Instant) {
+        self.pending
+            .get_or_insert_with(|| current.clone())
+            .apply_delta(delta);
+        self.mark_pending(now);
+    }
+
+    fn mark_pending(&mut self, now: Instant) {
+        self.first_pending_at.get_or_insert(now);
+        self.pending_count += 1;
+    }
+
+    /// When pending updates should be swapped in, if there are any. This
+    /// doesn't move as more updates come in, so a steady stream of updates
+    /// still gets applied regularly.
+    fn flush_deadline(&self) -> Option<Instant> {
+        self.first_pending_at
+            .map(|first| first + USERS_COALESCE_WINDOW)
+    }
+
+    /// Takes the pending users, if any and if they're within limits
+    fn take(&mut self, tn: &TenantDomain) -> Option<Arc<AllUsers>> {
+        let users = self.pending.take()?;
+        let pending_count = std::mem::take(&mut self.pending_count);
+        self.first_pending_at = None;
+
+        let count = users.users.len();
+        if count > self.hard_limit {
+            log::error!(
+                "[{tn}] Refusing users update with {count} users (limit is {}), keeping previous users",
+                self.hard_limit
+            );
+            return None;
+        }
+        if count > USERS_SOFT_LIMIT {
+            log::warn!("[{tn}] Tracking {count} users, that's more than {USERS_SOFT_LIMIT}");
+        }
+        if pending_count > 1 {
+            log::debug!("[{tn}] Coalesced {pending_count} users updates ({count} users)");
+        }
+
+        Some(Arc::new(users))
+    }
 }
 
 async fn handle_revision_changed(ts: Arc<CubTenantImpl>, pak: Box<Pak>, web: WebConfig) {
@@ -89,3 +253,148 @@ async fn handle_revision_changed(ts: Arc<CubTenantImpl>, pak: Box<Pak>, web: Web
     };
     ts.switch_to(rev);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls::types::cookie_key_from_sauce;
+    use config_types::{AwsSecrets, TenantConfig, TenantSecrets};
+    use credentials::{UserId, UserInfo};
+    use mom_types::TenantInitialState;
+    use time::OffsetDateTime;
+
+    fn good_morning(tn: &TenantDomain, cookie_sauce: &str) -> GoodMorning {
+        let mut tc = TenantConfig::new(tn.clone());
+        tc.secrets = Some(TenantSecrets {
+            aws: AwsSecrets {
+                access_key_id: "AKIA".to_string(),
+                secret_access_key: "sekrit".to_string(),
+            },
+            patreon: None,
+            github: None,
+            discord: None,
+            stripe: None,
+            git: None,
+            cookie_sauce: Some(cookie_sauce.to_string()),
+        });
+        GoodMorning {
+            initial_states: [(
+                tn.clone(),
+                TenantInitialState {
+                    pak: None,
+                    users: Default::default(),
+                    tc,
+                    base_dir: None,
+                },
+            )]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn test_good_morning_applies_missed_cookie_sauce() {
+        let tn = TenantDomain::new("example.org".to_string());
+        let ts = CubTenantImpl::for_tests(TenantConfig::new(tn.clone()));
+        let tenants = [(tn.clone(), ts.clone())].into();
+
+        // same sauce as we have: nobody gets logged out
+        apply_missed_cookie_sauces(&tenants, &good_morning(&tn, "sauce"));
+        assert_eq!(
+            ts.cookie_key().master(),
+            cookie_key_from_sauce("sauce").master()
+        );
+
+        apply_missed_cookie_sauces(&tenants, &good_morning(&tn, "fresh-sauce"));
+        assert_eq!(
+            ts.cookie_key().master(),
+            cookie_key_from_sauce("fresh-sauce").master()
+        );
+        assert!(!ts.rotate_cookie_key_if_changed("fresh-sauce"));
+    }
+
+    fn user(id: usize) -> UserInfo {
+        UserInfo {
+            id: UserId::new(id.to_string()),
+            fetched_at: OffsetDateTime::now_utc(),
+            patreon: None,
+            github: None,
+            discord: None,
+            in_discord: false,
+            gifted_tier: None,
+        }
+    }
+
+    fn delta_adding(id: usize) -> UsersDelta {
+        let u = user(id);
+        UsersDelta {
+            upserted: [(u.id.clone(), u)].into_iter().collect(),
+            removed: vec![],
+        }
+    }
+
+    #[test]
+    fn test_rapid_updates_are_coalesced() {
+        let tn = TenantDomain::new("example.org".to_string());
+        let current = AllUsers::default();
+        let mut coalescer = UsersCoalescer::default();
+        assert!(coalescer.flush_deadline().is_none());
+
+        let start = Instant::now();
+        for i in 0..100 {
+            let now = start + Duration::from_millis(i as u64);
+            coalescer.push_delta(&current, delta_adding(i), now);
+        }
+
+        // the deadline is anchored on the first update
+        assert_eq!(
+            coalescer.flush_deadline(),
+            Some(start + USERS_COALESCE_WINDOW)
+        );
+
+        // a single write gets all of them
+        let users = coalescer.take(&tn).unwrap();
+        assert_eq!(users.users.len(), 100);
+        assert!(coalescer.take(&tn).is_none());
+        assert!(coalescer.flush_deadline().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_then_deltas_are_coalesced() {
+        let tn = TenantDomain::new("example.org".to_string());
+        let current = AllUsers::default();
+        let mut coalescer = UsersCoalescer::default();
+        let now = Instant::now();
+
+        coalescer.push_delta(&current, delta_adding(1), now);
+        let snapshot = AllUsers {
+            users: [user(2), user(3)]
+                .into_iter()
+                .map(|u| (u.id.clone(), u))
+                .collect(),
+        };
+        // snapshot replaces whatever was pending
+        coalescer.push_snapshot(Arc::new(snapshot), now);
+        // later deltas apply on top of the pending snapshot, not `current`
+        coalescer.push_delta(&current, delta_adding(4), now);
+
+        let users = coalescer.take(&tn).unwrap();
+        let mut ids: Vec<_> = users.users.keys().map(|id| id.to_string()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_hard_limit_keeps_previous_users() {
+        let tn = TenantDomain::new("example.org".to_string());
+        let mut coalescer = UsersCoalescer {
+            hard_limit: 10,
+            ..Default::default()
+        };
+        let huge = AllUsers {
+            users: (0..=10).map(user).map(|u| (u.id.clone(), u)).collect(),
+        };
+        coalescer.push_snapshot(Arc::new(huge), Instant::now());
+        assert!(coalescer.take(&tn).is_none());
+        assert!(coalescer.flush_deadline().is_none());
+    }
+}
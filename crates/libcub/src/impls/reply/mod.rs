@@ -1,10 +1,10 @@
 use axum::{
     body::Body,
     extract::{FromRequest, Request},
-    http::{HeaderName, StatusCode, header},
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
-use config_types::is_production;
+use config_types::{is_development, is_production};
 use conflux::RevisionError;
 use content_type::ContentType;
 use eyre::Report;
@@ -191,6 +191,9 @@ impl LegacyHttpError {
                 r#"<pre class="trace home-ansi">{err_string}<details><summary>Backtrace</summary><div class="backtrace">{backtrace}</div></details></pre>"#
             )
         };
+        if is_development() {
+            trace_content.push_str(&request_logs_html());
+        }
         if is_production() {
             trace_content = "".into();
         }
@@ -321,6 +324,23 @@ impl LegacyHttpError {
     }
 }
 
+/// The logs captured for the current request (see `CaptureLogsLayer`), if any
+fn request_logs_html() -> String {
+    let Some(capture) = skelly::current_log_capture() else {
+        return String::new();
+    };
+    let lines = capture.lines();
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    let logs = libterm::load().format_ansi(&lines.join("\n"), FormatAnsiStyle::Html);
+    format!(
+        r#"<details class="request-logs"><summary>Logs for this request ({count} lines)</summary><pre class="trace home-ansi">{logs}</pre></details>"#,
+        count = lines.len()
+    )
+}
+
 macro_rules! impl_from {
     ($from:ty) => {
         impl From<$from> for LegacyHttpError {
@@ -380,20 +400,35 @@ impl LegacyHttpError {
     }
 }
 
-/// The two genders^W cache-control header: cache forever or don't cache at all.
-pub enum ClientCachePolicy {
-    // the URL is cache-busted (it includes the hash bit of the hapa), so we can send a long max-age
-    CacheBasicallyForever,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl ClientCachePolicy {
-    pub fn to_max_age(&self) -> &'static str {
-        match self {
-            ClientCachePolicy::CacheBasicallyForever => "max-age=31536000",
-        }
+    #[tokio::test]
+    async fn test_dev_error_page_includes_request_logs() {
+        let _guard = skelly::setup_for_tests();
+
+        let capture = skelly::LogCapture::default();
+        let err = skelly::capture_logs(capture, async {
+            log::info!("looking up the widget");
+            LegacyHttpError::from(eyre::eyre!("widget not found"))
+        })
+        .await;
+
+        let LegacyHttpError::Internal { err: body } = err else {
+            panic!("expected an internal error, got {err:?}");
+        };
+        assert!(body.contains("Logs for this request"), "{body}");
+        assert!(body.contains("looking up the widget"), "{body}");
     }
 
-    pub fn to_header_tuple(&self) -> (HeaderName, &'static str) {
-        (header::CACHE_CONTROL, self.to_max_age())
+    #[test]
+    fn test_error_page_without_capture_has_no_logs() {
+        let LegacyHttpError::Internal { err: body } =
+            LegacyHttpError::from(eyre::eyre!("widget not found"))
+        else {
+            panic!("expected an internal error");
+        };
+        assert!(!body.contains("Logs for this request"));
     }
 }
@@ -0,0 +1,146 @@
+use std::{sync::Arc, time::Duration};
+
+use config_types::{Environment, TenantDomain};
+use futures_util::future::join_all;
+use libobjectstore::ObjectStore;
+use log::{info, warn};
+
+/// How long a tenant's object store gets to answer the startup probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Probes every tenant's object store at once, so that bad `AwsSecrets` or
+/// `ObjectStorageConfig` show up at startup rather than on the first asset
+/// request. In production, any failure is an error; in development, it's a
+/// warning and we carry on.
+pub(crate) async fn probe_object_stores(
+    stores: &[(TenantDomain, Arc<dyn ObjectStore>)],
+    env: Environment,
+) -> eyre::Result<()> {
+    let results = join_all(stores.iter().map(|(tn, store)| async move {
+        let res = match tokio::time::timeout(PROBE_TIMEOUT, store.probe()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no answer after {PROBE_TIMEOUT:?}")),
+        };
+        (tn, res)
+    }))
+    .await;
+
+    let failures = results
+        .into_iter()
+        .filter_map(|(tn, res)| res.err().map(|e| format!("[{tn}] {e}")))
+        .collect::<Vec<_>>();
+    if failures.is_empty() {
+        info!("Probed {} object stores, all good", stores.len());
+        return Ok(());
+    }
+
+    if env.is_prod() {
+        eyre::bail!(
+            "Object store probe failed for {} tenant(s):\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+    for failure in &failures {
+        warn!("Object store probe failed, assets won't load: {failure}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_core::future::BoxFuture;
+    use libobjectstore::{
+        Bytes, Error, ErrorKind, GetOptions, GetResult, MultipartUpload, PutMultipartOptions,
+        PutOptions, PutResult, Result,
+    };
+    use objectstore_types::ObjectStoreKeyRef;
+
+    /// Turns every request down, like S3 does with a bad access key
+    struct RejectingStore;
+
+    fn rejected<T: 'static>() -> BoxFuture<'static, Result<T>> {
+        Box::pin(async {
+            Err(Error {
+                kind: ErrorKind::Other,
+                source: "InvalidAccessKeyId: The AWS Access Key Id you provided does not exist"
+                    .into(),
+            })
+        })
+    }
+
+    impl ObjectStore for RejectingStore {
+        fn put_opts(
+            &self,
+            _key: &ObjectStoreKeyRef,
+            _payload: Bytes,
+            _opts: PutOptions,
+        ) -> BoxFuture<'_, Result<PutResult>> {
+            rejected()
+        }
+
+        fn put_multipart_opts(
+            &self,
+            _key: &ObjectStoreKeyRef,
+            _opts: PutMultipartOptions,
+        ) -> BoxFuture<'_, Result<Box<dyn MultipartUpload>>> {
+            rejected()
+        }
+
+        fn get_opts(
+            &self,
+            _key: &ObjectStoreKeyRef,
+            _opts: GetOptions,
+        ) -> BoxFuture<'_, Result<Box<dyn GetResult>>> {
+            rejected()
+        }
+
+        fn desc(&self) -> String {
+            "rejecting".to_string()
+        }
+    }
+
+    fn stores(bad: &[&str]) -> Vec<(TenantDomain, Arc<dyn ObjectStore>)> {
+        ["alice.example.org", "bob.example.org"]
+            .into_iter()
+            .map(|tn| {
+                let store: Arc<dyn ObjectStore> = if bad.contains(&tn) {
+                    Arc::new(RejectingStore)
+                } else {
+                    libobjectstore::load().in_memory()
+                };
+                (TenantDomain::new(tn.to_string()), store)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_accepting_stores_pass() {
+        let stores = stores(&[]);
+        probe_object_stores(&stores, Environment::Production)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejecting_store_fails_in_production() {
+        let stores = stores(&["bob.example.org"]);
+        let err = probe_object_stores(&stores, Environment::Production)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[bob.example.org] "), "{err}");
+        assert!(err.contains("InvalidAccessKeyId"), "{err}");
+        assert!(!err.contains("alice.example.org"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_rejecting_store_only_warns_in_development() {
+        let stores = stores(&["alice.example.org", "bob.example.org"]);
+        probe_object_stores(&stores, Environment::Development)
+            .await
+            .unwrap();
+    }
+}
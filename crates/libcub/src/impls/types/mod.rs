@@ -2,7 +2,7 @@ use config_types::{
     CubConfig, TenantConfig, TenantDomain, TenantInfo, WebConfig, is_development, is_production,
 };
 use conflux::{RevisionError, RevisionId};
-use cub_types::{CubRevisionState, CubTenant, IndexedRevision};
+use cub_types::{CubRevisionState, CubTenant, DerivationCache, IndexedRevision};
 use facet::Facet;
 use hattip::prelude::BoxFuture;
 use libmomclient::{MomClient, MomTenantClient};
@@ -14,7 +14,7 @@ use template_types::TemplateCollection;
 use tokio::sync::broadcast;
 use tower_cookies::Key;
 
-use super::{global_state, vite::start_vite};
+use super::{config_reload::ReloadableConfig, global_state, vite::start_vite};
 
 #[derive(Facet, Clone)]
 #[repr(u8)]
@@ -38,9 +38,12 @@ pub enum DomainResolution {
 }
 
 pub struct CubGlobalState {
-    /// config
+    /// config, as of startup
     pub config: CubConfig,
 
+    /// the settings a SIGHUP can change
+    pub reloadable: RwLock<ReloadableConfig>,
+
     /// web config
     pub web: WebConfig,
 
@@ -50,6 +53,9 @@ pub struct CubGlobalState {
     /// shared mom deploy client
     pub mom_deploy_client: Arc<dyn MomClient>,
 
+    /// derivations the CDN keeps in memory, for all tenants
+    pub mem_cache: DerivationCache,
+
     /// this state can be updated by mom's messages (adding/removing tenants etc.)
     pub dynamic: Arc<RwLock<CubDynamicState>>,
 }
@@ -96,7 +102,8 @@ impl CubGlobalState {
 }
 
 pub struct CubTenantImpl {
-    pub cookie_key: Key,
+    /// swapped out when mom rotates the tenant's cookie sauce
+    pub cookie_key: RwLock<Key>,
     pub users: RwLock<Arc<AllUsers>>,
     pub ti: Arc<TenantInfo>,
     pub store: Arc<dyn ObjectStore>,
@@ -105,6 +112,57 @@ pub struct CubTenantImpl {
     pub vite_port: tokio::sync::OnceCell<Result<u16, String>>,
 }
 
+/// Derives the key auth cookies are encrypted with from a tenant's cookie sauce
+pub fn cookie_key_from_sauce(cookie_sauce: &str) -> Key {
+    let sauce_repetitions = (32 / cookie_sauce.len()) + 1;
+    let cookie_master_key = cookie_sauce.as_bytes().repeat(sauce_repetitions);
+    Key::derive_from(&cookie_master_key)
+}
+
+impl CubTenantImpl {
+    /// A tenant with no revision and an in-memory object store
+    #[cfg(test)]
+    pub(crate) fn for_tests(tc: TenantConfig) -> Arc<Self> {
+        Arc::new(Self {
+            cookie_key: RwLock::new(cookie_key_from_sauce("sauce")),
+            users: Default::default(),
+            ti: Arc::new(TenantInfo {
+                base_dir: format!("/tmp/{}", tc.name).into(),
+                tc,
+            }),
+            store: libobjectstore::load().in_memory(),
+            bx_rev: broadcast::channel(1).0,
+            rev_state: RwLock::new(CubRevisionState {
+                rev: None,
+                err: None,
+            }),
+            vite_port: Default::default(),
+        })
+    }
+
+    pub fn cookie_key(&self) -> Key {
+        self.cookie_key.read().clone()
+    }
+
+    /// Switches to a key derived from `cookie_sauce`: cookies encrypted with
+    /// the previous key stop decrypting, so everyone is logged out.
+    pub fn rotate_cookie_key(&self, cookie_sauce: &str) {
+        *self.cookie_key.write() = cookie_key_from_sauce(cookie_sauce);
+    }
+
+    /// Rotates the cookie key only if `cookie_sauce` isn't the one we're
+    /// already using. Returns whether it did.
+    pub fn rotate_cookie_key_if_changed(&self, cookie_sauce: &str) -> bool {
+        let key = cookie_key_from_sauce(cookie_sauce);
+        let mut current = self.cookie_key.write();
+        if current.master() == key.master() {
+            return false;
+        }
+        *current = key;
+        true
+    }
+}
+
 impl CubTenant for CubTenantImpl {
     fn tc(&self) -> &TenantConfig {
         &self.ti.tc
@@ -15,6 +15,7 @@ pub(crate) fn admin_routes() -> Router {
     Router::new()
         .route("/all-users", get(serve_all_users))
         .route("/opendoor", post(serve_opendoor))
+        .route("/request-revision", post(serve_request_revision))
         .layer(axum::middleware::from_fn(
             |req: axum::http::Request<Body>, next: axum::middleware::Next| async move {
                 let tr = req.extensions().get::<CubReqImpl>();
@@ -43,6 +44,16 @@ async fn serve_all_users(_tr: CubReqImpl) -> LegacyReply {
     FacetJson(allusers).into_legacy_reply()
 }
 
+/// Asks mom to send us this tenant's revision again, e.g. after it failed to load
+async fn serve_request_revision(tr: CubReqImpl) -> LegacyReply {
+    log::info!(
+        "[{}] Requesting current revision from mom",
+        tr.tenant.ti().tc.name
+    );
+    tr.tenant.tcli().request_revision().await?;
+    StatusCode::ACCEPTED.into_legacy_reply()
+}
+
 async fn serve_opendoor(_tr: CubReqImpl, body: Body) -> LegacyReply {
     log::info!("serve_opendoor: starting request");
     let tcli = _tr.tenant.tcli();
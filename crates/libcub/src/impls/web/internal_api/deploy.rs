@@ -1,24 +1,35 @@
 use crate::impls::{CubTenantImpl, cub_req::CubReqImpl, global_state};
-use axum::extract::ws;
+use axum::{body::Bytes, extract::ws};
 use camino::Utf8PathBuf;
 use config_types::{TenantDomain, WebConfig};
 use conflux::{InputPath, Pak, PathMappings};
 use cub_types::{CubTenant, PathMetadata};
 use facet::Facet;
-use libmomclient::MomTenantClient;
+use libmomclient::{AssetUploadProgress, MomTenantClient, put_assets_concurrent};
 use librevision::{InputEvent, RevisionKind, RevisionSpec};
 use libterm::FormatAnsiStyle;
 use mom_types::ListMissingArgs;
-use std::{collections::VecDeque, sync::Arc, time::Instant};
+use objectstore_types::ObjectStoreKey;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
 use tokio::io::AsyncBufReadExt;
 
+/// `?dry_run=1` goes through the whole deploy, but only reports what would
+/// be uploaded instead of uploading it.
 pub(super) async fn serve(
     ws: axum::extract::WebSocketUpgrade,
+    query: axum::extract::Query<HashMap<String, String>>,
     tr: CubReqImpl,
 ) -> impl axum::response::IntoResponse {
     let ts = tr.tenant.clone();
     let web = global_state().web;
-    ws.on_upgrade(move |ws| handle_deploy_socket(ws, ts, web))
+    let dry_run = query
+        .get("dry_run")
+        .is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+    ws.on_upgrade(move |ws| handle_deploy_socket(ws, ts, web, dry_run))
 }
 
 #[derive(Debug, Facet)]
@@ -114,8 +125,13 @@ async fn json_to_socket<'facet>(
     Ok(socket.send(ws::Message::text(json_string)).await?)
 }
 
-async fn handle_deploy_socket(mut socket: ws::WebSocket, ts: Arc<CubTenantImpl>, web: WebConfig) {
-    if let Err(e) = handle_deploy_socket_inner(&mut socket, ts, web).await {
+async fn handle_deploy_socket(
+    mut socket: ws::WebSocket,
+    ts: Arc<CubTenantImpl>,
+    web: WebConfig,
+    dry_run: bool,
+) {
+    if let Err(e) = handle_deploy_socket_inner(&mut socket, ts, web, dry_run).await {
         let error_message = DeployMessage::LogMessage(LogMessage {
             level: Level::Error,
             message: format!("Error: {e}"),
@@ -191,9 +207,7 @@ async fn run_vite_build_and_update_revision(
         .to_disk_path(&InputPath::from_static("/dist"))
         .expect("No mapping found for /dist path");
 
-    log::info!(
-        "[{tenant_name}] Using temporary build directory: {vite_build_dir}"
-    );
+    log::info!("[{tenant_name}] Using temporary build directory: {vite_build_dir}");
 
     // Run npm build command with real-time output streaming
     let mut command = tokio::process::Command::new("npx");
@@ -400,6 +414,7 @@ async fn handle_deploy_socket_inner(
     socket: &mut ws::WebSocket,
     tenant: Arc<CubTenantImpl>,
     web: WebConfig,
+    dry_run: bool,
 ) -> eyre::Result<()> {
     let tc = tenant.tc();
 
@@ -418,9 +433,13 @@ async fn handle_deploy_socket_inner(
 
     let gs = global_state();
 
-    log::info!("[{tenant_name}] Making mom tenant client");
-    let tcli: Arc<dyn MomTenantClient> =
-        Arc::from(gs.mom_deploy_client.mom_tenant_client(tenant_name.clone()));
+    log::info!("[{tenant_name}] Making mom tenant client (dry run: {dry_run})");
+    let tcli: Arc<dyn MomTenantClient> = Arc::from(if dry_run {
+        gs.mom_deploy_client
+            .mom_tenant_client_dry_run(tenant_name.clone())
+    } else {
+        gs.mom_deploy_client.mom_tenant_client(tenant_name.clone())
+    });
 
     log::info!("[{tenant_name}] Listing missing assets...");
     let missing_assets = tcli
@@ -437,7 +456,7 @@ async fn handle_deploy_socket_inner(
 
     let total_inputs = rev.pak.inputs.len();
     let missing_inputs = missing_assets.missing.len();
-    let mut uploaded_inputs = total_inputs - missing_inputs;
+    let uploaded_inputs = total_inputs - missing_inputs;
 
     if missing_inputs > 0 {
         json_to_socket(
@@ -445,7 +464,8 @@ async fn handle_deploy_socket_inner(
             &DeployMessage::LogMessage(LogMessage {
                 level: Level::Info,
                 message: format!(
-                    "Assets: {uploaded_inputs}/{total_inputs} already present, will upload {missing_inputs} new ones"
+                    "Assets: {uploaded_inputs}/{total_inputs} already present, {} {missing_inputs} new ones",
+                    if dry_run { "would upload" } else { "will upload" }
                 ),
             }),
         )
@@ -461,117 +481,108 @@ async fn handle_deploy_socket_inner(
     )
     .await?;
 
-    let (task_tx, task_rx) = flume::unbounded::<InputPath>();
-    let (result_tx, result_rx) = flume::unbounded::<eyre::Result<()>>();
-
-    const NUM_WORKERS: usize = 4;
-    for _ in 0..NUM_WORKERS {
-        let task_rx = task_rx.clone();
-        let result_tx = result_tx.clone();
-        let tcli = tcli.clone();
-        let rev = rev.clone();
-        let mappings = mappings.clone();
-
-        tokio::spawn(async move {
-            async fn handle_task(
-                key: InputPath,
-                curr_pak: &Pak,
-                tcli: &dyn MomTenantClient,
-                mappings: &PathMappings,
-            ) -> eyre::Result<()> {
-                let input = curr_pak
-                    .inputs
-                    .get(&key)
-                    .ok_or_else(|| eyre::eyre!("Input not found in revision for key: {}", key))?;
-                let disk_path = mappings.to_disk_path(&key)?;
-                log::debug!("Reading input file from disk path: {disk_path:?}");
-                let before_read = Instant::now();
-                let payload = match fs_err::tokio::read(&disk_path).await {
-                    Ok(data) => data,
-                    Err(e) => {
-                        return Err(eyre::eyre!(
-                            "Failed to read input file at {:?}: {}",
-                            disk_path,
-                            e
-                        ));
-                    }
-                };
-                let read_time = before_read.elapsed();
-
-                let expected_hash = librevision::load().input_hash_from_contents(&payload);
-                if expected_hash != input.hash {
-                    return Err(eyre::eyre!(
-                        "Hash mismatch for input {} (did things change on disk while we were deploying?): expected {:?}, got {:?}",
-                        key,
-                        input.hash,
-                        expected_hash
-                    ));
-                }
-
-                let before_upload = Instant::now();
-                tcli.put_asset(&input.key(), payload.into()).await?;
-                let upload_time = before_upload.elapsed();
-
-                log::info!(
-                    "Uploaded input {key:?} (read: {read_time:?}, upload: {upload_time:?})"
-                );
-
-                Ok(())
-            }
-
-            while let Ok(key) = task_rx.recv_async().await {
-                let result = handle_task(key.clone(), &rev.pak, tcli.as_ref(), &mappings).await;
-                result_tx.send(result).unwrap();
-            }
-        });
-    }
-    drop(result_tx);
-
-    // Send tasks to channel
-    for (_object_store_key, input_path) in missing_assets.missing {
-        task_tx.send(input_path).unwrap();
-    }
-    drop(task_tx);
-
-    let mut num_errors = 0;
-
-    while let Ok(res) = result_rx.recv_async().await {
-        match res {
-            Ok(_) => {
-                uploaded_inputs += 1;
-                json_to_socket(
-                    socket,
-                    &DeployMessage::AssetProgress(AssetProgress {
-                        uploaded: uploaded_inputs,
-                        total: total_inputs,
-                    }),
-                )
-                .await?;
-            }
+    /// Reads an input from disk, making sure it's still what the revision says it is
+    async fn load_input(
+        key: InputPath,
+        curr_pak: &Pak,
+        mappings: &PathMappings,
+    ) -> eyre::Result<(ObjectStoreKey, Bytes)> {
+        let input = curr_pak
+            .inputs
+            .get(&key)
+            .ok_or_else(|| eyre::eyre!("Input not found in revision for key: {}", key))?;
+        let disk_path = mappings.to_disk_path(&key)?;
+        log::debug!("Reading input file from disk path: {disk_path:?}");
+        let payload = match fs_err::tokio::read(&disk_path).await {
+            Ok(data) => data,
             Err(e) => {
-                num_errors += 1;
-                json_to_socket(
-                    socket,
-                    &DeployMessage::LogMessage(LogMessage {
-                        level: Level::Error,
-                        message: format!("{e}"),
-                    }),
-                )
-                .await?;
-                break;
+                return Err(eyre::eyre!(
+                    "Failed to read input file at {:?}: {}",
+                    disk_path,
+                    e
+                ));
             }
+        };
+
+        let expected_hash = librevision::load().input_hash_from_contents(&payload);
+        if expected_hash != input.hash {
+            return Err(eyre::eyre!(
+                "Hash mismatch for input {} (did things change on disk while we were deploying?): expected {:?}, got {:?}",
+                key,
+                input.hash,
+                expected_hash
+            ));
         }
+
+        Ok((input.key(), payload.into()))
     }
 
-    if num_errors > 0 {
-        return Err(eyre::eyre!(
-            "Encountered {} errors while uploading assets",
-            num_errors
-        ));
+    const UPLOAD_CONCURRENCY: usize = 4;
+    let items = missing_assets
+        .missing
+        .into_values()
+        .map(|input_path| load_input(input_path, &rev.pak, &mappings))
+        .collect::<Vec<_>>();
+
+    let (progress_tx, progress_rx) = flume::unbounded::<AssetUploadProgress>();
+    let upload = put_assets_concurrent(tcli.as_ref(), items, UPLOAD_CONCURRENCY, move |p| {
+        let _ = progress_tx.send(p);
+    });
+    let report = async {
+        let mut uploaded_bytes = 0;
+        while let Ok(p) = progress_rx.recv_async().await {
+            log::info!("Uploaded input {} (upload: {:?})", p.key, p.elapsed);
+            uploaded_bytes = p.uploaded_bytes;
+            json_to_socket(
+                socket,
+                &DeployMessage::AssetProgress(AssetProgress {
+                    uploaded: uploaded_inputs + p.uploaded_objects,
+                    total: total_inputs,
+                }),
+            )
+            .await?;
+        }
+        Ok::<_, eyre::Report>(uploaded_bytes)
+    };
+    let (upload_res, report_res) = tokio::join!(upload, report);
+    if let Err(e) = upload_res {
+        json_to_socket(
+            socket,
+            &DeployMessage::LogMessage(LogMessage {
+                level: Level::Error,
+                message: format!("{e:#}"),
+            }),
+        )
+        .await?;
+        return Err(e.wrap_err("Failed to upload assets"));
     }
+    let uploaded_bytes = report_res?;
 
     let mod_revision = librevision::load();
     let revpak = mod_revision.serialize_pak(&rev.pak);
+
+    if dry_run {
+        let formatted_assets = bytesize::ByteSize::b(uploaded_bytes as u64).display().iec();
+        let formatted_revpak = bytesize::ByteSize::b(revpak.len() as u64).display().iec();
+        json_to_socket(
+            socket,
+            &DeployMessage::LogMessage(LogMessage::info(format!(
+                "Dry run: would upload {missing_inputs} assets ({formatted_assets}) and a revision package ({formatted_revpak}). Nothing was deployed."
+            ))),
+        )
+        .await?;
+        json_to_socket(
+            socket,
+            &DeployMessage::DeployComplete(DeployComplete {
+                // nothing got deployed
+                complete: false,
+                domain: tenant.tc().name.clone(),
+            }),
+        )
+        .await?;
+        return Ok(());
+    }
+
     let revpak_size = revpak.len();
     let formatted_size = bytesize::ByteSize::b(revpak_size as u64).display().iec();
     json_to_socket(
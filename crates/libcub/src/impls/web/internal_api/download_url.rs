@@ -3,7 +3,10 @@ use config_types::is_development;
 use http::{StatusCode, Uri};
 use std::collections::HashMap;
 
-use crate::impls::reply::{IntoLegacyReply, LegacyHttpError, LegacyReply};
+use crate::impls::{
+    global_state,
+    reply::{IntoLegacyReply, LegacyHttpError, LegacyReply},
+};
 
 pub(crate) async fn download_url(
     query: axum::extract::Query<HashMap<String, String>>,
@@ -48,7 +51,10 @@ pub(crate) async fn download_url(
         .cloned()
         .unwrap_or_else(|| "application/octet-stream".to_string());
 
-    let body = match response.bytes().await {
+    // whatever we download ends up uploaded as an asset
+    let limit = global_state().config.body_limits.uploads.as_u64();
+    let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+    let body = match response.bytes_limited(limit).await {
         Ok(b) => b,
         Err(e) => {
             return LegacyHttpError::with_status(
@@ -1,9 +1,9 @@
 use crate::impls::{
     credentials::{auth_bundle_as_cookie, auth_bundle_remove_cookie},
     cub_req::{CubReqImpl, RenderArgs},
-    reply::{IntoLegacyReply, LegacyReply},
+    reply::{IntoLegacyReply, LegacyHttpError, LegacyReply},
 };
-use axum::{Form, Router, response::Redirect, routing::get};
+use axum::{Form, Router, http::StatusCode, response::Redirect, routing::get};
 use config_types::is_development;
 use credentials::{AuthBundle, GithubProfile, GithubUserId, PatreonProfile, UserId, UserInfo};
 use cub_types::{CubReq, CubTenant};
@@ -76,10 +76,37 @@ async fn serve_login_with_github(tr: CubReqImpl, params: Form<LoginParams>) -> L
     } else {
         GithubLoginPurpose::Regular
     };
+    set_github_purpose_cookie(&tr.cookies(), purpose);
     let location = libgithub::load().make_login_url(tr.tenant.tc(), tr.web(), purpose)?;
     Redirect::to(&location).into_legacy_reply()
 }
 
+const GITHUB_PURPOSE_COOKIE: &str = "github_login_purpose";
+
+/// Remembers which scopes we asked GitHub for. This is a private cookie, so
+/// unlike the OAuth `state` parameter, the user can't tamper with it.
+fn set_github_purpose_cookie(cookies: &PrivateCookies<'_>, purpose: GithubLoginPurpose) {
+    let value = match purpose {
+        GithubLoginPurpose::Admin => "admin",
+        GithubLoginPurpose::Regular => "regular",
+    };
+    let mut cookie = Cookie::new(GITHUB_PURPOSE_COOKIE, value);
+    cookie.set_path("/");
+    cookie.set_expires(time::OffsetDateTime::now_utc() + time::Duration::minutes(30));
+    cookies.add(cookie);
+}
+
+/// Returns `None` if the login flow wasn't started from here (or too long ago)
+fn take_github_purpose_cookie(cookies: &PrivateCookies<'_>) -> Option<GithubLoginPurpose> {
+    let cookie = cookies.get(GITHUB_PURPOSE_COOKIE)?;
+    let purpose = match cookie.value() {
+        "admin" => GithubLoginPurpose::Admin,
+        _ => GithubLoginPurpose::Regular,
+    };
+    cookies.remove(cookie);
+    Some(purpose)
+}
+
 async fn serve_login_with_discord(tr: CubReqImpl, params: Form<LoginParams>) -> LegacyReply {
     log::info!("Initiating login with Discord");
     set_return_to_cookie(&tr.cookies(), &params);
@@ -119,6 +146,7 @@ async fn serve_patreon_callback_inner(tr: &CubReqImpl) -> eyre::Result<Option<Us
     let callback_args = PatreonCallbackArgs {
         raw_query: tr.raw_query().to_owned(),
         logged_in_user_id: tr.auth_bundle.as_ref().map(|ab| ab.user_info.id.clone()),
+        strict_scopes: tr.tenant.tc().strict_oauth_scopes,
     };
     let res = tcli.patreon_callback(&callback_args).await?;
     Ok(res.map(|res| res.user_info))
@@ -127,9 +155,17 @@ async fn serve_patreon_callback_inner(tr: &CubReqImpl) -> eyre::Result<Option<Us
 async fn serve_github_callback(tr: CubReqImpl) -> LegacyReply {
     let ts = tr.tenant.clone();
     let tcli = tr.tenant.tcli();
+    let Some(purpose) = take_github_purpose_cookie(&tr.cookies()) else {
+        return Err(LegacyHttpError::with_status(
+            StatusCode::BAD_REQUEST,
+            "GitHub login wasn't started from this site (or took too long), please log in again",
+        ));
+    };
     let callback_args = libgithub::GithubCallbackArgs {
         raw_query: tr.raw_query().to_owned(),
         logged_in_user_id: tr.auth_bundle.as_ref().map(|ab| ab.user_info.id.clone()),
+        purpose,
+        strict_scopes: ts.tc().strict_oauth_scopes,
     };
     let callback_res = tcli.github_callback(&callback_args).await?;
 
@@ -148,6 +184,7 @@ async fn serve_github_callback(tr: CubReqImpl) -> LegacyReply {
             } else {
                 // we need that scope for the patron list
                 info!("admin logged in, but missing read:org scope, redirecting to login page");
+                set_github_purpose_cookie(&tr.cookies(), GithubLoginPurpose::Admin);
                 let admin_login_url =
                     mod_github.make_login_url(&ts.ti.tc, tr.web(), GithubLoginPurpose::Admin)?;
                 return Redirect::to(&admin_login_url).into_legacy_reply();
@@ -167,6 +204,7 @@ async fn serve_discord_callback_inner(tr: &CubReqImpl) -> eyre::Result<Option<Us
     let callback_args = libdiscord::DiscordCallbackArgs {
         raw_query: tr.raw_query().to_owned(),
         logged_in_user_id: tr.auth_bundle.as_ref().map(|ab| ab.user_info.id.clone()),
+        strict_scopes: tr.tenant.tc().strict_oauth_scopes,
     };
     let res = tcli.discord_callback(&callback_args).await?;
     Ok(res.map(|res| res.user_info))
@@ -277,6 +315,8 @@ async fn serve_login_for_dev(tr: CubReqImpl) -> LegacyReply {
             tier: Some("dev".to_string()),
             full_name: "Dev User".to_string(),
             avatar_url: Some("https://placehold.co/32".to_string()),
+            email: None,
+            entitled_cents: None,
         }),
         github: github_id.map(|id| GithubProfile {
             id,
@@ -285,6 +325,7 @@ async fn serve_login_for_dev(tr: CubReqImpl) -> LegacyReply {
             name: Some("Dev User".to_string()),
             login: "devuser".to_string(),
             avatar_url: Some("https://placehold.co/32".to_string()),
+            email: None,
         }),
         discord: None,
         gifted_tier: None,
@@ -1,50 +1,116 @@
 mod api;
 mod internal_api;
 mod login;
+mod object_passthrough;
 mod tags;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use crate::impls::{
     cub_req::{CubReqImpl, RenderArgs},
-    reply::{ClientCachePolicy, IntoLegacyReply, LegacyHttpError, LegacyReply},
+    reply::{IntoLegacyReply, LegacyHttpError, LegacyReply},
 };
 
 use axum::{
-    Router,
-    extract::{ConnectInfo, Request},
+    Extension, Router,
+    body::Body,
+    extract::{ConnectInfo, DefaultBodyLimit, Request},
+    middleware::Next,
     response::{IntoResponse, Redirect},
     routing::get,
 };
 use camino::Utf8PathBuf;
 use closest::{GetOrHelp, ResourceKind};
-use config_types::is_development;
+use config_types::{
+    ByteSize, CacheCategory, CachePolicyConfig, CubConfig, GitProxyConfig, is_development,
+};
 use conflux::{AccessOverride, CacheBuster, InputPathRef, Viewer};
 use content_type::ContentType;
 use credentials::UserApiKey;
 use cub_types::{CubReq, CubTenant};
+use eyre::Context as _;
 use http::{
-    StatusCode,
-    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    HeaderValue, StatusCode,
+    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, X_CONTENT_TYPE_OPTIONS},
 };
 use mom_types::VerifyApiKeyArgs;
 use objectstore_types::ObjectStoreKey;
 use owo_colors::OwoColorize;
 
-pub(crate) fn web_routes() -> Router {
-    Router::new()
+/// Routes not listed here get `config.body_limits.web`, from the common layers
+pub(crate) fn web_routes(config: &CubConfig) -> eyre::Result<Router> {
+    let limits = &config.body_limits;
+    let extras_limit = limits.extras.as_u64();
+    let git_client = build_git_client(&config.git_proxy)?;
+    Ok(Router::new()
         .nest("/tags", tags::tag_routes())
         .nest("/login", login::login_routes())
-        .nest("/internal-api", internal_api::internal_api_routes())
+        .nest(
+            "/internal-api",
+            internal_api::internal_api_routes().layer(body_limit(limits.uploads)),
+        )
         .nest("/api", api::public_api_routes())
         .route("/robots.txt", get(robots_txt))
         .route("/whoami", get(whoami))
         .route("/index.xml", get(atom_feed))
         .route("/extra-files/{*path}", get(extra_files))
-        .route("/extras/{*path}", get(extras_git).post(extras_git))
+        .route(
+            "/extras/{*path}",
+            get(extras_git)
+                .post(extras_git)
+                .layer(Extension(git_client))
+                .layer(axum::middleware::from_fn(
+                    move |req: Request, next: Next| streamed_body_limit(extras_limit, req, next),
+                )),
+        )
         .route("/favicon.ico", get(favicon))
         .route("/", get(serve_page_route))
-        .route("/{*path}", get(serve_page_route))
+        .route("/{*path}", get(serve_page_route)))
+}
+
+/// Caps the size of request bodies read through extractors (`Bytes`,
+/// `Json`...) on the routes it's layered on. The innermost one wins.
+pub(crate) fn body_limit(size: ByteSize) -> DefaultBodyLimit {
+    DefaultBodyLimit::max(usize::try_from(size.as_u64()).unwrap_or(usize::MAX))
+}
+
+/// Caps the size of request bodies that get streamed along instead of read
+/// through extractors, which [`body_limit`] doesn't cover. Bodies that say
+/// they're too big get a 413 right away, the others fail once they get there.
+async fn streamed_body_limit(limit: u64, req: Request, next: Next) -> axum::response::Response {
+    let declared_len = req
+        .headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+    if declared_len.is_some_and(|len| len > limit) {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body is bigger than {limit} bytes"),
+        )
+            .into_response();
+    }
+
+    let (parts, body) = req.into_parts();
+    next.run(Request::from_parts(parts, limited_body(body, limit)))
+        .await
+}
+
+/// Passes `body` along as it comes in, erroring out past `limit` bytes
+fn limited_body(body: Body, limit: u64) -> Body {
+    use futures_util::StreamExt;
+
+    let mut read = 0u64;
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        read += chunk.len() as u64;
+        if read > limit {
+            return Err(std::io::Error::other(format!(
+                "request body is bigger than {limit} bytes"
+            )));
+        }
+        Ok(chunk)
+    }))
 }
 
 async fn robots_txt() -> &'static str {
@@ -102,9 +168,8 @@ async fn serve_page_route(rx: CubReqImpl) -> LegacyReply {
         Ok(path) => path,
         Err(e) => {
             if rx.path.as_str().ends_with(".png") {
-                let cdn_base_url = &rx.tenant.tc().cdn_base_url(rx.web());
-                let cdn_url = format!("{}{}", cdn_base_url, rx.path);
-                return Ok(Redirect::to(&cdn_url).into_response());
+                let cdn_url = rx.path.to_cdn_url_string(rx.tenant.tc(), rx.web());
+                return Ok(Redirect::to(cdn_url.as_str()).into_response());
             }
 
             log::warn!("{e}");
@@ -133,7 +198,7 @@ async fn serve_page_route(rx: CubReqImpl) -> LegacyReply {
                 // makes it easier to share links directly from the browser's address bar
                 let redirect_url = format!(
                     "{}?draft_code={}",
-                    rx.path,
+                    rx.path.to_url_path(),
                     page.draft_code.as_ref().unwrap()
                 );
                 log::info!("Adding draft_code to URL for easy sharing: {redirect_url}");
@@ -191,43 +256,39 @@ async fn extra_files(
         ));
     }
 
-    let content_type = match path.rsplit_once('.').map(|x| x.1) {
-        Some("m4a") => ContentType::AAC,
-        Some("ogg") => ContentType::OGG,
-        Some("mp3") => ContentType::MP3,
-        Some("flac") => ContentType::FLAC,
-        _ => {
-            log::warn!("Unsupported file type requested: {path}");
-            return Err(LegacyHttpError::with_status(
-                StatusCode::NOT_FOUND,
-                "unsupported file type",
-            ));
-        }
-    };
-
     let store = tr.tenant.store.clone();
     let key = ObjectStoreKey::new(format!("extra-files/{path}"));
     log::info!(
         "Fetching object store key \x1b[33m{key}\x1b[0m for extra file \x1b[33m{path}\x1b[0m"
     );
 
-    let res = store.get(&key).await?;
-    let body = res.bytes().await?;
-
-    Ok((
-        StatusCode::OK,
-        [
-            (CONTENT_TYPE, content_type.as_str()),
-            (
-                ACCESS_CONTROL_ALLOW_ORIGIN,
-                &tr.tenant.tc().web_base_url(tr.web()),
-            ),
-            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
-            ClientCachePolicy::CacheBasicallyForever.to_header_tuple(),
-        ],
-        axum::body::Body::from(body),
-    )
-        .into_response())
+    let cache_policy = tr.tenant.rc()?.cache_policy;
+    let mut res =
+        object_passthrough::serve_object(store.as_ref(), &key, &tr.parts().headers).await?;
+    set_extra_file_headers(
+        res.headers_mut(),
+        &tr.tenant.tc().web_base_url(tr.web()),
+        &cache_policy,
+    )?;
+    Ok(res)
+}
+
+fn set_extra_file_headers(
+    headers: &mut http::HeaderMap,
+    web_base_url: &str,
+    cache_policy: &CachePolicyConfig,
+) -> eyre::Result<()> {
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_str(web_base_url)?,
+    );
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    // extra files can be replaced without their URL changing
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_str(&cache_policy.cache_control(CacheCategory::Mutable))?,
+    );
+    Ok(())
 }
 
 async fn favicon(rcx: CubReqImpl) -> LegacyReply {
@@ -248,19 +309,22 @@ async fn favicon(rcx: CubReqImpl) -> LegacyReply {
     Ok(Redirect::temporary(url.as_str()).into_response())
 }
 
-static GIT_CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
-
-fn git_client() -> &'static reqwest::Client {
-    GIT_CLIENT.get_or_init(reqwest::Client::new)
+fn build_git_client(config: &GitProxyConfig) -> eyre::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .read_timeout(Duration::from_millis(config.read_timeout_ms))
+        .user_agent(&config.user_agent)
+        .build()
+        .wrap_err("could not build the git proxy client")
 }
 
 async fn extras_git(
+    Extension(git_client): Extension<reqwest::Client>,
     axum::extract::Path(path): axum::extract::Path<String>,
     tr: CubReqImpl,
     req: Request,
 ) -> impl IntoResponse {
-    use axum::body::to_bytes;
-    use axum::http::{HeaderMap, StatusCode};
+    use axum::http::StatusCode;
     use axum::response::IntoResponse;
     use cub_types::CubTenant;
     use http::Method;
@@ -276,7 +340,7 @@ async fn extras_git(
         None
     };
 
-    if let Some(api_key) = token {
+    let (user_id, tier) = if let Some(api_key) = token {
         let api_key = UserApiKey::new(api_key);
 
         // Use mom tenant client to verify the API key and get tier
@@ -284,9 +348,6 @@ async fn extras_git(
 
         match tcli.verify_api_key(&VerifyApiKeyArgs { api_key }).await {
             Ok(response) => {
-                let tier = response.user_info.get_fasterthanlime_tier();
-                log::info!("Valid API key for user with tier: {tier:?}");
-
                 let rc = match tr.tenant.rc() {
                     Ok(rc) => rc,
                     Err(e) => {
@@ -295,6 +356,11 @@ async fn extras_git(
                             .into_response();
                     }
                 };
+                let tier = response.user_info.tier(&rc.tier_mapping);
+                log::info!(
+                    "Valid API key for user {} with tier: {tier:?}",
+                    response.user_info.id
+                );
                 let access_override = AccessOverride::from_raw_query(tr.raw_query());
                 let viewer = Viewer::new(rc, Some(&response.user_info), access_override);
 
@@ -308,6 +374,7 @@ async fn extras_git(
                     )
                         .into_response();
                 }
+                (response.user_info.id, tier)
             }
             Err(e) => {
                 log::warn!("Invalid API key: {e}");
@@ -327,7 +394,7 @@ async fn extras_git(
             "Authentication required",
         )
             .into_response();
-    }
+    };
 
     // Get the query string, if any, and append to the target URL
     let original_uri = req.uri();
@@ -352,19 +419,12 @@ async fn extras_git(
 
     // Determine the HTTP method
     let mut proxy_req = match method {
-        Method::GET => git_client().get(&target_url),
+        Method::GET => git_client.get(&target_url),
         Method::POST => {
-            // Read the body from the axum request
-            let body = req.into_body();
-            let body_bytes = match to_bytes(body, 10 * 1024 * 1024).await {
-                Ok(b) => b,
-                Err(e) => {
-                    log::error!("Failed to read POST body: {e}");
-                    return (StatusCode::BAD_REQUEST, format!("Failed to read body: {e}"))
-                        .into_response();
-                }
-            };
-            git_client().post(&target_url).body(body_bytes)
+            // pushes can be big: stream them along rather than buffering them,
+            // `streamed_body_limit` caps their size
+            let body = reqwest::Body::wrap_stream(req.into_body().into_data_stream());
+            git_client.post(&target_url).body(body)
         }
         // If you want to support more HTTP methods, add match arms here.
         m => {
@@ -387,6 +447,11 @@ async fn extras_git(
             continue;
         }
 
+        // the client sets its own
+        if header_name == http::header::USER_AGENT {
+            continue;
+        }
+
         // Skip the original authorization header since we'll replace it with git credentials
         if header_name == http::header::AUTHORIZATION {
             log::info!("  Skipping original Authorization header");
@@ -413,7 +478,14 @@ async fn extras_git(
         log::warn!("  No git credentials configured for this tenant");
     }
 
-    log::info!("Proxying request to: {target_url}");
+    log::info!("Proxying request to: {target_url} for user {user_id} (tier: {tier:?})");
+    forward_upstream(proxy_req).await
+}
+
+/// Sends the proxied request, and streams back whatever the git host answers.
+/// If it doesn't answer at all, that's a 502, or a 504 if it took too long.
+async fn forward_upstream(proxy_req: reqwest::RequestBuilder) -> axum::response::Response {
+    use axum::http::HeaderMap;
 
     match proxy_req.send().await {
         Ok(resp) => {
@@ -453,7 +525,6 @@ async fn extras_git(
             log::info!("Returning response with status: {status}");
 
             // Convert the stream to axum body
-            use axum::body::Body;
             use futures_util::StreamExt;
 
             let body = Body::from_stream(body_stream.map(|result| {
@@ -466,8 +537,13 @@ async fn extras_git(
             (status, headers, body).into_response()
         }
         Err(e) => {
-            log::error!("Proxy request failed: {e}");
-            (StatusCode::BAD_GATEWAY, format!("Proxy error: {e}")).into_response()
+            let status = if e.is_timeout() {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            log::error!("Proxy request failed ({status}): {e}");
+            (status, format!("Proxy error: {e}")).into_response()
         }
     }
 }
@@ -487,3 +563,130 @@ fn extract_token_from_basic_auth(auth_header: &str) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_types::BodyLimitsConfig;
+    use tower::ServiceExt;
+
+    async fn post_status(router: Router, path: &str, len: usize) -> StatusCode {
+        let req = Request::post(path)
+            .header(http::header::CONTENT_LENGTH, len)
+            .body(Body::from(vec![b'a'; len]))
+            .unwrap();
+        router.oneshot(req).await.unwrap().status()
+    }
+
+    #[test]
+    fn test_extra_files_cache_control_follows_policy() {
+        let mut headers = http::HeaderMap::new();
+        set_extra_file_headers(
+            &mut headers,
+            "https://example.org",
+            &CachePolicyConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(headers[CACHE_CONTROL], "max-age=31536000");
+
+        let policy = CachePolicyConfig {
+            mutable_max_age_secs: 300,
+            ..Default::default()
+        };
+        set_extra_file_headers(&mut headers, "https://example.org", &policy).unwrap();
+        assert_eq!(headers[CACHE_CONTROL], "max-age=300");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://example.org");
+    }
+
+    fn config(body_limits: BodyLimitsConfig) -> CubConfig {
+        CubConfig {
+            body_limits,
+            ..CubConfig::for_tests()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_limits_per_route_group() {
+        let limits = BodyLimitsConfig {
+            web: ByteSize::new(1024),
+            uploads: ByteSize::mib(1),
+            extras: ByteSize::mib(4),
+        };
+        // the common layers set the limit for everything else
+        let router = web_routes(&config(limits))
+            .unwrap()
+            .layer(body_limit(limits.web));
+
+        // there's no `CubReqLayer` here, so whatever gets past the limits
+        // fails later on: only 413s are about body sizes
+        assert_ne!(
+            post_status(
+                router.clone(),
+                "/internal-api/write-to-clipboard",
+                64 * 1024
+            )
+            .await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            post_status(
+                router.clone(),
+                "/internal-api/write-to-clipboard",
+                2 * 1024 * 1024
+            )
+            .await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_ne!(
+            post_status(router.clone(), "/extras/git-receive-pack", 2 * 1024 * 1024).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            post_status(router, "/extras/git-receive-pack", 8 * 1024 * 1024).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_is_cut_off_past_the_limit() {
+        let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(vec![b'a'; 1024]));
+        let body = limited_body(Body::from_stream(futures_util::stream::iter(chunks)), 3000);
+        let err = axum::body::to_bytes(body, usize::MAX).await.unwrap_err();
+        assert!(err.to_string().contains("bigger than 3000 bytes"), "{err}");
+
+        let body = limited_body(Body::from(vec![b'a'; 3000]), 3000);
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(bytes.len(), 3000);
+    }
+
+    #[tokio::test]
+    async fn test_hung_git_host_is_a_gateway_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // accept, then never say anything
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let client = build_git_client(&GitProxyConfig {
+            read_timeout_ms: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        let res = forward_upstream(client.get(format!("http://{addr}/ftl-extras/info/refs"))).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_git_host_is_a_bad_gateway() {
+        // bind then drop, so nothing listens there
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = build_git_client(&GitProxyConfig::default()).unwrap();
+        let res = forward_upstream(client.get(format!("http://{addr}/ftl-extras/info/refs"))).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+}
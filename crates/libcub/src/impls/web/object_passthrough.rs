@@ -0,0 +1,342 @@
+use axum::{body::Body, response::Response};
+use content_type::ContentType;
+use http::{
+    HeaderMap, StatusCode,
+    header::{
+        ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+        IF_RANGE, RANGE,
+    },
+};
+use libcdn::RequestedRange;
+use libobjectstore::{DEFAULT_GET_RETRY, GetOptions, GetRange, ObjectStore};
+use objectstore_types::ObjectStoreKeyRef;
+
+use crate::impls::reply::{LegacyHttpError, LegacyReply};
+
+/// Streams an object from `store` (without buffering it), honoring the first
+/// range of a `Range` header if there's one, as long as `If-Range` (if any)
+/// matches the object's ETag. The content type is guessed from
+/// the key's extension, or from the object's first bytes when the extension
+/// doesn't tell, falling back to `application/octet-stream`. Only media
+/// is served inline: anything a browser could run (HTML, SVG, JS...) is sent
+/// as an `application/octet-stream` attachment instead.
+///
+/// Access control is up to the caller, and so are CORS, caching and
+/// `X-Content-Type-Options` headers.
+pub(crate) async fn serve_object(
+    store: &dyn ObjectStore,
+    key: &ObjectStoreKeyRef,
+    req_headers: &HeaderMap,
+) -> LegacyReply {
+    let content_type = match ContentType::guess_from_path(key.as_str()) {
+        Some(content_type) => content_type,
+        None => sniff_content_type(store, key).await?,
+    };
+
+    let res = Response::builder().header(ACCEPT_RANGES, "bytes");
+    let res = if is_passive_media(content_type) {
+        res.header(CONTENT_TYPE, content_type.as_str())
+    } else {
+        log::info!("{key} is {content_type}, serving it as an attachment");
+        res.header(CONTENT_TYPE, ContentType::OctetStream.as_str())
+            .header(CONTENT_DISPOSITION, "attachment")
+    };
+
+    if req_headers.contains_key(RANGE) {
+        let head = store
+            .get_opts_retrying(
+                key,
+                GetOptions {
+                    head: true,
+                    ..Default::default()
+                },
+                DEFAULT_GET_RETRY,
+            )
+            .await?;
+        let size = head.size();
+        let etag = head.e_tag().map(strong_etag);
+
+        match libcdn::requested_range(req_headers, etag.as_deref(), size as _) {
+            RequestedRange::Partial(range) => {
+                let start = range.start as usize;
+                let end = (range.start + range.length) as usize;
+
+                let object = store
+                    .get_opts_retrying(
+                        key,
+                        GetOptions {
+                            range: Some(GetRange::Bounded(start..end)),
+                            ..Default::default()
+                        },
+                        DEFAULT_GET_RETRY,
+                    )
+                    .await?;
+                return Ok(with_etag(res, etag.as_deref())
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_LENGTH, range.length.to_string())
+                    .header(
+                        CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end - 1, size),
+                    )
+                    .body(Body::from_stream(object.into_stream()))?);
+            }
+            RequestedRange::Unsatisfiable => {
+                return Ok(res
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{size}"))
+                    .body(Body::empty())?);
+            }
+            RequestedRange::Full => {}
+        }
+    }
+
+    let object = store.get_retrying(key).await?;
+    let etag = object.e_tag().map(strong_etag);
+    Ok(with_etag(res, etag.as_deref())
+        .status(StatusCode::OK)
+        .header(CONTENT_LENGTH, object.size().to_string())
+        .body(Body::from_stream(object.into_stream()))?)
+}
+
+/// Object stores don't all quote their ETags, HTTP wants them quoted
+fn strong_etag(e_tag: &str) -> String {
+    if e_tag.starts_with('"') {
+        e_tag.to_string()
+    } else {
+        format!("\"{e_tag}\"")
+    }
+}
+
+fn with_etag(res: http::response::Builder, etag: Option<&str>) -> http::response::Builder {
+    match etag {
+        Some(etag) => res.header(ETAG, etag),
+        None => res,
+    }
+}
+
+/// Media browsers display but never run, so they're fine on the site's own
+/// origin
+fn is_passive_media(content_type: ContentType) -> bool {
+    matches!(
+        content_type,
+        ContentType::JXL
+            | ContentType::HEIC
+            | ContentType::AVIF
+            | ContentType::WEBP
+            | ContentType::PNG
+            | ContentType::JPG
+            | ContentType::GIF
+            | ContentType::ICO
+            | ContentType::MP4
+            | ContentType::WebM
+            | ContentType::M4A
+            | ContentType::OGG
+            | ContentType::MP3
+            | ContentType::FLAC
+            | ContentType::AAC
+            | ContentType::WOFF2
+    )
+}
+
+/// Reads the first few bytes of the object to figure out what it is
+async fn sniff_content_type(
+    store: &dyn ObjectStore,
+    key: &ObjectStoreKeyRef,
+) -> Result<ContentType, LegacyHttpError> {
+    let head = store
+        .get_opts_retrying(
+            key,
+            GetOptions {
+                head: true,
+                ..Default::default()
+            },
+            DEFAULT_GET_RETRY,
+        )
+        .await?;
+    let len = head.size().min(ContentType::SNIFF_LEN);
+
+    let sniffed = if len == 0 {
+        None
+    } else {
+        let prefix = store
+            .get_opts_retrying(
+                key,
+                GetOptions {
+                    range: Some(GetRange::Bounded(0..len)),
+                    ..Default::default()
+                },
+                DEFAULT_GET_RETRY,
+            )
+            .await?
+            .bytes()
+            .await?;
+        ContentType::sniff(&prefix)
+    };
+
+    let content_type = sniffed.unwrap_or(ContentType::OctetStream);
+    log::info!("No known extension for {key}, serving it as {content_type}");
+    Ok(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use libobjectstore::Bytes;
+    use objectstore_types::ObjectStoreKey;
+    use std::sync::Arc;
+
+    async fn store_with_song() -> (Arc<dyn ObjectStore>, ObjectStoreKey) {
+        let store = libobjectstore::load().in_memory();
+        let key = ObjectStoreKey::new("extra-files/album/song.mp3".to_string());
+        let payload = (0..100u8).collect::<Vec<_>>();
+        store.put(&key, payload.into()).await.unwrap();
+        (store, key)
+    }
+
+    async fn body_bytes(res: Response) -> Vec<u8> {
+        axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_full_download() {
+        let (store, key) = store_with_song().await;
+
+        let res = serve_object(store.as_ref(), &key, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "audio/mpeg");
+        assert_eq!(res.headers()[CONTENT_LENGTH], "100");
+        assert_eq!(res.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(body_bytes(res).await, (0..100u8).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_ranged_download() {
+        let (store, key) = store_with_song().await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_static("bytes=10-19"));
+        let res = serve_object(store.as_ref(), &key, &headers).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 10-19/100");
+        assert_eq!(res.headers()[CONTENT_LENGTH], "10");
+        assert_eq!(body_bytes(res).await, (10..20u8).collect::<Vec<_>>());
+
+        // open-ended ranges go to the end of the object
+        headers.insert(RANGE, HeaderValue::from_static("bytes=95-"));
+        let res = serve_object(store.as_ref(), &key, &headers).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes 95-99/100");
+        assert_eq!(body_bytes(res).await, (95..100u8).collect::<Vec<_>>());
+
+        headers.insert(RANGE, HeaderValue::from_static("bytes=500-600"));
+        let res = serve_object(store.as_ref(), &key, &headers).await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[CONTENT_RANGE], "bytes */100");
+        assert!(body_bytes(res).await.is_empty());
+
+        // malformed ranges get the whole thing
+        headers.insert(RANGE, HeaderValue::from_static("lines=1-2"));
+        let res = serve_object(store.as_ref(), &key, &headers).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_bytes(res).await.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_if_range() {
+        let (store, key) = store_with_song().await;
+        let res = serve_object(store.as_ref(), &key, &HeaderMap::new())
+            .await
+            .unwrap();
+        let etag = res.headers()[ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with('"'));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_static("bytes=10-19"));
+        headers.insert(IF_RANGE, etag.clone());
+        let res = serve_object(store.as_ref(), &key, &headers).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[ETAG], etag);
+
+        // the client's copy is from before the object changed
+        headers.insert(IF_RANGE, HeaderValue::from_static("\"stale\""));
+        let res = serve_object(store.as_ref(), &key, &headers).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_bytes(res).await.len(), 100);
+    }
+
+    async fn content_type_of(name: &str, payload: &'static [u8]) -> String {
+        let store = libobjectstore::load().in_memory();
+        let key = ObjectStoreKey::new(format!("extra-files/{name}"));
+        store.put(&key, payload.to_vec().into()).await.unwrap();
+
+        let res = serve_object(store.as_ref(), &key, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        res.headers()[CONTENT_TYPE].to_str().unwrap().to_string()
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[tokio::test]
+    async fn test_known_extension_wins() {
+        // the extension is trusted, whatever the bytes say
+        assert_eq!(content_type_of("album/cover.jpg", PNG).await, "image/jpeg");
+    }
+
+    #[tokio::test]
+    async fn test_active_content_is_an_attachment() {
+        let store = libobjectstore::load().in_memory();
+        for name in ["page.html", "logo.svg", "app.js"] {
+            let key = ObjectStoreKey::new(format!("extra-files/{name}"));
+            store
+                .put(&key, Bytes::from_static(b"<script>alert(1)</script>"))
+                .await
+                .unwrap();
+
+            let res = serve_object(store.as_ref(), &key, &HeaderMap::new())
+                .await
+                .unwrap();
+            assert_eq!(
+                res.headers()[CONTENT_TYPE],
+                "application/octet-stream",
+                "{name}"
+            );
+            assert_eq!(res.headers()[CONTENT_DISPOSITION], "attachment", "{name}");
+        }
+
+        let (store, key) = store_with_song().await;
+        let res = serve_object(store.as_ref(), &key, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert!(res.headers().get(CONTENT_DISPOSITION).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_extension_is_sniffed() {
+        assert_eq!(content_type_of("cover", PNG).await, "image/png");
+        assert_eq!(content_type_of("cover.xyz", PNG).await, "image/png");
+        assert_eq!(
+            content_type_of("track", b"\0\0\0\x20ftypM4A \0\0\0\0").await,
+            "audio/mp4"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsniffable_is_an_octet_stream() {
+        // we never sniff anything a browser would render or run
+        assert_eq!(
+            content_type_of("page", b"<!doctype html><script>alert(1)</script>").await,
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type_of("empty", b"").await,
+            "application/octet-stream"
+        );
+    }
+}
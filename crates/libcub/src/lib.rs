@@ -1,4 +1,5 @@
 use autotrait::autotrait;
+use camino::Utf8PathBuf;
 use tokio::net::TcpListener;
 
 use config_types::CubConfig;
@@ -11,21 +12,39 @@ pub fn load() -> &'static dyn Mod {
     &INSTANCE
 }
 
+/// What to open in the browser once cub is up
 pub enum OpenBehavior {
+    /// The first tenant's site
     OpenOnStart,
+    /// Exactly this URL: build it with [`OpenBehavior::open_url`]
+    OpenUrl(String),
     DontOpen,
 }
 
+impl OpenBehavior {
+    /// Opens `url` as-is once cub is up. Only absolute http(s) URLs are
+    /// accepted.
+    pub fn open_url(url: &str) -> Result<Self> {
+        let parsed =
+            url::Url::parse(url).map_err(|e| eyre::eyre!("Invalid URL to open ({url}): {e}"))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            eyre::bail!("Can only open http(s) URLs, not {url}");
+        }
+        Ok(Self::OpenUrl(url.to_string()))
+    }
+}
+
 #[autotrait]
 impl Mod for ModImpl {
     fn serve(
         &self,
         config: CubConfig,
+        config_path: Option<Utf8PathBuf>,
         ln: TcpListener,
         open_behavior: OpenBehavior,
     ) -> BoxFuture<'static, Result<()>> {
         Box::pin(async {
-            impls::serve(config, ln, open_behavior)
+            impls::serve(config, config_path, ln, open_behavior)
                 .await
                 .map_err(|e| eyre::eyre!("{}", e))
         })
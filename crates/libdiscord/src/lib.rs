@@ -1,16 +1,20 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use autotrait::autotrait;
 use config_types::{TenantConfig, WebConfig};
 use credentials::{
-    DiscordChannelId, DiscordGuildId, DiscordGuildIdRef, DiscordMessageId, DiscordProfile,
-    DiscordRoleId, DiscordRoleIdRef, DiscordUserId, DiscordUserIdRef, UserId,
+    Credentials, DiscordChannelId, DiscordGuildId, DiscordGuildIdRef, DiscordMessageId,
+    DiscordProfile, DiscordRoleId, DiscordRoleIdRef, DiscordUserId, DiscordUserIdRef, OAuthGrant,
+    PROFILE_CACHE_CAPACITY, ProfileCache, ScopeDowngrade, UserId,
 };
 use eyre::{Context, Result};
 use facet::Facet;
 use futures_core::future::BoxFuture;
 use libhttpclient::{
-    HttpClient, Uri,
+    HttpClient, RetryPolicy, Uri, decode_error_response, error_from_body,
     header::{HeaderName, HeaderValue},
 };
 use time::OffsetDateTime;
@@ -18,15 +22,63 @@ use url::Url;
 
 struct ModImpl {
     client: Arc<dyn HttpClient>,
+    limits: DiscordRequestLimits,
+    profile_cache: ProfileCache<DiscordProfile>,
+}
+
+/// Bounds on every call we make to Discord's API, so a hung or huge
+/// response (large member lists, say) can't stall us or balloon memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscordRequestLimits {
+    /// How long we wait for the whole response, body included
+    pub timeout: Duration,
+    /// Responses with a bigger body are an error
+    pub max_body_size: usize,
+}
+
+impl Default for DiscordRequestLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_body_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// What the module is set up with, see [`load_with`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModOptions {
+    /// How long profile fetches are cached for, per access token. Not cached
+    /// at all when unset.
+    pub profile_cache_ttl: Option<Duration>,
+
+    /// Bounds on every call to Discord's API
+    pub request_limits: DiscordRequestLimits,
 }
 
+static MOD: OnceLock<ModImpl> = OnceLock::new();
+
+/// What we ask users for when they log in with Discord
+const DISCORD_SCOPES: &str = "identify";
+
+/// Returns the module, loading it with the default options if nothing
+/// called [`load_with`] first
 pub fn load() -> &'static dyn Mod {
-    use std::sync::OnceLock;
+    MOD.get_or_init(|| ModImpl::new(ModOptions::default()))
+}
 
-    static MOD: OnceLock<ModImpl> = OnceLock::new();
-    MOD.get_or_init(|| ModImpl {
-        client: Arc::from(libhttpclient::load().client()),
-    })
+/// Loads the module with `opts`. That's only possible once, before anything
+/// calls [`load`]: after that, this is an error.
+pub fn load_with(opts: ModOptions) -> Result<&'static dyn Mod> {
+    let mut loaded_here = false;
+    let m = MOD.get_or_init(|| {
+        loaded_here = true;
+        ModImpl::new(opts)
+    });
+    if !loaded_here {
+        eyre::bail!("the Discord module was already loaded, can't set it up with {opts:?}");
+    }
+    Ok(m)
 }
 
 // Note: coolbearbot needs the following permissions:
@@ -44,7 +96,7 @@ impl Mod for ModImpl {
             q.append_pair("response_type", "code");
             q.append_pair("client_id", &discord_secrets.oauth_client_id);
             q.append_pair("redirect_uri", &make_discord_callback_url(tc, web));
-            q.append_pair("scope", "identify");
+            q.append_pair("scope", DISCORD_SCOPES);
         }
         Ok(u.to_string())
     }
@@ -54,7 +106,7 @@ impl Mod for ModImpl {
         tc: &'fut TenantConfig,
         web: WebConfig,
         args: &'fut DiscordCallbackArgs,
-    ) -> BoxFuture<'fut, Result<Option<DiscordCredentials>>> {
+    ) -> BoxFuture<'fut, Result<Option<OAuthGrant<DiscordCredentials>>>> {
         Box::pin(async move {
             let code = match url::form_urlencoded::parse(args.raw_query.as_bytes())
                 .find(|(key, _)| key == "code")
@@ -77,41 +129,18 @@ impl Mod for ModImpl {
                     ("client_id", &discord_secrets.oauth_client_id),
                     ("client_secret", &discord_secrets.oauth_client_secret),
                 ])
+                .retry(RetryPolicy::QUICK)
                 .send()
                 .await
                 .wrap_err("While getting Discord access token")?;
 
             if !res.status().is_success() {
-                let status = res.status();
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
+                return Err(decode_error_response(res).await);
             }
 
             let text = res.text().await?;
-            let creds = match facet_json::from_str::<DiscordCredentialsAPI>(&text) {
-                Ok(c) => c,
-                Err(e) => {
-                    log::warn!("Got Discord auth error: {text}");
-                    return Err(eyre::eyre!("Got Discord auth error: {e}"));
-                }
-            };
-
-            log::info!(
-                "Successfully obtained Discord token with scope {}",
-                &creds.scope
-            );
-
-            let creds = DiscordCredentials {
-                access_token: creds.access_token,
-                refresh_token: creds.refresh_token,
-                expires_at: OffsetDateTime::now_utc()
-                    + time::Duration::seconds(creds.expires_in as i64),
-            };
-
-            Ok(Some(creds))
+            let grant = grant_from_token_response(&text, args.strict_scopes)?;
+            Ok(Some(grant))
         })
     }
 
@@ -120,12 +149,20 @@ impl Mod for ModImpl {
         creds: &'fut DiscordCredentials,
     ) -> BoxFuture<'fut, Result<DiscordProfile>> {
         Box::pin(async move {
+            if let Some(profile) = self.profile_cache.get(&creds.access_token) {
+                return Ok(profile);
+            }
+
             #[derive(Facet)]
             struct DiscordUser {
                 id: String,
                 username: String,
                 global_name: Option<String>,
                 avatar: Option<String>,
+                #[facet(default)]
+                discriminator: Option<String>,
+                #[facet(default)]
+                email: Option<String>,
             }
 
             let res = self
@@ -137,12 +174,7 @@ impl Mod for ModImpl {
                 .await?;
 
             if !res.status().is_success() {
-                let status = res.status();
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
+                return Err(decode_error_response(res).await);
             }
 
             let user = res
@@ -155,9 +187,13 @@ impl Mod for ModImpl {
                 username: user.username,
                 global_name: user.global_name,
                 avatar_hash: user.avatar,
+                discriminator: user.discriminator,
+                email: user.email,
             };
 
             log::info!("Discord profile: {profile:#?}");
+            self.profile_cache
+                .insert(&creds.access_token, profile.clone());
             Ok(profile)
         })
     }
@@ -184,12 +220,7 @@ impl Mod for ModImpl {
                 .map_err(|e| eyre::eyre!("While refreshing Discord access token: {e}"))?;
 
             if !res.status().is_success() {
-                let status = res.status();
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
+                return Err(decode_error_response(res).await);
             }
 
             let creds = res.json::<DiscordCredentialsAPI>().await?;
@@ -225,12 +256,30 @@ impl Mod for ModImpl {
                     ("with_counts", "true"),
                 ],
             )?;
-            let guilds = json_req::<Vec<DiscordGuild>>(tc, self.client.get(uri)).await?;
+            let guilds = self
+                .json_req::<Vec<DiscordGuild>>(tc, self.client.get(uri))
+                .await?;
             log::info!("Successfully fetched {} bot guilds", guilds.len());
             Ok(guilds)
         })
     }
 
+    /// Fetches a single guild the bot is in, with member counts and boost status
+    fn get_guild<'fut>(
+        &'fut self,
+        guild_id: &'fut DiscordGuildIdRef,
+        tc: &'fut TenantConfig,
+    ) -> BoxFuture<'fut, Result<DiscordGuildDetails>> {
+        Box::pin(async move {
+            let uri = v10_uri(&format!("/guilds/{guild_id}"), &[("with_counts", "true")])?;
+            let guild = self
+                .json_req::<DiscordGuildDetails>(tc, self.client.get(uri))
+                .await?;
+            log::info!("Successfully fetched guild {guild_id}");
+            Ok(guild)
+        })
+    }
+
     fn list_guild_members<'fut>(
         &'fut self,
         guild_id: &'fut DiscordGuildIdRef,
@@ -241,7 +290,9 @@ impl Mod for ModImpl {
                 &format!("/guilds/{guild_id}/members"),
                 &[("limit", "1000")], // Discord's max limit per request
             )?;
-            let members = json_req::<Vec<DiscordGuildMember>>(tc, self.client.get(uri)).await?;
+            let members = self
+                .json_req::<Vec<DiscordGuildMember>>(tc, self.client.get(uri))
+                .await?;
 
             log::info!(
                 "Successfully fetched {} guild members for guild {}",
@@ -259,7 +310,9 @@ impl Mod for ModImpl {
     ) -> BoxFuture<'fut, Result<Vec<DiscordRole>>> {
         Box::pin(async move {
             let uri = v10_uri(&format!("/guilds/{guild_id}/roles"), &[])?;
-            let roles = json_req::<Vec<DiscordRole>>(tc, self.client.get(uri)).await?;
+            let roles = self
+                .json_req::<Vec<DiscordRole>>(tc, self.client.get(uri))
+                .await?;
             log::info!("Successfully fetched {} guild roles", roles.len());
             Ok(roles)
         })
@@ -278,7 +331,7 @@ impl Mod for ModImpl {
                 &[],
             )?;
 
-            let _text = text_req(tc, self.client.put(uri)).await?;
+            let _text = self.text_req(tc, self.client.put(uri)).await?;
 
             log::info!("Successfully added role {role_id} to user {user_id} in guild {guild_id}");
             Ok(())
@@ -298,7 +351,7 @@ impl Mod for ModImpl {
                 &[],
             )?;
 
-            let _text = text_req(tc, self.client.delete(uri)).await?;
+            let _text = self.text_req(tc, self.client.delete(uri)).await?;
 
             log::info!(
                 "Successfully removed role {role_id} from user {user_id} in guild {guild_id}"
@@ -314,7 +367,9 @@ impl Mod for ModImpl {
     ) -> BoxFuture<'fut, Result<Vec<DiscordChannel>>> {
         Box::pin(async move {
             let uri = v10_uri(&format!("/guilds/{guild_id}/channels"), &[])?;
-            let channels = json_req::<Vec<DiscordChannel>>(tc, self.client.get(uri)).await?;
+            let channels = self
+                .json_req::<Vec<DiscordChannel>>(tc, self.client.get(uri))
+                .await?;
             log::info!(
                 "Successfully fetched {} channels for guild {guild_id}",
                 channels.len()
@@ -323,21 +378,40 @@ impl Mod for ModImpl {
         })
     }
 
+    /// Posts a message without pinging anyone, even if `content` has
+    /// `@everyone` or user/role mentions in it.
     fn post_message_to_channel<'fut>(
         &'fut self,
         channel_id: &'fut DiscordChannelId,
         content: &'fut str,
         tc: &'fut TenantConfig,
+    ) -> BoxFuture<'fut, Result<DiscordMessage>> {
+        self.post_message_to_channel_with_mentions(
+            channel_id,
+            content,
+            DiscordAllowedMentions::none(),
+            tc,
+        )
+    }
+
+    /// Posts a message that only pings what `allowed_mentions` lets through
+    fn post_message_to_channel_with_mentions<'fut>(
+        &'fut self,
+        channel_id: &'fut DiscordChannelId,
+        content: &'fut str,
+        allowed_mentions: DiscordAllowedMentions,
+        tc: &'fut TenantConfig,
     ) -> BoxFuture<'fut, Result<DiscordMessage>> {
         Box::pin(async move {
             let uri = v10_uri(&format!("/channels/{channel_id}/messages"), &[])?;
 
             let message_payload = DiscordMessagePayload {
                 content: content.to_string(),
+                allowed_mentions,
             };
 
             let req = self.client.post(uri).json(&message_payload)?;
-            let message = json_req::<DiscordMessage>(tc, req).await?;
+            let message = self.json_req::<DiscordMessage>(tc, req).await?;
 
             log::info!("Successfully posted message to channel {channel_id}");
             Ok(message)
@@ -352,7 +426,9 @@ impl Mod for ModImpl {
     ) -> BoxFuture<'fut, Result<DiscordGuildMember>> {
         Box::pin(async move {
             let uri = v10_uri(&format!("/guilds/{guild_id}/members/{user_id}"), &[])?;
-            let member = json_req::<DiscordGuildMember>(tc, self.client.get(uri)).await?;
+            let member = self
+                .json_req::<DiscordGuildMember>(tc, self.client.get(uri))
+                .await?;
             log::info!("Successfully fetched guild member {user_id} for guild {guild_id}");
             Ok(member)
         })
@@ -366,6 +442,44 @@ pub struct DiscordCallbackArgs {
     /// if we're linking this discord account to an existing UserID, this is set
     #[facet(default)]
     pub logged_in_user_id: Option<UserId>,
+
+    /// fail the login if Discord granted fewer scopes than we asked for,
+    /// instead of just reporting it
+    #[facet(default)]
+    pub strict_scopes: bool,
+}
+
+/// Parses the response to our token request, and checks Discord granted
+/// all of [`DISCORD_SCOPES`]
+fn grant_from_token_response(
+    text: &str,
+    strict_scopes: bool,
+) -> Result<OAuthGrant<DiscordCredentials>> {
+    let creds = match facet_json::from_str::<DiscordCredentialsAPI>(text) {
+        Ok(c) => c,
+        Err(e) => {
+            log::warn!("Got Discord auth error: {text}");
+            return Err(eyre::eyre!("Got Discord auth error: {e}"));
+        }
+    };
+
+    log::info!(
+        "Successfully obtained Discord token with scope {}",
+        &creds.scope
+    );
+
+    let scope_downgrade =
+        ScopeDowngrade::check("discord", DISCORD_SCOPES, &creds.scope, strict_scopes)?;
+
+    Ok(OAuthGrant {
+        creds: DiscordCredentials {
+            access_token: creds.access_token,
+            refresh_token: creds.refresh_token,
+            expires_at: OffsetDateTime::now_utc()
+                + time::Duration::seconds(creds.expires_in as i64),
+        },
+        scope_downgrade,
+    })
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -404,11 +518,9 @@ pub struct DiscordUnlinkArgs {
     pub logged_in_user_id: UserId,
 }
 
-impl DiscordCredentials {
-    pub fn expire_soon(&self) -> bool {
-        let now = OffsetDateTime::now_utc();
-        let one_hour = time::Duration::hours(1);
-        self.expires_at - now < one_hour
+impl Credentials for DiscordCredentials {
+    fn expires_at(&self) -> OffsetDateTime {
+        self.expires_at
     }
 }
 
@@ -434,6 +546,30 @@ pub struct DiscordGuildMember {
     pub premium_since: Option<String>,
 }
 
+impl DiscordGuildMember {
+    /// When the user joined the guild, `None` if Discord didn't say
+    pub fn joined_at_parsed(&self) -> Result<Option<OffsetDateTime>> {
+        self.joined_at
+            .as_deref()
+            .map(parse_discord_timestamp)
+            .transpose()
+    }
+
+    /// When the user started boosting the guild, `None` if they're not boosting
+    pub fn premium_since_parsed(&self) -> Result<Option<OffsetDateTime>> {
+        self.premium_since
+            .as_deref()
+            .map(parse_discord_timestamp)
+            .transpose()
+    }
+}
+
+/// Discord sends ISO8601 timestamps, like `2021-03-04T09:56:23.123000+00:00`
+pub fn parse_discord_timestamp(input: &str) -> Result<OffsetDateTime> {
+    OffsetDateTime::parse(input, &time::format_description::well_known::Rfc3339)
+        .wrap_err_with(|| format!("invalid discord timestamp {input:?}"))
+}
+
 #[derive(Debug, Clone, Facet)]
 pub struct DiscordRole {
     /// Role id
@@ -480,14 +616,46 @@ pub struct DiscordGuild {
     pub approximate_presence_count: Option<u32>,
 }
 
+/// The full guild object, as returned by `GET /guilds/{guild_id}`
+#[derive(Debug, Clone, Facet)]
+pub struct DiscordGuildDetails {
+    /// Guild id
+    pub id: DiscordGuildId,
+    /// Guild name (2-100 characters, excluding trailing and leading whitespace)
+    pub name: String,
+    /// Icon hash
+    pub icon: Option<String>,
+    /// Banner hash
+    #[facet(default)]
+    pub banner: Option<String>,
+    /// Description of the guild (community guilds only)
+    #[facet(default)]
+    pub description: Option<String>,
+    /// Id of the guild owner
+    pub owner_id: DiscordUserId,
+    /// Enabled guild features
+    pub features: Vec<String>,
+    /// Server boost level (0 = none, 1 to 3 = tiers)
+    pub premium_tier: u8,
+    /// Number of boosts this guild currently has
+    #[facet(default)]
+    pub premium_subscription_count: Option<u32>,
+    /// Approximate number of members in this guild
+    #[facet(default)]
+    pub approximate_member_count: Option<u32>,
+    /// Approximate number of non-offline members in this guild
+    #[facet(default)]
+    pub approximate_presence_count: Option<u32>,
+}
+
 #[derive(Debug, Clone, Facet)]
 pub struct DiscordChannel {
     /// Channel id
     pub id: DiscordChannelId,
     /// Channel name
     pub name: String,
-    /// Channel type (0 = text, 2 = voice, etc.)
-    pub r#type: u8,
+    /// Channel type, see [`DiscordChannel::kind`]
+    pub r#type: DiscordChannelTypeCode,
     /// Channel topic (for text channels)
     #[facet(default)]
     pub topic: Option<String>,
@@ -506,17 +674,195 @@ pub struct DiscordChannel {
 pub struct DiscordPermissionOverwrite {
     /// Role or user id
     pub id: String,
-    /// Type of overwrite (0 = role, 1 = member)
-    pub r#type: u8,
+    /// Type of overwrite, see [`DiscordPermissionOverwrite::kind`]
+    pub r#type: DiscordOverwriteTypeCode,
     /// Permission bit set for allowed permissions
     pub allow: String,
     /// Permission bit set for denied permissions
     pub deny: String,
 }
 
+impl DiscordChannel {
+    pub fn kind(&self) -> DiscordChannelType {
+        self.r#type.into()
+    }
+}
+
+impl DiscordPermissionOverwrite {
+    pub fn kind(&self) -> DiscordOverwriteType {
+        self.r#type.into()
+    }
+}
+
+/// A channel type, exactly as Discord sends it over the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Facet)]
+#[repr(transparent)]
+#[facet(transparent)]
+pub struct DiscordChannelTypeCode(pub u8);
+
+/// What kind of channel a [`DiscordChannel`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiscordChannelType {
+    /// 0: a text channel within a guild
+    Text,
+    /// 1: a direct message between users
+    DirectMessage,
+    /// 2: a voice channel within a guild
+    Voice,
+    /// 3: a direct message between multiple users
+    GroupDirectMessage,
+    /// 4: an organizational category that contains up to 50 channels
+    Category,
+    /// 5: a channel users can follow and crosspost into their own guild
+    Announcement,
+    /// 10: a thread within an announcement channel
+    AnnouncementThread,
+    /// 11: a thread within a text or forum channel
+    PublicThread,
+    /// 12: a thread only visible to those invited, and moderators
+    PrivateThread,
+    /// 13: a voice channel for hosting events with an audience
+    StageVoice,
+    /// 14: the channel in a hub containing the listed servers
+    Directory,
+    /// 15: a channel that can only contain threads
+    Forum,
+    /// 16: a channel that can only contain threads, displayed as a gallery
+    Media,
+    /// Anything Discord added since this was written
+    Unknown(u8),
+}
+
+impl DiscordChannelType {
+    pub fn is_thread(self) -> bool {
+        matches!(
+            self,
+            Self::AnnouncementThread | Self::PublicThread | Self::PrivateThread
+        )
+    }
+}
+
+impl From<DiscordChannelTypeCode> for DiscordChannelType {
+    fn from(code: DiscordChannelTypeCode) -> Self {
+        match code.0 {
+            0 => Self::Text,
+            1 => Self::DirectMessage,
+            2 => Self::Voice,
+            3 => Self::GroupDirectMessage,
+            4 => Self::Category,
+            5 => Self::Announcement,
+            10 => Self::AnnouncementThread,
+            11 => Self::PublicThread,
+            12 => Self::PrivateThread,
+            13 => Self::StageVoice,
+            14 => Self::Directory,
+            15 => Self::Forum,
+            16 => Self::Media,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<DiscordChannelType> for DiscordChannelTypeCode {
+    fn from(kind: DiscordChannelType) -> Self {
+        Self(match kind {
+            DiscordChannelType::Text => 0,
+            DiscordChannelType::DirectMessage => 1,
+            DiscordChannelType::Voice => 2,
+            DiscordChannelType::GroupDirectMessage => 3,
+            DiscordChannelType::Category => 4,
+            DiscordChannelType::Announcement => 5,
+            DiscordChannelType::AnnouncementThread => 10,
+            DiscordChannelType::PublicThread => 11,
+            DiscordChannelType::PrivateThread => 12,
+            DiscordChannelType::StageVoice => 13,
+            DiscordChannelType::Directory => 14,
+            DiscordChannelType::Forum => 15,
+            DiscordChannelType::Media => 16,
+            DiscordChannelType::Unknown(other) => other,
+        })
+    }
+}
+
+/// A permission overwrite type, exactly as Discord sends it over the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Facet)]
+#[repr(transparent)]
+#[facet(transparent)]
+pub struct DiscordOverwriteTypeCode(pub u8);
+
+/// Who a [`DiscordPermissionOverwrite`] applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiscordOverwriteType {
+    /// 0: the overwrite's id is a role id
+    Role,
+    /// 1: the overwrite's id is a user id
+    Member,
+    /// Anything Discord added since this was written
+    Unknown(u8),
+}
+
+impl From<DiscordOverwriteTypeCode> for DiscordOverwriteType {
+    fn from(code: DiscordOverwriteTypeCode) -> Self {
+        match code.0 {
+            0 => Self::Role,
+            1 => Self::Member,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<DiscordOverwriteType> for DiscordOverwriteTypeCode {
+    fn from(kind: DiscordOverwriteType) -> Self {
+        Self(match kind {
+            DiscordOverwriteType::Role => 0,
+            DiscordOverwriteType::Member => 1,
+            DiscordOverwriteType::Unknown(other) => other,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Facet)]
 struct DiscordMessagePayload {
     content: String,
+    allowed_mentions: DiscordAllowedMentions,
+}
+
+/// Which mentions in a message actually notify people. Mentions that aren't
+/// allowed still render, they just don't ping.
+#[derive(Debug, Clone, Default, Facet)]
+pub struct DiscordAllowedMentions {
+    /// Mention kinds that are parsed from the content: "roles", "users" and/or
+    /// "everyone" (which covers `@here` too)
+    pub parse: Vec<String>,
+    /// Users that may be pinged
+    pub users: Vec<DiscordUserId>,
+    /// Roles that may be pinged
+    pub roles: Vec<DiscordRoleId>,
+}
+
+impl DiscordAllowedMentions {
+    /// Nobody gets pinged
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Lets `@everyone` and `@here` ping
+    pub fn with_everyone(mut self) -> Self {
+        self.parse.push("everyone".to_string());
+        self
+    }
+
+    /// Lets mentions of this user ping them
+    pub fn with_user(mut self, user_id: DiscordUserId) -> Self {
+        self.users.push(user_id);
+        self
+    }
+
+    /// Lets mentions of this role ping its members
+    pub fn with_role(mut self, role_id: DiscordRoleId) -> Self {
+        self.roles.push(role_id);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -539,6 +885,20 @@ pub struct DiscordMessage {
     pub mention_everyone: bool,
 }
 
+impl DiscordMessage {
+    pub fn timestamp_parsed(&self) -> Result<OffsetDateTime> {
+        parse_discord_timestamp(&self.timestamp)
+    }
+
+    /// `None` if the message was never edited
+    pub fn edited_timestamp_parsed(&self) -> Result<Option<OffsetDateTime>> {
+        self.edited_timestamp
+            .as_deref()
+            .map(parse_discord_timestamp)
+            .transpose()
+    }
+}
+
 fn v10_uri(path: &str, query_params: &[(&str, &str)]) -> eyre::Result<Uri> {
     if !path.starts_with('/') {
         panic!("someone forgot the leading slash in libdiscord");
@@ -557,51 +917,315 @@ fn v10_uri(path: &str, query_params: &[(&str, &str)]) -> eyre::Result<Uri> {
         .map_err(|e| eyre::eyre!("Invalid URL: {}", e))
 }
 
-async fn text_req(
-    tc: &TenantConfig,
+impl ModImpl {
+    fn new(opts: ModOptions) -> Self {
+        Self {
+            client: Arc::from(libhttpclient::load().client()),
+            limits: opts.request_limits,
+            profile_cache: ProfileCache::new(opts.profile_cache_ttl, PROFILE_CACHE_CAPACITY),
+        }
+    }
+
+    async fn text_req(
+        &self,
+        tc: &TenantConfig,
+        req: Box<dyn libhttpclient::RequestBuilder>,
+    ) -> eyre::Result<String> {
+        let discord_secrets = tc.discord_secrets()?;
+
+        let req = req
+            .polite_user_agent()
+            .header(
+                HeaderName::from_static("content-type"),
+                HeaderValue::from_static("application/json"),
+            )
+            .header(
+                HeaderName::from_static("authorization"),
+                HeaderValue::from_str(&format!("Bot {}", discord_secrets.bot_token))
+                    .map_err(|e| eyre::eyre!("Invalid bot token: {}", e))?,
+            );
+        send_limited(req, self.limits).await
+    }
+
+    async fn json_req<T: for<'de> Facet<'de>>(
+        &self,
+        tc: &TenantConfig,
+        req: Box<dyn libhttpclient::RequestBuilder>,
+    ) -> eyre::Result<T> {
+        let text = self.text_req(tc, req).await?;
+        match facet_json::from_str::<T>(&text) {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                log::warn!("Failed to parse response: {e}");
+                log::warn!("Full response text: {text}");
+                Err(eyre::eyre!("Failed to parse response: {e}"))
+            }
+        }
+    }
+}
+
+/// Sends `req` and reads the body as text, within `limits`
+async fn send_limited(
     req: Box<dyn libhttpclient::RequestBuilder>,
+    limits: DiscordRequestLimits,
 ) -> eyre::Result<String> {
-    let discord_secrets = tc.discord_secrets()?;
+    let DiscordRequestLimits {
+        timeout,
+        max_body_size,
+    } = limits;
 
-    let res = req
-        .polite_user_agent()
-        .header(
-            HeaderName::from_static("content-type"),
-            HeaderValue::from_static("application/json"),
-        )
-        .header(
-            HeaderName::from_static("authorization"),
-            HeaderValue::from_str(&format!("Bot {}", discord_secrets.bot_token))
-                .map_err(|e| eyre::eyre!("Invalid bot token: {}", e))?,
-        )
-        .send()
-        .await
-        .wrap_err("While sending request")?;
+    let work = async move {
+        let res = req.send().await.wrap_err("While sending request")?;
 
-    if !res.status().is_success() {
         let status = res.status();
-        let error = res
-            .text()
+        if !status.is_success() {
+            return Err(match res.bytes_limited(max_body_size).await {
+                Ok(body) => error_from_body(status, &body),
+                Err(e) => eyre::eyre!("got HTTP {status}, could not read error body: {e}"),
+            });
+        }
+
+        let body = res
+            .bytes_limited(max_body_size)
             .await
-            .unwrap_or_else(|_| "Could not get error text".into());
-        return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
-    }
+            .wrap_err("While reading Discord's response")?;
+        Ok(String::from_utf8(body)?)
+    };
 
-    let text = res.text().await?;
-    Ok(text)
+    tokio::time::timeout(timeout, work)
+        .await
+        .map_err(|_| eyre::eyre!("Discord didn't send a full response within {timeout:?}"))?
 }
 
-async fn json_req<T: for<'de> Facet<'de>>(
-    tc: &TenantConfig,
-    req: Box<dyn libhttpclient::RequestBuilder>,
-) -> eyre::Result<T> {
-    let text = text_req(tc, req).await?;
-    match facet_json::from_str::<T>(&text) {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            log::warn!("Failed to parse response: {e}");
-            log::warn!("Full response text: {text}");
-            Err(eyre::eyre!("Failed to parse response: {e}"))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libhttpclient::test_server::{Reply, TestServer};
+
+    #[test]
+    fn test_token_response_missing_a_scope() {
+        let response = |scope: &str| {
+            format!(
+                r#"{{"access_token":"access","refresh_token":"refresh","token_type":"Bearer","scope":"{scope}","expires_in":604800}}"#
+            )
+        };
+
+        let grant = grant_from_token_response(&response("identify"), true).unwrap();
+        assert!(grant.scope_downgrade.is_none());
+
+        let grant = grant_from_token_response(&response("email"), false).unwrap();
+        assert_eq!(grant.creds.access_token, "access");
+        let downgrade = grant.scope_downgrade.unwrap();
+        assert_eq!(downgrade.provider, "discord");
+        assert_eq!(downgrade.missing, vec!["identify".to_string()]);
+        assert_eq!(downgrade.granted, vec!["email".to_string()]);
+
+        assert!(grant_from_token_response(&response("email"), true).is_err());
+    }
+
+    #[test]
+    fn test_parse_guild_details_with_boosts() {
+        let sample = r#"{
+            "id": "197038439483310086",
+            "name": "Discord Testers",
+            "icon": "f64c482b807da4f539cff778d174971c",
+            "description": "The official place to report Discord Bugs!",
+            "splash": null,
+            "discovery_splash": null,
+            "features": ["ANIMATED_ICON", "COMMUNITY", "VANITY_URL"],
+            "emojis": [],
+            "banner": "9b6439a7de04f1d26af92f84ac9e1e4a",
+            "owner_id": "73193882359173120",
+            "region": null,
+            "afk_channel_id": null,
+            "afk_timeout": 300,
+            "verification_level": 3,
+            "default_message_notifications": 1,
+            "roles": [],
+            "mfa_level": 1,
+            "premium_tier": 3,
+            "premium_subscription_count": 33,
+            "preferred_locale": "en-US",
+            "approximate_member_count": 60814,
+            "approximate_presence_count": 20034
+        }"#;
+        let guild = facet_json::from_str::<DiscordGuildDetails>(sample).unwrap();
+        assert_eq!(guild.id.as_str(), "197038439483310086");
+        assert_eq!(guild.owner_id.as_str(), "73193882359173120");
+        assert_eq!(guild.premium_tier, 3);
+        assert_eq!(guild.premium_subscription_count, Some(33));
+        assert_eq!(guild.approximate_member_count, Some(60814));
+        assert_eq!(guild.approximate_presence_count, Some(20034));
+
+        // counts are only there when asked for, and boosts can be missing
+        let sample = r#"{
+            "id": "1",
+            "name": "Tiny",
+            "icon": null,
+            "owner_id": "2",
+            "features": [],
+            "premium_tier": 0
+        }"#;
+        let guild = facet_json::from_str::<DiscordGuildDetails>(sample).unwrap();
+        assert_eq!(guild.premium_tier, 0);
+        assert_eq!(guild.premium_subscription_count, None);
+        assert_eq!(guild.approximate_member_count, None);
+        assert_eq!(guild.banner, None);
+    }
+
+    #[test]
+    fn test_parse_member_timestamps() {
+        let sample = r#"{
+            "user": null,
+            "nick": "amos",
+            "roles": ["41771983423143936"],
+            "joined_at": "2015-04-26T06:26:56.936000+00:00",
+            "premium_since": null
+        }"#;
+        let member = facet_json::from_str::<DiscordGuildMember>(sample).unwrap();
+        let joined_at = member.joined_at_parsed().unwrap().unwrap();
+        assert_eq!(joined_at.unix_timestamp(), 1430029616);
+        assert_eq!(joined_at.millisecond(), 936);
+        assert_eq!(member.premium_since_parsed().unwrap(), None);
+
+        let member = DiscordGuildMember {
+            joined_at: Some("last tuesday".to_string()),
+            ..member
+        };
+        assert!(member.joined_at_parsed().is_err());
+    }
+
+    #[test]
+    fn test_channel_and_overwrite_types() {
+        let sample = r#"[
+            {
+                "id": "1",
+                "name": "general",
+                "type": 0,
+                "permission_overwrites": [
+                    { "id": "10", "type": 0, "allow": "0", "deny": "1024" },
+                    { "id": "20", "type": 1, "allow": "1024", "deny": "0" },
+                    { "id": "30", "type": 7, "allow": "0", "deny": "0" }
+                ]
+            },
+            { "id": "2", "name": "Voice", "type": 2 },
+            { "id": "3", "name": "Community", "type": 4 },
+            { "id": "4", "name": "announcements", "type": 5, "parent_id": "3" },
+            { "id": "5", "name": "help", "type": 15, "parent_id": "3" },
+            { "id": "6", "name": "from-the-future", "type": 42 }
+        ]"#;
+        let channels = facet_json::from_str::<Vec<DiscordChannel>>(sample).unwrap();
+        let kinds = channels.iter().map(|c| c.kind()).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                DiscordChannelType::Text,
+                DiscordChannelType::Voice,
+                DiscordChannelType::Category,
+                DiscordChannelType::Announcement,
+                DiscordChannelType::Forum,
+                DiscordChannelType::Unknown(42),
+            ]
+        );
+
+        let overwrites = channels[0]
+            .permission_overwrites
+            .iter()
+            .map(|o| o.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            overwrites,
+            vec![
+                DiscordOverwriteType::Role,
+                DiscordOverwriteType::Member,
+                DiscordOverwriteType::Unknown(7),
+            ]
+        );
+
+        // codes survive the round trip, unknown ones included
+        for code in 0..=u8::MAX {
+            let kind = DiscordChannelType::from(DiscordChannelTypeCode(code));
+            assert_eq!(
+                DiscordChannelTypeCode::from(kind),
+                DiscordChannelTypeCode(code)
+            );
+            let kind = DiscordOverwriteType::from(DiscordOverwriteTypeCode(code));
+            assert_eq!(
+                DiscordOverwriteTypeCode::from(kind),
+                DiscordOverwriteTypeCode(code)
+            );
         }
+        assert!(DiscordChannelType::PublicThread.is_thread());
+        assert!(!DiscordChannelType::Forum.is_thread());
+    }
+
+    #[test]
+    fn test_message_payload_pings_nobody_by_default() {
+        let payload = DiscordMessagePayload {
+            content: "@everyone thanks <@80351110224678912> for sponsoring!".to_string(),
+            allowed_mentions: DiscordAllowedMentions::none(),
+        };
+        let json = facet_json::to_string(&payload);
+        assert!(
+            json.contains(r#""allowed_mentions":{"parse":[],"users":[],"roles":[]}"#),
+            "{json}"
+        );
+
+        let payload = DiscordMessagePayload {
+            content: "@everyone thanks <@80351110224678912> for sponsoring!".to_string(),
+            allowed_mentions: DiscordAllowedMentions::none()
+                .with_user(DiscordUserId::new("80351110224678912".to_string()))
+                .with_everyone(),
+        };
+        let json = facet_json::to_string(&payload);
+        assert!(
+            json.contains(
+                r#""allowed_mentions":{"parse":["everyone"],"users":["80351110224678912"],"roles":[]}"#
+            ),
+            "{json}"
+        );
+    }
+
+    async fn serve(reply: Reply) -> Uri {
+        TestServer::always(reply).await.uri("/api/v10/guilds")
+    }
+
+    const TEST_LIMITS: DiscordRequestLimits = DiscordRequestLimits {
+        timeout: std::time::Duration::from_millis(200),
+        max_body_size: 1024,
+    };
+
+    fn get(uri: Uri) -> Box<dyn libhttpclient::RequestBuilder> {
+        libhttpclient::load().client().get(uri)
+    }
+
+    #[tokio::test]
+    async fn test_slow_response_times_out() {
+        // headers right away, but the body never finishes in time
+        let uri = serve(
+            Reply::new("200 OK")
+                .body("[{}]")
+                .body_delay(std::time::Duration::from_secs(5)),
+        )
+        .await;
+        let err = send_limited(get(uri), TEST_LIMITS).await.unwrap_err();
+        assert!(format!("{err:#}").contains("within 200ms"), "{err:#}");
+
+        let uri = serve(
+            Reply::new("200 OK")
+                .body("[]")
+                .body_delay(std::time::Duration::from_millis(10)),
+        )
+        .await;
+        assert_eq!(send_limited(get(uri), TEST_LIMITS).await.unwrap(), "[]");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_response_is_refused() {
+        let uri = serve(Reply::new("200 OK").body(vec![b' '; 4096])).await;
+
+        let err = send_limited(get(uri), TEST_LIMITS).await.unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("over the 1024 bytes limit"), "{err}");
     }
 }
@@ -1,10 +1,18 @@
 use std::{fmt, io::Write};
 
+use config_types::Environment;
+
+use crate::DoctorOptions;
+
 mod commands;
 use commands::check_all_commands;
 
+mod config;
+use config::{RealProbes, check_config};
+
 enum DoctorError {
     BinaryNotFound(FailedCheck),
+    ConfigCheckFailed { name: String, reason: String },
 }
 
 impl DoctorError {
@@ -14,6 +22,7 @@ impl DoctorError {
                 Gravity::Needed => true,
                 Gravity::Recommended => false,
             },
+            DoctorError::ConfigCheckFailed { .. } => true,
         }
     }
 }
@@ -58,11 +67,15 @@ impl fmt::Display for DoctorError {
                 failed_check.required_bin.purpose,
                 failed_check.required_bin.notes
             ),
+            DoctorError::ConfigCheckFailed { name, reason } => write!(
+                f,
+                "\x1b[31mCheck '\x1b[1m{name}\x1b[0m\x1b[31m' failed: {reason}\x1b[0m"
+            ),
         }
     }
 }
 
-pub(crate) async fn doctor() -> Result<(), i32> {
+pub(crate) async fn doctor(opts: DoctorOptions) -> Result<(), i32> {
     // rule: we only output to stderr, never stdout
 
     use std::sync::Arc;
@@ -85,12 +98,29 @@ pub(crate) async fn doctor() -> Result<(), i32> {
 
     let mut errors = Vec::new();
     errors.extend(check_all_commands().await);
+    let outcomes = check_config(&opts, Environment::default(), &RealProbes).await;
 
     done.store(true, Ordering::Relaxed);
     spinner.join().unwrap();
     eprint!("\r                                               \r");
     std::io::stderr().flush().unwrap();
 
+    if !outcomes.is_empty() {
+        eprintln!("Config checklist:");
+    }
+    for outcome in outcomes {
+        match outcome.result {
+            Ok(()) => eprintln!("  \x1b[32m✓\x1b[0m {}", outcome.name),
+            Err(reason) => {
+                eprintln!("  \x1b[31m✗\x1b[0m {}", outcome.name);
+                errors.push(DoctorError::ConfigCheckFailed {
+                    name: outcome.name,
+                    reason,
+                });
+            }
+        }
+    }
+
     if !errors.is_empty() {
         eprintln!("\nDoctor encountered {} errors", errors.len());
 
@@ -0,0 +1,390 @@
+use config_types::{AwsSecrets, CubConfigBundle, Environment, ObjectStorageConfig, TenantConfig};
+use futures_core::future::BoxFuture;
+use libhttpclient::{StatusCode, Uri};
+use objectstore_types::ObjectStoreKey;
+
+use crate::DoctorOptions;
+
+/// The result of a single config check, one line of the checklist. They're
+/// all critical: if any fails, the doctor exits non-zero.
+pub(crate) struct CheckOutcome {
+    pub(crate) name: String,
+    pub(crate) result: Result<(), String>,
+}
+
+impl CheckOutcome {
+    fn new(name: impl Into<String>, result: Result<(), String>) -> Self {
+        Self {
+            name: name.into(),
+            result,
+        }
+    }
+}
+
+/// Everything the checks need from the outside world, so tests can fake it
+pub(crate) trait Probes: Send + Sync {
+    /// Loads the cub config (and tenants, if serving roots)
+    fn load_config(&self, opts: &DoctorOptions) -> eyre::Result<CubConfigBundle>;
+
+    /// Loads a mom-style tenant config file
+    fn load_tenant_config(&self, opts: &DoctorOptions) -> eyre::Result<Vec<TenantConfig>>;
+
+    /// Hits mom's `/health` endpoint
+    fn mom_health<'fut>(&'fut self, mom_base_url: &'fut str) -> BoxFuture<'fut, eyre::Result<()>>;
+
+    /// Reads a key that doesn't exist: "not found" means the credentials work
+    fn objectstore_access<'fut>(
+        &'fut self,
+        config: &'fut ObjectStorageConfig,
+        secrets: &'fut AwsSecrets,
+    ) -> BoxFuture<'fut, eyre::Result<()>>;
+}
+
+pub(crate) struct RealProbes;
+
+impl Probes for RealProbes {
+    fn load_config(&self, opts: &DoctorOptions) -> eyre::Result<CubConfigBundle> {
+        libconfig::load().load_cub_config(opts.config.as_deref(), opts.roots.clone())
+    }
+
+    fn load_tenant_config(&self, opts: &DoctorOptions) -> eyre::Result<Vec<TenantConfig>> {
+        let Some(path) = opts.tenant_config.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let contents = fs_err::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    fn mom_health<'fut>(&'fut self, mom_base_url: &'fut str) -> BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let uri: Uri = format!("{mom_base_url}/health").parse()?;
+            let res = libhttpclient::load()
+                .client()
+                .get(uri.clone())
+                .send()
+                .await?;
+            if res.status() != StatusCode::OK {
+                return Err(eyre::eyre!("got HTTP {} from {uri}", res.status()));
+            }
+            Ok(())
+        })
+    }
+
+    fn objectstore_access<'fut>(
+        &'fut self,
+        config: &'fut ObjectStorageConfig,
+        secrets: &'fut AwsSecrets,
+    ) -> BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let store = libobjectstore::load().s3(config, secrets)?;
+            let key = ObjectStoreKey::new("home-doctor/does-not-exist".to_string());
+            match store.get(&key).await {
+                Ok(_) => Ok(()),
+                Err(e) if e.is_not_found() => Ok(()),
+                Err(e) => Err(eyre::eyre!("{e}: {}", e.source)),
+            }
+        })
+    }
+}
+
+/// Runs every config check, in checklist order. Returns nothing if we weren't
+/// asked to look at a config.
+pub(crate) async fn check_config(
+    opts: &DoctorOptions,
+    env: Environment,
+    probes: &dyn Probes,
+) -> Vec<CheckOutcome> {
+    if !opts.has_config() {
+        return Vec::new();
+    }
+
+    let mut outcomes = Vec::new();
+
+    let bundle = match probes.load_config(opts) {
+        Ok(bundle) => {
+            outcomes.push(CheckOutcome::new("cub config loads", Ok(())));
+            bundle
+        }
+        Err(e) => {
+            outcomes.push(CheckOutcome::new("cub config loads", Err(e.to_string())));
+            return outcomes;
+        }
+    };
+
+    let mut tenants: Vec<TenantConfig> = bundle.tenants.into_values().map(|ti| ti.tc).collect();
+    match probes.load_tenant_config(opts) {
+        Ok(more) => tenants.extend(more),
+        Err(e) => outcomes.push(CheckOutcome::new("tenant config loads", Err(e.to_string()))),
+    }
+    tenants.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+
+    for tc in &tenants {
+        outcomes.push(check_tenant(tc, env));
+    }
+
+    outcomes.push(check_mom(probes, &bundle.cc.mom_base_url).await);
+
+    for tc in &tenants {
+        if let Some(outcome) = check_objectstore(probes, tc).await {
+            outcomes.push(outcome);
+        }
+    }
+
+    outcomes
+}
+
+pub(crate) fn check_tenant(tc: &TenantConfig, env: Environment) -> CheckOutcome {
+    CheckOutcome::new(
+        format!("tenant {} is valid", tc.name),
+        tc.validate(env).map_err(|problems| problems.join("; ")),
+    )
+}
+
+pub(crate) async fn check_mom(probes: &dyn Probes, mom_base_url: &str) -> CheckOutcome {
+    CheckOutcome::new(
+        format!("mom is reachable at {mom_base_url}"),
+        probes
+            .mom_health(mom_base_url)
+            .await
+            .map_err(|e| e.to_string()),
+    )
+}
+
+/// Only for tenants that have object storage configured
+pub(crate) async fn check_objectstore(
+    probes: &dyn Probes,
+    tc: &TenantConfig,
+) -> Option<CheckOutcome> {
+    let config = tc.object_storage.as_ref()?;
+    let name = format!("object storage credentials work for {}", tc.name);
+    let Some(secrets) = tc.secrets.as_ref() else {
+        return Some(CheckOutcome::new(name, Err("no secrets".to_string())));
+    };
+    Some(CheckOutcome::new(
+        name,
+        probes
+            .objectstore_access(config, &secrets.aws)
+            .await
+            .map_err(|e| e.to_string()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use camino::Utf8PathBuf;
+    use config_types::{
+        CubConfig, S3BucketName, S3RegionName, TenantDomain, TenantInfo, TenantSecrets,
+    };
+
+    use super::*;
+
+    struct FakeProbes {
+        tenants: Vec<TenantConfig>,
+        config_error: Option<&'static str>,
+        mom_error: Option<&'static str>,
+        objectstore_error: Option<&'static str>,
+    }
+
+    impl Default for FakeProbes {
+        fn default() -> Self {
+            Self {
+                tenants: vec![TenantConfig::new(td("a.example"))],
+                config_error: None,
+                mom_error: None,
+                objectstore_error: None,
+            }
+        }
+    }
+
+    fn td(s: &str) -> TenantDomain {
+        TenantDomain::new(s.to_string())
+    }
+
+    fn err(e: Option<&'static str>) -> eyre::Result<()> {
+        match e {
+            Some(e) => Err(eyre::eyre!(e)),
+            None => Ok(()),
+        }
+    }
+
+    impl Probes for FakeProbes {
+        fn load_config(&self, _opts: &DoctorOptions) -> eyre::Result<CubConfigBundle> {
+            err(self.config_error)?;
+            let cc: CubConfig = serde_json::from_str("{}")?;
+            let tenants = self
+                .tenants
+                .iter()
+                .map(|tc| {
+                    let ti = TenantInfo {
+                        base_dir: Utf8PathBuf::from("/tmp").join(tc.name.as_str()),
+                        tc: tc.clone(),
+                    };
+                    (tc.name.clone(), ti)
+                })
+                .collect::<HashMap<_, _>>();
+            Ok(CubConfigBundle { cc, tenants })
+        }
+
+        fn load_tenant_config(&self, _opts: &DoctorOptions) -> eyre::Result<Vec<TenantConfig>> {
+            Ok(Vec::new())
+        }
+
+        fn mom_health<'fut>(&'fut self, _url: &'fut str) -> BoxFuture<'fut, eyre::Result<()>> {
+            Box::pin(async move { err(self.mom_error) })
+        }
+
+        fn objectstore_access<'fut>(
+            &'fut self,
+            _config: &'fut ObjectStorageConfig,
+            _secrets: &'fut AwsSecrets,
+        ) -> BoxFuture<'fut, eyre::Result<()>> {
+            Box::pin(async move { err(self.objectstore_error) })
+        }
+    }
+
+    fn opts() -> DoctorOptions {
+        DoctorOptions {
+            config: Some(Utf8PathBuf::from("cub-config.json")),
+            ..Default::default()
+        }
+    }
+
+    fn with_storage(name: &str) -> TenantConfig {
+        let mut tc = TenantConfig::new(td(name));
+        tc.object_storage = Some(ObjectStorageConfig {
+            bucket: S3BucketName::new("bucket".to_string()),
+            region: S3RegionName::new("eu-west-3".to_string()),
+            endpoint: None,
+        });
+        tc.secrets = Some(TenantSecrets {
+            aws: AwsSecrets {
+                access_key_id: "id".to_string(),
+                secret_access_key: "secret".to_string(),
+            },
+            patreon: None,
+            github: None,
+            discord: None,
+            stripe: None,
+            git: None,
+            cookie_sauce: None,
+        });
+        tc
+    }
+
+    fn summary(outcomes: &[CheckOutcome]) -> Vec<(String, bool)> {
+        outcomes
+            .iter()
+            .map(|o| (o.name.clone(), o.result.is_ok()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_no_config_no_checks() {
+        let outcomes = check_config(
+            &DoctorOptions::default(),
+            Environment::Development,
+            &FakeProbes::default(),
+        )
+        .await;
+        assert!(outcomes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_all_checks_pass() {
+        let probes = FakeProbes {
+            tenants: vec![
+                with_storage("b.example"),
+                TenantConfig::new(td("a.example")),
+            ],
+            ..Default::default()
+        };
+        let outcomes = check_config(&opts(), Environment::Development, &probes).await;
+        assert_eq!(
+            summary(&outcomes),
+            vec![
+                ("cub config loads".to_string(), true),
+                ("tenant a.example is valid".to_string(), true),
+                ("tenant b.example is valid".to_string(), true),
+                (
+                    "mom is reachable at http://localhost:1118".to_string(),
+                    true
+                ),
+                (
+                    "object storage credentials work for b.example".to_string(),
+                    true
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_that_does_not_load_stops_there() {
+        let probes = FakeProbes {
+            config_error: Some("expected value at line 1 column 1"),
+            ..Default::default()
+        };
+        let outcomes = check_config(&opts(), Environment::Development, &probes).await;
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(
+            outcomes[0].result.as_ref().unwrap_err(),
+            "expected value at line 1 column 1"
+        );
+    }
+
+    #[test]
+    fn test_check_tenant() {
+        let tc = TenantConfig::new(td("a.example"));
+        assert!(check_tenant(&tc, Environment::Development).result.is_ok());
+
+        let outcome = check_tenant(&tc, Environment::Production);
+        let problems = outcome.result.unwrap_err();
+        assert!(
+            problems.contains("object_storage must be set"),
+            "{problems}"
+        );
+        assert!(problems.contains("secrets must be set"), "{problems}");
+    }
+
+    #[tokio::test]
+    async fn test_check_mom() {
+        let probes = FakeProbes {
+            mom_error: Some("connection refused"),
+            ..Default::default()
+        };
+        let outcome = check_mom(&probes, "http://localhost:1118").await;
+        assert_eq!(outcome.result.unwrap_err(), "connection refused");
+
+        let outcome = check_mom(&FakeProbes::default(), "http://localhost:1118").await;
+        assert!(outcome.result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_check_objectstore() {
+        let probes = FakeProbes {
+            objectstore_error: Some("access denied"),
+            ..Default::default()
+        };
+
+        // nothing to check without object storage
+        let tc = TenantConfig::new(td("a.example"));
+        assert!(check_objectstore(&probes, &tc).await.is_none());
+
+        let tc = with_storage("a.example");
+        let outcome = check_objectstore(&probes, &tc).await.unwrap();
+        assert_eq!(outcome.result.unwrap_err(), "access denied");
+
+        let outcome = check_objectstore(&FakeProbes::default(), &tc)
+            .await
+            .unwrap();
+        assert!(outcome.result.is_ok());
+
+        let mut tc = with_storage("a.example");
+        tc.secrets = None;
+        let outcome = check_objectstore(&FakeProbes::default(), &tc)
+            .await
+            .unwrap();
+        assert_eq!(outcome.result.unwrap_err(), "no secrets");
+    }
+}
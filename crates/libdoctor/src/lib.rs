@@ -1,8 +1,29 @@
 use autotrait::autotrait;
+use camino::Utf8PathBuf;
 use futures_core::future::BoxFuture;
 
 mod impls;
 
+/// What to check, on top of the binaries we depend on
+#[derive(Default, Clone)]
+pub struct DoctorOptions {
+    /// cub config file, as passed to `home serve --config`
+    pub config: Option<Utf8PathBuf>,
+
+    /// tenant roots, as passed to `home serve`
+    pub roots: Vec<Utf8PathBuf>,
+
+    /// mom-style tenant config file (a JSON array of tenant configs)
+    pub tenant_config: Option<Utf8PathBuf>,
+}
+
+impl DoctorOptions {
+    /// Whether we've been pointed at a config at all
+    pub fn has_config(&self) -> bool {
+        self.config.is_some() || !self.roots.is_empty() || self.tenant_config.is_some()
+    }
+}
+
 struct ModImpl;
 
 pub fn load() -> &'static dyn Mod {
@@ -12,10 +33,11 @@ pub fn load() -> &'static dyn Mod {
 
 #[autotrait]
 impl Mod for ModImpl {
-    /// Load all modules once to make sure that they actually work
-    fn run(&self) -> BoxFuture<'static, ()> {
+    /// Load all modules once to make sure that they actually work, then check
+    /// the config if we were given one
+    fn run(&self, opts: DoctorOptions) -> BoxFuture<'static, ()> {
         Box::pin(async move {
-            if let Err(code) = impls::doctor().await {
+            if let Err(code) = impls::doctor(opts).await {
                 eprintln!("doctor failed, exiting with code {code}");
                 std::process::exit(code);
             }
@@ -1,22 +1,62 @@
 #![allow(non_snake_case)]
 
+use std::{sync::OnceLock, time::Duration};
+
 use autotrait::autotrait;
-use credentials::{GithubProfile, GithubUserId, UserId};
+use credentials::{
+    Credentials, GithubProfile, GithubUserId, OAuthGrant, PROFILE_CACHE_CAPACITY, ProfileCache,
+    ScopeDowngrade, UserId,
+};
 use facet::Facet;
 use futures_core::future::BoxFuture;
-use libhttpclient::{HeaderValue, HttpClient, Uri, header};
+use libhttpclient::{HeaderValue, HttpClient, RetryPolicy, Uri, decode_error_response, header};
 
 use config_types::{TenantConfig, WebConfig};
 use eyre::{Context, Result};
 use log::debug;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_encode};
 use time::OffsetDateTime;
 
-#[derive(Default)]
-struct ModImpl;
+struct ModImpl {
+    profile_cache: ProfileCache<GithubProfile>,
+}
+
+/// What the module is set up with, see [`load_with`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModOptions {
+    /// How long profile fetches are cached for, per access token. Not cached
+    /// at all when unset.
+    pub profile_cache_ttl: Option<Duration>,
+}
 
+static MOD: OnceLock<ModImpl> = OnceLock::new();
+
+/// Returns the module, loading it with the default options if nothing
+/// called [`load_with`] first
 pub fn load() -> &'static dyn Mod {
-    static MOD: ModImpl = ModImpl;
-    &MOD
+    MOD.get_or_init(|| ModImpl::new(ModOptions::default()))
+}
+
+/// Loads the module with `opts`. That's only possible once, before anything
+/// calls [`load`]: after that, this is an error.
+pub fn load_with(opts: ModOptions) -> Result<&'static dyn Mod> {
+    let mut loaded_here = false;
+    let m = MOD.get_or_init(|| {
+        loaded_here = true;
+        ModImpl::new(opts)
+    });
+    if !loaded_here {
+        eyre::bail!("the GitHub module was already loaded, can't set it up with {opts:?}");
+    }
+    Ok(m)
+}
+
+impl ModImpl {
+    fn new(opts: ModOptions) -> Self {
+        Self {
+            profile_cache: ProfileCache::new(opts.profile_cache_ttl, PROFILE_CACHE_CAPACITY),
+        }
+    }
 }
 
 #[autotrait]
@@ -46,9 +86,11 @@ impl Mod for ModImpl {
         tc: &'fut TenantConfig,
         web: WebConfig,
         args: &'fut GithubCallbackArgs,
-    ) -> BoxFuture<'fut, Result<Option<GithubCredentials>>> {
+    ) -> BoxFuture<'fut, Result<Option<OAuthGrant<GithubCredentials>>>> {
         Box::pin(async move {
-            let code = match url::form_urlencoded::parse(args.raw_query.as_bytes())
+            let query = url::form_urlencoded::parse(args.raw_query.as_bytes());
+            let code = match query
+                .clone()
                 .find(|(key, _)| key == "code")
                 .map(|(_, value)| value.into_owned())
             {
@@ -56,7 +98,6 @@ impl Mod for ModImpl {
                 None => return Ok(None),
                 Some(code) => code,
             };
-
             let gh_sec = tc.github_secrets()?;
 
             let res = libhttpclient::load()
@@ -71,45 +112,14 @@ impl Mod for ModImpl {
                     ("code", code.as_ref()),
                 ])
                 .header(header::ACCEPT, HeaderValue::from_static("application/json"))
+                .retry(RetryPolicy::QUICK)
                 .send_and_expect_200()
                 .await
                 .wrap_err("While getting GitHub access token")?;
 
             let text = res.text().await?;
-            let creds = match facet_json::from_str::<GithubCredentialsAPI>(&text) {
-                Ok(c) => c,
-                Err(_) => {
-                    log::warn!("Got GitHub auth error: {text}");
-
-                    // Try to deserialize as GitHub error response for better error messages
-                    if let Ok(error_response) = facet_json::from_str::<GithubErrorResponse>(&text) {
-                        return Err(eyre::eyre!(
-                            "GitHub auth error: {} - {}{}",
-                            error_response.error,
-                            error_response.error_description,
-                            error_response
-                                .error_uri
-                                .as_ref()
-                                .map(|uri| format!(" (see: {uri})"))
-                                .unwrap_or_default()
-                        ));
-                    } else {
-                        return Err(eyre::eyre!("Got Github auth error: {text}"));
-                    }
-                }
-            };
-            log::info!(
-                "Successfully obtained GitHub token with scope {}",
-                &creds.scope
-            );
-
-            let creds = GithubCredentials {
-                access_token: creds.access_token,
-                scope: creds.scope,
-                expires_at: OffsetDateTime::now_utc() + default_expires_in(),
-            };
-
-            Ok(Some(creds))
+            let grant = grant_from_token_response(&text, &args.purpose, args.strict_scopes)?;
+            Ok(Some(grant))
         })
     }
 
@@ -119,6 +129,10 @@ impl Mod for ModImpl {
         client: &'fut dyn HttpClient,
     ) -> BoxFuture<'fut, Result<GithubProfile>> {
         Box::pin(async move {
+            if let Some(profile) = self.profile_cache.get(&creds.access_token) {
+                return Ok(profile);
+            }
+
             #[derive(Facet)]
             struct GraphqlQuery {
                 query: String,
@@ -147,6 +161,8 @@ impl Mod for ModImpl {
                 login: String,
                 name: Option<String>,
                 avatarUrl: String,
+                /// public profile email, empty if not set
+                email: String,
             }
 
             #[derive(Facet)]
@@ -185,12 +201,7 @@ impl Mod for ModImpl {
                 .await?;
 
             if !res.status().is_success() {
-                let status = res.status();
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
+                return Err(decode_error_response(res).await);
             }
 
             let response = res
@@ -222,9 +233,12 @@ impl Mod for ModImpl {
                 name: viewer.name.clone(),
                 login: viewer.login.clone(),
                 avatar_url: Some(viewer.avatarUrl.clone()),
+                email: Some(viewer.email.clone()).filter(|email| !email.is_empty()),
             };
 
             log::info!("GitHub profile: {profile:#?}");
+            self.profile_cache
+                .insert(&creds.access_token, profile.clone());
             Ok(profile)
         })
     }
@@ -269,192 +283,55 @@ impl Mod for ModImpl {
         })
     }
 
+    /// Whether `login` is a member of the `org` organization. GitHub only
+    /// shows private memberships to members of the org: if the owner of
+    /// `creds` isn't one, only public memberships count.
+    fn is_org_member<'fut>(
+        &'fut self,
+        client: &'fut dyn HttpClient,
+        creds: &'fut GithubCredentials,
+        org: &'fut str,
+        login: &'fut str,
+    ) -> BoxFuture<'fut, Result<bool>> {
+        Box::pin(org_membership(client, GITHUB_API_BASE, creds, org, login))
+    }
+
+    /// Whether `login` is an active (not just invited) member of the
+    /// `team_slug` team of the `org` organization. Secret teams are only
+    /// visible to members of the org.
+    fn is_team_member<'fut>(
+        &'fut self,
+        client: &'fut dyn HttpClient,
+        creds: &'fut GithubCredentials,
+        org: &'fut str,
+        team_slug: &'fut str,
+        login: &'fut str,
+    ) -> BoxFuture<'fut, Result<bool>> {
+        Box::pin(team_membership(
+            client,
+            GITHUB_API_BASE,
+            creds,
+            org,
+            team_slug,
+            login,
+        ))
+    }
+
+    /// A page of sponsors over `max_page_size` bytes fails the whole listing
     fn list_sponsors<'fut>(
         &'fut self,
+        tc: &'fut TenantConfig,
         client: &'fut dyn HttpClient,
         github_creds: &'fut GithubCredentials,
+        max_page_size: usize,
     ) -> BoxFuture<'fut, Result<Vec<GithubProfile>>> {
-        Box::pin(async move {
-            let mut github_profiles: Vec<GithubProfile> = Vec::new();
-            let query = include_str!("github_sponsors.graphql");
-
-            #[derive(Facet)]
-            struct GraphqlQuery {
-                query: String,
-                variables: Variables,
-            }
-
-            #[derive(Facet)]
-            struct GraphqlResponse {
-                #[facet(default)]
-                data: Option<GraphqlResponseData>,
-                #[facet(default)]
-                errors: Option<Vec<GraphqlError>>,
-            }
-
-            #[derive(Facet, Debug)]
-            struct GraphqlError {
-                #[allow(dead_code)]
-                message: String,
-            }
-
-            #[derive(Facet)]
-            struct GraphqlResponseData {
-                viewer: Viewer,
-            }
-
-            #[derive(Facet)]
-            struct Viewer {
-                sponsors: Sponsors,
-            }
-
-            #[derive(Facet)]
-            #[allow(non_snake_case)]
-            struct Sponsors {
-                pageInfo: PageInfo,
-                nodes: Vec<Node>,
-            }
-
-            #[derive(Facet)]
-            #[allow(non_snake_case)]
-            struct PageInfo {
-                endCursor: Option<String>,
-            }
-
-            #[derive(Facet)]
-            #[allow(non_snake_case)]
-            struct Node {
-                databaseId: u64,
-                login: String,
-                name: Option<String>,
-                avatarUrl: Option<String>,
-                sponsorshipForViewerAsSponsorable: Option<SponsorshipForViewerAsSponsorable>,
-            }
-
-            #[derive(Facet)]
-            #[allow(non_snake_case)]
-            struct SponsorshipForViewerAsSponsorable {
-                privacyLevel: String,
-                tier: GitHubTier,
-            }
-
-            #[derive(Facet)]
-            #[allow(non_snake_case)]
-            struct GitHubTier {
-                monthlyPriceInDollars: Option<u32>,
-                isOneTime: bool,
-            }
-
-            #[derive(Debug, Facet)]
-            struct Variables {
-                first: u32,
-                after: Option<String>,
-            }
-
-            let mut query = GraphqlQuery {
-                query: query.into(),
-                variables: Variables {
-                    first: 100,
-                    after: None,
-                },
-            };
-
-            let mut page_num = 0;
-            loop {
-                page_num += 1;
-                debug!("Fetching GitHub page {page_num}");
-
-                let res = client
-                    .post(Uri::from_static("https://api.github.com/graphql"))
-                    .polite_user_agent()
-                    .json(&query)?
-                    .bearer_auth(&github_creds.access_token)
-                    .send()
-                    .await?;
-
-                if !res.status().is_success() {
-                    let status = res.status();
-                    let error = res
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "Could not get error text".into());
-                    let err = eyre::eyre!(format!("got HTTP {status}, server said: {error}"));
-                    return Err(err);
-                }
-
-                let res = res
-                    .json::<GraphqlResponse>()
-                    .await
-                    .map_err(|e| eyre::eyre!("could not deserialize GitHub API response: {e}"))?;
-
-                if let Some(errors) = res.errors {
-                    fn is_error_ignored(error: &GraphqlError) -> bool {
-                        // Sample error message: Although you appear to have the correct
-                        // authorization credentials, the `xelforce` organization has
-                        // enabled OAuth App access restrictions, meaning that data
-                        // access to third-parties is limited. For more information on
-                        // these restrictions, including how to enable this app, visit
-                        // https://docs.github.com/articles/restricting-access-to-your-organization-s-data/
-                        //
-                        // In this case GitHub still gives us access to the rest of the
-                        // data so we don't actually need to do anything about this
-                        // error except for ignoring it
-                        error.message.contains("OAuth App access restrictions")
-                    }
-
-                    for error in errors {
-                        if !is_error_ignored(&error) {
-                            log::error!("GitHub API error: {error:?}");
-                        }
-                    }
-                    // still return the sponsors we got so far
-                    return Ok(github_profiles);
-                }
-
-                let data = match res.data {
-                    Some(data) => data,
-                    None => {
-                        let err = eyre::eyre!("got no data from GitHub API");
-                        log::error!("{err}");
-                        // still return the sponsors we got so far
-                        return Ok(github_profiles);
-                    }
-                };
-
-                let viewer = &data.viewer;
-
-                for sponsor in &viewer.sponsors.nodes {
-                    if let Some(sponsorship) = sponsor.sponsorshipForViewerAsSponsorable.as_ref() {
-                        let monthly_usd = if sponsorship.tier.isOneTime {
-                            None
-                        } else {
-                            sponsorship.tier.monthlyPriceInDollars.map(|p| p as u64)
-                        };
-
-                        github_profiles.push(GithubProfile {
-                            id: GithubUserId::new(sponsor.databaseId.to_string()),
-                            monthly_usd,
-                            sponsorship_privacy_level: Some(sponsorship.privacyLevel.clone()),
-                            name: sponsor.name.clone(),
-                            login: sponsor.login.clone(),
-                            avatar_url: sponsor.avatarUrl.clone(),
-                        });
-                    }
-                }
-
-                match viewer.sponsors.pageInfo.endCursor.as_ref() {
-                    Some(end_cursor) => {
-                        query.variables.after = Some(end_cursor.clone());
-                    }
-                    None => {
-                        // all done!
-                        break;
-                    }
-                }
-            }
-
-            Ok(github_profiles)
-        })
+        Box::pin(list_sponsors(
+            client,
+            GITHUB_API_BASE,
+            tc,
+            github_creds,
+            max_page_size,
+        ))
     }
 }
 
@@ -465,6 +342,16 @@ pub struct GithubCallbackArgs {
     /// if we're linking this github account to an existing UserID, this is set
     #[facet(default)]
     pub logged_in_user_id: Option<UserId>,
+
+    /// what the login flow was started for, remembered by cub: the OAuth
+    /// `state` parameter comes back from the user, so it can't be trusted
+    /// to tell us which scopes we asked for
+    pub purpose: GithubLoginPurpose,
+
+    /// fail the login if GitHub granted fewer scopes than we asked for,
+    /// instead of just reporting it
+    #[facet(default)]
+    pub strict_scopes: bool,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -497,15 +384,15 @@ pub struct GithubCredentials {
     pub expires_at: OffsetDateTime,
 }
 
-impl GithubCredentials {
-    pub fn expire_soon(&self) -> bool {
-        let now = OffsetDateTime::now_utc();
-        let arbitrary_duration = time::Duration::hours(1);
-        self.expires_at - now < arbitrary_duration
+impl Credentials for GithubCredentials {
+    fn expires_at(&self) -> OffsetDateTime {
+        self.expires_at
     }
 }
 
 /// The purpose of the login (to determine the OAuth scopes needed for the login)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Facet)]
+#[repr(u8)]
 pub enum GithubLoginPurpose {
     // admin login
     Admin,
@@ -513,6 +400,394 @@ pub enum GithubLoginPurpose {
     Regular,
 }
 
+const GITHUB_API_BASE: &str = "https://api.github.com";
+
+/// Lists everyone sponsoring the owner of `github_creds`, a page at a time.
+/// A page over `max_page_size` bytes fails the whole listing.
+async fn list_sponsors(
+    client: &dyn HttpClient,
+    api_base: &str,
+    tc: &TenantConfig,
+    github_creds: &GithubCredentials,
+    max_page_size: usize,
+) -> Result<Vec<GithubProfile>> {
+    let mut github_profiles: Vec<GithubProfile> = Vec::new();
+    let query = include_str!("github_sponsors.graphql");
+
+    #[derive(Facet)]
+    struct GraphqlQuery {
+        query: String,
+        variables: Variables,
+    }
+
+    #[derive(Facet)]
+    struct GraphqlResponse {
+        #[facet(default)]
+        data: Option<GraphqlResponseData>,
+        #[facet(default)]
+        errors: Option<Vec<GraphqlError>>,
+    }
+
+    #[derive(Facet, Debug)]
+    struct GraphqlError {
+        #[allow(dead_code)]
+        message: String,
+    }
+
+    #[derive(Facet)]
+    struct GraphqlResponseData {
+        viewer: Viewer,
+    }
+
+    #[derive(Facet)]
+    struct Viewer {
+        sponsors: Sponsors,
+    }
+
+    #[derive(Facet)]
+    #[allow(non_snake_case)]
+    struct Sponsors {
+        pageInfo: PageInfo,
+        nodes: Vec<Node>,
+    }
+
+    #[derive(Facet)]
+    #[allow(non_snake_case)]
+    struct PageInfo {
+        endCursor: Option<String>,
+    }
+
+    #[derive(Facet)]
+    #[allow(non_snake_case)]
+    struct Node {
+        databaseId: u64,
+        login: String,
+        name: Option<String>,
+        avatarUrl: Option<String>,
+        sponsorshipForViewerAsSponsorable: Option<SponsorshipForViewerAsSponsorable>,
+    }
+
+    #[derive(Facet)]
+    #[allow(non_snake_case)]
+    struct SponsorshipForViewerAsSponsorable {
+        privacyLevel: String,
+        tier: GitHubTier,
+    }
+
+    #[derive(Facet)]
+    #[allow(non_snake_case)]
+    struct GitHubTier {
+        monthlyPriceInDollars: Option<u32>,
+        isOneTime: bool,
+    }
+
+    #[derive(Debug, Facet)]
+    struct Variables {
+        first: u32,
+        after: Option<String>,
+    }
+
+    let mut query = GraphqlQuery {
+        query: query.into(),
+        variables: Variables {
+            first: 100,
+            after: None,
+        },
+    };
+
+    let mut page_num = 0;
+    loop {
+        page_num += 1;
+        debug!("Fetching GitHub page {page_num}");
+
+        let res = client
+            .post(Uri::try_from(format!("{api_base}/graphql"))?)
+            .polite_user_agent_for(tc)
+            .json(&query)?
+            .bearer_auth(&github_creds.access_token)
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(decode_error_response(res).await);
+        }
+
+        let payload = res
+            .bytes_limited(max_page_size)
+            .await
+            .wrap_err_with(|| format!("reading GitHub sponsors page {page_num}"))?;
+        debug!("GitHub sponsors page {page_num} is {} bytes", payload.len());
+
+        let res = facet_json::from_str::<GraphqlResponse>(std::str::from_utf8(&payload)?)
+            .map_err(|e| eyre::eyre!("could not deserialize GitHub API response: {e}"))?;
+
+        if let Some(errors) = res.errors {
+            fn is_error_ignored(error: &GraphqlError) -> bool {
+                // Sample error message: Although you appear to have the correct
+                // authorization credentials, the `xelforce` organization has
+                // enabled OAuth App access restrictions, meaning that data
+                // access to third-parties is limited. For more information on
+                // these restrictions, including how to enable this app, visit
+                // https://docs.github.com/articles/restricting-access-to-your-organization-s-data/
+                //
+                // In this case GitHub still gives us access to the rest of the
+                // data so we don't actually need to do anything about this
+                // error except for ignoring it
+                error.message.contains("OAuth App access restrictions")
+            }
+
+            for error in errors {
+                if !is_error_ignored(&error) {
+                    log::error!("GitHub API error: {error:?}");
+                }
+            }
+            // still return the sponsors we got so far
+            return Ok(github_profiles);
+        }
+
+        let data = match res.data {
+            Some(data) => data,
+            None => {
+                let err = eyre::eyre!("got no data from GitHub API");
+                log::error!("{err}");
+                // still return the sponsors we got so far
+                return Ok(github_profiles);
+            }
+        };
+
+        let viewer = &data.viewer;
+
+        for sponsor in &viewer.sponsors.nodes {
+            if let Some(sponsorship) = sponsor.sponsorshipForViewerAsSponsorable.as_ref() {
+                let monthly_usd = if sponsorship.tier.isOneTime {
+                    None
+                } else {
+                    sponsorship.tier.monthlyPriceInDollars.map(|p| p as u64)
+                };
+
+                github_profiles.push(GithubProfile {
+                    id: GithubUserId::new(sponsor.databaseId.to_string()),
+                    monthly_usd,
+                    sponsorship_privacy_level: Some(sponsorship.privacyLevel.clone()),
+                    name: sponsor.name.clone(),
+                    login: sponsor.login.clone(),
+                    avatar_url: sponsor.avatarUrl.clone(),
+                    email: None,
+                });
+            }
+        }
+
+        match viewer.sponsors.pageInfo.endCursor.as_ref() {
+            Some(end_cursor) => {
+                query.variables.after = Some(end_cursor.clone());
+            }
+            None => {
+                // all done!
+                break;
+            }
+        }
+    }
+
+    Ok(github_profiles)
+}
+
+/// Left as-is in path segments: RFC 3986's unreserved characters
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// `api_base` followed by `segments`, each percent-encoded, so that an org,
+/// team or login can't point us at some other API path
+fn api_uri(api_base: &str, segments: &[&str]) -> Result<Uri> {
+    let mut uri = api_base.to_string();
+    for segment in segments {
+        if matches!(*segment, "" | "." | "..") {
+            eyre::bail!("refusing to build a GitHub API path with a {segment:?} segment");
+        }
+        uri.push('/');
+        uri.extend(percent_encode(segment.as_bytes(), PATH_SEGMENT));
+    }
+    Ok(Uri::try_from(uri)?)
+}
+
+/// Where GitHub redirected us, as long as it's on the API's origin: we're
+/// about to send our token there.
+fn same_origin_redirect(api_base: &str, location: &str) -> Result<Uri> {
+    let base = Uri::try_from(api_base)?;
+    let (Some(scheme), Some(authority)) = (base.scheme(), base.authority()) else {
+        eyre::bail!("GitHub API base {api_base} isn't an absolute URI");
+    };
+    if location.starts_with('/') && !location.starts_with("//") {
+        return Ok(Uri::try_from(format!("{scheme}://{authority}{location}"))?);
+    }
+
+    let uri = Uri::try_from(location)?;
+    if uri.scheme() != Some(scheme) || uri.authority() != Some(authority) {
+        eyre::bail!("GitHub redirected us away from {api_base}, to {location}");
+    }
+    Ok(uri)
+}
+
+async fn org_membership(
+    client: &dyn HttpClient,
+    api_base: &str,
+    creds: &GithubCredentials,
+    org: &str,
+    login: &str,
+) -> Result<bool> {
+    let uri = api_uri(api_base, &["orgs", org, "members", login])?;
+    let res = client
+        .get(uri)
+        .polite_user_agent()
+        .bearer_auth(&creds.access_token)
+        .send()
+        .await?;
+
+    match res.status().as_u16() {
+        204 => Ok(true),
+        404 => Ok(false),
+        // the token's owner isn't a member of the org: GitHub sends us to the
+        // public membership check instead (unless our client followed that already)
+        302 => {
+            let location = res
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .map(|l| l.to_string())
+                .ok_or_else(|| eyre::eyre!("GitHub redirected us without a location"))?;
+            let uri = same_origin_redirect(api_base, &location)?;
+            let res = client
+                .get(uri)
+                .polite_user_agent()
+                .bearer_auth(&creds.access_token)
+                .send()
+                .await?;
+            match res.status().as_u16() {
+                204 => Ok(true),
+                404 => Ok(false),
+                status => Err(eyre::eyre!(
+                    "unexpected status {status} checking public membership of {login} in {org}"
+                )),
+            }
+        }
+        status => {
+            let error = res.text().await.unwrap_or_default();
+            Err(eyre::eyre!(
+                "unexpected status {status} checking membership of {login} in {org}: {error}"
+            ))
+        }
+    }
+}
+
+async fn team_membership(
+    client: &dyn HttpClient,
+    api_base: &str,
+    creds: &GithubCredentials,
+    org: &str,
+    team_slug: &str,
+    login: &str,
+) -> Result<bool> {
+    #[derive(Facet)]
+    struct TeamMembership {
+        /// `active` or `pending`
+        state: String,
+    }
+
+    let uri = api_uri(
+        api_base,
+        &["orgs", org, "teams", team_slug, "memberships", login],
+    )?;
+    let res = client
+        .get(uri)
+        .polite_user_agent()
+        .bearer_auth(&creds.access_token)
+        .send()
+        .await?;
+
+    match res.status().as_u16() {
+        200 => {
+            let membership: TeamMembership = res.json().await?;
+            Ok(membership.state == "active")
+        }
+        404 => Ok(false),
+        status => {
+            let error = res.text().await.unwrap_or_default();
+            Err(eyre::eyre!(
+                "unexpected status {status} checking membership of {login} in {org}/{team_slug}: {error}"
+            ))
+        }
+    }
+}
+
+/// Parses the response to our access token request, and checks GitHub
+/// granted all the scopes `purpose` asked for
+fn grant_from_token_response(
+    text: &str,
+    purpose: &GithubLoginPurpose,
+    strict_scopes: bool,
+) -> Result<OAuthGrant<GithubCredentials>> {
+    let creds = match facet_json::from_str::<GithubCredentialsAPI>(text) {
+        Ok(c) => c,
+        Err(_) => {
+            log::warn!("Got GitHub auth error: {text}");
+
+            // Try to deserialize as GitHub error response for better error messages
+            if let Ok(error_response) = facet_json::from_str::<GithubErrorResponse>(text) {
+                return Err(eyre::eyre!(
+                    "GitHub auth error: {} - {}{}",
+                    error_response.error,
+                    error_response.error_description,
+                    error_response
+                        .error_uri
+                        .as_ref()
+                        .map(|uri| format!(" (see: {uri})"))
+                        .unwrap_or_default()
+                ));
+            } else {
+                return Err(eyre::eyre!("Got Github auth error: {text}"));
+            }
+        }
+    };
+    log::info!(
+        "Successfully obtained GitHub token with scope {}",
+        &creds.scope
+    );
+
+    let scope_downgrade = ScopeDowngrade::check(
+        "github",
+        github_login_purpose_to_scopes(purpose),
+        &with_implied_scopes(&creds.scope),
+        strict_scopes,
+    )?;
+
+    Ok(OAuthGrant {
+        creds: GithubCredentials {
+            access_token: creds.access_token,
+            scope: creds.scope,
+            expires_at: OffsetDateTime::now_utc() + default_expires_in(),
+        },
+        scope_downgrade,
+    })
+}
+
+/// GitHub reports broader scopes as-is (`user` rather than `read:user`) when
+/// the user granted them at some point, so spell out what they include
+fn with_implied_scopes(granted: &str) -> String {
+    let mut scopes = granted.to_string();
+    for scope in granted.split(',').map(str::trim) {
+        let implied = match scope {
+            "user" => "read:user",
+            "admin:org" | "write:org" => "read:org",
+            _ => continue,
+        };
+        scopes.push(',');
+        scopes.push_str(implied);
+    }
+    scopes
+}
+
 /// Returns GitHub OAuth scopes needed for the login
 pub fn github_login_purpose_to_scopes(purpose: &GithubLoginPurpose) -> &'static str {
     match purpose {
@@ -538,3 +813,164 @@ fn default_expires_in() -> time::Duration {
 pub struct GithubUnlinkArgs {
     pub logged_in_user_id: UserId,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libhttpclient::test_server::{Reply, TestServer};
+    use time::Duration;
+
+    fn creds(expires_at: OffsetDateTime) -> GithubCredentials {
+        GithubCredentials {
+            access_token: "gho_test".to_string(),
+            scope: "read:user".to_string(),
+            expires_at,
+        }
+    }
+
+    /// A fake GitHub API: alice is a member of bearcove and of its `core`
+    /// team, bob is invited to `core` but not a member of anything. We're
+    /// not a member of `secret`, so we only get redirected to public
+    /// memberships there, where carol is listed. alice is our only sponsor.
+    async fn fake_github() -> String {
+        let server = TestServer::start(|req| {
+            assert_eq!(
+                req.header("authorization"),
+                Some("Bearer gho_test"),
+                "{}",
+                req.head
+            );
+            let path = req.target();
+            match path {
+                "/orgs/bearcove/members/alice" => Reply::new("204 No Content"),
+                "/orgs/secret/members/carol" | "/orgs/secret/members/dave" => {
+                    let login = path.rsplit('/').next().unwrap();
+                    Reply::new("302 Found")
+                        .header("location", &format!("/orgs/secret/public_members/{login}"))
+                }
+                "/orgs/secret/public_members/carol" => Reply::new("204 No Content"),
+                "/orgs/elsewhere/members/erin" => Reply::new("302 Found").header(
+                    "location",
+                    "http://evil.example.org/orgs/elsewhere/public_members/erin",
+                ),
+                "/orgs/bearcove/teams/core/memberships/alice" => {
+                    Reply::new("200 OK").body(r#"{"url":"x","role":"member","state":"active"}"#)
+                }
+                "/graphql" => Reply::new("200 OK").body(SPONSORS_PAGE),
+                "/orgs/bearcove/teams/core/memberships/bob" => {
+                    Reply::new("200 OK").body(r#"{"url":"x","role":"member","state":"pending"}"#)
+                }
+                _ => Reply::new("404 Not Found"),
+            }
+        })
+        .await;
+        server.base_url()
+    }
+
+    #[tokio::test]
+    async fn test_org_and_team_membership() {
+        let api_base = fake_github().await;
+        let creds = creds(OffsetDateTime::now_utc() + Duration::hours(8));
+
+        // our client follows redirects on its own, the other one doesn't
+        let following = libhttpclient::load().client();
+        let not_following = libhttpclient::load()
+            .client_with_opts(libhttpclient::ClientOpts {
+                resolve_to_addrs: Default::default(),
+                follow_redirects: false,
+                pool_max_idle_per_host: None,
+                pool_idle_timeout: None,
+                proxy: None,
+                observer: None,
+            })
+            .unwrap();
+        for client in [following.as_ref(), not_following.as_ref()] {
+            let is_member =
+                async |org, login| org_membership(client, &api_base, &creds, org, login).await;
+            assert!(is_member("bearcove", "alice").await.unwrap());
+            assert!(!is_member("bearcove", "bob").await.unwrap());
+            // private memberships of an org we're not in are invisible
+            assert!(is_member("secret", "carol").await.unwrap());
+            assert!(!is_member("secret", "dave").await.unwrap());
+        }
+
+        // a login can't reach other API paths
+        let client = not_following.as_ref();
+        assert!(
+            !org_membership(client, &api_base, &creds, "bearcove", "alice/../../graphql")
+                .await
+                .unwrap()
+        );
+        assert!(
+            org_membership(client, &api_base, &creds, "bearcove", "..")
+                .await
+                .is_err()
+        );
+        // and our token doesn't follow redirects to other hosts
+        let err = org_membership(client, &api_base, &creds, "elsewhere", "erin")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("evil.example.org"), "{err}");
+
+        let client = following.as_ref();
+        let is_team_member = async |login| {
+            team_membership(client, &api_base, &creds, "bearcove", "core", login).await
+        };
+        assert!(is_team_member("alice").await.unwrap());
+        // invited, but hasn't accepted yet
+        assert!(!is_team_member("bob").await.unwrap());
+        assert!(!is_team_member("carol").await.unwrap());
+    }
+
+    const SPONSORS_PAGE: &str = r#"{"data":{"viewer":{"sponsors":{"pageInfo":{"endCursor":null},"nodes":[{"databaseId":1234,"login":"alice","name":"Alice","avatarUrl":null,"sponsorshipForViewerAsSponsorable":{"privacyLevel":"PUBLIC","tier":{"monthlyPriceInDollars":5,"isOneTime":false}}}]}}}}"#;
+
+    #[tokio::test]
+    async fn test_list_sponsors_page_size_limit() {
+        let api_base = fake_github().await;
+        let client = libhttpclient::load().client();
+        let tc = TenantConfig::new(config_types::TenantDomain::new("example.org".to_string()));
+        let creds = creds(OffsetDateTime::now_utc() + Duration::hours(8));
+
+        let max = SPONSORS_PAGE.len();
+        let sponsors = list_sponsors(client.as_ref(), &api_base, &tc, &creds, max)
+            .await
+            .unwrap();
+        assert_eq!(sponsors.len(), 1);
+        assert_eq!(sponsors[0].login, "alice");
+        assert_eq!(sponsors[0].monthly_usd, Some(5));
+
+        let max = SPONSORS_PAGE.len() - 1;
+        let err = list_sponsors(client.as_ref(), &api_base, &tc, &creds, max)
+            .await
+            .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("reading GitHub sponsors page 1"), "{err}");
+        assert!(
+            err.contains(&format!("over the {max} bytes limit")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_token_response_missing_a_scope() {
+        // the org restricts third-party access, so no read:org for us
+        let text = r#"{"access_token":"gho_test","scope":"read:user","token_type":"bearer"}"#;
+
+        let grant = grant_from_token_response(text, &GithubLoginPurpose::Regular, false).unwrap();
+        assert!(grant.scope_downgrade.is_none());
+
+        let grant = grant_from_token_response(text, &GithubLoginPurpose::Admin, false).unwrap();
+        assert_eq!(grant.creds.access_token, "gho_test");
+        let downgrade = grant.scope_downgrade.unwrap();
+        assert_eq!(downgrade.provider, "github");
+        assert_eq!(downgrade.missing, vec!["read:org".to_string()]);
+
+        let err = grant_from_token_response(text, &GithubLoginPurpose::Admin, true).unwrap_err();
+        assert!(err.downcast_ref::<ScopeDowngrade>().is_some());
+
+        // broader scopes count as what they include
+        let text = r#"{"access_token":"gho_test","scope":"admin:org,user","token_type":"bearer"}"#;
+        let grant = grant_from_token_response(text, &GithubLoginPurpose::Admin, true).unwrap();
+        assert!(grant.scope_downgrade.is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use facet::Facet;
+
+use crate::{MAX_ERROR_BODY_SIZE, Response, StatusCode};
+
+/// Reads the body of a non-2xx response and turns it into an error. Known
+/// JSON error shapes get summarized, anything else is quoted as-is.
+pub async fn decode_error_response(res: Box<dyn Response>) -> eyre::Report {
+    let status = res.status();
+    match res.bytes_limited(MAX_ERROR_BODY_SIZE).await {
+        Ok(body) => error_from_body(status, &body),
+        Err(e) => eyre::eyre!("got HTTP {status}, could not read error body: {e}"),
+    }
+}
+
+/// Like [`decode_error_response`], for when the body has already been read
+pub fn error_from_body(status: StatusCode, body: &[u8]) -> eyre::Report {
+    let body = String::from_utf8_lossy(body);
+    let said = summarize_error_json(&body).unwrap_or_else(|| body.trim().to_string());
+    eyre::eyre!("got HTTP {status}, server said: {said}")
+}
+
+/// JSON:API errors (Patreon), and GraphQL errors (GitHub)
+#[derive(Facet)]
+struct ErrorList {
+    errors: Vec<ErrorListItem>,
+}
+
+#[derive(Facet)]
+struct ErrorListItem {
+    #[facet(default)]
+    title: Option<String>,
+    #[facet(default)]
+    detail: Option<String>,
+    #[facet(default)]
+    message: Option<String>,
+}
+
+/// OAuth2 token endpoint errors (RFC 6749), everyone has those
+#[derive(Facet)]
+struct OAuthError {
+    error: String,
+    #[facet(default)]
+    error_description: Option<String>,
+}
+
+/// GitHub's REST API, and Discord's (which adds a numeric code)
+#[derive(Facet)]
+struct MessageError {
+    message: String,
+    #[facet(default)]
+    code: Option<i64>,
+}
+
+fn summarize_error_json(body: &str) -> Option<String> {
+    if let Ok(list) = facet_json::from_str::<ErrorList>(body) {
+        let items = list
+            .errors
+            .into_iter()
+            .filter_map(|item| {
+                let title = item.title.map(|t| t.trim_end_matches('.').to_string());
+                match (title, item.detail.or(item.message)) {
+                    (Some(title), Some(detail)) => Some(format!("{title}: {detail}")),
+                    (title, detail) => title.or(detail),
+                }
+            })
+            .collect::<Vec<_>>();
+        if !items.is_empty() {
+            return Some(items.join("; "));
+        }
+    }
+
+    if let Ok(err) = facet_json::from_str::<OAuthError>(body) {
+        return Some(match err.error_description {
+            Some(description) => format!("{} ({description})", err.error),
+            None => err.error,
+        });
+    }
+
+    if let Ok(err) = facet_json::from_str::<MessageError>(body) {
+        return Some(match err.code {
+            Some(code) if code != 0 => format!("{} (code {code})", err.message),
+            _ => err.message,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn said(body: &str) -> String {
+        error_from_body(StatusCode::UNAUTHORIZED, body.as_bytes()).to_string()
+    }
+
+    #[test]
+    fn test_github_errors() {
+        assert_eq!(
+            said(
+                r#"{"message":"Bad credentials","documentation_url":"https://docs.github.com/rest","status":"401"}"#
+            ),
+            "got HTTP 401 Unauthorized, server said: Bad credentials"
+        );
+        assert_eq!(
+            said(
+                r#"{"error":"bad_verification_code","error_description":"The code passed is incorrect or expired.","error_uri":"https://docs.github.com"}"#
+            ),
+            "got HTTP 401 Unauthorized, server said: bad_verification_code (The code passed is incorrect or expired.)"
+        );
+        assert_eq!(
+            said(
+                r#"{"errors":[{"type":"NOT_FOUND","path":["user"],"message":"Could not resolve to a User with the login of 'nobody'."}]}"#
+            ),
+            "got HTTP 401 Unauthorized, server said: Could not resolve to a User with the login of 'nobody'."
+        );
+    }
+
+    #[test]
+    fn test_discord_errors() {
+        assert_eq!(
+            said(r#"{"message": "401: Unauthorized", "code": 0}"#),
+            "got HTTP 401 Unauthorized, server said: 401: Unauthorized"
+        );
+        // validation errors come with an `errors` object, which isn't a list
+        assert_eq!(
+            said(
+                r#"{"code": 50035, "errors": {"content": {"_errors": [{"code": "BASE_TYPE_MAX_LENGTH", "message": "Must be 2000 or fewer in length."}]}}, "message": "Invalid Form Body"}"#
+            ),
+            "got HTTP 401 Unauthorized, server said: Invalid Form Body (code 50035)"
+        );
+        assert_eq!(
+            said(
+                r#"{"error": "invalid_grant", "error_description": "Invalid \"code\" in request."}"#
+            ),
+            "got HTTP 401 Unauthorized, server said: invalid_grant (Invalid \"code\" in request.)"
+        );
+    }
+
+    #[test]
+    fn test_patreon_errors() {
+        assert_eq!(
+            said(
+                r#"{"errors":[{"code":1,"code_name":"Unauthorized","detail":"The server could not verify that you are authorized to access the URL requested.","id":"b5a8a3d1","status":"401","title":"Unauthorized."}]}"#
+            ),
+            "got HTTP 401 Unauthorized, server said: Unauthorized: The server could not verify that you are authorized to access the URL requested."
+        );
+        assert_eq!(
+            said(r#"{"error": "invalid_grant"}"#),
+            "got HTTP 401 Unauthorized, server said: invalid_grant"
+        );
+    }
+
+    #[test]
+    fn test_unknown_bodies_are_quoted() {
+        assert_eq!(
+            said("<html>upstream connect error</html>\n"),
+            "got HTTP 401 Unauthorized, server said: <html>upstream connect error</html>"
+        );
+        assert_eq!(
+            said(r#"{"errors": []}"#),
+            r#"got HTTP 401 Unauthorized, server said: {"errors": []}"#
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use autotrait::autotrait;
 pub use bytes::Bytes;
+use config_types::TenantConfig;
 use facet::Facet;
 use facet_json::DeserError;
 use facet_reflect::Peek;
@@ -7,17 +8,92 @@ use futures_core::{future::BoxFuture, stream::BoxStream};
 use mom_types::MomStructuredError;
 use reqwest_middleware::ClientWithMiddleware;
 use reqwest_retry::{Jitter, RetryTransientMiddleware, policies::ExponentialBackoff};
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+pub use backoff::RetryPolicy;
 pub use form_urlencoded;
+
+mod error_body;
+pub use error_body::{decode_error_response, error_from_body};
+mod request_id;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_server;
 pub use http::{
     HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header, request, response,
 };
+pub use request_id::{REQUEST_ID_HEADER, current_request_id, with_request_id};
 
 #[derive(Clone)]
 pub struct ClientOpts {
     pub resolve_to_addrs: HashMap<String, Vec<std::net::SocketAddr>>,
     pub follow_redirects: bool,
+    /// Max idle connections kept around per host (reqwest's default: no limit)
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept before being closed (reqwest's default: 90 seconds)
+    pub pool_idle_timeout: Option<Duration>,
+    /// Proxy all traffic through this. When unset, the `HTTP_PROXY`, `HTTPS_PROXY`
+    /// and `NO_PROXY` environment variables are honored.
+    pub proxy: Option<ProxyConfig>,
+    /// Called once per request, after the response headers come in (or the request fails)
+    pub observer: Option<RequestObserver>,
+}
+
+#[derive(Clone)]
+pub struct ProxyConfig {
+    /// e.g. `http://proxy.internal:3128`
+    pub url: String,
+    pub basic_auth: Option<ProxyBasicAuth>,
+}
+
+#[derive(Clone)]
+pub struct ProxyBasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// Gets to see every request a client makes, for logging or timing purposes
+pub type RequestObserver = Arc<dyn Fn(&RequestTrace) + Send + Sync>;
+
+/// What a [`RequestObserver`] is told about a request
+#[derive(Debug, Clone)]
+pub struct RequestTrace {
+    pub method: Method,
+    pub uri: Uri,
+    /// Headers we sent, with credentials redacted
+    pub request_headers: HeaderMap,
+    /// None if we never got a response (connection error, etc.)
+    pub status: Option<StatusCode>,
+    /// Time until the response headers came in, retries included
+    pub elapsed: Duration,
+}
+
+/// How much [`Response::bytes`] (and `text`, `json`) will read before giving
+/// up. Callers expecting more use [`Response::bytes_limited`].
+pub const DEFAULT_MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// How much of an oversized body we quote in the error
+const OVER_LIMIT_PREFIX_SIZE: usize = 256;
+
+/// How much of an error response body we read to put in the error message
+const MAX_ERROR_BODY_SIZE: usize = 1024 * 1024;
+
+fn redact_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in [
+        header::AUTHORIZATION,
+        header::PROXY_AUTHORIZATION,
+        header::COOKIE,
+        header::SET_COOKIE,
+    ] {
+        if headers.contains_key(&name) {
+            headers.insert(name, HeaderValue::from_static("[redacted]"));
+        }
+    }
+    headers
 }
 
 pub fn load() -> &'static dyn Mod {
@@ -31,32 +107,55 @@ struct ModImpl;
 #[autotrait]
 impl Mod for ModImpl {
     fn client(&self) -> Box<dyn HttpClient> {
-        Box::new(HttpClientImpl::new(None))
+        Box::new(HttpClientImpl::new(None).unwrap())
     }
 
-    fn client_with_opts(&self, opts: ClientOpts) -> Box<dyn HttpClient> {
-        Box::new(HttpClientImpl::new(Some(opts)))
+    /// Fails if the options don't make sense (an invalid proxy URL, say)
+    fn client_with_opts(&self, opts: ClientOpts) -> eyre::Result<Box<dyn HttpClient>> {
+        Ok(Box::new(HttpClientImpl::new(Some(opts))?))
     }
 }
 
 struct HttpClientImpl {
     client: reqwest_middleware::ClientWithMiddleware,
+    /// Same client, without the retry middleware, for requests that bring
+    /// their own [`RetryPolicy`]
+    bare_client: reqwest_middleware::ClientWithMiddleware,
+    observer: Option<RequestObserver>,
 }
 
 impl HttpClientImpl {
-    fn new(opts: Option<ClientOpts>) -> Self {
+    fn new(opts: Option<ClientOpts>) -> eyre::Result<Self> {
         let mut builder = reqwest::Client::builder();
+        let mut observer = None;
         if let Some(opts) = opts {
+            observer = opts.observer;
             for (host, addrs) in opts.resolve_to_addrs {
                 builder = builder.resolve_to_addrs(&host, &addrs);
             }
+            if let Some(max_idle) = opts.pool_max_idle_per_host {
+                builder = builder.pool_max_idle_per_host(max_idle);
+            }
+            if let Some(idle_timeout) = opts.pool_idle_timeout {
+                builder = builder.pool_idle_timeout(idle_timeout);
+            }
+            if let Some(proxy) = opts.proxy {
+                let mut reqwest_proxy = reqwest::Proxy::all(&proxy.url)
+                    .map_err(|e| eyre::eyre!("invalid proxy URL {}: {e}", proxy.url))?
+                    // an explicit proxy still shouldn't be used for hosts in `NO_PROXY`
+                    .no_proxy(reqwest::NoProxy::from_env());
+                if let Some(auth) = proxy.basic_auth {
+                    reqwest_proxy = reqwest_proxy.basic_auth(&auth.username, &auth.password);
+                }
+                builder = builder.proxy(reqwest_proxy);
+            }
             if opts.follow_redirects {
                 builder = builder.redirect(reqwest::redirect::Policy::limited(10));
             } else {
                 builder = builder.redirect(reqwest::redirect::Policy::none());
             }
         }
-        let client = builder.build().unwrap();
+        let client = builder.build()?;
 
         // TODO: allow disabling retries in ClientOpts
         let retry_policy = ExponentialBackoff::builder()
@@ -64,13 +163,15 @@ impl HttpClientImpl {
             .jitter(Jitter::Bounded)
             .base(2)
             .build_with_total_retry_duration(Duration::from_secs(24 * 60 * 60));
-        let client_with_middleware = reqwest_middleware::ClientBuilder::new(client)
+        let client_with_middleware = reqwest_middleware::ClientBuilder::new(client.clone())
             .with(RetryTransientMiddleware::new_with_policy(retry_policy))
             .build();
 
-        Self {
+        Ok(Self {
             client: client_with_middleware,
-        }
+            bare_client: reqwest_middleware::ClientBuilder::new(client).build(),
+            observer,
+        })
     }
 }
 
@@ -79,12 +180,16 @@ impl HttpClient for HttpClientImpl {
     fn request(&self, method: Method, uri: Uri) -> Box<dyn RequestBuilder> {
         Box::new(RequestBuilderImpl {
             client: self.client.clone(),
+            bare_client: self.bare_client.clone(),
+            retry: None,
             method,
             uri,
             headers: Default::default(),
             body: None,
+            body_stream: None,
             form: None,
             auth: None,
+            observer: self.observer.clone(),
         })
     }
 
@@ -107,12 +212,70 @@ impl HttpClient for HttpClientImpl {
 
 struct RequestBuilderImpl {
     client: ClientWithMiddleware,
+    bare_client: ClientWithMiddleware,
+    retry: Option<RetryPolicy>,
     method: Method,
     uri: Uri,
     headers: HeaderMap,
     body: Option<Bytes>,
+    /// Set by `body_stream`: can only be sent once
+    body_stream: Option<reqwest::Body>,
     form: Option<String>,
     auth: Option<(String, Option<String>)>,
+    observer: Option<RequestObserver>,
+}
+
+impl RequestBuilderImpl {
+    async fn send_once(
+        &self,
+        client: &ClientWithMiddleware,
+        body_stream: Option<reqwest::Body>,
+    ) -> eyre::Result<Box<dyn Response>> {
+        let method = self.method.clone();
+        let uri = self.uri.clone();
+        let headers = self.headers.clone();
+        let mut request = client.request(method.clone(), uri.to_string());
+
+        request = request.headers(headers.clone());
+
+        if let Some(body) = self.body.clone() {
+            request = request.body(body);
+        }
+
+        if let Some(body_stream) = body_stream {
+            request = request.body(body_stream);
+        }
+
+        if let Some(form) = self.form.clone() {
+            request = request.body(form);
+        }
+
+        if let Some((username, password)) = self.auth.clone() {
+            match password {
+                Some(password) => {
+                    request = request.basic_auth(username, Some(password));
+                }
+                None => {
+                    request = request.bearer_auth(&username);
+                }
+            }
+        }
+
+        let start = Instant::now();
+        let response = request.send().await;
+        if let Some(observer) = &self.observer {
+            observer(&RequestTrace {
+                method,
+                uri,
+                request_headers: redact_headers(&headers),
+                status: response.as_ref().ok().map(|res| res.status()),
+                elapsed: start.elapsed(),
+            });
+        }
+
+        let response = response?;
+        Ok(Box::new(ResponseImpl::new(response)) as Box<dyn Response>)
+    }
 }
 
 #[autotrait]
@@ -122,6 +285,23 @@ impl RequestBuilder for RequestBuilderImpl {
         self
     }
 
+    /// Sends `body` as it's produced rather than all at once, announcing a
+    /// `Content-Length` of `len`. A stream can't be replayed, so requests
+    /// with one are never retried.
+    fn body_stream(
+        mut self: Box<Self>,
+        body: BoxStream<'static, eyre::Result<Bytes>>,
+        len: u64,
+    ) -> Box<dyn RequestBuilder> {
+        use futures_util::TryStreamExt as _;
+
+        let body = body.map_err(Box::<dyn std::error::Error + Send + Sync>::from);
+        self.body_stream = Some(reqwest::Body::wrap_stream(body));
+        self.headers
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+        self
+    }
+
     fn form(mut self: Box<Self>, form: String) -> Box<dyn RequestBuilder> {
         self.form = Some(form);
         self.headers.insert(
@@ -138,10 +318,18 @@ impl RequestBuilder for RequestBuilderImpl {
 
     /// Sets a "polite" user agent, letting the server know where to reach us.
     fn polite_user_agent(mut self: Box<Self>) -> Box<dyn RequestBuilder> {
-        const POLITE_USER_AGENT: HeaderValue =
-            HeaderValue::from_static("home/1.0 (home/1.0 +https://github.com/bearcove/home)");
+        self.headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static(POLITE_USER_AGENT),
+        );
+        self
+    }
 
-        self.headers.insert(header::USER_AGENT, POLITE_USER_AGENT);
+    /// Like `polite_user_agent`, but also says which tenant the request is
+    /// made for (and who to contact about it, if configured).
+    fn polite_user_agent_for(mut self: Box<Self>, tc: &TenantConfig) -> Box<dyn RequestBuilder> {
+        self.headers
+            .insert(header::USER_AGENT, polite_user_agent_for_tenant(tc));
         self
     }
 
@@ -170,40 +358,40 @@ impl RequestBuilder for RequestBuilderImpl {
         self
     }
 
-    fn send(self: Box<Self>) -> BoxFuture<'static, eyre::Result<Box<dyn Response>>> {
-        let method = self.method.clone();
-        let uri = self.uri.clone();
-        let headers = self.headers.clone();
-        let body = self.body.clone();
-        let form = self.form.clone();
-        let auth = self.auth.clone();
+    /// Retry 5xx responses and connection errors according to `policy`
+    /// rather than the client's default (which keeps at it for a long time).
+    /// Anything else, 4xx included, is returned right away.
+    fn retry(mut self: Box<Self>, policy: RetryPolicy) -> Box<dyn RequestBuilder> {
+        self.retry = Some(policy);
+        self
+    }
 
+    fn send(mut self: Box<Self>) -> BoxFuture<'static, eyre::Result<Box<dyn Response>>> {
         Box::pin(async move {
-            let mut request = self.client.request(method, uri.to_string());
-
-            request = request.headers(headers);
-
-            if let Some(body) = body {
-                request = request.body(body);
+            if let Some(body_stream) = self.body_stream.take() {
+                // the retry middleware would refuse it, as it can't clone it
+                return self.send_once(&self.bare_client, Some(body_stream)).await;
             }
 
-            if let Some(form) = form {
-                request = request.body(form);
-            }
+            let Some(policy) = self.retry else {
+                return self.send_once(&self.client, None).await;
+            };
 
-            if let Some((username, password)) = auth {
-                match password {
-                    Some(password) => {
-                        request = request.basic_auth(username, Some(password));
-                    }
-                    None => {
-                        request = request.bearer_auth(&username);
-                    }
+            let mut backoff = policy.backoff();
+            loop {
+                let res = self.send_once(&self.bare_client, None).await;
+                let transient = match &res {
+                    Ok(res) => res.status().is_server_error(),
+                    Err(_) => true,
+                };
+                if !transient {
+                    return res;
+                }
+                match backoff.next_delay() {
+                    Some(delay) => tokio::time::sleep(delay).await,
+                    None => return res,
                 }
             }
-
-            let response = request.send().await?;
-            Ok(Box::new(ResponseImpl::new(response)) as Box<dyn Response>)
         })
     }
 
@@ -216,7 +404,14 @@ impl RequestBuilder for RequestBuilderImpl {
             let status = response.status();
             if !status.is_success() {
                 let headers = response.headers_only_string_safe();
-                let bytes = response.bytes().await?;
+                let bytes = match response.bytes_limited(MAX_ERROR_BODY_SIZE).await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        return Err(eyre::eyre!(
+                            "{hostname} replied with HTTP status {status} (could not read body: {e})"
+                        ));
+                    }
+                };
                 let response_body = match String::from_utf8(bytes.clone()) {
                     Ok(s) => {
                         if let Some(mse) = headers.get("x-mom-structured-error") {
@@ -305,6 +500,11 @@ impl Response for ResponseImpl {
         self.response.status()
     }
 
+    /// All response headers, including those whose values aren't valid UTF-8
+    fn headers(&self) -> HeaderMap {
+        self.response.headers().clone()
+    }
+
     fn headers_only_string_safe(&self) -> HashMap<String, String> {
         let mut headers = HashMap::new();
         for (key, value) in self.response.headers() {
@@ -315,9 +515,46 @@ impl Response for ResponseImpl {
         headers
     }
 
+    /// The whole body, up to [`DEFAULT_MAX_BODY_SIZE`]
     fn bytes(self: Box<Self>) -> BoxFuture<'static, eyre::Result<Vec<u8>>> {
-        let response = self.response;
-        Box::pin(async move { Ok(response.bytes().await?.to_vec()) })
+        self.bytes_limited(DEFAULT_MAX_BODY_SIZE)
+    }
+
+    /// The whole body, erroring out as soon as it's known to be over `max`
+    /// bytes. The error quotes the start of the body.
+    fn bytes_limited(self: Box<Self>, max: usize) -> BoxFuture<'static, eyre::Result<Vec<u8>>> {
+        let mut response = self.response;
+        Box::pin(async move {
+            if let Some(len) = response.content_length() {
+                if len > max as u64 {
+                    // read just enough to show what it was
+                    let mut prefix = Vec::new();
+                    while prefix.len() < OVER_LIMIT_PREFIX_SIZE {
+                        match response.chunk().await {
+                            Ok(Some(chunk)) => prefix.extend_from_slice(&chunk),
+                            Ok(None) | Err(_) => break,
+                        }
+                    }
+                    eyre::bail!(
+                        "Response body is {len} bytes, over the {max} bytes limit{}",
+                        quote_prefix(&prefix)
+                    );
+                }
+            }
+
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                if body.len() + chunk.len() > max {
+                    body.extend_from_slice(&chunk);
+                    eyre::bail!(
+                        "Response body is over the {max} bytes limit{}",
+                        quote_prefix(&body)
+                    );
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(body)
+        })
     }
 
     fn bytes_stream(self: Box<Self>) -> BoxStream<'static, eyre::Result<Bytes>> {
@@ -337,13 +574,48 @@ impl Response for ResponseImpl {
     }
 }
 
+/// `, starts with "..."`, for errors about oversized bodies
+fn quote_prefix(body: &[u8]) -> String {
+    if body.is_empty() {
+        return String::new();
+    }
+    let prefix = String::from_utf8_lossy(&body[..body.len().min(OVER_LIMIT_PREFIX_SIZE)]);
+    format!(", starts with {prefix:?}")
+}
+
+const POLITE_USER_AGENT: &str = "home/1.0 (home/1.0 +https://github.com/bearcove/home)";
+
+/// The polite user agent, with the tenant domain and contact email added to
+/// the comment. Falls back to the generic one if that doesn't make a valid
+/// header value (a contact email with a newline in it, say).
+pub fn polite_user_agent_for_tenant(tc: &TenantConfig) -> HeaderValue {
+    let mut comment = format!(
+        "home/1.0 +https://github.com/bearcove/home; +https://{}",
+        tc.name
+    );
+    if let Some(contact) = tc.contact_email.as_deref() {
+        comment.push_str("; ");
+        comment.push_str(contact);
+    }
+    HeaderValue::from_str(&format!("home/1.0 ({comment})"))
+        .unwrap_or_else(|_| HeaderValue::from_static(POLITE_USER_AGENT))
+}
+
 impl dyn Response {
     pub fn json<T>(self: Box<Self>) -> BoxFuture<'static, eyre::Result<T>>
+    where
+        T: for<'a> Facet<'a>,
+    {
+        self.json_limited(DEFAULT_MAX_BODY_SIZE)
+    }
+
+    /// Like [`json`](Self::json), for bodies that may be over [`DEFAULT_MAX_BODY_SIZE`]
+    pub fn json_limited<T>(self: Box<Self>, max: usize) -> BoxFuture<'static, eyre::Result<T>>
     where
         T: for<'a> Facet<'a>,
     {
         Box::pin(async move {
-            let bytes = self.bytes().await?;
+            let bytes = self.bytes_limited(max).await?;
             facet_json::from_str(std::str::from_utf8(&bytes[..]).map_err(|e| eyre::eyre!("{e}"))?)
                 .map_err(|e| eyre::eyre!("{e}"))
         })
@@ -358,3 +630,336 @@ impl dyn RequestBuilder {
         self.json_peek(Peek::new(body))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+    use config_types::TenantDomain;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_redact_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer sekrit".parse().unwrap());
+        headers.insert(header::ACCEPT, "application/json".parse().unwrap());
+        headers.insert(header::COOKIE, "session=sekrit".parse().unwrap());
+        headers.append(header::COOKIE, "other=sekrit".parse().unwrap());
+
+        let redacted = redact_headers(&headers);
+        assert_eq!(redacted[header::AUTHORIZATION], "[redacted]");
+        assert_eq!(redacted[header::ACCEPT], "application/json");
+        // `insert` replaces every value, not just the first one
+        let cookies: Vec<_> = redacted.get_all(header::COOKIE).iter().collect();
+        assert_eq!(cookies, vec!["[redacted]"]);
+        assert!(!redacted.contains_key(header::PROXY_AUTHORIZATION));
+        assert!(!redacted.contains_key(header::SET_COOKIE));
+    }
+
+    #[test]
+    fn test_polite_user_agent_names_the_tenant() {
+        let mut tc = TenantConfig::new(TenantDomain::new("fasterthanli.me".to_string()));
+        let ua = polite_user_agent_for_tenant(&tc);
+        let ua = ua.to_str().unwrap();
+        assert!(ua.starts_with("home/1.0 ("), "{ua}");
+        assert!(ua.contains("+https://fasterthanli.me"), "{ua}");
+
+        tc.contact_email = Some("amos@example.org".to_string());
+        let ua = polite_user_agent_for_tenant(&tc);
+        assert!(ua.to_str().unwrap().contains("amos@example.org"), "{ua:?}");
+
+        // we'd rather be generic than send a broken header
+        tc.contact_email = Some("amos@example.org\r\nX-Evil: 1".to_string());
+        assert_eq!(polite_user_agent_for_tenant(&tc), POLITE_USER_AGENT);
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_status_and_timing() {
+        const DELAY: Duration = Duration::from_millis(50);
+
+        // 418 isn't transient, so the retry middleware leaves it alone
+        let server = TestServer::always(Reply::new("418 I'm a teapot").delay(DELAY)).await;
+
+        let traces: Arc<Mutex<Vec<RequestTrace>>> = Default::default();
+        let client = load()
+            .client_with_opts(ClientOpts {
+                resolve_to_addrs: Default::default(),
+                follow_redirects: false,
+                pool_max_idle_per_host: None,
+                pool_idle_timeout: None,
+                proxy: None,
+                observer: Some({
+                    let traces = traces.clone();
+                    Arc::new(move |trace: &RequestTrace| traces.lock().unwrap().push(trace.clone()))
+                }),
+            })
+            .unwrap();
+
+        let uri = server.uri("/teapot");
+        let res = client
+            .get(uri.clone())
+            .header(header::AUTHORIZATION, "Bearer sekrit".parse().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+
+        let traces = traces.lock().unwrap();
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        assert_eq!(trace.method, Method::GET);
+        assert_eq!(trace.uri, uri);
+        assert_eq!(trace.status, Some(StatusCode::IM_A_TEAPOT));
+        assert!(trace.elapsed >= DELAY, "{:?}", trace.elapsed);
+        assert_eq!(trace.request_headers[header::AUTHORIZATION], "[redacted]");
+    }
+
+    #[tokio::test]
+    async fn test_sequential_requests_reuse_connection() {
+        let server = TestServer::always(Reply::new("200 OK").body("ok").keep_alive()).await;
+
+        let client = load()
+            .client_with_opts(ClientOpts {
+                resolve_to_addrs: Default::default(),
+                follow_redirects: false,
+                pool_max_idle_per_host: Some(4),
+                pool_idle_timeout: Some(Duration::from_secs(30)),
+                proxy: None,
+                observer: None,
+            })
+            .unwrap();
+
+        for _ in 0..2 {
+            let res = client
+                .get(server.uri("/ping"))
+                .send_and_expect_200()
+                .await
+                .unwrap();
+            // reading the body to the end hands the connection back to the pool
+            assert_eq!(res.text().await.unwrap(), "ok");
+        }
+        assert_eq!(server.connections(), 1);
+    }
+
+    const FAST_RETRY: RetryPolicy = RetryPolicy {
+        initial: Duration::from_millis(10),
+        ..RetryPolicy::QUICK
+    };
+
+    async fn get(reply: Reply) -> Box<dyn Response> {
+        let server = TestServer::always(reply).await;
+        load()
+            .client()
+            .get(server.uri("/body"))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bytes_limited_under_limit() {
+        let body = vec![b'a'; 1000];
+        let reply = Reply::new("200 OK").body(body.clone());
+
+        let res = get(reply.clone()).await;
+        assert_eq!(res.bytes_limited(1000).await.unwrap(), body);
+
+        let res = get(reply.chunked()).await;
+        assert_eq!(res.bytes_limited(1000).await.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_limited_over_limit() {
+        let mut body = b"<html>".to_vec();
+        body.resize(1000, b'a');
+        let reply = Reply::new("200 OK").body(body);
+
+        // content-length tells us upfront
+        let err = get(reply.clone())
+            .await
+            .bytes_limited(999)
+            .await
+            .unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("999 bytes limit"), "{msg}");
+        assert!(msg.contains(r#"starts with "<html>aaa"#), "{msg}");
+        assert!(msg.len() < 512, "{msg}");
+
+        // chunked: we only find out while reading
+        let err = get(reply.chunked())
+            .await
+            .bytes_limited(999)
+            .await
+            .unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("999 bytes limit"), "{msg}");
+        assert!(msg.contains(r#"starts with "<html>aaa"#), "{msg}");
+        assert!(msg.len() < 512, "{msg}");
+    }
+
+    #[tokio::test]
+    async fn test_error_body_is_bounded() {
+        let server =
+            TestServer::always(
+                Reply::new("400 Bad Request").body(vec![b'a'; MAX_ERROR_BODY_SIZE + 1]),
+            )
+            .await;
+        let err = load()
+            .client()
+            .get(server.uri("/body"))
+            .send_and_expect_200()
+            .await
+            .err()
+            .unwrap();
+        let msg = err.to_string();
+        assert!(msg.contains("400 Bad Request"), "{msg}");
+        assert!(msg.contains("could not read body"), "{msg}");
+        assert!(msg.len() < 1024, "{msg}");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_is_length_delimited() {
+        let server = TestServer::always(Reply::new("200 OK")).await;
+
+        let chunks: Vec<eyre::Result<Bytes>> = vec![
+            Ok(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"world")),
+        ];
+        let res = load()
+            .client()
+            .put(server.uri("/upload"))
+            .body_stream(Box::pin(futures_util::stream::iter(chunks)), 11)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.header("content-length"),
+            Some("11"),
+            "{}",
+            request.head
+        );
+        assert_eq!(
+            request.header("transfer-encoding"),
+            None,
+            "{}",
+            request.head
+        );
+        assert_eq!(request.body, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_requests_go_through_proxy() {
+        let proxy = TestServer::always(Reply::new("200 OK").body("via proxy")).await;
+
+        let client = load()
+            .client_with_opts(ClientOpts {
+                resolve_to_addrs: Default::default(),
+                follow_redirects: false,
+                pool_max_idle_per_host: None,
+                pool_idle_timeout: None,
+                proxy: Some(ProxyConfig {
+                    url: proxy.base_url(),
+                    basic_auth: Some(ProxyBasicAuth {
+                        username: "amos".to_string(),
+                        password: "hunter2".to_string(),
+                    }),
+                }),
+                observer: None,
+            })
+            .unwrap();
+
+        // nothing listens there: the only way to get an answer is through the proxy
+        let uri: Uri = "http://upstream.invalid/hello".parse().unwrap();
+        let res = client.get(uri).send_and_expect_200().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), "via proxy");
+
+        let request = &proxy.requests()[0];
+        // plain HTTP through a proxy uses the absolute form of the request target
+        assert_eq!(request.method(), "GET");
+        assert_eq!(request.target(), "http://upstream.invalid/hello");
+        // "amos:hunter2", base64-encoded
+        assert_eq!(
+            request.header("proxy-authorization"),
+            Some("Basic YW1vczpodW50ZXIy"),
+            "{}",
+            request.head
+        );
+    }
+
+    #[test]
+    fn test_invalid_proxy_url_is_an_error() {
+        let res = load().client_with_opts(ClientOpts {
+            resolve_to_addrs: Default::default(),
+            follow_redirects: false,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+            proxy: Some(ProxyConfig {
+                url: "http://[not-an-ip".to_string(),
+                basic_auth: None,
+            }),
+            observer: None,
+        });
+        let err = res.err().unwrap();
+        assert!(err.to_string().contains("invalid proxy URL"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_retries_server_errors() {
+        let server = TestServer::sequence(vec![
+            Reply::new("502 Bad Gateway").body("upstream is napping"),
+            Reply::new("200 OK").body("token"),
+        ])
+        .await;
+        let res = load()
+            .client()
+            .post(server.uri("/token"))
+            .retry(FAST_RETRY)
+            .send_and_expect_200()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "token");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_leaves_client_errors_alone() {
+        let server = TestServer::sequence(vec![
+            Reply::new("400 Bad Request").body("bad_verification_code"),
+            Reply::new("200 OK").body("token"),
+        ])
+        .await;
+        let err = load()
+            .client()
+            .post(server.uri("/token"))
+            .retry(FAST_RETRY)
+            .send_and_expect_200()
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("bad_verification_code"), "{err}");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy_gives_up() {
+        let server = TestServer::sequence(vec![
+            Reply::new("503 Service Unavailable"),
+            Reply::new("502 Bad Gateway"),
+            Reply::new("500 Internal Server Error").body("still down"),
+            Reply::new("200 OK").body("too late"),
+        ])
+        .await;
+        let res = load()
+            .client()
+            .post(server.uri("/token"))
+            .retry(FAST_RETRY)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.requests().len(), 3);
+    }
+}
@@ -0,0 +1,34 @@
+/// The header request ids travel in, between cub and mom
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `fut` with `id` as the current request id, so that calls made while
+/// serving that request can pass it along.
+pub async fn with_request_id<F: Future>(id: String, fut: F) -> F::Output {
+    REQUEST_ID.scope(id, fut).await
+}
+
+/// The id of the request being served, if we're serving one
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_id_is_scoped() {
+        assert_eq!(current_request_id(), None);
+        let seen = with_request_id("abc-123".to_string(), async {
+            tokio::task::yield_now().await;
+            current_request_id()
+        })
+        .await;
+        assert_eq!(seen.as_deref(), Some("abc-123"));
+        assert_eq!(current_request_id(), None);
+    }
+}
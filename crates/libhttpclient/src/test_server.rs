@@ -0,0 +1,278 @@
+//! A bare-bones HTTP/1.1 server for tests that need to see exactly what goes
+//! over the wire, or a server that misbehaves in ways real ones rarely do.
+//! Enabled by the `test-util` feature.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::Uri;
+
+/// A request, as the server received it
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The request line and headers, as sent
+    pub head: String,
+    /// As long as the `content-length` header said
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn method(&self) -> &str {
+        self.head.split_whitespace().next().unwrap_or_default()
+    }
+
+    /// Usually a path, but an absolute URI when we're acting as a proxy
+    pub fn target(&self) -> &str {
+        self.head.split_whitespace().nth(1).unwrap_or_default()
+    }
+
+    /// The first value of header `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+    }
+}
+
+/// What the server answers a request with. By default, the body comes with a
+/// `content-length`, and the connection is closed afterwards.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    status: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    chunked: bool,
+    keep_alive: bool,
+    delay: Duration,
+    body_delay: Duration,
+}
+
+impl Reply {
+    /// `status` is what goes after the HTTP version, like `200 OK`
+    pub fn new(status: &str) -> Self {
+        Self {
+            status: status.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            chunked: false,
+            keep_alive: false,
+            delay: Duration::ZERO,
+            body_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the body with chunked transfer encoding instead
+    pub fn chunked(mut self) -> Self {
+        self.chunked = true;
+        self
+    }
+
+    /// Leaves the connection open for more requests
+    pub fn keep_alive(mut self) -> Self {
+        self.keep_alive = true;
+        self
+    }
+
+    /// Waits this long before answering at all
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sends the head right away, but the body only after this long
+    pub fn body_delay(mut self, delay: Duration) -> Self {
+        self.body_delay = delay;
+        self
+    }
+
+    fn head_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if self.chunked {
+            head.push_str("transfer-encoding: chunked\r\n");
+        } else {
+            head.push_str(&format!("content-length: {}\r\n", self.body.len()));
+        }
+        if !self.keep_alive {
+            head.push_str("connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+
+    fn body_bytes(&self) -> Vec<u8> {
+        if !self.chunked {
+            return self.body.clone();
+        }
+        let mut body = Vec::new();
+        for chunk in self.body.chunks(100) {
+            body.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            body.extend_from_slice(chunk);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"0\r\n\r\n");
+        body
+    }
+}
+
+type Respond = dyn Fn(&Request) -> Reply + Send + Sync;
+
+/// Listens on a random local port for as long as the runtime lives
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    /// Answers every request with whatever `respond` returns for it
+    pub async fn start(respond: impl Fn(&Request) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests: Arc<Mutex<Vec<Request>>> = Default::default();
+        let connections: Arc<AtomicUsize> = Default::default();
+        let respond: Arc<Respond> = Arc::new(respond);
+
+        tokio::spawn({
+            let requests = requests.clone();
+            let connections = connections.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(serve_connection(stream, requests.clone(), respond.clone()));
+                }
+            }
+        });
+
+        Self {
+            addr,
+            requests,
+            connections,
+        }
+    }
+
+    /// Answers every request with `reply`
+    pub async fn always(reply: Reply) -> Self {
+        Self::start(move |_| reply.clone()).await
+    }
+
+    /// Answers the n-th request with the n-th reply, and anything past
+    /// the end with a 500
+    pub async fn sequence(replies: Vec<Reply>) -> Self {
+        let next = AtomicUsize::new(0);
+        Self::start(move |_| {
+            let n = next.fetch_add(1, Ordering::SeqCst);
+            replies
+                .get(n)
+                .cloned()
+                .unwrap_or_else(|| Reply::new("500 Internal Server Error"))
+        })
+        .await
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `http://127.0.0.1:port`, without a trailing slash
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// `path` must start with a slash
+    pub fn uri(&self, path: &str) -> Uri {
+        format!("{}{path}", self.base_url()).parse().unwrap()
+    }
+
+    /// Every request received so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// How many connections were accepted so far
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    requests: Arc<Mutex<Vec<Request>>>,
+    respond: Arc<Respond>,
+) {
+    let mut buf = Vec::new();
+    while let Some(request) = read_request(&mut stream, &mut buf).await {
+        let reply = respond(&request);
+        requests.lock().unwrap().push(request);
+
+        tokio::time::sleep(reply.delay).await;
+        // the client may have given up already, that's what some tests are about
+        if stream.write_all(&reply.head_bytes()).await.is_err() {
+            return;
+        }
+        tokio::time::sleep(reply.body_delay).await;
+        if stream.write_all(&reply.body_bytes()).await.is_err() || !reply.keep_alive {
+            return;
+        }
+    }
+}
+
+/// Reads one request off `stream`. `buf` holds whatever was read past the
+/// end of the previous one. Returns `None` once the client hangs up.
+async fn read_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Option<Request> {
+    let mut chunk = [0u8; 64 * 1024];
+    let head_len = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+    let mut request = Request {
+        head,
+        body: Vec::new(),
+    };
+
+    let body_len = request
+        .header("content-length")
+        .and_then(|len| len.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < head_len + body_len {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+    request.body = buf[head_len..head_len + body_len].to_vec();
+    buf.drain(..head_len + body_len);
+    Some(request)
+}
@@ -13,7 +13,7 @@ use itertools::Itertools;
 use libhttpclient::HttpClient;
 use libobjectstore::ObjectStore;
 use log::{debug, error, info};
-use mom_types::AllUsers;
+use mom_types::{AllUsers, UsersBroadcastState};
 use objectstore_types::ObjectStoreKey;
 use owo_colors::OwoColorize;
 use parking_lot::Mutex;
@@ -25,12 +25,14 @@ use mom_types::{
     TranscodeJobInfo, TranscodeParams,
 };
 
+mod cookie_sauce;
 mod db;
 mod deriver;
 mod discord_roles;
 mod endpoints;
 mod ffmpeg;
 mod ffmpeg_stream;
+mod resumable_outputs;
 mod site;
 mod users;
 
@@ -56,11 +58,15 @@ pub(crate) struct MomTenantState {
 
     pub(crate) users_inflight: InflightSlots<(), Arc<AllUsers>>,
     pub(crate) users: Arc<Mutex<Arc<AllUsers>>>,
+    /// what we last broadcast about users, so we can send deltas
+    pub(crate) users_broadcast: Mutex<UsersBroadcastState>,
     pub(crate) pak: Arc<Mutex<Option<Pak>>>,
 
     pub(crate) object_store: Arc<dyn ObjectStore>,
 
     pub(crate) transcode_jobs: Mutex<HashMap<TranscodeParams, TranscodeJobInfo>>,
+    /// outputs of recent websocket transcodes, for resuming their download
+    pub(crate) transcode_outputs: resumable_outputs::ResumableOutputs,
     pub(crate) derive_jobs: Mutex<HashMap<DeriveParams, DeriveJobInfo>>,
 
     pub(crate) ti: Arc<TenantInfo>,
@@ -111,6 +117,20 @@ impl MomTenantState {
             payload,
         }))
     }
+
+    /// Broadcasts either a delta or a full snapshot of users (without their
+    /// emails), depending on what was sent previously.
+    pub(crate) fn broadcast_users(&self, users: Arc<AllUsers>) -> eyre::Result<()> {
+        let users = Arc::new(users.for_cubs());
+        let payload = self.users_broadcast.lock().next_payload(users);
+        match payload {
+            Some(payload) => self.broadcast_event(payload),
+            None => {
+                log::debug!("[{}] Users unchanged, not broadcasting", self.ti.tc.name);
+                Ok(())
+            }
+        }
+    }
 }
 
 pub(crate) type SqlitePool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
@@ -184,6 +204,36 @@ pub async fn serve(args: MomServeArgs) -> eyre::Result<()> {
         tenants.keys().join(", ")
     );
 
+    let profile_cache_ttl = config.profile_cache_ttl_secs.map(Duration::from_secs);
+    if let Some(ttl) = profile_cache_ttl {
+        log::info!("Caching provider profiles for {ttl:?}");
+    }
+    libgithub::load_with(libgithub::ModOptions { profile_cache_ttl })?;
+    libpatreon::load_with(libpatreon::ModOptions { profile_cache_ttl })?;
+
+    let request_limits = {
+        let configured = config.discord_request_limits;
+        let defaults = libdiscord::DiscordRequestLimits::default();
+        let limits = libdiscord::DiscordRequestLimits {
+            timeout: configured
+                .timeout_secs
+                .map_or(defaults.timeout, Duration::from_secs),
+            max_body_size: configured
+                .max_body_size
+                .map_or(defaults.max_body_size, |max| {
+                    usize::try_from(max.as_u64()).unwrap_or(usize::MAX)
+                }),
+        };
+        if limits != defaults {
+            log::info!("Discord request limits: {limits:?}");
+        }
+        limits
+    };
+    libdiscord::load_with(libdiscord::ModOptions {
+        profile_cache_ttl,
+        request_limits,
+    })?;
+
     // compute initial global state
     {
         let (tx_event, rx_event) = broadcast::channel(16);
@@ -240,16 +290,22 @@ pub async fn serve(args: MomServeArgs) -> eyre::Result<()> {
                         .unwrap();
                     Box::pin(async move {
                         let res = Arc::new(users::refresh_sponsors(&ts).await?);
-                        ts.broadcast_event(TenantEventPayload::UsersUpdated(res.clone()))?;
+                        // store before broadcasting: a cub that reconnects in
+                        // between must get these users in its good morning,
+                        // or the deltas that follow won't apply cleanly
+                        *ts.users.lock() = res.clone();
+                        ts.broadcast_users(res.clone())?;
 
                         Ok(res)
                     })
                 }),
                 users: Default::default(),
+                users_broadcast: Default::default(),
                 pak: Arc::new(Mutex::new(pak)),
                 object_store,
                 ti: Arc::new(ti),
                 transcode_jobs: Default::default(),
+                transcode_outputs: Default::default(),
                 derive_jobs: Default::default(),
             };
 
@@ -321,7 +377,6 @@ pub async fn serve(args: MomServeArgs) -> eyre::Result<()> {
                 match ts.users_inflight.query(()).await {
                     Ok(users) => {
                         log::debug!("[{}] Fetched {} sponsors", tenant_name, users.users.len());
-                        *ts.users.lock() = users;
                     }
                     Err(e) => {
                         log::debug!("[{tenant_name}] Failed to fetch sponsors: {e} / {e:?}")
@@ -0,0 +1,62 @@
+use config_types::TenantConfig;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::impls::{MomTenantState, global_state};
+
+/// How many times the tenant's cookie sauce was rotated
+pub(crate) fn load_generation(conn: &Connection) -> eyre::Result<u64> {
+    let generation: Option<u64> = conn
+        .query_row(
+            "SELECT generation FROM cookie_sauce_generation WHERE id = 0",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(generation.unwrap_or_default())
+}
+
+/// Bumps the tenant's cookie sauce generation, returns the new one
+pub(crate) fn bump_generation(conn: &Connection) -> eyre::Result<u64> {
+    Ok(conn.query_row(
+        "
+        INSERT INTO cookie_sauce_generation (id, generation) VALUES (0, 1)
+        ON CONFLICT (id) DO UPDATE SET generation = generation + 1
+        RETURNING generation
+        ",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+impl MomTenantState {
+    /// Whether the tenant's cookie sauce is derived from the global one
+    /// (and can be rotated), as opposed to set explicitly in its secrets
+    pub(crate) fn has_derived_cookie_sauce(&self) -> bool {
+        self.ti
+            .tc
+            .secrets
+            .as_ref()
+            .is_some_and(|secrets| secrets.cookie_sauce.is_none())
+    }
+
+    pub(crate) fn derive_cookie_sauce(&self, generation: u64) -> String {
+        mom_types::derive_cookie_sauce(
+            &global_state().config.secrets.cookie_sauce,
+            &self.ti.tc.name,
+            generation,
+        )
+    }
+
+    /// The tenant config as we send it to cubs, with the cookie sauce filled
+    /// in from the current generation if it's derived
+    pub(crate) fn tc_for_cubs(&self) -> eyre::Result<TenantConfig> {
+        let mut tc = self.ti.tc.clone();
+        if self.has_derived_cookie_sauce() {
+            let generation = load_generation(&*self.pool.get()?)?;
+            if let Some(secrets) = tc.secrets.as_mut() {
+                secrets.cookie_sauce = Some(self.derive_cookie_sauce(generation));
+            }
+        }
+        Ok(tc)
+    }
+}
@@ -29,7 +29,11 @@ include_migrations! {
     m0011_discord,
     m0012_users_vs_profiles,
     m0013_user_gifted_tier,
-    m0014_discord_guild
+    m0014_discord_guild,
+    m0015_discord_discriminator,
+    m0016_profile_emails,
+    m0017_patreon_entitled_cents,
+    m0018_cookie_sauce_generation
 }
 
 pub fn migrate_all_sqlite(
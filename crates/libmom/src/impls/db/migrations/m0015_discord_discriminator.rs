@@ -0,0 +1,19 @@
+use rusqlite::Connection;
+
+pub struct Migration;
+
+impl super::SqlMigration for Migration {
+    fn tag(&self) -> &'static str {
+        "m0015_discord_discriminator"
+    }
+
+    fn up(&self, conn: &Connection) -> eyre::Result<()> {
+        // Legacy discriminator, used to pick a default avatar
+        conn.execute(
+            "ALTER TABLE discord_profiles ADD COLUMN discriminator TEXT",
+            [],
+        )?;
+
+        Ok(())
+    }
+}
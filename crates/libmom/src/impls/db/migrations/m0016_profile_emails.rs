@@ -0,0 +1,18 @@
+use rusqlite::Connection;
+
+pub struct Migration;
+
+impl super::SqlMigration for Migration {
+    fn tag(&self) -> &'static str {
+        "m0016_profile_emails"
+    }
+
+    fn up(&self, conn: &Connection) -> eyre::Result<()> {
+        // Only filled in when the provider gives it to us (depends on scopes)
+        for table in ["patreon_profiles", "github_profiles", "discord_profiles"] {
+            conn.execute(&format!("ALTER TABLE {table} ADD COLUMN email TEXT"), [])?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use rusqlite::Connection;
+
+pub struct Migration;
+
+impl super::SqlMigration for Migration {
+    fn tag(&self) -> &'static str {
+        "m0017_patreon_entitled_cents"
+    }
+
+    fn up(&self, conn: &Connection) -> eyre::Result<()> {
+        // What the member is currently entitled to, in cents: only known for
+        // profiles that came from listing the campaign's members
+        conn.execute(
+            "ALTER TABLE patreon_profiles ADD COLUMN entitled_cents INTEGER",
+            [],
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use rusqlite::Connection;
+
+pub struct Migration;
+
+impl super::SqlMigration for Migration {
+    fn tag(&self) -> &'static str {
+        "m0018_cookie_sauce_generation"
+    }
+
+    fn up(&self, conn: &Connection) -> eyre::Result<()> {
+        // How many times the tenant's derived cookie sauce was rotated. A
+        // single row: no row at all means it never was.
+        conn.execute(
+            "
+            CREATE TABLE cookie_sauce_generation (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                generation INTEGER NOT NULL
+            )
+            ",
+            [],
+        )?;
+
+        Ok(())
+    }
+}
@@ -23,19 +23,32 @@ use mom_types::{
 use super::ffmpeg_stream::{DetailedTranscodeEvent, FFmpegTranscode};
 
 pub async fn do_derive(ts: Arc<MomTenantState>, params: DeriveParams) -> Reply {
+    let response = derive(ts, params, None).await?;
+    let in_progress = matches!(response, DeriveResponse::AlreadyInProgress(_));
+
+    let mut res = FacetJson(response).into_reply()?;
+    if in_progress {
+        *res.status_mut() = axum::http::StatusCode::CONFLICT;
+    }
+    Ok(res)
+}
+
+/// Runs a derivation and stores its output. Transcoding events are forwarded
+/// to `events` if it's set (and as long as the receiver is around).
+pub async fn derive(
+    ts: Arc<MomTenantState>,
+    params: DeriveParams,
+    events: Option<mpsc::Sender<TranscodeEvent>>,
+) -> eyre::Result<DeriveResponse> {
     let mut info = {
         let mut locks = ts.derive_jobs.lock();
         if let Some(info) = locks.get(&params) {
             log::info!("Derive already in progress: {info:?}");
-            let mut res = FacetJson(DeriveResponse::AlreadyInProgress(
+            return Ok(DeriveResponse::AlreadyInProgress(
                 DeriveResponseAlreadyInProgress {
                     info: format!("derive already in progress: {info:#?}"),
                 },
-            ))
-            .into_reply()
-            .unwrap();
-            *res.status_mut() = axum::http::StatusCode::CONFLICT;
-            return Ok(res);
+            ));
         }
 
         let info = DeriveJobInfo {
@@ -112,17 +125,18 @@ pub async fn do_derive(ts: Arc<MomTenantState>, params: DeriveParams) -> Reply {
                 (VContainer::WebM, VCodec::VP9) => TargetFormat::VP9,
                 (VContainer::MP4, VCodec::AV1) => TargetFormat::AV1,
                 (VContainer::MP4, VCodec::AVC) => TargetFormat::AVC,
+                (VContainer::MP4, VCodec::HEVC) => TargetFormat::HEVC,
                 (container, vc) => {
                     return Err(eyre!(
                         "Unsupported video container/codec combination: {container:?}/{vc:?}"
-                    )
-                    .into());
+                    ));
                 }
             };
 
-            let permit = match acquire_permit_or_429() {
-                Ok(value) => value,
-                Err(value) => return value,
+            let Some(permit) = try_acquire_ffmpeg_encode_permit() else {
+                return Ok(DeriveResponse::TooManyRequests(
+                    DeriveResponseTooManyRequests {},
+                ));
             };
             let mut transcode_task =
                 std::pin::pin!(transcode_media_data(input_bytes, target_format, tx, permit));
@@ -130,15 +144,17 @@ pub async fn do_derive(ts: Arc<MomTenantState>, params: DeriveParams) -> Reply {
             loop {
                 tokio::select! {
                     ev = rx.recv() => {
-                        if let Some(TranscodeEvent::Progress(progress)) = ev {
+                        let Some(ev) = ev else {
+                            log::debug!("Transcode progress channel closed");
+                            break transcode_task.await?;
+                        };
+                        if let TranscodeEvent::Progress(progress) = &ev {
                             log::info!("Transcode progress: {progress}");
                             info.last_ping = Instant::now();
-                            info.last_progress = Some(progress);
+                            info.last_progress = Some(progress.clone());
                             broadcast_info(&info);
-                        } else {
-                            log::debug!("Transcode progress channel closed");
-                            break transcode_task.await?;
                         }
+                        forward_event(&events, ev).await;
                     }
                     result = &mut transcode_task => {
                         break result?;
@@ -154,11 +170,12 @@ pub async fn do_derive(ts: Arc<MomTenantState>, params: DeriveParams) -> Reply {
                 ICodec::JXL => TargetFormat::ThumbJXL,
                 ICodec::AVIF => TargetFormat::ThumbAVIF,
                 ICodec::WEBP => TargetFormat::ThumbWEBP,
-                other => return Err(eyre!("Unsupported thumbnail codec: {other:?}").into()),
+                other => return Err(eyre!("Unsupported thumbnail codec: {other:?}")),
             };
-            let permit = match acquire_permit_or_429() {
-                Ok(value) => value,
-                Err(value) => return value,
+            let Some(permit) = try_acquire_ffmpeg_encode_permit() else {
+                return Ok(DeriveResponse::TooManyRequests(
+                    DeriveResponseTooManyRequests {},
+                ));
             };
             let mut transcode_task =
                 std::pin::pin!(transcode_media_data(input_bytes, target_format, tx, permit));
@@ -166,15 +183,17 @@ pub async fn do_derive(ts: Arc<MomTenantState>, params: DeriveParams) -> Reply {
             loop {
                 tokio::select! {
                     ev = rx.recv() => {
-                        if let Some(TranscodeEvent::Progress(progress)) = ev {
+                        let Some(ev) = ev else {
+                            log::debug!("Transcode progress channel closed");
+                            break transcode_task.await?;
+                        };
+                        if let TranscodeEvent::Progress(progress) = &ev {
                             log::info!("Transcode progress: {progress}");
                             info.last_ping = Instant::now();
-                            info.last_progress = Some(progress);
+                            info.last_progress = Some(progress.clone());
                             broadcast_info(&info);
-                        } else {
-                            log::debug!("Transcode progress channel closed");
-                            break transcode_task.await?;
                         }
+                        forward_event(&events, ev).await;
                     }
                     result = &mut transcode_task => {
                         break result?;
@@ -227,27 +246,19 @@ pub async fn do_derive(ts: Arc<MomTenantState>, params: DeriveParams) -> Reply {
         output_size as f64 / input.size as f64
     );
 
-    // Return success response
-    let response = DeriveResponse::Done(DeriveResponseDone {
+    Ok(DeriveResponse::Done(DeriveResponseDone {
         output_size,
         // this lets the cube check whether mom and it agree on the output key
         dest: dest_key,
-    });
-
-    FacetJson(response).into_reply()
+    }))
 }
 
-#[allow(clippy::result_large_err)]
-fn acquire_permit_or_429() -> Result<FfmpegEncodePermit, Reply> {
-    Ok(match try_acquire_ffmpeg_encode_permit() {
-        Some(permit) => permit,
-        None => {
-            return Err(FacetJson(DeriveResponse::TooManyRequests(
-                DeriveResponseTooManyRequests {},
-            ))
-            .into_reply());
-        }
-    })
+/// Forwards a transcoding event to whoever is streaming this derivation, if anyone.
+/// The derivation goes on even if they went away.
+async fn forward_event(events: &Option<mpsc::Sender<TranscodeEvent>>, ev: TranscodeEvent) {
+    if let Some(events) = events {
+        let _ = events.send(ev).await;
+    }
 }
 
 // FIXME: don't hardcode that limit?
@@ -288,17 +299,25 @@ pub async fn transcode_media_data(
     tx: mpsc::Sender<TranscodeEvent>,
     permit: FfmpegEncodePermit,
 ) -> eyre::Result<Vec<u8>> {
+    let (Some(ffmpeg_ext), Some(final_ext)) = (
+        target_format.ffmpeg_output_ext(),
+        target_format.final_output_ext(),
+    ) else {
+        eyre::bail!("Don't know which extension to give {target_format:?} outputs");
+    };
+
     let temp_dir = TempDir::new()?;
     let input_path = temp_dir.path().join("input");
-    let output_path = temp_dir
-        .path()
-        .join(format!("output.{}", target_format.ffmpeg_output_ext()));
+    let output_path = temp_dir.path().join(format!("output.{ffmpeg_ext}"));
+    // same as `output_path` unless there's a postprocessing step
+    let final_path = output_path.with_extension(final_ext);
 
     fs_err::tokio::write(&input_path, &input_data).await?;
 
     transcode_media(
         input_path.clone(),
         output_path.clone(),
+        final_path.clone(),
         target_format,
         tx,
         permit,
@@ -314,13 +333,14 @@ pub async fn transcode_media_data(
         )
     })?;
 
-    let output_data = fs_err::tokio::read(&output_path).await?;
+    let output_data = fs_err::tokio::read(&final_path).await?;
     Ok(output_data)
 }
 
 async fn transcode_media(
     input_path: std::path::PathBuf,
     output_path: std::path::PathBuf,
+    final_path: std::path::PathBuf,
     target_format: TargetFormat,
     tx: mpsc::Sender<TranscodeEvent>,
     permit: FfmpegEncodePermit,
@@ -353,7 +373,7 @@ async fn transcode_media(
                         )
                         .map_err(|e| eyre!("{e}"))?;
                     // it's kind of wasteful to write this back to disk, but that's the way it is right now.
-                    fs_err::tokio::write(&output_path, output_payload).await?;
+                    fs_err::tokio::write(&final_path, output_payload).await?;
                 }
                 return Ok(());
             }
@@ -296,9 +296,8 @@ pub(crate) async fn synchronize_one_discord_role(
     let cx = gather_discord_roles_context(ts).await?;
 
     // Get expected tier for this user
-    let expected_tier = user_info
-        .get_fasterthanlime_tier()
-        .map(|(tier, _cause)| tier);
+    let tier_mapping = ts.rc().map(|rc| rc.tier_mapping).unwrap_or_default();
+    let expected_tier = user_info.tier(&tier_mapping).map(|(tier, _cause)| tier);
 
     // Try to fetch the specific guild member
     let member = match discord_mod
@@ -360,10 +359,11 @@ pub(crate) async fn synchronize_all_discord_roles(
     let cx = gather_discord_roles_context(ts).await?;
 
     // Build a map from Discord user ID to their expected tier
+    let tier_mapping = ts.rc().map(|rc| rc.tier_mapping).unwrap_or_default();
     let mut discord_tier_map: HashMap<DiscordUserId, FasterthanlimeTier> = HashMap::new();
     for user_info in users.users.values() {
         if let Some(discord_profile) = &user_info.discord {
-            if let Some((tier, _cause)) = user_info.get_fasterthanlime_tier() {
+            if let Some((tier, _cause)) = user_info.tier(&tier_mapping) {
                 discord_tier_map.insert(discord_profile.id.clone(), tier);
             }
         }
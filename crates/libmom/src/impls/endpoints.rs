@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
 use crate::Result;
 use axum::extract::ws;
@@ -11,8 +11,9 @@ use log::{error, info, warn};
 use tenant_extractor::TenantExtractor;
 use tokio::signal::unix::SignalKind;
 
+use crate::impls::site::{FacetJson, IntoReply, Reply};
 use crate::impls::{MomGlobalState, global_state};
-use mom_types::{GoodMorning, MomEvent, TenantInitialState};
+use mom_types::{GoodMorning, ListTenantsResponse, MomEvent, TenantInitialState};
 
 mod tenant;
 mod tenant_extractor;
@@ -65,6 +66,7 @@ pub(super) async fn serve(listener: tokio::net::TcpListener) -> Result<()> {
             ),
         )
         .route("/events", get(get_events))
+        .route("/tenants", get(list_tenants))
         .layer(axum::middleware::from_fn(
             move |mut req: axum::http::Request<axum::body::Body>, next: axum::middleware::Next| {
                 async move {
@@ -154,6 +156,38 @@ pub(super) async fn serve(listener: tokio::net::TcpListener) -> Result<()> {
     Ok(())
 }
 
+async fn list_tenants(axum::Extension(kp): axum::Extension<KeyPermissions>) -> Reply {
+    FacetJson(accessible_tenants(&kp, global_state().tenants.keys())).into_reply()
+}
+
+/// Scoped keys may name tenants we don't host: those are listed separately.
+fn accessible_tenants<'a>(
+    kp: &KeyPermissions,
+    known: impl IntoIterator<Item = &'a TenantDomain>,
+) -> ListTenantsResponse {
+    let known = known.into_iter().collect::<HashSet<_>>();
+    let mut tenants = known
+        .iter()
+        .filter(|tn| kp.has_access_to(tn))
+        .map(|&tn| tn.clone())
+        .collect::<Vec<_>>();
+    tenants.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    let mut unknown_tenants = match kp {
+        KeyPermissions::Skeleton => Vec::new(),
+        KeyPermissions::Tenants(listed) => listed
+            .iter()
+            .filter(|tn| !known.contains(tn))
+            .cloned()
+            .collect(),
+    };
+    unknown_tenants.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    ListTenantsResponse {
+        all_tenants: matches!(kp, KeyPermissions::Skeleton),
+        tenants,
+        unknown_tenants,
+    }
+}
+
 async fn get_events(ws: axum::extract::WebSocketUpgrade) -> impl axum::response::IntoResponse {
     info!("got /events request");
     ws.on_failed_upgrade(|err| {
@@ -175,7 +209,7 @@ async fn handle_socket(mut socket: ws::WebSocket) {
 
     for (tn, ts) in gs.tenants.iter() {
         let revision = ts.pak.lock().clone();
-        let users = ts.users.lock().clone();
+        let users = Arc::new(ts.users.lock().for_cubs());
         log::info!(
             "in good morning, for tenant {}, sending {} users",
             tn,
@@ -183,14 +217,14 @@ async fn handle_socket(mut socket: ws::WebSocket) {
         );
 
         // Create a TenantConfig with derived cookie sauce
-        let mut tc = ts.ti.tc.clone();
-        if let Some(ref mut secrets) = tc.secrets {
-            if secrets.cookie_sauce.is_none() {
-                let global_cookie_sauce = &gs.config.secrets.cookie_sauce;
-                let derived_sauce = mom_types::derive_cookie_sauce(global_cookie_sauce, tn);
-                secrets.cookie_sauce = Some(derived_sauce);
+        let tc = match ts.tc_for_cubs() {
+            Ok(tc) => tc,
+            Err(e) => {
+                // the cub still gets every other tenant's state
+                log::error!("Failed to derive cookie sauce for tenant {tn}, leaving it out: {e}");
+                continue;
             }
-        }
+        };
 
         gm.initial_states.insert(
             tn.clone(),
@@ -250,3 +284,37 @@ async fn handle_socket(mut socket: ws::WebSocket) {
     }
     log::info!("WebSocket message loop ended");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tn(s: &str) -> TenantDomain {
+        TenantDomain::new(s.to_string())
+    }
+
+    #[test]
+    fn test_accessible_tenants() {
+        let known = [tn("b.example"), tn("a.example"), tn("c.example")];
+
+        let res = accessible_tenants(&KeyPermissions::Skeleton, &known);
+        assert!(res.all_tenants);
+        assert_eq!(
+            res.tenants,
+            vec![tn("a.example"), tn("b.example"), tn("c.example")]
+        );
+        assert!(res.unknown_tenants.is_empty());
+        assert_eq!(res.to_string(), "global key (all tenants)");
+
+        let scoped =
+            KeyPermissions::Tenants([tn("c.example"), tn("gone.example"), tn("a.example")].into());
+        let res = accessible_tenants(&scoped, &known);
+        assert!(!res.all_tenants);
+        assert_eq!(res.tenants, vec![tn("a.example"), tn("c.example")]);
+        assert_eq!(res.unknown_tenants, vec![tn("gone.example")]);
+        assert_eq!(
+            res.to_string(),
+            "scoped key for [a.example, c.example], also lists unknown tenants [gone.example]"
+        );
+    }
+}
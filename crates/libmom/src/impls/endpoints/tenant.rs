@@ -2,28 +2,30 @@ use std::collections::HashMap;
 
 use axum::routing::get;
 use config_types::is_development;
-use credentials::UserId;
+use credentials::{OAuthGrant, UserId};
 use libhttpclient::Uri;
 use rusqlite::OptionalExtension;
 
+use crate::impls::cookie_sauce;
 use crate::impls::discord_roles::synchronize_one_discord_role;
 use crate::impls::users::{
-    fetch_user_info, save_discord_credentials, save_discord_profile, save_github_credentials,
-    save_github_profile, save_patreon_credentials, save_patreon_profile,
+    PatreonProfileSource, fetch_user_info, save_discord_credentials, save_discord_profile,
+    save_github_credentials, save_github_profile, save_patreon_credentials, save_patreon_profile,
 };
 use crate::impls::{MomTenantState, global_state};
 use axum::{Extension, Router};
 use axum::{
     body::Bytes,
     extract::Path,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     routing::{post, put},
 };
 use libgithub::GithubCallbackArgs;
 use libpatreon::PatreonCallbackArgs;
 use mom_types::{
-    GithubCallbackResponse, ListMissingArgs, ListMissingResponse, PatreonCallbackResponse,
-    RefreshProfileArgs, TenantEventPayload,
+    CONTENT_SHA256_HEADER, GithubCallbackResponse, ListMissingArgs, ListMissingResponse,
+    PatreonCallbackResponse, RefreshProfileArgs, RotateCookieSauceResponse, TenantEventPayload,
+    verify_content_sha256,
 };
 use objectstore_types::{ObjectStoreKey, ObjectStoreKeyRef};
 
@@ -44,17 +46,33 @@ pub fn tenant_routes() -> Router {
         .route("/github/unlink", post(github_unlink))
         .route("/discord/unlink", post(discord_unlink))
         .route("/refresh-userinfo", post(refresh_userinfo))
+        .route("/users", get(users))
         .route("/make-api-key", post(make_api_key))
         .route("/verify-api-key", post(verify_api_key))
+        .route("/rotate-cookie-sauce", post(rotate_cookie_sauce))
         .route("/objectstore/list-missing", post(objectstore_list_missing))
         .route("/objectstore/put/{*key}", put(objectstore_put_key))
         .route("/media/upload", get(media::upload))
         .route("/media/transcode", post(media::transcode))
+        .route("/media/transcode/status", post(media::transcode_status))
         .route("/derive", post(derive::derive))
+        .route("/derive/stream", get(derive::derive_stream))
+        .route("/derive/batch", get(derive::derive_batch))
         .route("/revision/upload/{revision_id}", put(revision_upload_revid))
+        .route("/revision/request", post(revision_request))
         .route("/opendoor", post(opendoor::opendoor))
 }
 
+/// Reports scope downgrades, which the login survives but later calls
+/// needing those scopes won't
+fn creds_from_grant<C>(ts: &MomTenantState, grant: Option<OAuthGrant<C>>) -> Option<C> {
+    let grant = grant?;
+    if let Some(downgrade) = grant.scope_downgrade.as_ref() {
+        log::warn!("[{}] {downgrade}", ts.ti.tc.name);
+    }
+    Some(grant.creds)
+}
+
 async fn patreon_callback(
     Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
     body: Bytes,
@@ -67,9 +85,10 @@ async fn patreon_callback(
 
     let client = global_state().client.as_ref();
 
-    let creds = mod_patreon
+    let grant = mod_patreon
         .handle_oauth_callback(&ts.ti.tc, global_state().web, &args, client)
         .await?;
+    let creds = creds_from_grant(&ts, grant);
 
     let res: Option<PatreonCallbackResponse> = match creds {
         Some(creds) => {
@@ -101,7 +120,7 @@ async fn patreon_callback(
                 }
             };
 
-            save_patreon_profile(pool, &profile, &user_id)?;
+            save_patreon_profile(pool, &profile, &user_id, PatreonProfileSource::OAuth)?;
             let user_info = { fetch_user_info(pool, &user_id)?.unwrap() };
 
             Some(PatreonCallbackResponse { user_info })
@@ -122,9 +141,10 @@ async fn github_callback(
     let mod_github = libgithub::load();
 
     let web = global_state().web;
-    let creds = mod_github
+    let grant = mod_github
         .handle_oauth_callback(&ts.ti.tc, web, &args)
         .await?;
+    let creds = creds_from_grant(&ts, grant);
     let client = global_state().client.as_ref();
 
     let res: Option<GithubCallbackResponse> = match creds {
@@ -181,9 +201,10 @@ async fn discord_callback(
     let mod_discord = libdiscord::load();
 
     let web = global_state().web;
-    let creds = mod_discord
+    let grant = mod_discord
         .handle_oauth_callback(&ts.ti.tc, web, &args)
         .await?;
+    let creds = creds_from_grant(&ts, grant);
 
     let res: Option<mom_types::DiscordCallbackResponse> = match creds {
         Some(creds) => {
@@ -310,6 +331,13 @@ async fn refresh_userinfo(
     FacetJson(user_info).into_reply()
 }
 
+/// The tenant's current users, for cubs that missed (or can't wait for) the
+/// next `UsersUpdated` event. Same as what gets broadcast: no emails.
+async fn users(Extension(TenantExtractor(ts)): Extension<TenantExtractor>) -> Reply {
+    let users = ts.users.lock().for_cubs();
+    FacetJson(users).into_reply()
+}
+
 async fn objectstore_list_missing(
     Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
     body: Bytes,
@@ -363,8 +391,9 @@ async fn objectstore_list_missing(
                 log::warn!("...ignoring");
             }
             Ok(res) => {
+                // grows with the tenant, well past the HTTP client's default
                 let remote_res = res
-                    .json::<ListMissingResponse>()
+                    .json_limited::<ListMissingResponse>(256 * 1024 * 1024)
                     .await
                     .map_err(|e| eyre::eyre!("Failed to parse production mom response: {e}"))?;
 
@@ -394,15 +423,40 @@ async fn objectstore_list_missing(
     FacetJson(ListMissingResponse { missing }).into_reply()
 }
 
+/// Checks the body against the content hash the client sent, if any, and
+/// returns it so it can be echoed back.
+fn verified_content_hash(headers: &HeaderMap, payload: &[u8]) -> eyre::Result<Option<String>> {
+    let Some(expected) = headers.get(CONTENT_SHA256_HEADER) else {
+        return Ok(None);
+    };
+    let expected = expected.to_str()?;
+    verify_content_sha256(payload, expected)?;
+    Ok(Some(expected.to_string()))
+}
+
+fn ok_with_content_hash(hash: Option<String>) -> Reply {
+    match hash {
+        Some(hash) => (StatusCode::OK, [(CONTENT_SHA256_HEADER, hash)]).into_reply(),
+        None => StatusCode::OK.into_reply(),
+    }
+}
+
 async fn objectstore_put_key(
     Path(path): Path<HashMap<String, String>>,
     Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
+    headers: HeaderMap,
     payload: Bytes,
 ) -> Reply {
     let key = path
         .get("key")
         .cloned()
         .ok_or_else(|| eyre::eyre!("Missing key"))?;
+    let content_hash = match verified_content_hash(&headers, &payload) {
+        Ok(hash) => hash,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Rejecting {key}: {e}")).into_reply();
+        }
+    };
     let key = ObjectStoreKeyRef::from_str(&key);
     let size = payload.len();
     log::debug!("Putting asset into object store: key={key}, size={size}",);
@@ -421,18 +475,29 @@ async fn objectstore_put_key(
     }
 
     // Return 200 if everything went fine
-    StatusCode::OK.into_reply()
+    ok_with_content_hash(content_hash)
 }
 
 async fn revision_upload_revid(
     Path(path): Path<HashMap<String, String>>,
     Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
+    headers: HeaderMap,
     payload: Bytes,
 ) -> Reply {
     let revision_id = path
         .get("revision_id")
         .cloned()
         .ok_or_else(|| eyre::eyre!("Missing revision_id"))?;
+    let content_hash = match verified_content_hash(&headers, &payload) {
+        Ok(hash) => hash,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Rejecting revision {revision_id}: {e}"),
+            )
+                .into_reply();
+        }
+    };
     log::debug!("Uploading revision package; revision_id={revision_id}");
 
     // Load the revision from JSON
@@ -471,6 +536,26 @@ async fn revision_upload_revid(
     });
 
     // Return 200 immediately after spawning the background task
+    ok_with_content_hash(content_hash)
+}
+
+/// Re-broadcasts the tenant's current revision, so cubs that failed to load
+/// it (or missed it) get another shot without restarting.
+async fn revision_request(Extension(TenantExtractor(ts)): Extension<TenantExtractor>) -> Reply {
+    let Some(pak) = ts.pak.lock().clone() else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("no revision uploaded yet for {}", ts.ti.tc.name),
+        )
+            .into_reply();
+    };
+    log::info!(
+        "[{}] Revision requested, re-broadcasting {}",
+        ts.ti.tc.name,
+        pak.id
+    );
+    ts.broadcast_event(TenantEventPayload::RevisionChanged(Box::new(pak)))?;
+
     StatusCode::OK.into_reply()
 }
 
@@ -499,3 +584,27 @@ async fn verify_api_key(
 
     FacetJson(mom_types::VerifyApiKeyResponse { user_info }).into_reply()
 }
+
+/// Rotates the tenant's cookie sauce, which invalidates every auth cookie:
+/// everyone gets logged out. Only works for tenants whose sauce is derived
+/// from the global one.
+async fn rotate_cookie_sauce(Extension(TenantExtractor(ts)): Extension<TenantExtractor>) -> Reply {
+    if !ts.has_derived_cookie_sauce() {
+        return (
+            StatusCode::CONFLICT,
+            "This tenant's cookie_sauce is set in its secrets, change it there instead",
+        )
+            .into_reply();
+    }
+
+    let generation = cookie_sauce::bump_generation(&*ts.pool.get()?)?;
+    log::warn!(
+        "[{}] Rotated cookie sauce, now at generation {generation}: everyone is logged out",
+        ts.ti.tc.name
+    );
+    ts.broadcast_event(TenantEventPayload::CookieSauceRotated(
+        ts.derive_cookie_sauce(generation),
+    ))?;
+
+    FacetJson(RotateCookieSauceResponse { generation }).into_reply()
+}
@@ -1,8 +1,21 @@
 use axum::Extension;
+use axum::extract::ws;
+use eyre::bail;
+use futures_core::Stream;
+use futures_util::StreamExt as _;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 
-use super::TenantExtractor;
-use crate::impls::site::Reply;
-use mom_types::DeriveParams;
+use super::{MomTenantState, TenantExtractor, media::json_to_socket};
+use crate::impls::site::{IntoReply, Reply};
+use mom_types::{
+    DeriveBatchItem, DeriveBatchMessage, DeriveBatchOutcome, DeriveParams, DeriveResponse,
+    DeriveStreamMessage,
+};
+
+/// How many derivations of a batch run at once
+const DERIVE_BATCH_CONCURRENCY: usize = 4;
 
 pub(crate) async fn derive(
     Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
@@ -16,3 +29,220 @@ pub(crate) async fn derive(
         .await
         .unwrap()
 }
+
+/// Like [`derive`], but over a websocket, so we can stream transcoding events
+/// while the derivation runs.
+pub(crate) async fn derive_stream(
+    TenantExtractor(ts): TenantExtractor,
+    ws: axum::extract::WebSocketUpgrade,
+) -> Reply {
+    ws.on_upgrade(move |socket| handle_ws(socket, ts))
+        .into_reply()
+}
+
+async fn handle_ws(mut socket: ws::WebSocket, ts: Arc<MomTenantState>) {
+    if let Err(e) = handle_ws_inner(&mut socket, ts).await {
+        log::warn!("Error in derive socket: {e:?}");
+        let error_message = DeriveStreamMessage::Error(format!("Error: {e}"));
+        if let Err(send_err) = json_to_socket(&mut socket, &error_message).await {
+            log::error!("Failed to send error message to websocket: {send_err}");
+        }
+    }
+}
+
+async fn handle_ws_inner(socket: &mut ws::WebSocket, ts: Arc<MomTenantState>) -> eyre::Result<()> {
+    let params = loop {
+        match socket.recv().await {
+            Some(msg) => match msg? {
+                ws::Message::Text(text) => {
+                    let message: DeriveStreamMessage =
+                        facet_json::from_str(&text).map_err(|e| e.into_owned())?;
+                    match message {
+                        DeriveStreamMessage::Params(params) => break params,
+                        _ => bail!("Expected derive params"),
+                    }
+                }
+                ws::Message::Close(_) => bail!("WebSocket closed before derive params were sent"),
+                _ => {}
+            },
+            None => bail!("WebSocket closed before derive params were sent"),
+        }
+    };
+
+    // spawned for the same reason as in `derive`: if the client goes away, the
+    // derivation still finishes.
+    let (tx, mut rx) = mpsc::channel(100);
+    let task = tokio::spawn(crate::impls::deriver::derive(ts, params, Some(tx)));
+
+    while let Some(ev) = rx.recv().await {
+        json_to_socket(socket, &DeriveStreamMessage::TranscodingEvent(ev)).await?;
+    }
+    let response = task.await??;
+    json_to_socket(socket, &DeriveStreamMessage::Response(response)).await?;
+
+    Ok(())
+}
+
+/// Runs many derivations, a few at a time, reporting each one as it finishes.
+/// Meant for pre-generating a revision's assets at deploy time.
+pub(crate) async fn derive_batch(
+    TenantExtractor(ts): TenantExtractor,
+    ws: axum::extract::WebSocketUpgrade,
+) -> Reply {
+    ws.on_upgrade(move |socket| handle_batch_ws(socket, ts))
+        .into_reply()
+}
+
+async fn handle_batch_ws(mut socket: ws::WebSocket, ts: Arc<MomTenantState>) {
+    if let Err(e) = handle_batch_ws_inner(&mut socket, ts).await {
+        log::warn!("Error in derive batch socket: {e:?}");
+        let error_message = DeriveBatchMessage::Error(format!("Error: {e}"));
+        if let Err(send_err) = json_to_socket(&mut socket, &error_message).await {
+            log::error!("Failed to send error message to websocket: {send_err}");
+        }
+    }
+}
+
+async fn handle_batch_ws_inner(
+    socket: &mut ws::WebSocket,
+    ts: Arc<MomTenantState>,
+) -> eyre::Result<()> {
+    let batch = loop {
+        match socket.recv().await {
+            Some(msg) => match msg? {
+                ws::Message::Text(text) => {
+                    let message: DeriveBatchMessage =
+                        facet_json::from_str(&text).map_err(|e| e.into_owned())?;
+                    match message {
+                        DeriveBatchMessage::Params(batch) => break batch,
+                        _ => bail!("Expected derive batch params"),
+                    }
+                }
+                ws::Message::Close(_) => bail!("WebSocket closed before derive params were sent"),
+                _ => {}
+            },
+            None => bail!("WebSocket closed before derive params were sent"),
+        }
+    };
+    log::info!("Deriving a batch of {} items", batch.len());
+
+    // each derivation is spawned, so the ones that started finish even if the
+    // client goes away. The ones that haven't started yet never will.
+    let mut items = std::pin::pin!(run_batch(batch, DERIVE_BATCH_CONCURRENCY, |params| {
+        let ts = ts.clone();
+        async move {
+            match tokio::spawn(crate::impls::deriver::derive(ts, params, None)).await {
+                Ok(res) => res,
+                Err(e) => Err(eyre::eyre!("derivation task failed: {e}")),
+            }
+        }
+    }));
+    while let Some(item) = items.next().await {
+        json_to_socket(socket, &DeriveBatchMessage::Item(item)).await?;
+    }
+    json_to_socket(socket, &DeriveBatchMessage::Done).await?;
+
+    Ok(())
+}
+
+/// Runs `derive` on every item of `batch`, at most `concurrency` at a time,
+/// yielding results as they come in
+fn run_batch<F, Fut>(
+    batch: Vec<DeriveParams>,
+    concurrency: usize,
+    derive: F,
+) -> impl Stream<Item = DeriveBatchItem>
+where
+    F: Fn(DeriveParams) -> Fut,
+    Fut: Future<Output = eyre::Result<DeriveResponse>>,
+{
+    futures_util::stream::iter(batch.into_iter().enumerate())
+        .map(move |(index, params)| {
+            let derivation = derive(params);
+            async move {
+                let outcome = match derivation.await {
+                    Ok(response) => DeriveBatchOutcome::Response(response),
+                    Err(e) => DeriveBatchOutcome::Error(format!("{e}")),
+                };
+                DeriveBatchItem { index, outcome }
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use conflux::{Derivation, DerivationKind, Input, InputHash, InputPath};
+    use mom_types::DeriveResponseDone;
+    use objectstore_types::ObjectStoreKey;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn params(n: usize) -> DeriveParams {
+        let path = InputPath::new(format!("/content/img{n}.png"));
+        DeriveParams {
+            input: Input {
+                hash: InputHash::new(format!("hash{n}")),
+                path: path.clone(),
+                mtime: time::OffsetDateTime::UNIX_EPOCH,
+                size: 100,
+                content_type: content_type::ContentType::PNG,
+            },
+            derivation: Derivation {
+                input: path,
+                kind: DerivationKind::Identity,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_bounds_concurrency_and_reports_every_item() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let batch = (0..7).map(params).collect::<Vec<_>>();
+        let items = run_batch(batch, 3, |params| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+
+                if params.input.path.as_str() == "/content/img4.png" {
+                    eyre::bail!("img4 is cursed");
+                }
+                Ok(DeriveResponse::Done(DeriveResponseDone {
+                    output_size: 10,
+                    dest: ObjectStoreKey::new(format!("derivations{}", params.input.path)),
+                }))
+            }
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+        let mut indices = items.iter().map(|item| item.index).collect::<Vec<_>>();
+        indices.sort();
+        assert_eq!(indices, (0..7).collect::<Vec<_>>());
+
+        for item in &items {
+            match &item.outcome {
+                DeriveBatchOutcome::Error(e) => {
+                    assert_eq!(item.index, 4);
+                    assert!(e.contains("cursed"), "{e}");
+                }
+                DeriveBatchOutcome::Response(DeriveResponse::Done(done)) => {
+                    assert_ne!(item.index, 4);
+                    assert_eq!(
+                        done.dest.as_str(),
+                        format!("derivations/content/img{}.png", item.index)
+                    );
+                }
+                DeriveBatchOutcome::Response(_) => panic!("unexpected response"),
+            }
+        }
+    }
+}
@@ -6,7 +6,7 @@ use axum::http::StatusCode;
 use eyre::eyre;
 use facet::Facet;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
 use super::{MomTenantState, Reply, TenantExtractor};
@@ -17,9 +17,18 @@ use crate::impls::{
 use mom_types::{
     TranscodeJobInfo, TranscodeParams, TranscodeResponse, TranscodeResponseAlreadyInProgress,
     TranscodeResponseDone,
-    media_types::{HeadersMessage, TranscodeEvent, TranscodingCompleteMessage, WebSocketMessage},
+    media_types::{
+        HeadersMessage, ResumeDownloadMessage, TranscodeEvent, TranscodingCompleteMessage,
+        WebSocketMessage,
+    },
 };
 
+/// How long the output of a websocket transcode can be resumed for
+const RESUME_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Outputs are sent in slices of at most this many bytes
+const CHUNK_SIZE: usize = 2_000_000; // 2MB
+
 #[axum::debug_handler]
 pub(crate) async fn upload(
     TenantExtractor(ts): TenantExtractor,
@@ -42,7 +51,7 @@ async fn handle_ws(mut socket: ws::WebSocket, ts: Arc<MomTenantState>) {
     // See https://docs.rs/axum/latest/axum/extract/ws/enum.Message.html#variant.Close
 }
 
-async fn handle_ws_inner(socket: &mut ws::WebSocket, _ts: Arc<MomTenantState>) -> eyre::Result<()> {
+async fn handle_ws_inner(socket: &mut ws::WebSocket, ts: Arc<MomTenantState>) -> eyre::Result<()> {
     let mut headers: Option<HeadersMessage> = None;
     let mut input_data: Vec<u8> = Vec::new();
 
@@ -56,12 +65,19 @@ async fn handle_ws_inner(socket: &mut ws::WebSocket, _ts: Arc<MomTenantState>) -
                     WebSocketMessage::Headers(h) => {
                         headers = Some(h);
                     }
+                    WebSocketMessage::ResumeDownload(resume) if headers.is_none() => {
+                        return resume_download(socket, &ts, resume).await;
+                    }
                     WebSocketMessage::UploadDone(u) => {
                         if u.uploaded_size != input_data.len() {
                             return Err(eyre!("Uploaded size does not match input data size"));
                         }
                         break 'read_msg;
                     }
+                    WebSocketMessage::Cancel => {
+                        log::info!("Upload cancelled by the client");
+                        return Ok(());
+                    }
                     _ => return Err(eyre!("Unexpected message type")),
                 }
             }
@@ -105,27 +121,120 @@ async fn handle_ws_inner(socket: &mut ws::WebSocket, _ts: Arc<MomTenantState>) -
             result = &mut transcode_task => {
                 break result?;
             }
+            msg = socket.recv() => {
+                // returning drops the transcode, which kills ffmpeg and frees
+                // its permit
+                match msg {
+                    Some(Ok(ws::Message::Text(text))) => {
+                        let message: WebSocketMessage =
+                            facet_json::from_str(&text).map_err(|e| e.into_owned())?;
+                        match message {
+                            WebSocketMessage::Cancel => {
+                                log::info!("Transcode cancelled by the client");
+                                return Ok(());
+                            }
+                            _ => return Err(eyre!("Unexpected message type during transcode")),
+                        }
+                    }
+                    Some(Ok(ws::Message::Close(_))) | None => {
+                        return Err(eyre!("WebSocket closed during transcode"));
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                }
+            }
         }
     };
     let output_size = output_data.len();
+    let output_data = Bytes::from(output_data);
+
+    let resume_token = {
+        use rand::Rng;
+
+        rand::rng()
+            .sample_iter(&rand::distr::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>()
+    };
+    let resume_token = if ts
+        .transcode_outputs
+        .insert(resume_token.clone(), output_data.clone())
+    {
+        tokio::spawn({
+            let ts = ts.clone();
+            let resume_token = resume_token.clone();
+            async move {
+                tokio::time::sleep(RESUME_WINDOW).await;
+                ts.transcode_outputs.remove(&resume_token);
+            }
+        });
+        Some(resume_token)
+    } else {
+        log::info!("Transcode output is too big ({output_size} bytes) to be resumable");
+        None
+    };
+
     json_to_socket(
         socket,
-        &WebSocketMessage::TranscodingComplete(TranscodingCompleteMessage { output_size }),
+        &WebSocketMessage::TranscodingComplete(TranscodingCompleteMessage {
+            output_size,
+            resume_token: resume_token.clone(),
+        }),
     )
     .await?;
 
-    // Send the output data in slices of maximum 2MB
-    const CHUNK_SIZE: usize = 2_000_000; // 2MB
-    for chunk in output_data.chunks(CHUNK_SIZE) {
+    send_output(socket, output_data).await?;
+    // all sent, there's nothing left to resume
+    if let Some(resume_token) = resume_token {
+        ts.transcode_outputs.remove(&resume_token);
+    }
+    Ok(())
+}
+
+async fn resume_download(
+    socket: &mut ws::WebSocket,
+    ts: &MomTenantState,
+    resume: ResumeDownloadMessage,
+) -> eyre::Result<()> {
+    let output_data = ts
+        .transcode_outputs
+        .get(&resume.resume_token)
+        .ok_or_else(|| {
+            eyre!("Nothing to resume: the transcode output expired (or never existed)")
+        })?;
+    if resume.offset > output_data.len() {
+        return Err(eyre!(
+            "Cannot resume at byte {}, the output is only {} bytes",
+            resume.offset,
+            output_data.len()
+        ));
+    }
+
+    log::info!(
+        "Resuming transcode output download at {}/{} bytes",
+        resume.offset,
+        output_data.len()
+    );
+    send_output(socket, output_data.slice(resume.offset..)).await?;
+    ts.transcode_outputs.remove(&resume.resume_token);
+    Ok(())
+}
+
+async fn send_output(socket: &mut ws::WebSocket, output_data: Bytes) -> eyre::Result<()> {
+    let mut offset = 0;
+    while offset < output_data.len() {
+        let end = (offset + CHUNK_SIZE).min(output_data.len());
         socket
-            .send(ws::Message::binary(Bytes::copy_from_slice(chunk)))
+            .send(ws::Message::binary(output_data.slice(offset..end)))
             .await?;
+        offset = end;
     }
 
     Ok(())
 }
 
-async fn json_to_socket<'facet>(
+pub(super) async fn json_to_socket<'facet>(
     socket: &mut ws::WebSocket,
     payload: &'facet impl Facet<'facet>,
 ) -> eyre::Result<()> {
@@ -134,6 +243,28 @@ async fn json_to_socket<'facet>(
     Ok(())
 }
 
+/// Reports on the job transcoding `params`, without starting one. 404s if
+/// there's no such job: it either never started or is finished.
+pub(crate) async fn transcode_status(
+    Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
+    body: String,
+) -> Reply {
+    let params: TranscodeParams = facet_json::from_str(&body)?;
+    let status = ts
+        .transcode_jobs
+        .lock()
+        .get(&params)
+        .map(|info| info.status());
+    match status {
+        Some(status) => FacetJson(status).into_reply(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("no transcode job for {}", params.output),
+        )
+            .into_reply(),
+    }
+}
+
 // #[axum::debug_handler]
 pub(crate) async fn transcode(
     Extension(TenantExtractor(ts)): Extension<TenantExtractor>,
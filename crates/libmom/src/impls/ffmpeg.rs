@@ -71,6 +71,29 @@ pub fn configure_ffmpeg_command(
                 .arg("-ab")
                 .arg("196k");
         }
+        TargetFormat::HEVC => {
+            cmd.arg("-f")
+                .arg("mp4")
+                .arg("-c:v")
+                .arg("libx265")
+                .arg("-crf")
+                .arg("28")
+                .arg("-preset")
+                .arg("slow")
+                // Safari only plays HEVC in mp4 when tagged `hvc1` (ffmpeg defaults to `hev1`)
+                .arg("-tag:v")
+                .arg("hvc1");
+
+            // Same audio as AVC: anything that plays HEVC in mp4 plays AAC
+            cmd.arg("-pix_fmt")
+                .arg("yuv420p")
+                .arg("-movflags")
+                .arg("+faststart")
+                .arg("-c:a")
+                .arg("aac")
+                .arg("-ab")
+                .arg("196k");
+        }
         TargetFormat::ThumbJXL => {
             assert!(output_path.to_str().unwrap().ends_with(".jxl"));
             cmd.arg("-c:v")
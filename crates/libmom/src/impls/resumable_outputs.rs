@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+
+use axum::body::Bytes;
+use parking_lot::Mutex;
+
+/// How many bytes of transcode outputs a tenant keeps around for resuming
+const RESUMABLE_OUTPUTS_BUDGET: usize = 512 * 1024 * 1024;
+
+/// Outputs of recent websocket transcodes, by resume token, so clients whose
+/// connection dropped mid-download can pick up where they left off. They're
+/// held in memory, so there's a budget: past it, the oldest outputs go first.
+pub(crate) struct ResumableOutputs {
+    budget: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// oldest first
+    entries: VecDeque<(String, Bytes)>,
+    total: usize,
+}
+
+impl Default for ResumableOutputs {
+    fn default() -> Self {
+        Self::new(RESUMABLE_OUTPUTS_BUDGET)
+    }
+}
+
+impl ResumableOutputs {
+    pub(crate) fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            inner: Default::default(),
+        }
+    }
+
+    /// Keeps `output` around under `token`, evicting older outputs if needed.
+    /// Returns false (and keeps nothing) if it wouldn't fit in the budget
+    /// even on its own.
+    pub(crate) fn insert(&self, token: String, output: Bytes) -> bool {
+        if output.len() > self.budget {
+            return false;
+        }
+
+        let mut inner = self.inner.lock();
+        while inner.total + output.len() > self.budget {
+            let Some((evicted_token, evicted)) = inner.entries.pop_front() else {
+                break;
+            };
+            log::debug!(
+                "Evicting resumable output {evicted_token} ({} bytes) to make room",
+                evicted.len()
+            );
+            inner.total -= evicted.len();
+        }
+        inner.total += output.len();
+        inner.entries.push_back((token, output));
+        true
+    }
+
+    pub(crate) fn get(&self, token: &str) -> Option<Bytes> {
+        let inner = self.inner.lock();
+        inner
+            .entries
+            .iter()
+            .find(|(t, _)| t == token)
+            .map(|(_, output)| output.clone())
+    }
+
+    pub(crate) fn remove(&self, token: &str) {
+        let mut inner = self.inner.lock();
+        let Some(index) = inner.entries.iter().position(|(t, _)| t == token) else {
+            return;
+        };
+        if let Some((_, output)) = inner.entries.remove(index) {
+            inner.total -= output.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(len: usize) -> Bytes {
+        Bytes::from(vec![0u8; len])
+    }
+
+    #[test]
+    fn test_oldest_outputs_are_evicted() {
+        let outputs = ResumableOutputs::new(100);
+        assert!(outputs.insert("a".to_string(), output(40)));
+        assert!(outputs.insert("b".to_string(), output(40)));
+        assert!(outputs.insert("c".to_string(), output(40)));
+
+        assert!(outputs.get("a").is_none());
+        assert_eq!(outputs.get("b").unwrap().len(), 40);
+        assert_eq!(outputs.get("c").unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_too_big_outputs_are_not_kept() {
+        let outputs = ResumableOutputs::new(100);
+        assert!(outputs.insert("a".to_string(), output(40)));
+        assert!(!outputs.insert("huge".to_string(), output(101)));
+        assert!(outputs.get("huge").is_none());
+        // ...and didn't push anything out
+        assert!(outputs.get("a").is_some());
+    }
+
+    #[test]
+    fn test_removed_outputs_free_the_budget() {
+        let outputs = ResumableOutputs::new(100);
+        assert!(outputs.insert("a".to_string(), output(60)));
+        outputs.remove("a");
+        assert!(outputs.get("a").is_none());
+
+        assert!(outputs.insert("b".to_string(), output(50)));
+        assert!(outputs.insert("c".to_string(), output(50)));
+        assert!(outputs.get("b").is_some());
+    }
+}
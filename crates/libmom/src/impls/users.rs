@@ -1,6 +1,7 @@
 use credentials::{
-    DiscordUserId, DiscordUserIdRef, GithubProfile, GithubUserId, GithubUserIdRef, PatreonProfile,
-    PatreonUserId, PatreonUserIdRef, UserApiKey, UserId, UserIdRef, UserInfo,
+    CREDENTIALS_REFRESH_THRESHOLD, Credentials, DiscordUserId, DiscordUserIdRef, GithubProfile,
+    GithubUserId, GithubUserIdRef, PatreonProfile, PatreonUserId, PatreonUserIdRef, UserApiKey,
+    UserId, UserIdRef, UserInfo,
 };
 use libdiscord::DiscordCredentials;
 use libgithub::GithubCredentials;
@@ -48,6 +49,17 @@ pub(crate) async fn refresh_sponsors(ts: &MomTenantState) -> eyre::Result<AllUse
     fetch_all_users(ts).await
 }
 
+/// How big a single page of GitHub/Patreon sponsors can get. Way more than a
+/// page of sponsors takes, but a broken response won't eat all our memory.
+fn max_sponsor_page_size() -> usize {
+    global_state()
+        .config
+        .max_sponsor_page_size
+        .map_or(16 * 1024 * 1024, |max| {
+            usize::try_from(max.as_u64()).unwrap_or(usize::MAX)
+        })
+}
+
 async fn refresh_patreon_sponsors(
     ts: &MomTenantState,
     client: &dyn HttpClient,
@@ -67,7 +79,9 @@ async fn refresh_patreon_sponsors(
         .await?
         .ok_or_else(|| eyre::eyre!("creator needs to log in with Patreon first"))?;
 
-    let profiles = patreon.list_sponsors(&rc, client, &creds).await?;
+    let profiles = patreon
+        .list_sponsors(&ts.ti.tc, &rc, client, &creds, max_sponsor_page_size())
+        .await?;
 
     // Check which Patreon profiles already exist in the database
     let conn = ts.pool.get()?;
@@ -108,7 +122,12 @@ async fn refresh_patreon_sponsors(
         };
 
         // Save the Patreon profile to the database (for all profiles)
-        save_patreon_profile(&ts.pool, profile, &user_id)?;
+        save_patreon_profile(
+            &ts.pool,
+            profile,
+            &user_id,
+            PatreonProfileSource::SponsorSync,
+        )?;
     }
 
     Ok(())
@@ -130,7 +149,9 @@ async fn refresh_github_sponsors(ts: &MomTenantState, client: &dyn HttpClient) -
     let creds = fetch_uptodate_github_credentials(ts, &creator_github_id)
         .await?
         .ok_or_else(|| eyre::eyre!("creator needs to log in with Github first"))?;
-    let profiles = github.list_sponsors(client, &creds).await?;
+    let profiles = github
+        .list_sponsors(&ts.ti.tc, client, &creds, max_sponsor_page_size())
+        .await?;
 
     // Check which GitHub profiles already exist in the database
     let conn = ts.pool.get()?;
@@ -189,16 +210,21 @@ pub(crate) fn fetch_user_info(
                 p.tier as p_tier,
                 p.full_name as p_full_name,
                 p.avatar_url as p_avatar_url,
+                p.email as p_email,
+                p.entitled_cents as p_entitled_cents,
                 g.id as g_id,
                 g.monthly_usd as g_monthly_usd,
                 g.sponsorship_privacy_level as g_sponsorship_privacy_level,
                 g.name as g_name,
                 g.login as g_login,
                 g.avatar_url as g_avatar_url,
+                g.email as g_email,
                 d.id as d_id,
                 d.username as d_username,
                 d.global_name as d_global_name,
                 d.avatar_hash as d_avatar_hash,
+                d.discriminator as d_discriminator,
+                d.email as d_email,
                 CASE WHEN dgm.user_id IS NOT NULL THEN 1 ELSE 0 END as in_discord
             FROM users u
             LEFT JOIN patreon_profiles p ON u.id = p.user_id
@@ -222,6 +248,8 @@ pub(crate) fn fetch_user_info(
                             tier: row.get("p_tier")?,
                             full_name: row.get("p_full_name")?,
                             avatar_url: row.get("p_avatar_url")?,
+                            email: row.get("p_email")?,
+                            entitled_cents: row.get("p_entitled_cents")?,
                         })
                     } else {
                         None
@@ -239,6 +267,7 @@ pub(crate) fn fetch_user_info(
                             name: row.get("g_name")?,
                             login: row.get("g_login")?,
                             avatar_url: row.get("g_avatar_url")?,
+                            email: row.get("g_email")?,
                         })
                     } else {
                         None
@@ -254,6 +283,8 @@ pub(crate) fn fetch_user_info(
                             username: row.get("d_username")?,
                             global_name: row.get("d_global_name")?,
                             avatar_hash: row.get("d_avatar_hash")?,
+                            discriminator: row.get("d_discriminator")?,
+                            email: row.get("d_email")?,
                         })
                     } else {
                         None
@@ -322,7 +353,7 @@ pub(crate) async fn fetch_uptodate_github_credentials(
 
     let client = global_state().client.as_ref();
 
-    if creds.expire_soon() {
+    if creds.expire_soon(CREDENTIALS_REFRESH_THRESHOLD) {
         let github = libgithub::load();
         let refreshed_creds = github
             .refresh_credentials(&ts.ti.tc, &creds, client)
@@ -379,8 +410,9 @@ pub(crate) fn save_github_profile(
             name,
             login,
             avatar_url,
+            email,
             updated_at
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, CURRENT_TIMESTAMP)
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, CURRENT_TIMESTAMP)
         ON CONFLICT(id) DO UPDATE SET
             user_id = excluded.user_id,
             monthly_usd = excluded.monthly_usd,
@@ -388,6 +420,7 @@ pub(crate) fn save_github_profile(
             name = excluded.name,
             login = excluded.login,
             avatar_url = excluded.avatar_url,
+            email = COALESCE(excluded.email, email),
             updated_at = excluded.updated_at
         ",
         rusqlite::params![
@@ -397,7 +430,8 @@ pub(crate) fn save_github_profile(
             profile.sponsorship_privacy_level,
             profile.name,
             profile.login,
-            profile.avatar_url
+            profile.avatar_url,
+            profile.email
         ],
     )?;
     Ok(())
@@ -441,7 +475,7 @@ pub(crate) async fn fetch_uptodate_patreon_credentials(
 
     let client = global_state().client.as_ref();
 
-    if creds.expire_soon() {
+    if creds.expire_soon(CREDENTIALS_REFRESH_THRESHOLD) {
         let patreon = libpatreon::load();
         let refreshed_creds = patreon
             .refresh_credentials(&ts.ti.tc, &creds, client)
@@ -471,10 +505,22 @@ pub(crate) fn save_patreon_credentials(
     Ok(())
 }
 
+/// Where a Patreon profile we're saving comes from
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PatreonProfileSource {
+    /// the creator's member list: authoritative about entitlements, a missing
+    /// amount means the member isn't entitled to anything anymore
+    SponsorSync,
+    /// the user's own OAuth token, which doesn't tell us how much they pledge,
+    /// so we keep whatever the last sponsor sync found
+    OAuth,
+}
+
 pub(crate) fn save_patreon_profile(
     pool: &SqlitePool,
     profile: &PatreonProfile,
     user_id: &UserId,
+    source: PatreonProfileSource,
 ) -> eyre::Result<()> {
     let conn = pool.get()?;
     conn.execute(
@@ -485,13 +531,20 @@ pub(crate) fn save_patreon_profile(
             tier,
             full_name,
             avatar_url,
+            email,
+            entitled_cents,
             updated_at
-        ) VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, CURRENT_TIMESTAMP)
         ON CONFLICT(id) DO UPDATE SET
             user_id = excluded.user_id,
             tier = excluded.tier,
             full_name = excluded.full_name,
             avatar_url = excluded.avatar_url,
+            email = COALESCE(excluded.email, email),
+            entitled_cents = CASE
+                WHEN ?8 THEN COALESCE(excluded.entitled_cents, entitled_cents)
+                ELSE excluded.entitled_cents
+            END,
             updated_at = excluded.updated_at
         ",
         rusqlite::params![
@@ -499,7 +552,10 @@ pub(crate) fn save_patreon_profile(
             user_id.to_string(),
             profile.tier,
             profile.full_name,
-            profile.avatar_url
+            profile.avatar_url,
+            profile.email,
+            profile.entitled_cents,
+            source == PatreonProfileSource::OAuth
         ],
     )?;
     Ok(())
@@ -541,7 +597,7 @@ pub(crate) async fn fetch_uptodate_discord_credentials(
         return Ok(None);
     };
 
-    if creds.expire_soon() {
+    if creds.expire_soon(CREDENTIALS_REFRESH_THRESHOLD) {
         let discord = libdiscord::load();
         let refreshed_creds = discord.refresh_credentials(&ts.ti.tc, &creds).await?;
         save_discord_credentials(&ts.pool, discord_user_id, &refreshed_creds)?;
@@ -583,13 +639,17 @@ pub(crate) fn save_discord_profile(
             username,
             global_name,
             avatar_hash,
+            discriminator,
+            email,
             updated_at
-        ) VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, CURRENT_TIMESTAMP)
         ON CONFLICT(id) DO UPDATE SET
             user_id = excluded.user_id,
             username = excluded.username,
             global_name = excluded.global_name,
             avatar_hash = excluded.avatar_hash,
+            discriminator = excluded.discriminator,
+            email = COALESCE(excluded.email, email),
             updated_at = excluded.updated_at
         ",
         rusqlite::params![
@@ -597,7 +657,9 @@ pub(crate) fn save_discord_profile(
             user_id.to_string(),
             profile.username,
             profile.global_name,
-            profile.avatar_hash
+            profile.avatar_hash,
+            profile.discriminator,
+            profile.email
         ],
     )?;
     Ok(())
@@ -659,7 +721,7 @@ pub(crate) async fn refresh_userinfo(
 
             let patreon = libpatreon::load();
             let profile = patreon.fetch_profile(&rc, &creds, client).await?;
-            save_patreon_profile(&ts.pool, &profile, &id)?;
+            save_patreon_profile(&ts.pool, &profile, &id, PatreonProfileSource::OAuth)?;
 
             Some(profile)
         } else {
@@ -748,16 +810,21 @@ pub(crate) async fn fetch_all_users(ts: &MomTenantState) -> eyre::Result<AllUser
             p.tier as p_tier,
             p.full_name as p_full_name,
             p.avatar_url as p_avatar_url,
+            p.email as p_email,
+            p.entitled_cents as p_entitled_cents,
             g.id as g_id,
             g.monthly_usd as g_monthly_usd,
             g.sponsorship_privacy_level as g_sponsorship_privacy_level,
             g.name as g_name,
             g.login as g_login,
             g.avatar_url as g_avatar_url,
+            g.email as g_email,
             d.id as d_id,
             d.username as d_username,
             d.global_name as d_global_name,
             d.avatar_hash as d_avatar_hash,
+            d.discriminator as d_discriminator,
+            d.email as d_email,
             CASE WHEN dgm.user_id IS NOT NULL THEN 1 ELSE 0 END as in_discord
         FROM users u
         LEFT JOIN patreon_profiles p ON u.id = p.user_id
@@ -781,6 +848,8 @@ pub(crate) async fn fetch_all_users(ts: &MomTenantState) -> eyre::Result<AllUser
                         tier: row.get("p_tier")?,
                         full_name: row.get("p_full_name")?,
                         avatar_url: row.get("p_avatar_url")?,
+                        email: row.get("p_email")?,
+                        entitled_cents: row.get("p_entitled_cents")?,
                     })
                 } else {
                     None
@@ -798,6 +867,7 @@ pub(crate) async fn fetch_all_users(ts: &MomTenantState) -> eyre::Result<AllUser
                         name: row.get("g_name")?,
                         login: row.get("g_login")?,
                         avatar_url: row.get("g_avatar_url")?,
+                        email: row.get("g_email")?,
                     })
                 } else {
                     None
@@ -813,6 +883,8 @@ pub(crate) async fn fetch_all_users(ts: &MomTenantState) -> eyre::Result<AllUser
                         username: row.get("d_username")?,
                         global_name: row.get("d_global_name")?,
                         avatar_hash: row.get("d_avatar_hash")?,
+                        discriminator: row.get("d_discriminator")?,
+                        email: row.get("d_email")?,
                     })
                 } else {
                     None
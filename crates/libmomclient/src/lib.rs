@@ -1,32 +1,97 @@
 use autotrait::autotrait;
-use config_types::{MOM_DEV_API_KEY, MomApiKey, production_mom_url};
+use camino::Utf8Path;
+use config_types::{MOM_DEV_API_KEY, MomApiKey, TenantDomain, production_mom_url};
 use credentials::UserInfo;
-use eyre::bail;
-use futures_core::future::BoxFuture;
+use eyre::{Context as _, bail};
+use futures_core::{future::BoxFuture, stream::BoxStream};
 use libdiscord::DiscordCallbackArgs;
 use mom_types::{
-    DeriveParams, DeriveResponse, GithubCallbackResponse, ListMissingArgs, ListMissingResponse,
-    MomEvent, PatreonCallbackResponse, RefreshProfileArgs, TranscodeParams, TranscodeResponse,
-    media_types::{HeadersMessage, TranscodeEvent, UploadDoneMessage, WebSocketMessage},
+    AllUsers, CONTENT_SHA256_HEADER, DeriveBatchItem, DeriveBatchMessage, DeriveBatchOutcome,
+    DeriveParams, DeriveResponse, DeriveStreamMessage, GithubCallbackResponse, ListMissingArgs,
+    ListMissingResponse, ListTenantsResponse, MomEvent, PatreonCallbackResponse,
+    RefreshProfileArgs, TranscodeJobStatus, TranscodeParams, TranscodeResponse, content_sha256,
+    media_types::{
+        HeadersMessage, ResumeDownloadMessage, TranscodeEvent, UploadDoneMessage, WebSocketMessage,
+    },
 };
 use std::str::FromStr;
 
 use libhttpclient::{
-    HeaderMap, HeaderValue, Response, Uri,
+    HeaderMap, HeaderName, HeaderValue, Response, RetryPolicy, Uri,
     header::{self},
 };
 use log::info;
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bytes::Bytes;
 use conflux::RevisionIdRef;
 use libgithub::GithubCallbackArgs;
 use libhttpclient::{HttpClient, RequestBuilder};
 use libpatreon::PatreonCallbackArgs;
-use objectstore_types::ObjectStoreKeyRef;
+use libwebsock::{ConnectFn, ConnectionState, WebSocketStream};
+use objectstore_types::{ObjectStoreKey, ObjectStoreKeyRef};
 
 pub trait MomEventListener: Send + 'static {
     fn on_event<'fut>(&'fut self, event: MomEvent) -> BoxFuture<'fut, ()>;
+
+    /// Called when mom sent an event we couldn't parse, right before we stop
+    /// relaying events. Usually means mom is newer (or older) than we are.
+    fn on_schema_mismatch(&self, _mismatch: &MomSchemaMismatch) {}
+}
+
+/// How much of an unparseable event we keep around for error messages
+const MISMATCH_PAYLOAD_MAX_LEN: usize = 512;
+
+/// Some responses grow with the tenant (all its users, all its missing
+/// objects), well past the HTTP client's default body size
+const MAX_TENANT_SIZED_RESPONSE: usize = 256 * 1024 * 1024;
+
+/// Mom sent an event that doesn't match our idea of [`MomEvent`]. Find it
+/// with `err.downcast_ref::<MomSchemaMismatch>()`.
+#[derive(Debug, Clone)]
+pub struct MomSchemaMismatch {
+    /// The raw event, truncated to a reasonable length
+    pub payload: String,
+    /// What the JSON parser had to say about it
+    pub parse_error: String,
+}
+
+impl std::fmt::Display for MomSchemaMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mom event doesn't match our schema: {} (payload: {})",
+            self.parse_error, self.payload
+        )
+    }
+}
+
+impl std::error::Error for MomSchemaMismatch {}
+
+/// Parses an event frame from mom, failing with [`MomSchemaMismatch`]
+pub fn parse_mom_event(payload: &str) -> Result<MomEvent> {
+    facet_json::from_str::<MomEvent>(payload).map_err(|e| {
+        let mut end = payload.len().min(MISMATCH_PAYLOAD_MAX_LEN);
+        while !payload.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut truncated = payload[..end].to_string();
+        if end < payload.len() {
+            truncated.push('…');
+        }
+        eyre::Report::new(MomSchemaMismatch {
+            payload: truncated,
+            parse_error: e.into_owned().to_string(),
+        })
+    })
+}
+
+enum Relayed {
+    Event(MomEvent),
+    SchemaMismatch(MomSchemaMismatch),
 }
 
 pub use eyre::Result;
@@ -77,77 +142,67 @@ impl Mod for ModImpl {
                         .build()
                         .unwrap();
 
-                    'connect_loop: loop {
-                        log::debug!("Connecting to mom... ({uri})");
-                        async fn random_sleep() {
-                            let jitter = rand::random::<u64>() % 500;
-                            tokio::time::sleep(std::time::Duration::from_millis(1000 + jitter))
-                                .await;
+                    let connect: ConnectFn = Box::new({
+                        let uri = uri.clone();
+                        move || -> BoxFuture<'static, Result<Box<dyn WebSocketStream>>> {
+                            log::debug!("Connecting to mom... ({uri})");
+                            let mut headers = HeaderMap::new();
+                            headers.insert(
+                                libhttpclient::header::AUTHORIZATION,
+                                HeaderValue::from_str(&format!("Bearer {}", mcc.api_key()))
+                                    .unwrap(),
+                            );
+                            let connecting =
+                                libwebsock::load().websocket_connect(uri.clone(), headers);
+                            Box::pin(async move {
+                                tokio::time::timeout(Duration::from_secs(3), connecting)
+                                    .await
+                                    .map_err(|_| eyre::eyre!("Timeout connecting to mom"))?
+                            })
                         }
-
-                        let before = Instant::now();
-                        let mod_websock = libwebsock::load();
-
-                        let mut ws = match tokio::time::timeout(
-                            std::time::Duration::from_secs(3),
-                            mod_websock.websocket_connect(uri.clone(), {
-                                let mut map = HeaderMap::new();
-                                map.insert(
-                                    libhttpclient::header::AUTHORIZATION,
-                                    HeaderValue::from_str(&format!("Bearer {}", mcc.api_key()))
-                                        .unwrap(),
-                                );
-                                map
-                            }),
-                        )
-                        .await
-                        {
-                            Ok(Ok(res)) => res,
-                            Ok(Err(e)) => {
-                                log::warn!("Failed to connect to mom: {e}");
-                                random_sleep().await;
-                                continue 'connect_loop;
+                    });
+                    let mut ws = libwebsock::reconnect(connect, MOM_EVENTS_RECONNECT)
+                        .on_state_change(Arc::new(move |state| match state {
+                            ConnectionState::Connecting { attempt, delay } => {
+                                log::debug!("Connecting to mom in {delay:?} (attempt {attempt})");
                             }
-                            Err(_) => {
-                                log::warn!("Timeout connecting to mom");
-                                random_sleep().await;
-                                continue 'connect_loop;
+                            ConnectionState::Connected => {
+                                log::info!("🧸 mom connection established! uri={uri}");
                             }
+                            ConnectionState::Disconnected { reason } => {
+                                log::warn!("Lost connection to mom ({reason}), will reconnect");
+                            }
+                        }));
+
+                    loop {
+                        let before_recv = Instant::now();
+                        let ev = match ws.receive().await {
+                            Some(Ok(ev)) => ev,
+                            Some(Err(e)) => return Err(e),
+                            None => bail!("Gave up connecting to mom"),
                         };
-                        let elapsed = before.elapsed();
-                        log::info!("🧸 mom connection established! uri={uri} elapsed={elapsed:?}");
-
-                        #[allow(unused_labels)]
-                        'receive_loop: loop {
-                            let before_recv = Instant::now();
-                            let ev = match ws.receive().await {
-                                None => {
-                                    log::warn!("Connection closed by mom");
-                                    log::warn!("...will reconnect now");
-                                    continue 'connect_loop;
-                                }
-                                Some(Ok(ev)) => ev,
-                                Some(Err(e)) => {
-                                    log::warn!("Failed to receive mom event: {e}");
-                                    log::warn!("...will reconnect now");
-                                    continue 'connect_loop;
-                                }
-                            };
 
-                            let ev = match ev {
-                                libwebsock::Message::Text(ev) => ev,
-                                _ => {
-                                    bail!("Expected text frame")
-                                }
-                            };
+                        let ev = match ev {
+                            libwebsock::Message::Text(ev) => ev,
+                            _ => {
+                                bail!("Expected text frame")
+                            }
+                        };
 
-                            let ev = facet_json::from_str::<MomEvent>(&ev)
-                                .map_err(|e| e.into_owned())?;
-                            let elapsed = before_recv.elapsed();
-                            log::debug!("Got event from mom: ev={ev:?}, elapsed={elapsed:?}");
+                        let ev = match parse_mom_event(&ev) {
+                            Ok(ev) => ev,
+                            Err(e) => {
+                                if let Some(mismatch) = e.downcast_ref::<MomSchemaMismatch>() {
+                                    let _ =
+                                        ev_tx.send(Relayed::SchemaMismatch(mismatch.clone())).await;
+                                }
+                                return Err(e);
+                            }
+                        };
+                        let elapsed = before_recv.elapsed();
+                        log::debug!("Got event from mom: ev={ev:?}, elapsed={elapsed:?}");
 
-                            let _ = ev_tx.send(ev).await;
-                        }
+                        let _ = ev_tx.send(Relayed::Event(ev)).await;
                     }
                 }
             };
@@ -156,16 +211,23 @@ impl Mod for ModImpl {
                 let res: Result<()> = relay_fut.await;
                 if let Err(e) = res {
                     log::error!("Failed to relay mom events: {e}");
-                    log::error!(
-                        "Is the local mom newer? Maybe? If the schema changed, you can develop locally by exporting the environment variable FORCE_LOCAL_MOM=1"
-                    );
+                    if e.downcast_ref::<MomSchemaMismatch>().is_some() {
+                        log::error!(
+                            "Is the local mom newer? Maybe? If the schema changed, you can develop locally by exporting the environment variable FORCE_LOCAL_MOM=1"
+                        );
+                    }
                 }
             });
 
             tokio::spawn({
                 async move {
-                    while let Some(ev) = ev_rx.recv().await {
-                        ev_listener.on_event(ev).await;
+                    while let Some(relayed) = ev_rx.recv().await {
+                        match relayed {
+                            Relayed::Event(ev) => ev_listener.on_event(ev).await,
+                            Relayed::SchemaMismatch(mismatch) => {
+                                ev_listener.on_schema_mismatch(&mismatch)
+                            }
+                        }
                     }
                 }
             });
@@ -184,6 +246,9 @@ pub struct MomClientConfig {
     pub base_url: String,
     /// The API key used to authenticate with the Mom server.
     pub api_key: Option<MomApiKey>,
+    /// In development, send revision and asset uploads to the production mom
+    /// instead of `base_url`. See [`ProdMomChoice`].
+    pub use_prod_mom_in_dev: bool,
 }
 
 impl MomClientConfig {
@@ -201,6 +266,61 @@ impl MomClientConfig {
     }
 }
 
+/// Which mom revision and asset uploads go to, and which knob decided it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProdMomChoice {
+    /// Not in development: always the configured mom
+    NotDevelopment,
+    /// `FORCE_LOCAL_MOM` is set: the configured mom, whatever the config says
+    ForceLocalMomEnv,
+    /// `use_prod_mom_in_dev` is off: the configured mom
+    ConfigDisabled,
+    /// `use_prod_mom_in_dev` is on (the default): the production mom
+    ConfigEnabled,
+}
+
+impl ProdMomChoice {
+    /// The env var wins over the config: it's the quicker one to flip for a
+    /// single run.
+    pub fn resolve(is_development: bool, use_prod_mom_in_dev: bool, force_local_mom: bool) -> Self {
+        if !is_development {
+            Self::NotDevelopment
+        } else if force_local_mom {
+            Self::ForceLocalMomEnv
+        } else if !use_prod_mom_in_dev {
+            Self::ConfigDisabled
+        } else {
+            Self::ConfigEnabled
+        }
+    }
+
+    pub fn uses_production_mom(self) -> bool {
+        matches!(self, Self::ConfigEnabled)
+    }
+
+    /// For logs
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::NotDevelopment => "not in development",
+            Self::ForceLocalMomEnv => "FORCE_LOCAL_MOM is set",
+            Self::ConfigDisabled => "use_prod_mom_in_dev is off",
+            Self::ConfigEnabled => "use_prod_mom_in_dev is on",
+        }
+    }
+}
+
+/// Whether `FORCE_LOCAL_MOM` is set to `1` or `true`. Read once per process.
+pub fn force_local_mom() -> bool {
+    use std::sync::OnceLock;
+    static FORCE_LOCAL_MOM_ONCE: OnceLock<bool> = OnceLock::new();
+    *FORCE_LOCAL_MOM_ONCE
+        .get_or_init(|| parse_force_local_mom(std::env::var("FORCE_LOCAL_MOM").ok().as_deref()))
+}
+
+fn parse_force_local_mom(val: Option<&str>) -> bool {
+    val.is_some_and(|val| val == "1" || val.eq_ignore_ascii_case("true"))
+}
+
 struct MomClientImpl {
     hclient: Arc<dyn HttpClient>,
     mcc: MomClientConfig,
@@ -216,14 +336,78 @@ impl MomClient for MomClientImpl {
             base_path: format!("/tenant/{tenant_name}"),
             hclient: self.hclient.clone(),
             mcc: self.mcc.clone(),
+            dry_run: false,
+        })
+    }
+
+    /// Like `mom_tenant_client`, but uploads (`put_asset`, `put_revpak`) are
+    /// logged instead of sent. Everything else, like listing missing objects,
+    /// still talks to mom.
+    fn mom_tenant_client_dry_run(
+        &self,
+        tenant_name: config_types::TenantDomain,
+    ) -> Box<dyn MomTenantClient> {
+        Box::new(MomTenantClientImpl {
+            base_path: format!("/tenant/{tenant_name}"),
+            hclient: self.hclient.clone(),
+            mcc: self.mcc.clone(),
+            dry_run: true,
+        })
+    }
+
+    /// Tenants our API key can access: all of them for the readonly key,
+    /// only some of them for scoped keys. Its `Display` is handy when mom
+    /// answers 403.
+    fn list_accessible_tenants(&self) -> BoxFuture<'_, Result<ListTenantsResponse>> {
+        Box::pin(async move {
+            let uri = Uri::try_from(format!("{}/tenants", self.mcc.base_url))?;
+            let res = self
+                .hclient
+                .get(uri)
+                .with_auth(&self.mcc)
+                .send_and_expect_200()
+                .await?;
+            res.json().await
+        })
+    }
+
+    /// How long a round-trip to mom's (no-op) health endpoint takes, for
+    /// liveness probes and metrics. Errors out rather than retrying if mom
+    /// is down.
+    fn ping(&self) -> BoxFuture<'_, Result<Duration>> {
+        Box::pin(async move {
+            let uri = Uri::try_from(format!("{}/health", self.mcc.base_url))?;
+            let before_ping = Instant::now();
+            tokio::time::timeout(
+                PING_TIMEOUT,
+                self.hclient
+                    .get(uri)
+                    .retry(PING_RETRY)
+                    .send_and_expect_200(),
+            )
+            .await
+            .map_err(|_| eyre::eyre!("mom didn't answer a ping within {PING_TIMEOUT:?}"))??;
+            Ok(before_ping.elapsed())
         })
     }
 }
 
+/// How long [`MomClient::ping`] waits for mom
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A ping that fails is an answer in itself
+const PING_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: Some(1),
+    ..RetryPolicy::QUICK
+};
+
+#[derive(Clone)]
 struct MomTenantClientImpl {
     mcc: MomClientConfig,
     base_path: String,
     hclient: Arc<dyn HttpClient>,
+    /// If set, `put_asset` and `put_revpak` only log what they would upload
+    dry_run: bool,
 }
 
 impl MomTenantClientImpl {
@@ -240,28 +424,94 @@ impl MomTenantClientImpl {
             .unwrap()
     }
 
+    /// Opens a websocket to the mom server, retrying the connect with backoff.
+    /// note: path is a relative path, like `media/upload` (no leading slash)
+    async fn connect_ws(&self, relative_path: &str) -> Result<Box<dyn WebSocketStream>> {
+        let base_uri = self.config_mom_uri(relative_path);
+        let uri = Uri::builder()
+            .scheme(if base_uri.scheme_str() == Some("https") {
+                "wss"
+            } else {
+                "ws"
+            })
+            .authority(base_uri.authority().unwrap().as_str())
+            .path_and_query(base_uri.path_and_query().unwrap().as_str())
+            .build()
+            .unwrap();
+        info!("Connecting to mom websocket: {uri}");
+
+        let connect: ConnectFn = Box::new({
+            let uri = uri.clone();
+            let mcc = self.mcc.clone();
+            move || -> BoxFuture<'static, Result<Box<dyn WebSocketStream>>> {
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    libhttpclient::header::AUTHORIZATION,
+                    HeaderValue::from_str(&format!("Bearer {}", mcc.api_key())).unwrap(),
+                );
+                let connecting = libwebsock::load().websocket_connect(uri.clone(), headers);
+                Box::pin(async move {
+                    tokio::time::timeout(WS_CONNECT_TIMEOUT, connecting)
+                        .await
+                        .map_err(|_| eyre::eyre!("timed out after {WS_CONNECT_TIMEOUT:?}"))?
+                })
+            }
+        });
+        libwebsock::reconnect(connect, WS_CONNECT_RETRY)
+            .on_state_change(Arc::new({
+                let uri = uri.clone();
+                move |state| {
+                    if let ConnectionState::Disconnected { reason } = state {
+                        log::warn!("Failed to connect to {uri}: {reason}");
+                    }
+                }
+            }))
+            .into_connected()
+            .await
+            .wrap_err_with(|| format!("could not connect to {uri}"))
+    }
+
+    /// PUTs `payload` along with its content hash. If mom echoes the hash back,
+    /// it has to match: otherwise the body got mangled along the way.
+    async fn put_with_content_hash(&self, uri: Uri, payload: Bytes) -> Result<()> {
+        let hash = content_sha256(&payload);
+        let res = self
+            .hclient
+            .put(uri.clone())
+            .with_auth(&self.mcc)
+            .header(
+                HeaderName::from_static(CONTENT_SHA256_HEADER),
+                HeaderValue::from_str(&hash).unwrap(),
+            )
+            .body(payload)
+            .send_and_expect_200()
+            .await?;
+        if let Some(echoed) = res.headers().get(CONTENT_SHA256_HEADER) {
+            if echoed.as_bytes() != hash.as_bytes() {
+                bail!(
+                    "mom echoed content hash {echoed:?} for {uri}, but we sent {hash}: upload was corrupted"
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// Makes a URL for the mom server, for revision/asset uploads
     /// note: path is a relative path, like `objectstore/list-missing` (no leading slash)
     fn prod_mom_url(&self, relative_path: &str) -> (String, Uri) {
-        use config_types::is_development;
-
-        use std::sync::OnceLock;
-        static FORCE_LOCAL_MOM_ONCE: OnceLock<bool> = OnceLock::new();
-        let force_local_mom = *FORCE_LOCAL_MOM_ONCE.get_or_init(|| {
-            std::env::var("FORCE_LOCAL_MOM")
-                .map(|val| val == "1" || val.eq_ignore_ascii_case("true"))
-                .unwrap_or(false)
-        });
-
-        let base_url = if is_development() && !force_local_mom {
+        let choice = ProdMomChoice::resolve(
+            config_types::is_development(),
+            self.mcc.use_prod_mom_in_dev,
+            force_local_mom(),
+        );
+        let base_url = if choice.uses_production_mom() {
             production_mom_url().to_string()
         } else {
             self.mcc.base_url.clone()
         };
         log::debug!(
-            "Resolving MOM URL for prod_mom_url: is_development={}, force_local_mom={}, production_mom_url={}, mcc_base_url={}, selected_base_url={}",
-            config_types::is_development(),
-            force_local_mom,
+            "Resolving MOM URL for prod_mom_url: {} ({choice:?}), production_mom_url={}, mcc_base_url={}, selected_base_url={}",
+            choice.reason(),
             production_mom_url(),
             self.mcc.base_url,
             base_url
@@ -375,6 +625,38 @@ impl MomTenantClient for MomTenantClientImpl {
         })
     }
 
+    /// Fetches the tenant's current users, instead of waiting for mom to
+    /// push them over the event stream
+    fn get_users(&self) -> BoxFuture<'_, Result<AllUsers>> {
+        Box::pin(async move {
+            let uri = self.config_mom_uri("users");
+            let res = self
+                .hclient
+                .get(uri)
+                .with_auth(&self.mcc)
+                .send_and_expect_200()
+                .await?;
+            res.json_limited::<AllUsers>(MAX_TENANT_SIZED_RESPONSE)
+                .await
+        })
+    }
+
+    /// Rotates the tenant's cookie sauce. This invalidates every auth cookie,
+    /// so everyone gets logged out: only meant for when the sauce may have
+    /// leaked. Fails for tenants that set their cookie sauce explicitly.
+    fn rotate_cookie_sauce(&self) -> BoxFuture<'_, Result<mom_types::RotateCookieSauceResponse>> {
+        Box::pin(async move {
+            let uri = self.config_mom_uri("rotate-cookie-sauce");
+            let res = self
+                .hclient
+                .post(uri)
+                .with_auth(&self.mcc)
+                .send_and_expect_200()
+                .await?;
+            res.json::<mom_types::RotateCookieSauceResponse>().await
+        })
+    }
+
     fn make_api_key<'fut>(
         &'fut self,
         body: &'fut mom_types::MakeApiKeyArgs,
@@ -412,7 +694,8 @@ impl MomTenantClient for MomTenantClientImpl {
                 let (_, uri) = self.prod_mom_url("objectstore/list-missing");
                 let req = self.hclient.post(uri).with_auth(&self.mcc).json(body)?;
                 let res = req.send_and_expect_200().await?;
-                res.json::<ListMissingResponse>().await
+                res.json_limited::<ListMissingResponse>(MAX_TENANT_SIZED_RESPONSE)
+                    .await
             }
         })
     }
@@ -425,17 +708,42 @@ impl MomTenantClient for MomTenantClientImpl {
         Box::pin({
             async move {
                 let (_, uri) = self.prod_mom_url(&format!("objectstore/put/{key}"));
-                self.hclient
-                    .put(uri)
-                    .with_auth(&self.mcc)
-                    .body(payload)
-                    .send_and_expect_200()
-                    .await?;
-                Ok(())
+                if self.dry_run {
+                    info!("[dry run] Would upload {} bytes to {uri}", payload.len());
+                    return Ok(());
+                }
+                self.put_with_content_hash(uri, payload).await
             }
         })
     }
 
+    /// Like `put_asset`, but streams the file at `path` rather than holding
+    /// it all in memory, for big inputs like videos. No content hash is sent
+    /// (that would mean reading the file twice): mom only gets the length to
+    /// check the upload against.
+    fn put_asset_from_path<'fut>(
+        &'fut self,
+        key: &'fut ObjectStoreKeyRef,
+        path: &'fut Utf8Path,
+    ) -> BoxFuture<'fut, Result<()>> {
+        Box::pin(async move {
+            let (_, uri) = self.prod_mom_url(&format!("objectstore/put/{key}"));
+            let file = fs_err::tokio::File::open(path).await?;
+            let len = file.metadata().await?.len();
+            if self.dry_run {
+                info!("[dry run] Would upload {len} bytes from {path} to {uri}");
+                return Ok(());
+            }
+            self.hclient
+                .put(uri)
+                .with_auth(&self.mcc)
+                .body_stream(file_chunks(file), len)
+                .send_and_expect_200()
+                .await?;
+            Ok(())
+        })
+    }
+
     fn put_revpak<'fut>(
         &'fut self,
         id: &'fut RevisionIdRef,
@@ -445,6 +753,13 @@ impl MomTenantClient for MomTenantClientImpl {
             let revision_id: &RevisionIdRef = id;
             async move {
                 let (_, uri) = self.prod_mom_url(&format!("revision/upload/{revision_id}"));
+                if self.dry_run {
+                    info!(
+                        "[dry run] Would upload {} bytes of revision to {uri}",
+                        payload.len()
+                    );
+                    return Ok(());
+                }
                 info!("Uploading revision to URL: {uri}");
                 {
                     let path = "/tmp/payload.json";
@@ -457,17 +772,25 @@ impl MomTenantClient for MomTenantClientImpl {
                         }
                     }
                 }
-                self.hclient
-                    .put(uri)
-                    .with_auth(&self.mcc)
-                    .body(payload)
-                    .send_and_expect_200()
-                    .await?;
-                Ok(())
+                self.put_with_content_hash(uri, payload).await
             }
         })
     }
 
+    /// Asks mom to re-broadcast the tenant's current revision, for when a
+    /// cub is stuck with a revision it failed to load.
+    fn request_revision(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let uri = self.config_mom_uri("revision/request");
+            self.hclient
+                .post(uri)
+                .with_auth(&self.mcc)
+                .send_and_expect_200()
+                .await?;
+            Ok(())
+        })
+    }
+
     fn media_transcode(&self, params: TranscodeParams) -> BoxFuture<'_, Result<TranscodeResponse>> {
         Box::pin(async move {
             let uri = self.config_mom_uri("media/transcode");
@@ -478,6 +801,34 @@ impl MomTenantClient for MomTenantClientImpl {
         })
     }
 
+    /// Where mom's job transcoding `params` is at, or `None` if there's no
+    /// such job (it never started, or it's done). Doesn't start one.
+    fn transcode_status(
+        &self,
+        params: TranscodeParams,
+    ) -> BoxFuture<'_, Result<Option<TranscodeJobStatus>>> {
+        Box::pin(async move {
+            let uri = self.config_mom_uri("media/transcode/status");
+            let res = self
+                .hclient
+                .post(uri)
+                .with_auth(&self.mcc)
+                .json(&params)?
+                .send()
+                .await?;
+            let status = res.status();
+            if status == libhttpclient::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            if !status.is_success() {
+                return Err(libhttpclient::decode_error_response(res)
+                    .await
+                    .wrap_err("asking mom for transcode status"));
+            }
+            Ok(Some(res.json().await?))
+        })
+    }
+
     fn derive(&self, params: DeriveParams) -> BoxFuture<'_, Result<DeriveResponse>> {
         Box::pin(async move {
             let uri = self.config_mom_uri("derive");
@@ -488,36 +839,63 @@ impl MomTenantClient for MomTenantClientImpl {
         })
     }
 
+    fn derive_streaming(
+        &self,
+        params: DeriveParams,
+        listener: Box<dyn TranscodingEventListener>,
+    ) -> BoxFuture<'_, Result<DeriveResponse>> {
+        Box::pin(async move {
+            let mut ws = self.connect_ws("derive/stream").await?;
+            let msg = DeriveStreamMessage::Params(params);
+            ws.send_text(facet_json::to_string(&msg)).await?;
+            receive_derive_stream(ws.as_mut(), listener.as_ref()).await
+        })
+    }
+
+    /// Derives everything in `batch`, to pre-generate a revision's assets.
+    /// Mom decides how many run at once. `listener` hears about each
+    /// derivation as it finishes; outcomes are returned in `batch` order.
+    fn derive_batch(
+        &self,
+        batch: Vec<DeriveParams>,
+        listener: Box<dyn DeriveBatchListener>,
+    ) -> BoxFuture<'_, Result<Vec<DeriveBatchOutcome>>> {
+        Box::pin(async move {
+            if batch.is_empty() {
+                return Ok(Vec::new());
+            }
+            let len = batch.len();
+            let mut ws = self.connect_ws("derive/batch").await?;
+            let msg = DeriveBatchMessage::Params(batch);
+            ws.send_text(facet_json::to_string(&msg)).await?;
+            receive_derive_batch(ws.as_mut(), len, listener.as_ref()).await
+        })
+    }
+
     fn media_uploader(
         &self,
         listener: Box<dyn TranscodingEventListener>,
     ) -> BoxFuture<'_, Result<Box<dyn MediaUploader>>> {
         Box::pin(async move {
-            let base_uri = self.config_mom_uri("media/upload");
-            let uri = Uri::builder()
-                .scheme(if base_uri.scheme_str() == Some("https") {
-                    "wss"
-                } else {
-                    "ws"
-                })
-                .authority(base_uri.authority().unwrap().as_str())
-                .path_and_query(base_uri.path_and_query().unwrap().as_str())
-                .build()
-                .unwrap();
-            info!("Uploading video to: {uri}");
-
-            let ws = libwebsock::load()
-                .websocket_connect(uri, {
-                    let mut map = HeaderMap::new();
-                    map.insert(
-                        libhttpclient::header::AUTHORIZATION,
-                        HeaderValue::from_str(&format!("Bearer {}", self.mcc.api_key())).unwrap(),
-                    );
-                    map
-                })
-                .await?;
+            // only the initial connect is retried: once we've started streaming
+            // chunks, a dropped connection fails the upload. Downloading the
+            // result can be resumed on a fresh connection, if mom allows it.
+            let ws = self.connect_ws("media/upload").await?;
+            let reconnect: ConnectFn = Box::new({
+                let this = self.clone();
+                move || -> BoxFuture<'static, Result<Box<dyn WebSocketStream>>> {
+                    let this = this.clone();
+                    Box::pin(async move { this.connect_ws("media/upload").await })
+                }
+            });
 
-            let b: Box<dyn MediaUploader> = Box::new(MediaUploaderImpl { ws, listener });
+            let b: Box<dyn MediaUploader> = Box::new(MediaUploaderImpl {
+                ws,
+                reconnect: Some(reconnect),
+                listener,
+                progress: None,
+                uploaded_bytes: 0,
+            });
             Ok(b)
         })
     }
@@ -532,9 +910,198 @@ impl MomTenantClient for MomTenantClientImpl {
     }
 }
 
+/// Reported by [`put_assets_concurrent`] after each object is uploaded
+#[derive(Debug, Clone)]
+pub struct AssetUploadProgress {
+    /// the object that was just uploaded
+    pub key: ObjectStoreKey,
+    /// its size in bytes
+    pub size: usize,
+    /// how long its upload took
+    pub elapsed: Duration,
+
+    /// objects uploaded so far, including this one
+    pub uploaded_objects: usize,
+    /// bytes uploaded so far, including this one
+    pub uploaded_bytes: usize,
+    pub total_objects: usize,
+}
+
+/// Reads `file` a chunk at a time, for streaming uploads
+fn file_chunks(file: fs_err::tokio::File) -> BoxStream<'static, Result<Bytes>> {
+    const CHUNK_SIZE: usize = 256 * 1024;
+
+    Box::pin(futures_util::stream::try_unfold(
+        file,
+        |mut file| async move {
+            use tokio::io::AsyncReadExt as _;
+
+            let mut chunk = vec![0u8; CHUNK_SIZE];
+            let n = file.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            chunk.truncate(n);
+            Ok(Some((Bytes::from(chunk), file)))
+        },
+    ))
+}
+
+/// Uploads assets with `put_asset`, at most `concurrency` at a time, in no
+/// particular order. Each item is a future that produces the key and payload:
+/// they're only polled once there's room, so payloads can be read lazily.
+///
+/// Stops at (and returns) the first error, dropping uploads still in flight.
+pub async fn put_assets_concurrent<I, Fut>(
+    tcli: &dyn MomTenantClient,
+    items: I,
+    concurrency: usize,
+    mut progress: impl FnMut(AssetUploadProgress),
+) -> Result<()>
+where
+    I: IntoIterator<Item = Fut>,
+    I::IntoIter: ExactSizeIterator,
+    Fut: Future<Output = Result<(ObjectStoreKey, Bytes)>>,
+{
+    use futures_util::{StreamExt as _, TryStreamExt as _};
+
+    let items = items.into_iter();
+    let total_objects = items.len();
+    let mut uploads = futures_util::stream::iter(items)
+        .map(|item| async move {
+            let (key, payload) = item.await?;
+            let size = payload.len();
+            let before_upload = Instant::now();
+            tcli.put_asset(&key, payload)
+                .await
+                .wrap_err_with(|| format!("uploading {key}"))?;
+            Ok::<_, eyre::Report>((key, size, before_upload.elapsed()))
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut uploaded_objects = 0;
+    let mut uploaded_bytes = 0;
+    while let Some((key, size, elapsed)) = uploads.try_next().await? {
+        uploaded_objects += 1;
+        uploaded_bytes += size;
+        progress(AssetUploadProgress {
+            key,
+            size,
+            elapsed,
+            uploaded_objects,
+            uploaded_bytes,
+            total_objects,
+        });
+    }
+    Ok(())
+}
+
+/// How we reconnect to mom's event stream: forever, since cub is useless
+/// without it
+const MOM_EVENTS_RECONNECT: RetryPolicy = RetryPolicy {
+    max_attempts: None,
+    initial: Duration::from_secs(1),
+    max: Duration::from_secs(10),
+    jitter: Duration::from_millis(500),
+    max_total: None,
+};
+
+/// How long we wait for a single websocket connect attempt
+const WS_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times we reconnect to resume a single transcode output download
+const MAX_DOWNLOAD_RESUMES: u32 = 3;
+
+/// How long a cancelled upload waits for mom to hang up
+const CANCEL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How we connect to mom for one-off websocket sessions (uploads, derivations)
+const WS_CONNECT_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: Some(5),
+    initial: Duration::from_millis(500),
+    max: Duration::from_secs(8),
+    jitter: Duration::from_millis(125),
+    max_total: None,
+};
+
+/// Forwards transcoding events to `listener` until mom sends the final response
+async fn receive_derive_stream(
+    ws: &mut dyn WebSocketStream,
+    listener: &dyn TranscodingEventListener,
+) -> Result<DeriveResponse> {
+    loop {
+        let msg = match ws.receive().await {
+            Some(frame) => frame?,
+            None => bail!("Connection closed before the derivation finished"),
+        };
+        let libwebsock::Message::Text(text) = msg else {
+            continue;
+        };
+        let msg: DeriveStreamMessage = facet_json::from_str(&text).map_err(|e| e.into_owned())?;
+        match msg {
+            DeriveStreamMessage::TranscodingEvent(ev) => {
+                if let Err(e) = listener.on_transcoding_event(ev).await {
+                    bail!("Could not notify progress: {e}");
+                }
+            }
+            DeriveStreamMessage::Response(response) => return Ok(response),
+            DeriveStreamMessage::Error(err) => bail!("{err}"),
+            DeriveStreamMessage::Params(_) => bail!("Unexpected message type"),
+        }
+    }
+}
+
+/// Collects the outcomes of a `len`-item batch, in order, until mom says it's done
+async fn receive_derive_batch(
+    ws: &mut dyn WebSocketStream,
+    len: usize,
+    listener: &dyn DeriveBatchListener,
+) -> Result<Vec<DeriveBatchOutcome>> {
+    let mut outcomes: Vec<Option<DeriveBatchOutcome>> = (0..len).map(|_| None).collect();
+    let mut finished = 0;
+    loop {
+        let msg = match ws.receive().await {
+            Some(frame) => frame?,
+            None => bail!("Connection closed before the batch finished ({finished} of {len} done)"),
+        };
+        let libwebsock::Message::Text(text) = msg else {
+            continue;
+        };
+        let msg: DeriveBatchMessage = facet_json::from_str(&text).map_err(|e| e.into_owned())?;
+        match msg {
+            DeriveBatchMessage::Item(item) => {
+                let index = item.index;
+                if index >= len || outcomes[index].is_some() {
+                    bail!(
+                        "mom reported item {index} of a {len}-item batch twice, or out of bounds"
+                    );
+                }
+                listener.on_item(&item);
+                outcomes[index] = Some(item.outcome);
+                finished += 1;
+            }
+            DeriveBatchMessage::Done => {
+                if finished != len {
+                    bail!(
+                        "mom says the batch is done, but only {finished} of {len} items finished"
+                    );
+                }
+                return Ok(outcomes.into_iter().flatten().collect());
+            }
+            DeriveBatchMessage::Error(err) => bail!("{err}"),
+            DeriveBatchMessage::Params(_) => bail!("Unexpected message type"),
+        }
+    }
+}
+
 struct MediaUploaderImpl {
-    ws: Box<dyn libwebsock::WebSocketStream>,
+    ws: Box<dyn WebSocketStream>,
+    /// Opens a fresh connection, to resume downloading the output
+    reconnect: Option<ConnectFn>,
     listener: Box<dyn TranscodingEventListener>,
+    progress: Option<Box<dyn UploadProgressListener>>,
+    /// Total bytes sent via `upload_chunk` so far
+    uploaded_bytes: usize,
 }
 
 #[autotrait(!Sync)]
@@ -548,9 +1115,47 @@ impl MediaUploader for MediaUploaderImpl {
         })
     }
 
+    fn on_upload_progress(&mut self, progress: Box<dyn UploadProgressListener>) {
+        self.progress = Some(progress);
+    }
+
     fn upload_chunk(&mut self, chunk: Bytes) -> BoxFuture<'_, Result<()>> {
         Box::pin(async move {
+            let len = chunk.len();
             self.ws.send_binary(chunk).await?;
+            self.uploaded_bytes += len;
+            if let Some(progress) = &self.progress {
+                progress.on_upload_progress(self.uploaded_bytes);
+            }
+            Ok(())
+        })
+    }
+
+    fn uploaded_bytes(&self) -> usize {
+        self.uploaded_bytes
+    }
+
+    /// Tells mom to give up on this upload (and its transcode, if it started)
+    /// and closes the connection. The uploader is useless afterwards.
+    fn cancel(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let msg = WebSocketMessage::Cancel;
+            self.ws.send_text(facet_json::to_string(&msg)).await?;
+            self.ws
+                .send(libwebsock::Message::Close(Some(libwebsock::CloseFrame {
+                    code: libwebsock::CloseCode::Normal,
+                    reason: "upload cancelled".into(),
+                })))
+                .await?;
+
+            // let mom hang up on its end, so the close handshake completes
+            let drain = async { while let Some(Ok(_)) = self.ws.receive().await {} };
+            if tokio::time::timeout(CANCEL_CLOSE_TIMEOUT, drain)
+                .await
+                .is_err()
+            {
+                log::warn!("mom didn't close the upload connection after we cancelled");
+            }
             Ok(())
         })
     }
@@ -561,6 +1166,13 @@ impl MediaUploader for MediaUploaderImpl {
         mut chunk_receiver: Box<dyn ChunkReceiver + 'a>,
     ) -> BoxFuture<'a, Result<()>> {
         Box::pin(async move {
+            if uploaded_size != self.uploaded_bytes {
+                bail!(
+                    "Upload size mismatch: announcing {uploaded_size} bytes, but sent {}",
+                    self.uploaded_bytes
+                );
+            }
+
             log::debug!("Sending UploadDone message with size {uploaded_size}");
             let msg = WebSocketMessage::UploadDone(UploadDoneMessage { uploaded_size });
             let json = facet_json::to_string(&msg);
@@ -591,39 +1203,51 @@ impl MediaUploader for MediaUploaderImpl {
                                 log::info!(
                                     "Transcoding complete! Expecting {size} bytes of output"
                                 );
+                                if size == 0 {
+                                    log::warn!(
+                                        "Transcoding produced no output, nothing to download"
+                                    );
+                                    return Ok(());
+                                }
 
-                                // Start receiving binary frames and forwarding them
+                                let mut resumes = 0;
                                 loop {
-                                    let res = match self.ws.receive().await {
-                                        Some(res) => res,
-                                        None => {
-                                            log::error!("WebSocket connection closed unexpectedly");
-                                            bail!("WebSocket connection closed unexpectedly");
-                                        }
+                                    let reason = match receive_output(
+                                        self.ws.as_mut(),
+                                        size,
+                                        &mut received_bytes,
+                                        chunk_receiver.as_mut(),
+                                    )
+                                    .await?
+                                    {
+                                        OutputEnd::Complete => return Ok(()),
+                                        OutputEnd::Dropped(reason) => reason,
+                                    };
+
+                                    let (Some(resume_token), Some(reconnect)) =
+                                        (complete.resume_token.as_ref(), self.reconnect.as_mut())
+                                    else {
+                                        bail!(
+                                            "Connection closed during download: received {received_bytes} of {size} bytes ({reason})"
+                                        );
                                     };
-                                    match res? {
-                                        libwebsock::Message::Binary(chunk) => {
-                                            received_bytes += chunk.len();
-                                            log::trace!(
-                                                "Received chunk of {} bytes ({}/{} total)",
-                                                chunk.len(),
-                                                received_bytes,
-                                                size
-                                            );
-                                            // Forward chunk using chunk receiver
-                                            chunk_receiver.on_chunk(chunk).await?;
-
-                                            if received_bytes == size {
-                                                log::info!(
-                                                    "Successfully received complete response ({size} bytes)"
-                                                );
-                                                return Ok(());
-                                            }
-                                        }
-                                        _ => {
-                                            bail!("Expected binary frame");
-                                        }
+                                    if resumes == MAX_DOWNLOAD_RESUMES {
+                                        bail!(
+                                            "Connection closed during download: received {received_bytes} of {size} bytes ({reason}), gave up after {resumes} resumes"
+                                        );
                                     }
+                                    resumes += 1;
+
+                                    log::warn!(
+                                        "Connection closed after {received_bytes}/{size} bytes of output ({reason}), resuming"
+                                    );
+                                    self.ws = reconnect().await?;
+                                    let msg =
+                                        WebSocketMessage::ResumeDownload(ResumeDownloadMessage {
+                                            resume_token: resume_token.clone(),
+                                            offset: received_bytes,
+                                        });
+                                    self.ws.send_text(facet_json::to_string(&msg)).await?;
                                 }
                             }
                             WebSocketMessage::Error(err) => {
@@ -644,24 +1268,1272 @@ impl MediaUploader for MediaUploaderImpl {
     }
 }
 
+/// How receiving the transcode output ended
+enum OutputEnd {
+    /// All `size` bytes made it
+    Complete,
+    /// The connection dropped before that, for the given reason
+    Dropped(String),
+}
+
+/// Forwards binary frames to `chunk_receiver` until `received_bytes` reaches `size`
+async fn receive_output(
+    ws: &mut dyn WebSocketStream,
+    size: usize,
+    received_bytes: &mut usize,
+    chunk_receiver: &mut (dyn ChunkReceiver + '_),
+) -> Result<OutputEnd> {
+    loop {
+        let msg = match ws.receive().await {
+            Some(Ok(msg)) => msg,
+            Some(Err(e)) => return Ok(OutputEnd::Dropped(e.to_string())),
+            None => return Ok(OutputEnd::Dropped("connection closed".to_string())),
+        };
+        match msg {
+            libwebsock::Message::Binary(chunk) => {
+                if *received_bytes + chunk.len() > size {
+                    bail!(
+                        "Received too much output: chunk of {} bytes after {received_bytes} bytes would exceed the expected {size} bytes",
+                        chunk.len()
+                    );
+                }
+                *received_bytes += chunk.len();
+                log::trace!(
+                    "Received chunk of {} bytes ({}/{} total)",
+                    chunk.len(),
+                    received_bytes,
+                    size
+                );
+                chunk_receiver.on_chunk(chunk).await?;
+
+                if *received_bytes == size {
+                    log::info!("Successfully received complete response ({size} bytes)");
+                    return Ok(OutputEnd::Complete);
+                }
+            }
+            libwebsock::Message::Text(text) => {
+                if let Ok(WebSocketMessage::Error(err)) =
+                    facet_json::from_str::<WebSocketMessage>(&text)
+                {
+                    bail!("{err}");
+                }
+                bail!("Expected binary frame (received {received_bytes} of {size} bytes)");
+            }
+            libwebsock::Message::Close(_) => {
+                return Ok(OutputEnd::Dropped("closed by peer".to_string()));
+            }
+            _ => {
+                bail!("Expected binary frame (received {received_bytes} of {size} bytes)");
+            }
+        }
+    }
+}
+
 pub trait TranscodingEventListener: Send + Sync + 'static {
     fn on_transcoding_event(&self, ev: TranscodeEvent) -> BoxFuture<'_, Result<()>>;
 }
 
+pub trait DeriveBatchListener: Send + Sync + 'static {
+    /// Called as each derivation of a batch finishes, in no particular order
+    fn on_item(&self, item: &DeriveBatchItem);
+}
+
+pub trait UploadProgressListener: Send + 'static {
+    /// Called after each chunk is sent, with the total number of bytes sent so far
+    fn on_upload_progress(&self, uploaded_bytes: usize);
+}
+
 pub trait ChunkReceiver: Send + Sync {
     fn on_chunk(&mut self, chunk: Bytes) -> BoxFuture<'_, Result<()>>;
 }
 
 trait WithAuth {
+    /// Authenticates with mom, and passes along the id of the request we're
+    /// serving, if any, so both sides' logs can be matched up.
     fn with_auth(self: Box<Self>, mcc: &MomClientConfig) -> Box<dyn RequestBuilder>;
 }
 
 impl WithAuth for dyn RequestBuilder {
     fn with_auth(self: Box<Self>, mcc: &MomClientConfig) -> Box<dyn RequestBuilder> {
-        use libhttpclient::header::HeaderValue;
-        self.header(
+        let req = self.header(
             header::AUTHORIZATION,
             HeaderValue::from_str(&format!("Bearer {}", mcc.api_key())).unwrap(),
+        );
+        match libhttpclient::current_request_id().and_then(|id| HeaderValue::from_str(&id).ok()) {
+            Some(id) => req.header(
+                HeaderName::from_static(libhttpclient::REQUEST_ID_HEADER),
+                id,
+            ),
+            None => req,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libhttpclient::test_server::{Reply, TestServer};
+    use libwebsock::{Message, test_ws::ChannelWs};
+    use mom_types::{
+        DeriveResponseDone,
+        media_types::{TargetFormat, TranscodingCompleteMessage, TranscodingProgress},
+    };
+    use objectstore_types::ObjectStoreKey;
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    #[test]
+    fn test_incompatible_event_is_a_schema_mismatch() {
+        let err = parse_mom_event(r#"{"BrandNewEvent":{"shiny":true}}"#).unwrap_err();
+        let mismatch = err.downcast_ref::<MomSchemaMismatch>().unwrap();
+        assert_eq!(mismatch.payload, r#"{"BrandNewEvent":{"shiny":true}}"#);
+        assert!(!mismatch.parse_error.is_empty());
+
+        // huge payloads are truncated (on a char boundary)
+        let payload = format!(r#"{{"GoodMorning":"{}"}}"#, "é".repeat(1000));
+        let err = parse_mom_event(&payload).unwrap_err();
+        let mismatch = err.downcast_ref::<MomSchemaMismatch>().unwrap();
+        assert!(mismatch.payload.len() <= MISMATCH_PAYLOAD_MAX_LEN + '…'.len_utf8());
+        assert!(mismatch.payload.ends_with('…'));
+        assert!(payload.starts_with(mismatch.payload.trim_end_matches('…')));
+    }
+
+    #[test]
+    fn test_users_response_parses() {
+        let body = r#"{
+            "users": {
+                "42": {
+                    "id": "42",
+                    "fetched_at": "2025-06-01T12:00:00Z",
+                    "in_discord": true,
+                    "gifted_tier": "Silver"
+                },
+                "43": {
+                    "id": "43",
+                    "fetched_at": "2025-06-01T12:00:00Z"
+                }
+            }
+        }"#;
+        let users: AllUsers = facet_json::from_str(body).unwrap();
+        assert_eq!(users.users.len(), 2);
+
+        let alice = &users.users[&credentials::UserId::new("42".to_string())];
+        assert!(alice.in_discord);
+        assert_eq!(alice.gifted_tier.as_deref(), Some("Silver"));
+        assert!(alice.patreon.is_none());
+
+        let bob = &users.users[&credentials::UserId::new("43".to_string())];
+        assert!(!bob.in_discord);
+        assert_eq!(bob.gifted_tier, None);
+    }
+
+    struct IgnoreEvents;
+
+    impl TranscodingEventListener for IgnoreEvents {
+        fn on_transcoding_event(&self, _ev: TranscodeEvent) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    struct RecordProgress(Arc<Mutex<Vec<usize>>>);
+
+    impl UploadProgressListener for RecordProgress {
+        fn on_upload_progress(&self, uploaded_bytes: usize) {
+            self.0.lock().unwrap().push(uploaded_bytes);
+        }
+    }
+
+    struct DiscardChunks;
+
+    impl ChunkReceiver for DiscardChunks {
+        fn on_chunk(&mut self, _chunk: Bytes) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    type Handles = (
+        tokio::sync::mpsc::UnboundedReceiver<Message>,
+        tokio::sync::mpsc::UnboundedSender<Message>,
+    );
+
+    fn channel_ws() -> (ChannelWs, Handles) {
+        let (ws, peer) = libwebsock::test_ws::channel_ws();
+        (ws, (peer.sent, peer.incoming))
+    }
+
+    fn uploader() -> (MediaUploaderImpl, Handles) {
+        let (ws, handles) = channel_ws();
+        let uploader = MediaUploaderImpl {
+            ws: Box::new(ws),
+            reconnect: None,
+            listener: Box::new(IgnoreEvents),
+            progress: None,
+            uploaded_bytes: 0,
+        };
+        (uploader, handles)
+    }
+
+    fn transcoding_complete(output_size: usize) -> Message {
+        transcoding_complete_resumable(output_size, None)
+    }
+
+    fn transcoding_complete_resumable(output_size: usize, resume_token: Option<&str>) -> Message {
+        let msg = WebSocketMessage::TranscodingComplete(TranscodingCompleteMessage {
+            output_size,
+            resume_token: resume_token.map(|t| t.to_string()),
+        });
+        Message::Text(facet_json::to_string(&msg).into())
+    }
+
+    /// Uploads nothing, then has mom announce `output_size` bytes and send `chunks`
+    async fn download(output_size: usize, chunks: &[usize]) -> Result<()> {
+        let (mut uploader, (_sent_rx, incoming_tx)) = uploader();
+        incoming_tx.send(transcoding_complete(output_size)).unwrap();
+        for &len in chunks {
+            incoming_tx
+                .send(Message::Binary(Bytes::from(vec![0u8; len])))
+                .unwrap();
+        }
+        drop(incoming_tx);
+
+        uploader
+            .done_and_download_result(0, Box::new(DiscardChunks))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_upload_progress_is_cumulative() {
+        let (mut uploader, (mut sent_rx, _incoming_tx)) = uploader();
+        let totals = Arc::new(Mutex::new(Vec::new()));
+        uploader.on_upload_progress(Box::new(RecordProgress(totals.clone())));
+
+        for len in [3, 0, 5, 1] {
+            uploader
+                .upload_chunk(Bytes::from(vec![0u8; len]))
+                .await
+                .unwrap();
+        }
+
+        let totals = totals.lock().unwrap().clone();
+        assert_eq!(totals, vec![3, 3, 8, 9]);
+        assert!(totals.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(uploader.uploaded_bytes(), 9);
+
+        let mut sent = 0;
+        while let Ok(Message::Binary(chunk)) = sent_rx.try_recv() {
+            sent += chunk.len();
+        }
+        assert_eq!(sent, 9);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_sends_cancel_then_close() {
+        let (mut uploader, (mut sent_rx, incoming_tx)) = uploader();
+        uploader
+            .upload_chunk(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        // mom hangs up once it's seen the close
+        drop(incoming_tx);
+
+        uploader.cancel().await.unwrap();
+
+        assert!(matches!(sent_rx.try_recv().unwrap(), Message::Binary(_)));
+        let Message::Text(text) = sent_rx.try_recv().unwrap() else {
+            panic!("expected the cancel message first");
+        };
+        let msg: WebSocketMessage = facet_json::from_str(&text).unwrap();
+        assert!(matches!(msg, WebSocketMessage::Cancel), "{msg:?}");
+        let Message::Close(Some(frame)) = sent_rx.try_recv().unwrap() else {
+            panic!("expected a close frame after the cancel message");
+        };
+        assert_eq!(frame.code, libwebsock::CloseCode::Normal);
+        assert!(sent_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_done_rejects_size_mismatch() {
+        let (mut uploader, (mut sent_rx, _incoming_tx)) = uploader();
+        uploader
+            .upload_chunk(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        let _ = sent_rx.try_recv();
+
+        let err = uploader
+            .done_and_download_result(6, Box::new(DiscardChunks))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("mismatch"), "{err}");
+        // nothing announced to mom
+        assert!(sent_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_complete() {
+        download(10, &[4, 6]).await.unwrap();
+        // nothing to wait for
+        download(0, &[]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_under_delivery() {
+        let err = download(10, &[4]).await.unwrap_err();
+        assert!(err.to_string().contains("received 4 of 10 bytes"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_download_over_delivery() {
+        let err = download(4, &[3, 3]).await.unwrap_err();
+        assert!(
+            err.to_string().contains("exceed the expected 4 bytes"),
+            "{err}"
+        );
+    }
+
+    struct CollectChunks(Arc<Mutex<Vec<u8>>>);
+
+    impl ChunkReceiver for CollectChunks {
+        fn on_chunk(&mut self, chunk: Bytes) -> BoxFuture<'_, Result<()>> {
+            self.0.lock().unwrap().extend_from_slice(&chunk);
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_after_drop() {
+        let output = (0..10u8).collect::<Vec<_>>();
+
+        // the first connection drops after 4 bytes of output
+        let (mut uploader, (_sent_rx, incoming_tx)) = uploader();
+        incoming_tx
+            .send(transcoding_complete_resumable(10, Some("tok")))
+            .unwrap();
+        incoming_tx
+            .send(Message::Binary(Bytes::copy_from_slice(&output[..4])))
+            .unwrap();
+        drop(incoming_tx);
+
+        // the second one delivers the rest, once asked to
+        let (ws, (mut resumed_sent_rx, resumed_incoming_tx)) = channel_ws();
+        resumed_incoming_tx
+            .send(Message::Binary(Bytes::copy_from_slice(&output[4..])))
+            .unwrap();
+        let ws = Arc::new(Mutex::new(Some(ws)));
+        uploader.reconnect = Some(Box::new(
+            move || -> BoxFuture<'static, Result<Box<dyn WebSocketStream>>> {
+                let ws = ws.lock().unwrap().take();
+                Box::pin(async move {
+                    match ws {
+                        Some(ws) => Ok(Box::new(ws) as Box<dyn WebSocketStream>),
+                        None => bail!("connection refused"),
+                    }
+                })
+            },
+        ));
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        uploader
+            .done_and_download_result(0, Box::new(CollectChunks(received.clone())))
+            .await
+            .unwrap();
+        assert_eq!(*received.lock().unwrap(), output);
+
+        let Ok(Message::Text(text)) = resumed_sent_rx.try_recv() else {
+            panic!("expected a resume message");
+        };
+        match facet_json::from_str::<WebSocketMessage>(&text).unwrap() {
+            WebSocketMessage::ResumeDownload(resume) => {
+                assert_eq!(resume.resume_token, "tok");
+                assert_eq!(resume.offset, 4);
+            }
+            other => panic!("expected a resume message, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_without_resume_token_fails() {
+        // mom didn't offer to resume: even if we could reconnect, we don't
+        let (mut uploader, (_sent_rx, incoming_tx)) = uploader();
+        incoming_tx.send(transcoding_complete(10)).unwrap();
+        incoming_tx
+            .send(Message::Binary(Bytes::from(vec![0u8; 4])))
+            .unwrap();
+        drop(incoming_tx);
+
+        let reconnects = Arc::new(AtomicU32::new(0));
+        uploader.reconnect = Some(Box::new({
+            let reconnects = reconnects.clone();
+            move || -> BoxFuture<'static, Result<Box<dyn WebSocketStream>>> {
+                reconnects.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move { bail!("connection refused") })
+            }
+        }));
+
+        let err = uploader
+            .done_and_download_result(0, Box::new(DiscardChunks))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("received 4 of 10 bytes"), "{err}");
+        assert_eq!(reconnects.load(Ordering::SeqCst), 0);
+    }
+
+    struct RecordEvents(Arc<Mutex<Vec<TranscodeEvent>>>);
+
+    impl TranscodingEventListener for RecordEvents {
+        fn on_transcoding_event(&self, ev: TranscodeEvent) -> BoxFuture<'_, Result<()>> {
+            self.0.lock().unwrap().push(ev);
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    fn derive_message(msg: DeriveStreamMessage) -> Message {
+        Message::Text(facet_json::to_string(&msg).into())
+    }
+
+    fn progress(frame: u32) -> TranscodeEvent {
+        TranscodeEvent::Progress(TranscodingProgress {
+            frame,
+            fps: 30.0,
+            quality: 28.0,
+            size_kb: frame * 10,
+            bitrate_kbps: 800.0,
+            speed: 1.5,
+            processed_time: frame as f64 / 30.0,
+            total_time: 10.0,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_derive_stream_progress_to_completion() {
+        let (mut ws, (_sent_rx, incoming_tx)) = channel_ws();
+
+        // the fake mom: a few progress events, then the result
+        for frame in [30, 120, 300] {
+            incoming_tx
+                .send(derive_message(DeriveStreamMessage::TranscodingEvent(
+                    progress(frame),
+                )))
+                .unwrap();
+        }
+        incoming_tx
+            .send(derive_message(DeriveStreamMessage::Response(
+                DeriveResponse::Done(DeriveResponseDone {
+                    output_size: 1234,
+                    dest: ObjectStoreKey::new("derivations/abc.mp4".to_string()),
+                }),
+            )))
+            .unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let response = receive_derive_stream(&mut ws, &RecordEvents(events.clone()))
+            .await
+            .unwrap();
+
+        let DeriveResponse::Done(done) = response else {
+            panic!("expected the derivation to be done");
+        };
+        assert_eq!(done.output_size, 1234);
+        assert_eq!(done.dest.as_str(), "derivations/abc.mp4");
+
+        let frames = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|ev| match ev {
+                TranscodeEvent::Progress(p) => p.frame,
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(frames, vec![30, 120, 300]);
+    }
+
+    struct RecordItems(Arc<Mutex<Vec<usize>>>);
+
+    impl DeriveBatchListener for RecordItems {
+        fn on_item(&self, item: &DeriveBatchItem) {
+            self.0.lock().unwrap().push(item.index);
+        }
+    }
+
+    fn batch_message(msg: DeriveBatchMessage) -> Message {
+        Message::Text(facet_json::to_string(&msg).into())
+    }
+
+    #[tokio::test]
+    async fn test_derive_batch_runs_to_completion() {
+        let (mut ws, (_sent_rx, incoming_tx)) = channel_ws();
+
+        // the fake mom: items finish out of order, one of them fails
+        let done = |n: usize| {
+            DeriveBatchOutcome::Response(DeriveResponse::Done(DeriveResponseDone {
+                output_size: n,
+                dest: ObjectStoreKey::new(format!("derivations/{n}.avif")),
+            }))
+        };
+        for (index, outcome) in [
+            (2, done(2)),
+            (0, done(0)),
+            (1, DeriveBatchOutcome::Error("ffmpeg fell over".to_string())),
+        ] {
+            incoming_tx
+                .send(batch_message(DeriveBatchMessage::Item(DeriveBatchItem {
+                    index,
+                    outcome,
+                })))
+                .unwrap();
+        }
+        incoming_tx
+            .send(batch_message(DeriveBatchMessage::Done))
+            .unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let outcomes = receive_derive_batch(&mut ws, 3, &RecordItems(seen.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), vec![2, 0, 1]);
+        assert_eq!(outcomes.len(), 3);
+        for (index, outcome) in outcomes.iter().enumerate() {
+            match outcome {
+                DeriveBatchOutcome::Response(DeriveResponse::Done(done)) => {
+                    assert_eq!(done.output_size, index);
+                }
+                DeriveBatchOutcome::Error(e) => {
+                    assert_eq!(index, 1);
+                    assert!(e.contains("ffmpeg"), "{e}");
+                }
+                DeriveBatchOutcome::Response(_) => panic!("unexpected response"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_derive_batch_incomplete() {
+        let (mut ws, (_sent_rx, incoming_tx)) = channel_ws();
+        incoming_tx
+            .send(batch_message(DeriveBatchMessage::Item(DeriveBatchItem {
+                index: 0,
+                outcome: DeriveBatchOutcome::Error("nope".to_string()),
+            })))
+            .unwrap();
+        drop(incoming_tx);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let err = receive_derive_batch(&mut ws, 2, &RecordItems(seen))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("1 of 2 done"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_derive_stream_errors() {
+        let (mut ws, (_sent_rx, incoming_tx)) = channel_ws();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        incoming_tx
+            .send(derive_message(DeriveStreamMessage::Error(
+                "ffmpeg fell over".to_string(),
+            )))
+            .unwrap();
+        let err = receive_derive_stream(&mut ws, &RecordEvents(events.clone()))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ffmpeg fell over"), "{err}");
+
+        // mom went away mid-derivation
+        incoming_tx
+            .send(derive_message(DeriveStreamMessage::TranscodingEvent(
+                progress(1),
+            )))
+            .unwrap();
+        drop(incoming_tx);
+        let err = receive_derive_stream(&mut ws, &RecordEvents(events))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Connection closed"), "{err}");
+    }
+
+    struct RecordedRequest {
+        method: libhttpclient::Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Option<Bytes>,
+    }
+
+    /// An HTTP client that records requests instead of sending them, and
+    /// answers every one of them with `status`, `headers` and `body`.
+    struct FakeHttpClient {
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+        status: libhttpclient::StatusCode,
+        headers: HeaderMap,
+        body: String,
+    }
+
+    impl HttpClient for FakeHttpClient {
+        fn request(&self, method: libhttpclient::Method, uri: Uri) -> Box<dyn RequestBuilder> {
+            Box::new(FakeRequest {
+                requests: self.requests.clone(),
+                status: self.status,
+                headers: self.headers.clone(),
+                body: self.body.clone(),
+                req: RecordedRequest {
+                    method,
+                    uri,
+                    headers: HeaderMap::new(),
+                    body: None,
+                },
+                body_stream: None,
+            })
+        }
+
+        fn get(&self, uri: Uri) -> Box<dyn RequestBuilder> {
+            self.request(libhttpclient::Method::GET, uri)
+        }
+
+        fn post(&self, uri: Uri) -> Box<dyn RequestBuilder> {
+            self.request(libhttpclient::Method::POST, uri)
+        }
+
+        fn put(&self, uri: Uri) -> Box<dyn RequestBuilder> {
+            self.request(libhttpclient::Method::PUT, uri)
+        }
+
+        fn delete(&self, uri: Uri) -> Box<dyn RequestBuilder> {
+            self.request(libhttpclient::Method::DELETE, uri)
+        }
+    }
+
+    struct FakeRequest {
+        requests: Arc<Mutex<Vec<RecordedRequest>>>,
+        status: libhttpclient::StatusCode,
+        headers: HeaderMap,
+        body: String,
+        req: RecordedRequest,
+        /// Set by `body_stream`, read into `req.body` when sent
+        body_stream: Option<BoxStream<'static, Result<Bytes>>>,
+    }
+
+    impl RequestBuilder for FakeRequest {
+        fn body(mut self: Box<Self>, body: Bytes) -> Box<dyn RequestBuilder> {
+            self.req.body = Some(body);
+            self
+        }
+
+        fn body_stream(
+            mut self: Box<Self>,
+            body: BoxStream<'static, Result<Bytes>>,
+            _len: u64,
+        ) -> Box<dyn RequestBuilder> {
+            self.body_stream = Some(body);
+            self
+        }
+
+        fn form(self: Box<Self>, form: String) -> Box<dyn RequestBuilder> {
+            self.body(Bytes::from(form))
+        }
+
+        fn header(
+            mut self: Box<Self>,
+            key: libhttpclient::HeaderName,
+            value: HeaderValue,
+        ) -> Box<dyn RequestBuilder> {
+            self.req.headers.insert(key, value);
+            self
+        }
+
+        fn polite_user_agent(self: Box<Self>) -> Box<dyn RequestBuilder> {
+            self
+        }
+
+        fn polite_user_agent_for(
+            self: Box<Self>,
+            _tc: &config_types::TenantConfig,
+        ) -> Box<dyn RequestBuilder> {
+            self
+        }
+
+        fn browser_like_user_agent(self: Box<Self>) -> Box<dyn RequestBuilder> {
+            self
+        }
+
+        fn basic_auth(
+            self: Box<Self>,
+            _username: &str,
+            _password: Option<&str>,
+        ) -> Box<dyn RequestBuilder> {
+            self
+        }
+
+        fn bearer_auth(self: Box<Self>, _token: &str) -> Box<dyn RequestBuilder> {
+            self
+        }
+
+        fn retry(self: Box<Self>, _policy: libhttpclient::RetryPolicy) -> Box<dyn RequestBuilder> {
+            self
+        }
+
+        fn send(self: Box<Self>) -> BoxFuture<'static, Result<Box<dyn Response>>> {
+            let FakeRequest {
+                requests,
+                status,
+                headers,
+                body,
+                mut req,
+                body_stream,
+            } = *self;
+            Box::pin(async move {
+                if let Some(body_stream) = body_stream {
+                    use futures_util::TryStreamExt as _;
+                    let chunks: Vec<Bytes> = body_stream.try_collect().await?;
+                    req.body = Some(Bytes::from(chunks.concat()));
+                }
+                requests.lock().unwrap().push(req);
+                let response = FakeResponse {
+                    status,
+                    headers,
+                    body,
+                };
+                Ok(Box::new(response) as Box<dyn Response>)
+            })
+        }
+
+        fn send_and_expect_200(self: Box<Self>) -> BoxFuture<'static, Result<Box<dyn Response>>> {
+            Box::pin(async move {
+                let response = self.send().await?;
+                let status = response.status();
+                if !status.is_success() {
+                    bail!("mom replied with HTTP status {status}");
+                }
+                Ok(response)
+            })
+        }
+
+        fn json_peek<'a>(
+            self: Box<Self>,
+            body: facet_reflect::Peek<'a, 'a>,
+        ) -> Result<Box<dyn RequestBuilder>, facet_json::DeserError<'static>> {
+            Ok(self.body(Bytes::from(facet_json::peek_to_string(body))))
+        }
+
+        fn query(self: Box<Self>, _params: &[(&str, &str)]) -> Box<dyn RequestBuilder> {
+            self
+        }
+    }
+
+    struct FakeResponse {
+        status: libhttpclient::StatusCode,
+        headers: HeaderMap,
+        body: String,
+    }
+
+    impl Response for FakeResponse {
+        fn status(&self) -> libhttpclient::StatusCode {
+            self.status
+        }
+
+        fn headers(&self) -> HeaderMap {
+            self.headers.clone()
+        }
+
+        fn headers_only_string_safe(&self) -> std::collections::HashMap<String, String> {
+            Default::default()
+        }
+
+        fn bytes(self: Box<Self>) -> BoxFuture<'static, Result<Vec<u8>>> {
+            Box::pin(async move { Ok(self.body.into_bytes()) })
+        }
+
+        fn bytes_limited(self: Box<Self>, _max: usize) -> BoxFuture<'static, Result<Vec<u8>>> {
+            self.bytes()
+        }
+
+        fn bytes_stream(
+            self: Box<Self>,
+        ) -> futures_core::stream::BoxStream<'static, Result<Bytes>> {
+            Box::pin(futures_util::stream::once(async move {
+                Ok(Bytes::from(self.body))
+            }))
+        }
+
+        fn text(self: Box<Self>) -> BoxFuture<'static, Result<String>> {
+            Box::pin(async move { Ok(self.body) })
+        }
+    }
+
+    fn tenant_client(
+        status: libhttpclient::StatusCode,
+    ) -> (MomTenantClientImpl, Arc<Mutex<Vec<RecordedRequest>>>) {
+        tenant_client_with_body(status, String::new())
+    }
+
+    fn tenant_client_with_body(
+        status: libhttpclient::StatusCode,
+        body: String,
+    ) -> (MomTenantClientImpl, Arc<Mutex<Vec<RecordedRequest>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let client = MomTenantClientImpl {
+            mcc: MomClientConfig {
+                base_url: "http://mom.example".to_string(),
+                api_key: Some(MomApiKey::new("sekrit".to_string())),
+                use_prod_mom_in_dev: true,
+            },
+            base_path: "/tenant/example.org".to_string(),
+            hclient: Arc::new(FakeHttpClient {
+                requests: requests.clone(),
+                status,
+                headers: HeaderMap::new(),
+                body,
+            }),
+            dry_run: false,
+        };
+        (client, requests)
+    }
+
+    #[tokio::test]
+    async fn test_request_revision_call_shape() {
+        let (client, requests) = tenant_client(libhttpclient::StatusCode::OK);
+        client.request_revision().await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let req = &requests[0];
+        assert_eq!(req.method, libhttpclient::Method::POST);
+        assert_eq!(
+            req.uri.to_string(),
+            "http://mom.example/tenant/example.org/revision/request"
+        );
+        assert_eq!(
+            req.headers.get(header::AUTHORIZATION).unwrap(),
+            "Bearer sekrit"
+        );
+        assert!(req.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_forwarded() {
+        let (client, requests) = tenant_client(libhttpclient::StatusCode::OK);
+        libhttpclient::with_request_id("abc-123".to_string(), client.request_revision())
+            .await
+            .unwrap();
+        client.request_revision().await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0]
+                .headers
+                .get(libhttpclient::REQUEST_ID_HEADER)
+                .unwrap(),
+            "abc-123"
+        );
+        // outside of a request, there's nothing to forward
+        assert!(
+            requests[1]
+                .headers
+                .get(libhttpclient::REQUEST_ID_HEADER)
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_revision_surfaces_mom_errors() {
+        let (client, requests) = tenant_client(libhttpclient::StatusCode::NOT_FOUND);
+        let err = client.request_revision().await.unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    fn transcode_params() -> TranscodeParams {
+        TranscodeParams {
+            input: ObjectStoreKey::new("uploads/in.mov".to_string()),
+            target_format: TargetFormat::AV1,
+            output: ObjectStoreKey::new("uploads/out.mp4".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transcode_status_with_and_without_progress() {
+        let (client, requests) = tenant_client_with_body(
+            libhttpclient::StatusCode::OK,
+            r#"{"running_for_ms":1500,"last_ping_ms_ago":1500}"#.to_string(),
+        );
+        let status = client
+            .transcode_status(transcode_params())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.running_for_ms, 1500);
+        assert!(status.last_progress.is_none());
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests[0].method, libhttpclient::Method::POST);
+            assert_eq!(
+                requests[0].uri.to_string(),
+                "http://mom.example/tenant/example.org/media/transcode/status"
+            );
+            let body = requests[0].body.as_ref().unwrap();
+            let sent: TranscodeParams =
+                facet_json::from_str(std::str::from_utf8(body).unwrap()).unwrap();
+            assert_eq!(sent, transcode_params());
+        }
+
+        let (client, _) = tenant_client_with_body(
+            libhttpclient::StatusCode::OK,
+            r#"{"running_for_ms":42000,"last_ping_ms_ago":300,"last_progress":{"frame":900,"fps":30.0,"quality":28.0,"size_kb":2048,"bitrate_kbps":800.5,"speed":1.5,"processed_time":30.0,"total_time":60.0}}"#.to_string(),
+        );
+        let status = client
+            .transcode_status(transcode_params())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.last_ping_ms_ago, 300);
+        let progress = status.last_progress.unwrap();
+        assert_eq!(progress.frame, 900);
+        assert_eq!(progress.total_time, 60.0);
+    }
+
+    #[tokio::test]
+    async fn test_transcode_status_no_such_job() {
+        let (client, _) = tenant_client_with_body(
+            libhttpclient::StatusCode::NOT_FOUND,
+            "no transcode job for uploads/out.mp4".to_string(),
+        );
+        assert!(
+            client
+                .transcode_status(transcode_params())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let (client, _) = tenant_client_with_body(
+            libhttpclient::StatusCode::INTERNAL_SERVER_ERROR,
+            "oh no".to_string(),
+        );
+        let err = client
+            .transcode_status(transcode_params())
+            .await
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("oh no"), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn test_list_accessible_tenants() {
+        let tn = |s: &str| TenantDomain::new(s.to_string());
+        let global = ListTenantsResponse {
+            all_tenants: true,
+            tenants: vec![tn("a.example"), tn("b.example")],
+            unknown_tenants: vec![],
+        };
+        let scoped = ListTenantsResponse {
+            all_tenants: false,
+            tenants: vec![tn("a.example")],
+            unknown_tenants: vec![tn("gone.example")],
+        };
+
+        for res in [global, scoped] {
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let client = MomClientImpl {
+                hclient: Arc::new(FakeHttpClient {
+                    requests: requests.clone(),
+                    status: libhttpclient::StatusCode::OK,
+                    headers: HeaderMap::new(),
+                    body: facet_json::to_string(&res),
+                }),
+                mcc: MomClientConfig {
+                    base_url: "http://mom.example".to_string(),
+                    api_key: Some(MomApiKey::new("some-key".to_string())),
+                    use_prod_mom_in_dev: true,
+                },
+            };
+
+            assert_eq!(client.list_accessible_tenants().await.unwrap(), res);
+
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].method, libhttpclient::Method::GET);
+            assert_eq!(requests[0].uri.to_string(), "http://mom.example/tenants");
+            assert_eq!(
+                requests[0].headers.get(header::AUTHORIZATION).unwrap(),
+                "Bearer some-key"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping_measures_round_trip() {
+        // a sluggish mom
+        let server = TestServer::always(
+            Reply::new("200 OK")
+                .body("OK")
+                .delay(Duration::from_millis(50)),
         )
+        .await;
+
+        let client = MomClientImpl {
+            hclient: Arc::from(libhttpclient::load().client()),
+            mcc: MomClientConfig {
+                base_url: server.base_url(),
+                api_key: None,
+                use_prod_mom_in_dev: true,
+            },
+        };
+        let rtt = client.ping().await.unwrap();
+        assert!(rtt >= Duration::from_millis(50), "{rtt:?}");
+        assert!(rtt < PING_TIMEOUT, "{rtt:?}");
+        let requests = server.requests();
+        assert_eq!(requests[0].method(), "GET");
+        assert_eq!(requests[0].target(), "/health");
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_lists_missing_but_never_puts() {
+        let key = ObjectStoreKey::new("inputs/abc".to_string());
+        let listed = ListMissingResponse {
+            missing: [(
+                key.clone(),
+                conflux::InputPath::from_static("/content/a.png"),
+            )]
+            .into(),
+        };
+        let (mut client, requests) = tenant_client_with_body(
+            libhttpclient::StatusCode::OK,
+            facet_json::to_string(&listed),
+        );
+        client.dry_run = true;
+
+        let missing = client
+            .objectstore_list_missing(&ListMissingArgs {
+                objects_to_query: listed.missing.clone(),
+                mark_these_as_uploaded: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(missing.missing.len(), 1);
+        assert!(missing.missing.contains_key(&key));
+
+        client
+            .put_asset(&key, Bytes::from_static(b"png bytes"))
+            .await
+            .unwrap();
+        let revision_id = conflux::RevisionId::new("rev_test".to_string());
+        client
+            .put_revpak(&revision_id, Bytes::from_static(b"{}"))
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, libhttpclient::Method::POST);
+        assert!(
+            requests
+                .iter()
+                .all(|req| req.method != libhttpclient::Method::PUT)
+        );
+    }
+
+    fn asset_items(
+        count: usize,
+        loading: Arc<AtomicU32>,
+        max_loading: Arc<AtomicU32>,
+    ) -> Vec<impl Future<Output = Result<(ObjectStoreKey, Bytes)>>> {
+        (0..count)
+            .map(|i| {
+                let loading = loading.clone();
+                let max_loading = max_loading.clone();
+                async move {
+                    let now = loading.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_loading.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    loading.fetch_sub(1, Ordering::SeqCst);
+                    Ok((
+                        ObjectStoreKey::new(format!("inputs/{i}")),
+                        Bytes::from(vec![0u8; i]),
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_put_assets_concurrent_uploads_everything() {
+        const COUNT: usize = 100;
+        let (client, requests) = tenant_client(libhttpclient::StatusCode::OK);
+        let loading = Arc::new(AtomicU32::new(0));
+        let max_loading = Arc::new(AtomicU32::new(0));
+
+        let mut reports = Vec::new();
+        put_assets_concurrent(
+            &client,
+            asset_items(COUNT, loading, max_loading.clone()),
+            4,
+            |p| reports.push(p),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(max_loading.load(Ordering::SeqCst), 4);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), COUNT);
+        let mut sizes = requests
+            .iter()
+            .map(|req| {
+                assert_eq!(req.method, libhttpclient::Method::PUT);
+                req.body.as_ref().unwrap().len()
+            })
+            .collect::<Vec<_>>();
+        sizes.sort();
+        assert_eq!(sizes, (0..COUNT).collect::<Vec<_>>());
+
+        assert_eq!(reports.len(), COUNT);
+        let mut keys = reports
+            .iter()
+            .map(|p| p.key.to_string())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), COUNT);
+        let last = reports.last().unwrap();
+        assert_eq!(last.uploaded_objects, COUNT);
+        assert_eq!(last.total_objects, COUNT);
+        assert_eq!(last.uploaded_bytes, (0..COUNT).sum::<usize>());
+    }
+
+    #[tokio::test]
+    async fn test_put_assets_concurrent_returns_first_error() {
+        let (client, _requests) = tenant_client(libhttpclient::StatusCode::INTERNAL_SERVER_ERROR);
+        let mut reports = 0;
+        let err = put_assets_concurrent(
+            &client,
+            asset_items(10, Default::default(), Default::default()),
+            4,
+            |_| reports += 1,
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("500"), "{err:#}");
+        assert_eq!(reports, 0);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_send_content_hash() {
+        let (client, requests) = tenant_client(libhttpclient::StatusCode::OK);
+        let key = ObjectStoreKey::new("inputs/abc".to_string());
+        client
+            .put_asset(&key, Bytes::from_static(b"png bytes"))
+            .await
+            .unwrap();
+        let revision_id = conflux::RevisionId::new("rev_test".to_string());
+        client
+            .put_revpak(&revision_id, Bytes::from_static(b"{}"))
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for req in requests.iter() {
+            let body = req.body.as_ref().unwrap();
+            let sent = req.headers.get(CONTENT_SHA256_HEADER).unwrap();
+            assert_eq!(sent.to_str().unwrap(), content_sha256(body));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_uploads_check_echoed_content_hash() {
+        let payload = Bytes::from_static(b"png bytes");
+        let key = ObjectStoreKey::new("inputs/abc".to_string());
+
+        for (echoed, should_pass) in [
+            (content_sha256(&payload), true),
+            (content_sha256(b"something else"), false),
+        ] {
+            let (mut client, _requests) = tenant_client(libhttpclient::StatusCode::OK);
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_SHA256_HEADER, echoed.parse().unwrap());
+            client.hclient = Arc::new(FakeHttpClient {
+                requests: Default::default(),
+                status: libhttpclient::StatusCode::OK,
+                headers,
+                body: String::new(),
+            });
+
+            let res = client.put_asset(&key, payload.clone()).await;
+            assert_eq!(res.is_ok(), should_pass, "{res:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_asset_from_path_streams_the_file() {
+        // big enough to take a few chunks
+        let contents = (0..600_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &contents).unwrap();
+        let path = Utf8Path::from_path(file.path()).unwrap();
+
+        let server = TestServer::always(Reply::new("200 OK")).await;
+
+        let client = MomTenantClientImpl {
+            mcc: MomClientConfig {
+                base_url: server.base_url(),
+                api_key: Some(MomApiKey::new("sekrit".to_string())),
+                use_prod_mom_in_dev: false,
+            },
+            base_path: "/tenant/example.org".to_string(),
+            hclient: Arc::from(libhttpclient::load().client()),
+            dry_run: false,
+        };
+        let key = ObjectStoreKey::new("inputs/video".to_string());
+        client.put_asset_from_path(&key, path).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method(), "PUT");
+        assert_eq!(
+            request.target(),
+            "/tenant/example.org/objectstore/put/inputs/video"
+        );
+        assert_eq!(request.header("authorization"), Some("Bearer sekrit"));
+        assert_eq!(
+            request.header("content-length"),
+            Some(contents.len().to_string().as_str())
+        );
+        assert_eq!(request.body, contents);
+    }
+
+    #[test]
+    fn test_prod_mom_choice_combinations() {
+        use ProdMomChoice::*;
+
+        // (is_development, use_prod_mom_in_dev, FORCE_LOCAL_MOM) => choice
+        let cases = [
+            ((false, false, false), NotDevelopment),
+            ((false, false, true), NotDevelopment),
+            ((false, true, false), NotDevelopment),
+            ((false, true, true), NotDevelopment),
+            ((true, false, false), ConfigDisabled),
+            ((true, false, true), ForceLocalMomEnv),
+            ((true, true, false), ConfigEnabled),
+            ((true, true, true), ForceLocalMomEnv),
+        ];
+        for ((is_dev, use_prod, force_local), expected) in cases {
+            let choice = ProdMomChoice::resolve(is_dev, use_prod, force_local);
+            assert_eq!(choice, expected, "{is_dev} {use_prod} {force_local}");
+            assert_eq!(
+                choice.uses_production_mom(),
+                is_dev && use_prod && !force_local
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_force_local_mom() {
+        assert!(!parse_force_local_mom(None));
+        assert!(!parse_force_local_mom(Some("")));
+        assert!(!parse_force_local_mom(Some("0")));
+        assert!(parse_force_local_mom(Some("1")));
+        assert!(parse_force_local_mom(Some("TRUE")));
     }
 }
@@ -13,6 +13,11 @@ use object_store::aws::AmazonS3Builder;
 use object_store::path::Path;
 use std::fmt;
 
+mod probe;
+mod retry;
+pub use backoff::RetryPolicy;
+pub use retry::DEFAULT_GET_RETRY;
+
 /// Options for a put request
 #[derive(Default, Clone)]
 pub struct PutOptions {
@@ -559,6 +564,12 @@ impl GetResult for GetResultWrapper {
             .map(|v| -> &str { v })
     }
 
+    /// The store's ETag for the object, if it has one. S3 quotes them,
+    /// other stores may not.
+    fn e_tag(&self) -> Option<&str> {
+        self.0.meta.e_tag.as_deref()
+    }
+
     fn bytes(self: Box<Self>) -> BoxFuture<'static, Result<Bytes>> {
         Box::pin(async move { self.0.bytes().await.map_err(to_spec_error) })
     }
@@ -591,6 +602,10 @@ impl GetResult for SimpleGetResult {
         self.content_type.as_deref()
     }
 
+    fn e_tag(&self) -> Option<&str> {
+        None
+    }
+
     fn bytes(self: Box<Self>) -> BoxFuture<'static, Result<Bytes>> {
         Box::pin(async move { Ok(self.bytes) })
     }
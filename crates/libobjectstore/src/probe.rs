@@ -0,0 +1,27 @@
+use futures_core::future::BoxFuture;
+use objectstore_types::ObjectStoreKey;
+
+use crate::{GetOptions, ObjectStore, Result};
+
+/// A key nothing ever gets written to
+const PROBE_KEY: &str = "probe/does-not-exist";
+
+impl dyn ObjectStore + '_ {
+    /// Checks that the store answers at all, by HEAD-ing a key that doesn't
+    /// exist: "not found" means we got through (and that our credentials
+    /// were accepted), anything else is returned as-is.
+    pub fn probe(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let key = ObjectStoreKey::new(PROBE_KEY.to_string());
+            let opts = GetOptions {
+                head: true,
+                ..Default::default()
+            };
+            match self.get_opts(&key, opts).await {
+                Ok(_) => Ok(()),
+                Err(e) if e.is_not_found() => Ok(()),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use backoff::RetryPolicy;
+use futures_core::future::BoxFuture;
+use objectstore_types::ObjectStoreKeyRef;
+
+use crate::{Bytes, GetOptions, GetResult, ObjectStore, Result};
+
+/// How the `*_retrying` getters on [`ObjectStore`] wait out a flaky store by
+/// default: we give up (with the last error) after about 10 seconds
+pub const DEFAULT_GET_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: None,
+    initial: Duration::from_millis(100),
+    max: Duration::from_secs(2),
+    jitter: Duration::ZERO,
+    max_total: Some(Duration::from_secs(10)),
+};
+
+impl dyn ObjectStore + '_ {
+    /// Like `get_opts`, but tries again when the store errors out. Not found
+    /// is an answer, not an error: that's returned right away.
+    pub fn get_opts_retrying<'a>(
+        &'a self,
+        key: &'a ObjectStoreKeyRef,
+        opts: GetOptions,
+        policy: RetryPolicy,
+    ) -> BoxFuture<'a, Result<Box<dyn GetResult>>> {
+        Box::pin(retrying(key, policy, move || {
+            self.get_opts(key, opts.clone())
+        }))
+    }
+
+    pub fn get_retrying<'a>(
+        &'a self,
+        key: &'a ObjectStoreKeyRef,
+    ) -> BoxFuture<'a, Result<Box<dyn GetResult>>> {
+        self.get_opts_retrying(key, GetOptions::default(), DEFAULT_GET_RETRY)
+    }
+
+    /// Gets the whole object in memory. Failing to read the body counts as
+    /// a failed attempt too, so that's retried as well.
+    pub fn get_bytes_retrying<'a>(
+        &'a self,
+        key: &'a ObjectStoreKeyRef,
+    ) -> BoxFuture<'a, Result<Bytes>> {
+        Box::pin(retrying(key, DEFAULT_GET_RETRY, move || async move {
+            self.get(key).await?.bytes().await
+        }))
+    }
+}
+
+async fn retrying<T, F, Fut>(
+    key: &ObjectStoreKeyRef,
+    policy: RetryPolicy,
+    mut attempt: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff = policy.backoff();
+    loop {
+        let e = match attempt().await {
+            Ok(res) => return Ok(res),
+            Err(e) if e.is_not_found() => return Err(e),
+            Err(e) => e,
+        };
+        let attempts = backoff.failures() + 1;
+        let Some(delay) = backoff.next_delay() else {
+            log::warn!("Giving up on {key} after {attempts} attempts: {e}");
+            return Err(e);
+        };
+        log::warn!("Error getting {key} (attempt {attempts}), retrying in {delay:?}: {e}");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, MultipartUpload, PutMultipartOptions, PutOptions, PutResult};
+    use objectstore_types::ObjectStoreKey;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Fails the first `failures` gets, then behaves like the store it wraps
+    struct FlakyStore {
+        inner: Arc<dyn ObjectStore>,
+        failures: usize,
+        gets: AtomicUsize,
+    }
+
+    impl ObjectStore for FlakyStore {
+        fn put_opts(
+            &self,
+            key: &ObjectStoreKeyRef,
+            payload: Bytes,
+            opts: PutOptions,
+        ) -> BoxFuture<'_, Result<PutResult>> {
+            self.inner.put_opts(key, payload, opts)
+        }
+
+        fn put_multipart_opts(
+            &self,
+            key: &ObjectStoreKeyRef,
+            opts: PutMultipartOptions,
+        ) -> BoxFuture<'_, Result<Box<dyn MultipartUpload>>> {
+            self.inner.put_multipart_opts(key, opts)
+        }
+
+        fn get_opts(
+            &self,
+            key: &ObjectStoreKeyRef,
+            opts: GetOptions,
+        ) -> BoxFuture<'_, Result<Box<dyn GetResult>>> {
+            if self.gets.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Box::pin(async {
+                    Err::<Box<dyn GetResult>, _>(crate::Error {
+                        kind: ErrorKind::Other,
+                        source: "connection reset by peer".into(),
+                    })
+                });
+            }
+            self.inner.get_opts(key, opts)
+        }
+
+        fn desc(&self) -> String {
+            "flaky".to_string()
+        }
+    }
+
+    async fn flaky_store(failures: usize) -> (FlakyStore, ObjectStoreKey) {
+        let inner = crate::load().in_memory();
+        let key = ObjectStoreKey::new("derivations/ab/abcdef.avif".to_string());
+        inner.put(&key, Bytes::from_static(b"avif!")).await.unwrap();
+        let store = FlakyStore {
+            inner,
+            failures,
+            gets: AtomicUsize::new(0),
+        };
+        (store, key)
+    }
+
+    const FAST: RetryPolicy = RetryPolicy {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(4),
+        max_total: Some(Duration::from_secs(5)),
+        ..DEFAULT_GET_RETRY
+    };
+
+    #[tokio::test]
+    async fn test_transient_errors_are_retried() {
+        let (flaky, key) = flaky_store(2).await;
+        let store: &dyn ObjectStore = &flaky;
+
+        let res = store
+            .get_opts_retrying(&key, GetOptions::default(), FAST)
+            .await
+            .unwrap();
+        assert_eq!(&res.bytes().await.unwrap()[..], b"avif!");
+        assert_eq!(flaky.gets.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_is_not_retried() {
+        let (flaky, _) = flaky_store(0).await;
+        let store: &dyn ObjectStore = &flaky;
+
+        let missing = ObjectStoreKey::new("derivations/00/nope.avif".to_string());
+        let err = store
+            .get_opts_retrying(&missing, GetOptions::default(), FAST)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.is_not_found());
+        assert_eq!(flaky.gets.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_total() {
+        let (flaky, key) = flaky_store(usize::MAX).await;
+        let store: &dyn ObjectStore = &flaky;
+
+        let policy = RetryPolicy {
+            max_total: Some(Duration::from_millis(20)),
+            ..FAST
+        };
+        let err = store
+            .get_opts_retrying(&key, GetOptions::default(), policy)
+            .await
+            .err()
+            .unwrap();
+        assert!(!err.is_not_found());
+        assert!(flaky.gets.load(Ordering::SeqCst) > 1);
+    }
+}
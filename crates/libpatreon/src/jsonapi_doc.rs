@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use eyre::Result;
+use serde::{Deserialize, de::DeserializeOwned};
+
+/// A JSON:API document, as Patreon sends them: the identity endpoint, pages
+/// of campaign members, and webhook bodies all look like this.
+#[derive(Deserialize, Debug)]
+pub(crate) struct Document {
+    #[serde(default)]
+    data: Option<PrimaryData>,
+    #[serde(default)]
+    pub(crate) included: Vec<Resource>,
+    #[serde(default)]
+    pub(crate) links: Option<Links>,
+    #[serde(default)]
+    errors: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum PrimaryData {
+    Many(Vec<Resource>),
+    One(Box<Resource>),
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Links {
+    pub(crate) next: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Resource {
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+    pub(crate) id: String,
+    #[serde(default)]
+    attributes: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    relationships: HashMap<String, Relationship>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Relationship {
+    #[serde(default)]
+    data: Option<Linkage>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Linkage {
+    Many(Vec<ResourceId>),
+    One(ResourceId),
+}
+
+/// Points at a resource, normally one from `included`
+#[derive(Deserialize, Debug)]
+pub(crate) struct ResourceId {
+    /// Patreon sometimes leaves it out, callers say what they expect instead
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    pub(crate) id: String,
+}
+
+impl Document {
+    pub(crate) fn parse(payload: &[u8]) -> Result<Self> {
+        let doc: Document = serde_json::from_slice(payload)?;
+        if let Some(errors) = doc.errors.as_ref() {
+            return Err(eyre::eyre!("jsonapi errors: {errors:?}"));
+        }
+        Ok(doc)
+    }
+
+    /// The primary data, when it's a single resource
+    pub(crate) fn single(&self) -> Result<&Resource> {
+        match &self.data {
+            Some(PrimaryData::One(resource)) => Ok(resource),
+            _ => Err(eyre::eyre!("no top-level resource in jsonapi doc")),
+        }
+    }
+
+    /// The primary data, when it's a list of resources
+    pub(crate) fn many(&self) -> Result<&[Resource]> {
+        match &self.data {
+            Some(PrimaryData::Many(resources)) => Ok(resources),
+            _ => Err(eyre::eyre!("no top-level list of resources in jsonapi doc")),
+        }
+    }
+
+    pub(crate) fn resolver(&self) -> Resolver<'_> {
+        Resolver::new(&self.included)
+    }
+}
+
+impl Resource {
+    pub(crate) fn attributes<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let val = serde_json::Value::Object(self.attributes.clone());
+        Ok(serde_json::from_value(val)?)
+    }
+
+    /// Where a to-one relationship points, if it's there and not null
+    pub(crate) fn related_id(&self, name: &str) -> Option<&ResourceId> {
+        match self.relationships.get(name)?.data.as_ref()? {
+            Linkage::One(id) => Some(id),
+            Linkage::Many(_) => None,
+        }
+    }
+
+    /// Where a to-many relationship points, if it's there
+    pub(crate) fn related_ids(&self, name: &str) -> Option<&[ResourceId]> {
+        match self.relationships.get(name)?.data.as_ref()? {
+            Linkage::Many(ids) => Some(ids),
+            Linkage::One(_) => None,
+        }
+    }
+}
+
+/// Looks up `included` resources by (type, id), and follows relationships
+/// into them
+pub(crate) struct Resolver<'doc> {
+    by_key: HashMap<(&'doc str, &'doc str), &'doc Resource>,
+}
+
+impl<'doc> Resolver<'doc> {
+    pub(crate) fn new(included: &'doc [Resource]) -> Self {
+        Self {
+            by_key: included
+                .iter()
+                .map(|res| ((res.kind.as_str(), res.id.as_str()), res))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn get(&self, kind: &str, id: &str) -> Option<&'doc Resource> {
+        self.by_key.get(&(kind, id)).copied()
+    }
+
+    /// `kind` is what we expect the resource to be, if the pointer doesn't say
+    pub(crate) fn resolve(&self, rid: &ResourceId, kind: &str) -> Result<&'doc Resource> {
+        let kind = rid.kind.as_deref().unwrap_or(kind);
+        self.get(kind, &rid.id)
+            .ok_or_else(|| eyre::eyre!("Could not find {kind} {} in jsonapi doc", rid.id))
+    }
+
+    pub(crate) fn related_one(
+        &self,
+        res: &Resource,
+        name: &str,
+        kind: &str,
+    ) -> Result<&'doc Resource> {
+        let rid = res.related_id(name).ok_or_else(|| {
+            eyre::eyre!(
+                "Could not get single relationship {name} for {} in jsonapi doc",
+                res.kind
+            )
+        })?;
+        self.resolve(rid, kind)
+    }
+
+    pub(crate) fn related_many(
+        &self,
+        res: &Resource,
+        name: &str,
+        kind: &str,
+    ) -> Result<Vec<&'doc Resource>> {
+        let ids = res.related_ids(name).ok_or_else(|| {
+            eyre::eyre!(
+                "Could not get multi relationship {name} for {} in jsonapi doc",
+                res.kind
+            )
+        })?;
+        ids.iter().map(|rid| self.resolve(rid, kind)).collect()
+    }
+}
+
+/// Resources the identity endpoint, member pages and webhooks all include,
+/// as `included` (without the brackets). User 42 is a member of campaign
+/// 12345 (at the Silver tier) and of campaign 999, and user 43 has no tier.
+#[cfg(test)]
+pub(crate) const FIXTURE_INCLUDED: &str = r#"
+    { "type": "campaign", "id": "12345", "attributes": { "vanity": "fasterthanlime" } },
+    { "type": "campaign", "id": "999", "attributes": { "vanity": "someone-else" } },
+    { "type": "tier", "id": "777", "attributes": { "title": "Silver" } },
+    { "type": "tier", "id": "888", "attributes": { "title": "Elsewhere" } },
+    { "type": "user", "id": "42", "attributes": { "thumb_url": "https://example.org/42.png" } },
+    { "type": "user", "id": "43", "attributes": { "thumb_url": "https://example.org/43.png" } },
+    {
+        "type": "member",
+        "id": "member-elsewhere",
+        "attributes": { "patron_status": "active_patron" },
+        "relationships": {
+            "campaign": { "data": { "type": "campaign", "id": "999" } },
+            "currently_entitled_tiers": { "data": [{ "type": "tier", "id": "888" }] }
+        }
+    },
+    {
+        "type": "member",
+        "id": "member-42",
+        "attributes": {
+            "full_name": "Alice Example ",
+            "patron_status": "active_patron",
+            "currently_entitled_amount_cents": 1000
+        },
+        "relationships": {
+            "campaign": { "data": { "type": "campaign", "id": "12345" } },
+            "currently_entitled_tiers": { "data": [{ "type": "tier", "id": "777" }] },
+            "user": { "data": { "type": "user", "id": "42" } }
+        }
+    },
+    {
+        "type": "member",
+        "id": "member-43",
+        "attributes": { "full_name": "Bob", "patron_status": "former_patron" },
+        "relationships": {
+            "campaign": { "data": { "type": "campaign", "id": "12345" } },
+            "currently_entitled_tiers": { "data": [] },
+            "user": { "data": { "id": "43" } }
+        }
+    }
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(data: &str) -> Document {
+        Document::parse(
+            format!(r#"{{ "data": {data}, "included": [{FIXTURE_INCLUDED}] }}"#).as_bytes(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolves_by_type_and_id() {
+        let doc = fixture(r#"{ "type": "user", "id": "42" }"#);
+        let resolver = doc.resolver();
+
+        let user = doc.single().unwrap();
+        assert_eq!(resolver.get("user", &user.id).unwrap().kind, "user");
+        // same id, different type
+        assert!(resolver.get("tier", "42").is_none());
+        assert!(doc.many().is_err());
+
+        #[derive(Deserialize)]
+        struct Campaign {
+            vanity: String,
+        }
+        let campaign: Campaign = resolver
+            .get("campaign", "999")
+            .unwrap()
+            .attributes()
+            .unwrap();
+        assert_eq!(campaign.vanity, "someone-else");
+    }
+
+    #[test]
+    fn test_walks_relationships() {
+        let doc = fixture(
+            r#"[
+                {
+                    "type": "user",
+                    "id": "42",
+                    "relationships": {
+                        "memberships": { "data": [
+                            { "type": "member", "id": "member-elsewhere" },
+                            { "type": "member", "id": "member-42" }
+                        ] }
+                    }
+                },
+                {
+                    "type": "user",
+                    "id": "44",
+                    "relationships": {
+                        "memberships": { "data": [{ "type": "member", "id": "member-gone" }] }
+                    }
+                }
+            ]"#,
+        );
+        let resolver = doc.resolver();
+        let users = doc.many().unwrap();
+
+        let memberships = resolver
+            .related_many(&users[0], "memberships", "member")
+            .unwrap();
+        let campaigns: Vec<_> = memberships
+            .iter()
+            .map(|m| &resolver.related_one(m, "campaign", "campaign").unwrap().id)
+            .collect();
+        assert_eq!(campaigns, ["999", "12345"]);
+
+        // pointers without a type resolve as what the caller expects
+        let bob = resolver.get("member", "member-43").unwrap();
+        assert_eq!(resolver.related_one(bob, "user", "user").unwrap().id, "43");
+        assert!(
+            resolver
+                .related_many(bob, "currently_entitled_tiers", "tier")
+                .unwrap()
+                .is_empty()
+        );
+
+        // missing relationships, and pointers to things that aren't included
+        let elsewhere = resolver.get("member", "member-elsewhere").unwrap();
+        assert!(resolver.related_one(elsewhere, "user", "user").is_err());
+        assert!(
+            resolver
+                .related_many(&users[1], "memberships", "member")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_error_documents() {
+        let err = Document::parse(br#"{ "errors": [{ "code": 1, "detail": "nope" }] }"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("nope"), "{err}");
+    }
+}
@@ -1,29 +1,66 @@
 use autotrait::autotrait;
 use config_types::{RevisionConfig, TenantConfig, WebConfig};
+use credentials::CREDENTIALS_REFRESH_THRESHOLD;
+use credentials::Credentials;
+use credentials::OAuthGrant;
 use credentials::PatreonProfile;
 use credentials::PatreonUserId;
+use credentials::ScopeDowngrade;
 use credentials::UserId;
+use credentials::{PROFILE_CACHE_CAPACITY, ProfileCache};
 use eyre::Context as _;
 use eyre::Result;
 use facet::Facet;
 use futures_core::future::BoxFuture;
-use libhttpclient::{HttpClient, Uri};
+use libhttpclient::{HttpClient, RetryPolicy, StatusCode, Uri, decode_error_response, header};
 use time::OffsetDateTime;
 use url::Url;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::OnceLock, time::Duration};
 
-mod jsonapi_ext;
-use jsonapi_ext::*;
+mod jsonapi_doc;
+use jsonapi_doc::{Document, Resolver, Resource};
 
-mod model;
-use model::*;
+mod webhook;
+pub use webhook::*;
 
-pub struct ModImpl;
+pub struct ModImpl {
+    /// Raw identity responses: the profile we make out of them depends on
+    /// the revision config, so that part isn't cached
+    identity_cache: ProfileCache<String>,
+}
+
+/// What we ask patrons for when they log in
+const PATREON_SCOPES: &str = "identity identity.memberships";
+
+/// What the module is set up with, see [`load_with`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModOptions {
+    /// How long identity fetches are cached for, per access token. Not
+    /// cached at all when unset.
+    pub profile_cache_ttl: Option<Duration>,
+}
 
+static MOD: OnceLock<ModImpl> = OnceLock::new();
+
+/// Returns the module, loading it with the default options if nothing
+/// called [`load_with`] first
 pub fn load() -> &'static dyn Mod {
-    static MOD: ModImpl = ModImpl;
-    &MOD
+    MOD.get_or_init(|| ModImpl::new(ModOptions::default()))
+}
+
+/// Loads the module with `opts`. That's only possible once, before anything
+/// calls [`load`]: after that, this is an error.
+pub fn load_with(opts: ModOptions) -> Result<&'static dyn Mod> {
+    let mut loaded_here = false;
+    let m = MOD.get_or_init(|| {
+        loaded_here = true;
+        ModImpl::new(opts)
+    });
+    if !loaded_here {
+        eyre::bail!("the Patreon module was already loaded, can't set it up with {opts:?}");
+    }
+    Ok(m)
 }
 
 #[autotrait]
@@ -35,7 +72,7 @@ impl Mod for ModImpl {
         q.append_pair("response_type", "code");
         q.append_pair("client_id", &patreon_secrets.oauth_client_id);
         q.append_pair("redirect_uri", &self.make_patreon_callback_url(tc, web));
-        q.append_pair("scope", "identity identity.memberships");
+        q.append_pair("scope", PATREON_SCOPES);
         drop(q);
 
         Ok(u.to_string())
@@ -48,7 +85,7 @@ impl Mod for ModImpl {
         web: WebConfig,
         args: &'fut PatreonCallbackArgs,
         client: &'fut dyn HttpClient,
-    ) -> BoxFuture<'fut, Result<Option<PatreonCredentials>>> {
+    ) -> BoxFuture<'fut, Result<Option<OAuthGrant<PatreonCredentials>>>> {
         Box::pin(async move {
             let code = match url::form_urlencoded::parse(args.raw_query.as_bytes())
                 .find(|(key, _)| key == "code")
@@ -73,39 +110,18 @@ impl Mod for ModImpl {
             let res = client
                 .post(Uri::from_static("https://patreon.com/api/oauth2/token"))
                 .form(tok_params)
+                .retry(RetryPolicy::QUICK)
                 .send()
                 .await
                 .wrap_err("POST to /api/oauth2/token for oauth callback")?;
 
-            let status = res.status();
-            if !status.is_success() {
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
+            if !res.status().is_success() {
+                return Err(decode_error_response(res).await);
             }
 
             let text = res.text().await?;
-            let creds_api = match facet_json::from_str::<PatreonCredentialsAPI>(&text) {
-                Ok(c) => c,
-                Err(e) => {
-                    log::warn!("Got Patreon auth error: {text}");
-                    return Err(eyre::eyre!("Got Patreon auth error: {e}"));
-                }
-            };
-            log::info!(
-                "Successfully obtained Patreon token with scope {}",
-                &creds_api.scope
-            );
-
-            let creds = PatreonCredentials {
-                access_token: creds_api.access_token,
-                refresh_token: creds_api.refresh_token,
-                expires_at: OffsetDateTime::now_utc()
-                    + time::Duration::seconds(creds_api.expires_in as i64),
-            };
-            Ok(Some(creds))
+            let grant = grant_from_token_response(&text, args.strict_scopes)?;
+            Ok(Some(grant))
         })
     }
 
@@ -135,13 +151,8 @@ impl Mod for ModImpl {
                 .send()
                 .await
                 .wrap_err("POST to /api/oauth2/token for refresh")?;
-            let status = res.status();
-            if !status.is_success() {
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!("got HTTP {status}, server said: {error}"));
+            if !res.status().is_success() {
+                return Err(decode_error_response(res).await);
             }
 
             let pat_creds = res.json::<PatreonCredentials>().await?;
@@ -169,306 +180,575 @@ impl Mod for ModImpl {
                 .join(",");
                 q.append_pair("include", &include);
                 q.append_pair("fields[member]", "patron_status");
-                q.append_pair("fields[user]", "full_name,thumb_url");
+                q.append_pair("fields[user]", "full_name,thumb_url,email");
                 q.append_pair("fields[tier]", "title");
             }
 
             let identity_url = identity_url.to_string();
 
             let identity_uri = identity_url.parse::<Uri>().unwrap();
-            let res = client
-                .get(identity_uri.clone())
-                .bearer_auth(&creds.access_token)
-                .send()
-                .await
-                .wrap_err("GET /api/oauth2/v2/identity")?;
-
-            let status = res.status();
-            if !status.is_success() {
-                let error = res
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Could not get error text".into());
-                return Err(eyre::eyre!(
-                    "got HTTP {status} from {identity_uri}, server said: {error}"
-                ));
-            }
-
-            let payload: String = res.text().await?;
-            log::info!("Got Patreon response: {payload}");
-
-            log::info!("Parsing Patreon JsonApiDocument from payload");
-            let doc: jsonapi::model::DocumentData =
-                match serde_json::from_str::<jsonapi::api::JsonApiDocument>(&payload)? {
-                    jsonapi::api::JsonApiDocument::Data(doc) => {
-                        log::info!("Successfully parsed JsonApiDocument as Data");
-                        doc
-                    }
-                    jsonapi::api::JsonApiDocument::Error(errors) => {
-                        log::info!("JsonApiDocument contains errors: {errors:?}");
-                        return Err(eyre::eyre!("jsonapi errors: {errors:?}"));
-                    }
-                };
-
-            log::info!("Extracting user from primary data");
-            let user = match &doc.data {
-                Some(jsonapi::api::PrimaryData::Single(user)) => {
-                    log::info!("Found top-level user resource");
-                    user
-                }
-                _ => {
-                    log::info!("No top-level user resource found");
-                    return Err(eyre::eyre!("no top-level user resource"));
-                }
-            };
-
-            let mut tier_title = None;
-
-            #[derive(Debug, serde::Deserialize)]
-            struct UserAttributes {
-                full_name: String,
-                thumb_url: String,
-            }
-            log::info!("Getting user attributes");
-            let user_attrs: UserAttributes = user.get_attributes()?;
-            log::info!(
-                "Found user attributes: full_name={}, thumb_url={}",
-                user_attrs.full_name,
-                user_attrs.thumb_url
-            );
+            let payload = match self.identity_cache.get(&creds.access_token) {
+                Some(payload) => payload,
+                None => {
+                    let res = client
+                        .get(identity_uri.clone())
+                        .bearer_auth(&creds.access_token)
+                        .send()
+                        .await
+                        .wrap_err("GET /api/oauth2/v2/identity")?;
 
-            log::info!("Getting user memberships");
-            let memberships = user.get_multi_relationship(&doc, "memberships")?;
-            log::info!("Found {} memberships", memberships.len());
-
-            'each_membership: for (i, &membership) in memberships.iter().enumerate() {
-                log::info!("Processing membership #{}", i + 1);
-
-                let campaign = match membership.get_single_relationship(&doc, "campaign") {
-                    Ok(campaign) => {
-                        log::info!(
-                            "Found campaign for membership #{}: id={}",
-                            i + 1,
-                            campaign.id
-                        );
-                        campaign
-                    }
-                    Err(e) => {
-                        log::warn!("{e}, skipping campaign for membership #{}", i + 1);
-                        continue;
+                    if !res.status().is_success() {
+                        return Err(decode_error_response(res)
+                            .await
+                            .wrap_err(format!("GET {identity_uri}")));
                     }
-                };
-
-                let campaign_match = rc.patreon_campaign_ids.contains(&campaign.id);
-                log::info!(
-                    "Campaign {} is in our configured campaign_ids: {}",
-                    campaign.id,
-                    campaign_match
-                );
-                if !campaign_match {
-                    log::info!(
-                        "Skipping campaign {} (not in our configured list)",
-                        campaign.id
-                    );
-                    continue;
-                }
 
-                let tiers =
-                    match membership.get_multi_relationship(&doc, "currently_entitled_tiers") {
-                        Ok(tiers) => {
-                            log::info!("Found {} tiers for membership #{}", tiers.len(), i + 1);
-                            tiers
-                        }
-                        Err(e) => {
-                            log::warn!("{e}, skipping tiers for membership #{}", i + 1);
-                            continue;
-                        }
-                    };
-
-                if let Some(tier) = tiers.first() {
-                    log::info!("Processing first tier: id={}", tier.id);
-
-                    #[derive(Debug, serde::Deserialize)]
-                    struct TierAttributes {
-                        title: String,
-                    }
-                    let tier_attrs: TierAttributes = tier.get_attributes()?;
-                    log::info!("Tier title: {}", tier_attrs.title);
-
-                    tier_title = Some(tier_attrs.title);
-                    log::info!(
-                        "Found matching tier '{}' - breaking from membership loop",
-                        tier_title.as_ref().unwrap()
-                    );
-                    break 'each_membership;
-                } else {
-                    log::info!("No tiers found for this membership");
+                    let payload: String = res.text().await?;
+                    log::trace!("Got Patreon response: {payload}");
+                    self.identity_cache
+                        .insert(&creds.access_token, payload.clone());
+                    payload
                 }
-            }
-
-            log::info!("Creating profile with patreon_id={}", user.id);
-            let has_tier = tier_title.is_some();
-            log::info!("User has tier from memberships: {has_tier}");
-
-            let profile = PatreonProfile {
-                id: PatreonUserId::new(user.id.clone()),
-                tier: tier_title,
-                full_name: user_attrs.full_name,
-                avatar_url: Some(user_attrs.thumb_url),
             };
 
+            let profile = profile_from_identity(&payload, rc, &mut TierTitles::default())?;
             log::info!("Refreshed Patreon profile: {profile:#?}",);
             Ok(profile)
         })
     }
 
     /// List all sponsors using `credentials`, which must be the owner of the campaign ID
-    /// for the given `RevisionConfig`.
+    /// for the given `RevisionConfig`. A page of members over `max_page_size` bytes
+    /// is an error.
     fn list_sponsors<'fut>(
         &'fut self,
+        tc: &'fut TenantConfig,
         rc: &'fut RevisionConfig,
         client: &'fut dyn HttpClient,
         credentials: &'fut PatreonCredentials,
+        max_page_size: usize,
     ) -> BoxFuture<'fut, Result<Vec<PatreonProfile>>> {
         Box::pin(async move {
             // Check if credentials are expiring soon
-            if credentials.expire_soon() {
+            if credentials.expire_soon(CREDENTIALS_REFRESH_THRESHOLD) {
                 return Err(eyre::eyre!("Patreon credentials are expiring soon"));
             }
 
-            let patreon_campaign_id = rc
-                .patreon_campaign_ids
-                .first()
-                .expect("patreon_campaign_ids should have at least one element");
-
-            let mut patrons: Vec<PatreonProfile> = Vec::new();
-
-            let mut api_uri = Uri::builder()
-                .scheme("https")
-                .authority("www.patreon.com")
-                .path_and_query(
-                    libhttpclient::form_urlencoded::Serializer::new(format!(
-                        "/api/oauth2/v2/campaigns/{patreon_campaign_id}/members?"
-                    ))
-                    .append_pair("include", "currently_entitled_tiers,user")
-                    .append_pair("fields[member]", "full_name")
-                    .append_pair("fields[user]", "thumb_url")
-                    .append_pair("fields[tier]", "title")
-                    .append_pair("page[size]", "50")
-                    .finish(),
+            if rc.patreon_campaign_ids.is_empty() {
+                return Err(eyre::eyre!(
+                    "No Patreon campaign IDs configured, can't list sponsors"
+                ));
+            }
+
+            let mut tier_titles = TierTitles::default();
+            let mut per_campaign = Vec::with_capacity(rc.patreon_campaign_ids.len());
+            for campaign_id in &rc.patreon_campaign_ids {
+                let sponsors = list_campaign_sponsors(
+                    tc,
+                    client,
+                    credentials,
+                    campaign_id,
+                    &mut tier_titles,
+                    max_page_size,
                 )
-                .build()
-                .unwrap();
-
-            let mut num_page = 0;
-            loop {
-                num_page += 1;
-                log::info!("Fetching Patreon page {num_page}");
-                log::debug!("Fetch uri: {api_uri}");
-
-                let res = client
-                    .get(api_uri.clone())
-                    .bearer_auth(&credentials.access_token)
-                    .polite_user_agent()
-                    .send()
-                    .await?;
-
-                let status = res.status();
-                if !status.is_success() {
-                    let error = res
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "Could not get error text".into());
-                    return Err(eyre::eyre!(
-                        "got HTTP {status} from {api_uri}, server said: {error}"
-                    ));
-                }
+                .await
+                .wrap_err_with(|| format!("listing sponsors of campaign {campaign_id}"))?;
+                log::info!(
+                    "Patreon campaign {campaign_id} has {} sponsors",
+                    sponsors.len()
+                );
+                per_campaign.push(sponsors);
+            }
 
-                let patreon_payload = res.text().await?;
-                // std::fs::write("/tmp/patreon-payload.json", &patreon_payload)
-                //     .wrap_err("Failed to write patreon payload to /tmp/patreon-payload.json")?;
-                // eprintln!(
-                //     "Wrote Patreon API response payload to /tmp/patreon-payload.json for debugging"
-                // );
-
-                let patreon_response: PatreonResponse = serde_json::from_str(&patreon_payload)?;
-                let mut tiers_per_id: HashMap<String, Tier> = Default::default();
-                let mut users_per_id: HashMap<String, User> = Default::default();
-
-                for item in patreon_response.included {
-                    match item {
-                        Item::Tier(tier) => {
-                            tiers_per_id.insert(tier.common.id.clone(), tier);
-                        }
-                        Item::User(user) => {
-                            users_per_id.insert(user.common.id.clone(), user);
-                        }
-                        _ => {}
-                    }
-                }
+            Ok(merge_sponsors(per_campaign))
+        })
+    }
+}
 
-                for item in patreon_response.data {
-                    if let Item::Member(member) = item {
-                        if let Some(full_name) = member.attributes.full_name.as_deref() {
-                            let tier_title = if let Some(entitled) = member
-                                .common
-                                .relationships
-                                .currently_entitled_tiers
-                                .as_ref()
-                            {
-                                entitled.data.iter().find_map(|item_ref| {
-                                    let ItemRef::Tier(tier_id) = item_ref;
-                                    tiers_per_id
-                                        .get(&tier_id.id)
-                                        .and_then(|tier| tier.attributes.title.as_deref())
-                                        .map(|title| title.to_string())
-                                })
-                            } else {
-                                None
-                            };
-
-                            let mut thumb_url: Option<String> = None;
-                            let user_id =
-                                if let Some(user_rel) = member.common.relationships.user.as_ref() {
-                                    let user_id = user_rel.data.id.clone();
-                                    if let Some(user_item) = users_per_id.get(&user_id) {
-                                        thumb_url = user_item.attributes.thumb_url.clone();
-                                    }
-
-                                    user_id
-                                } else {
-                                    continue;
-                                };
-
-                            let patron = PatreonProfile {
-                                id: PatreonUserId::new(user_id.clone()),
-                                tier: tier_title,
-                                full_name: full_name.trim().to_string(),
-                                avatar_url: thumb_url,
-                            };
-                            patrons.push(patron);
-                        }
-                    }
-                }
+/// Lists the sponsors of a single campaign, following pagination.
+async fn list_campaign_sponsors(
+    tc: &TenantConfig,
+    client: &dyn HttpClient,
+    credentials: &PatreonCredentials,
+    campaign_id: &str,
+    tier_titles: &mut TierTitles,
+    max_page_size: usize,
+) -> Result<Vec<PatreonProfile>> {
+    let api_uri = Uri::builder()
+        .scheme("https")
+        .authority("www.patreon.com")
+        .path_and_query(
+            libhttpclient::form_urlencoded::Serializer::new(format!(
+                "/api/oauth2/v2/campaigns/{campaign_id}/members?"
+            ))
+            .append_pair("include", "currently_entitled_tiers,user")
+            .append_pair(
+                "fields[member]",
+                "full_name,currently_entitled_amount_cents",
+            )
+            .append_pair("fields[user]", "thumb_url")
+            .append_pair("fields[tier]", "title")
+            .append_pair("page[size]", "50")
+            .finish(),
+        )
+        .build()
+        .wrap_err_with(|| format!("building members URI for campaign {campaign_id}"))?;
 
-                match patreon_response.links.and_then(|l| l.next) {
-                    Some(next) => {
-                        api_uri = match next.parse::<Uri>() {
-                            Ok(uri) => uri,
-                            Err(e) => return Err(eyre::eyre!("Failed to parse next URI: {}", e)),
-                        };
-                        continue;
-                    }
-                    None => break,
-                }
+    let mut source = PatreonMembersSource {
+        tc,
+        client,
+        access_token: &credentials.access_token,
+        max_page_size,
+    };
+    paginate_members(&mut source, campaign_id, api_uri, tier_titles).await
+}
+
+/// At 50 members per page, that's 10k sponsors: anything past that is
+/// probably Patreon sending us in circles.
+const MAX_MEMBER_PAGES: usize = 200;
+
+/// How many times we retry a page when Patreon answers with a 429
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// How long we wait after a 429 that didn't come with a `Retry-After`
+/// (doubled after each retry)
+const INITIAL_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(2);
+
+/// We won't wait longer than this between retries, whatever `Retry-After` says
+const MAX_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A page of members as returned by Patreon, before parsing
+struct RawPage {
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    /// the page, or what Patreon had to say if the status isn't a success
+    body: Result<String>,
+}
+
+/// Where pages of members come from: Patreon's API, or a script in tests
+trait MembersSource: Send {
+    fn fetch<'a>(&'a mut self, uri: &'a Uri) -> BoxFuture<'a, Result<RawPage>>;
+
+    fn sleep(&mut self, duration: Duration) -> BoxFuture<'_, ()>;
+}
+
+struct PatreonMembersSource<'a> {
+    tc: &'a TenantConfig,
+    client: &'a dyn HttpClient,
+    access_token: &'a str,
+    /// a page bigger than this is an error, rather than buffered in full
+    max_page_size: usize,
+}
+
+impl MembersSource for PatreonMembersSource<'_> {
+    fn fetch<'a>(&'a mut self, uri: &'a Uri) -> BoxFuture<'a, Result<RawPage>> {
+        Box::pin(async move {
+            let res = self
+                .client
+                .get(uri.clone())
+                .bearer_auth(self.access_token)
+                .polite_user_agent_for(self.tc)
+                .send()
+                .await?;
+
+            let status = res.status();
+            let retry_after = res
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+            let body = if status.is_success() {
+                let body = res
+                    .bytes_limited(self.max_page_size)
+                    .await
+                    .wrap_err("reading Patreon members page")?;
+                Ok(String::from_utf8(body)?)
+            } else {
+                Err(decode_error_response(res).await)
+            };
+
+            Ok(RawPage {
+                status,
+                retry_after,
+                body,
+            })
+        })
+    }
+
+    fn sleep(&mut self, duration: Duration) -> BoxFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Parses the delay-seconds form of `Retry-After`. (The HTTP-date form is
+/// left to our own backoff.)
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Follows `links.next` from `first_uri`, collecting sponsors. If something
+/// goes wrong after at least one page, we log it and return what we have.
+async fn paginate_members(
+    source: &mut dyn MembersSource,
+    campaign_id: &str,
+    first_uri: Uri,
+    tier_titles: &mut TierTitles,
+) -> Result<Vec<PatreonProfile>> {
+    let mut patrons: Vec<PatreonProfile> = Vec::new();
+    let mut api_uri = first_uri;
+    let mut num_pages = 0;
+
+    let outcome: Result<()> = loop {
+        if num_pages >= MAX_MEMBER_PAGES {
+            break Err(eyre::eyre!(
+                "still had pages to go after {MAX_MEMBER_PAGES} pages"
+            ));
+        }
+
+        log::info!(
+            "Fetching Patreon page {} of campaign {campaign_id}",
+            num_pages + 1
+        );
+        log::debug!("Fetch uri: {api_uri}");
+
+        let payload = match fetch_page(source, &api_uri).await {
+            Ok(payload) => payload,
+            Err(e) => break Err(e),
+        };
+        log::debug!(
+            "Patreon page {} of campaign {campaign_id} is {} bytes",
+            num_pages + 1,
+            payload.len()
+        );
+        let (page_patrons, next) = match parse_members_page(&payload, tier_titles) {
+            Ok(page) => page,
+            Err(e) => break Err(e),
+        };
+        num_pages += 1;
+        patrons.extend(page_patrons);
+
+        let Some(next) = next else {
+            break Ok(());
+        };
+        let next = match next.parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(e) => break Err(eyre::eyre!("Failed to parse next URI: {}", e)),
+        };
+        if next == api_uri {
+            break Err(eyre::eyre!(
+                "next link points to the page we just fetched ({next})"
+            ));
+        }
+        api_uri = next;
+    };
+
+    if let Err(e) = outcome {
+        if num_pages == 0 {
+            return Err(e);
+        }
+        log::warn!(
+            "Stopped listing sponsors of campaign {campaign_id} after {num_pages} pages: {e}. \
+            Returning the {} sponsors gathered so far",
+            patrons.len()
+        );
+    }
+
+    Ok(patrons)
+}
+
+/// Fetches a page, waiting and retrying when Patreon rate-limits us.
+async fn fetch_page(source: &mut dyn MembersSource, uri: &Uri) -> Result<String> {
+    let mut backoff = INITIAL_RATE_LIMIT_BACKOFF;
+    let mut retries = 0;
+    loop {
+        let page = source.fetch(uri).await?;
+        let status = page.status;
+
+        if status == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RATE_LIMIT_RETRIES {
+            retries += 1;
+            let wait = page
+                .retry_after
+                .unwrap_or(backoff)
+                .min(MAX_RATE_LIMIT_BACKOFF);
+            log::warn!(
+                "Patreon rate-limited us (retry {retries}/{MAX_RATE_LIMIT_RETRIES}), waiting {wait:?}"
+            );
+            source.sleep(wait).await;
+            backoff *= 2;
+            continue;
+        }
+
+        return page.body.wrap_err_with(|| format!("GET {uri}"));
+    }
+}
+
+/// Builds a profile out of a document from the identity endpoint. Only
+/// memberships of the campaigns in `rc` count towards the tier.
+fn profile_from_identity(
+    payload: &str,
+    rc: &RevisionConfig,
+    tier_titles: &mut TierTitles,
+) -> Result<PatreonProfile> {
+    log::info!("Parsing Patreon JSON:API document from payload");
+    let doc = Document::parse(payload.as_bytes())?;
+    let resolver = doc.resolver();
+
+    log::info!("Extracting user from primary data");
+    let user = doc.single()?;
+    if user.kind != "user" {
+        return Err(eyre::eyre!("no top-level user resource"));
+    }
+
+    let mut tier_title = None;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct UserAttributes {
+        full_name: String,
+        thumb_url: String,
+        // only there if we have the `identity[email]` scope
+        email: Option<String>,
+    }
+    log::info!("Getting user attributes");
+    let user_attrs: UserAttributes = user.attributes()?;
+    log::info!(
+        "Found user attributes: full_name={}, thumb_url={}",
+        user_attrs.full_name,
+        user_attrs.thumb_url
+    );
+
+    log::info!("Getting user memberships");
+    let memberships = resolver.related_many(user, "memberships", "member")?;
+    log::info!("Found {} memberships", memberships.len());
+
+    for (i, &membership) in memberships.iter().enumerate() {
+        log::info!("Processing membership #{}", i + 1);
+
+        let campaign = match resolver.related_one(membership, "campaign", "campaign") {
+            Ok(campaign) => {
+                log::info!(
+                    "Found campaign for membership #{}: id={}",
+                    i + 1,
+                    campaign.id
+                );
+                campaign
+            }
+            Err(e) => {
+                log::warn!("{e}, skipping campaign for membership #{}", i + 1);
+                continue;
+            }
+        };
+
+        let campaign_match = rc.patreon_campaign_ids.contains(&campaign.id);
+        log::info!(
+            "Campaign {} is in our configured campaign_ids: {}",
+            campaign.id,
+            campaign_match
+        );
+        if !campaign_match {
+            log::info!(
+                "Skipping campaign {} (not in our configured list)",
+                campaign.id
+            );
+            continue;
+        }
+
+        let tiers = match resolver.related_many(membership, "currently_entitled_tiers", "tier") {
+            Ok(tiers) => {
+                log::info!("Found {} tiers for membership #{}", tiers.len(), i + 1);
+                tiers
             }
+            Err(e) => {
+                log::warn!("{e}, skipping tiers for membership #{}", i + 1);
+                continue;
+            }
+        };
+
+        if let Some(tier) = tiers.first() {
+            log::info!("Processing first tier: id={}", tier.id);
+
+            tier_title = tier_titles.title_of(tier)?;
+            log::info!(
+                "Found matching tier '{}' - breaking from membership loop",
+                tier_title.as_deref().unwrap_or_default()
+            );
+            break;
+        } else {
+            log::info!("No tiers found for this membership");
+        }
+    }
+
+    log::info!("Creating profile with patreon_id={}", user.id);
+    let has_tier = tier_title.is_some();
+    log::info!("User has tier from memberships: {has_tier}");
+
+    Ok(PatreonProfile {
+        id: PatreonUserId::new(user.id.clone()),
+        tier: tier_title,
+        full_name: user_attrs.full_name,
+        avatar_url: Some(user_attrs.thumb_url),
+        email: user_attrs.email,
+        entitled_cents: None,
+    })
+}
+
+/// Parses one page of a campaign's members, returning the sponsors on that page
+/// and the URL of the next page, if any.
+fn parse_members_page(
+    payload: &str,
+    tier_titles: &mut TierTitles,
+) -> Result<(Vec<PatreonProfile>, Option<String>)> {
+    let doc = Document::parse(payload.as_bytes())?;
+    let included = IncludedItems::new(&doc.included, tier_titles)?;
+
+    let mut patrons = Vec::new();
+    for member in doc.many()?.iter().filter(|res| res.kind == "member") {
+        if let Some(profile) = included.member_profile(member)? {
+            patrons.push(profile);
+        }
+    }
 
-            Ok(patrons)
+    let next = doc.links.and_then(|l| l.next);
+    Ok((patrons, next))
+}
+
+/// Tier titles by tier id. Every page of members (and every membership in
+/// an identity document) refers to the same handful of tiers, so we keep
+/// those around for the whole sync and only extract each title once.
+#[derive(Default)]
+struct TierTitles {
+    titles: HashMap<String, Option<String>>,
+    /// how many tier resources we actually deserialized
+    #[cfg(test)]
+    deserialized: usize,
+}
+
+impl TierTitles {
+    /// Returns the title of a tier resource, only deserializing its
+    /// attributes the first time we see it
+    fn title_of(&mut self, tier: &Resource) -> Result<Option<String>> {
+        if let Some(title) = self.titles.get(&tier.id) {
+            return Ok(title.clone());
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct TierAttributes {
+            title: Option<String>,
+        }
+        let tier_attrs: TierAttributes = tier.attributes()?;
+        #[cfg(test)]
+        self.deserialized += 1;
+        self.titles
+            .insert(tier.id.clone(), tier_attrs.title.clone());
+        Ok(tier_attrs.title)
+    }
+
+    fn get(&self, tier_id: &str) -> Option<&str> {
+        self.titles.get(tier_id)?.as_deref()
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MemberAttributes {
+    full_name: Option<String>,
+    /// what the member pledges for, in cents (only if requested in `fields[member]`)
+    #[serde(default)]
+    currently_entitled_amount_cents: Option<u32>,
+    /// `active_patron`, `declined_patron`, `former_patron`, or null if they never pledged
+    #[serde(default)]
+    patron_status: Option<String>,
+}
+
+/// The tiers and users that come alongside members in a JSON:API response
+struct IncludedItems<'a> {
+    tier_titles: &'a TierTitles,
+    resolver: Resolver<'a>,
+}
+
+impl<'a> IncludedItems<'a> {
+    fn new(included: &'a [Resource], tier_titles: &'a mut TierTitles) -> Result<Self> {
+        for tier in included.iter().filter(|res| res.kind == "tier") {
+            tier_titles.title_of(tier)?;
+        }
+
+        Ok(Self {
+            tier_titles,
+            resolver: Resolver::new(included),
         })
     }
+
+    /// Builds a profile for a member, or returns None if they have no name
+    /// or aren't linked to a Patreon user.
+    fn member_profile(&self, member: &Resource) -> Result<Option<PatreonProfile>> {
+        #[derive(Debug, serde::Deserialize)]
+        struct UserAttributes {
+            thumb_url: Option<String>,
+        }
+
+        let attrs: MemberAttributes = member.attributes()?;
+        let Some(full_name) = attrs.full_name.as_deref() else {
+            return Ok(None);
+        };
+        let Some(user) = member.related_id("user") else {
+            return Ok(None);
+        };
+
+        let tier_title = member
+            .related_ids("currently_entitled_tiers")
+            .unwrap_or_default()
+            .iter()
+            .find_map(|tier| {
+                self.tier_titles
+                    .get(&tier.id)
+                    .map(|title| title.to_string())
+            });
+        let thumb_url = match self.resolver.resolve(user, "user") {
+            Ok(user) => user.attributes::<UserAttributes>()?.thumb_url,
+            Err(_) => None,
+        };
+
+        Ok(Some(PatreonProfile {
+            id: PatreonUserId::new(user.id.clone()),
+            tier: tier_title,
+            full_name: full_name.trim().to_string(),
+            avatar_url: thumb_url,
+            email: None,
+            entitled_cents: attrs.currently_entitled_amount_cents,
+        }))
+    }
+}
+
+/// Merges the sponsors of several campaigns, keeping one entry per Patreon user.
+/// The first campaign a user appears in wins, unless it didn't give them a tier
+/// and a later one does.
+fn merge_sponsors(per_campaign: Vec<Vec<PatreonProfile>>) -> Vec<PatreonProfile> {
+    let mut merged: Vec<PatreonProfile> = Vec::new();
+    let mut index_by_id: HashMap<PatreonUserId, usize> = HashMap::new();
+
+    for sponsor in per_campaign.into_iter().flatten() {
+        match index_by_id.get(&sponsor.id) {
+            Some(&index) => {
+                let existing = &mut merged[index];
+                if existing.tier.is_none() && sponsor.tier.is_some() {
+                    *existing = sponsor;
+                }
+            }
+            None => {
+                index_by_id.insert(sponsor.id.clone(), merged.len());
+                merged.push(sponsor);
+            }
+        }
+    }
+
+    merged
 }
 
 impl ModImpl {
+    fn new(opts: ModOptions) -> Self {
+        Self {
+            identity_cache: ProfileCache::new(opts.profile_cache_ttl, PROFILE_CACHE_CAPACITY),
+        }
+    }
+
     fn make_patreon_callback_url(&self, tc: &TenantConfig, web: WebConfig) -> String {
         let base_url = tc.web_base_url(web);
         let url = format!("{base_url}/login/patreon/callback");
@@ -477,6 +757,38 @@ impl ModImpl {
     }
 }
 
+/// Parses the response to our token request, and checks Patreon granted
+/// all of [`PATREON_SCOPES`]
+fn grant_from_token_response(
+    text: &str,
+    strict_scopes: bool,
+) -> Result<OAuthGrant<PatreonCredentials>> {
+    let creds_api = match facet_json::from_str::<PatreonCredentialsAPI>(text) {
+        Ok(c) => c,
+        Err(e) => {
+            log::warn!("Got Patreon auth error: {text}");
+            return Err(eyre::eyre!("Got Patreon auth error: {e}"));
+        }
+    };
+    log::info!(
+        "Successfully obtained Patreon token with scope {}",
+        &creds_api.scope
+    );
+
+    let scope_downgrade =
+        ScopeDowngrade::check("patreon", PATREON_SCOPES, &creds_api.scope, strict_scopes)?;
+
+    Ok(OAuthGrant {
+        creds: PatreonCredentials {
+            access_token: creds_api.access_token,
+            refresh_token: creds_api.refresh_token,
+            expires_at: OffsetDateTime::now_utc()
+                + time::Duration::seconds(creds_api.expires_in as i64),
+        },
+        scope_downgrade,
+    })
+}
+
 /// Patreon credentials as returned by the Patreon API
 #[derive(Debug, Clone, Facet)]
 struct PatreonCredentialsAPI {
@@ -498,11 +810,9 @@ pub struct PatreonCredentials {
     pub expires_at: OffsetDateTime,
 }
 
-impl PatreonCredentials {
-    pub fn expire_soon(&self) -> bool {
-        let now = OffsetDateTime::now_utc();
-        let twenty_four_hours = time::Duration::hours(1);
-        self.expires_at - now < twenty_four_hours
+impl Credentials for PatreonCredentials {
+    fn expires_at(&self) -> OffsetDateTime {
+        self.expires_at
     }
 }
 
@@ -517,6 +827,11 @@ pub struct PatreonCallbackArgs {
     /// if we're linking this patreon account to an existing UserID, this is set
     #[facet(default)]
     pub logged_in_user_id: Option<UserId>,
+
+    /// fail the login if Patreon granted fewer scopes than we asked for,
+    /// instead of just reporting it
+    #[facet(default)]
+    pub strict_scopes: bool,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -528,3 +843,561 @@ pub struct PatreonRefreshCredentialsArgs {
 pub struct PatreonUnlinkArgs {
     pub logged_in_user_id: UserId,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libhttpclient::{
+        error_from_body,
+        test_server::{Reply, TestServer},
+    };
+
+    #[test]
+    fn test_token_response_missing_a_scope() {
+        let response = |scope: &str| {
+            serde_json::json!({
+                "access_token": "access",
+                "refresh_token": "refresh",
+                "expires_in": 2678400,
+                "scope": scope,
+                "token_type": "Bearer",
+            })
+            .to_string()
+        };
+
+        let grant =
+            grant_from_token_response(&response("identity identity.memberships"), true).unwrap();
+        assert!(grant.scope_downgrade.is_none());
+
+        // the patron declined sharing their memberships
+        let grant = grant_from_token_response(&response("identity"), false).unwrap();
+        assert_eq!(grant.creds.refresh_token, "refresh");
+        let downgrade = grant.scope_downgrade.unwrap();
+        assert_eq!(downgrade.provider, "patreon");
+        assert_eq!(downgrade.missing, vec!["identity.memberships".to_string()]);
+
+        assert!(grant_from_token_response(&response("identity"), true).is_err());
+    }
+
+    /// A page of members, as `(user id, full name, tier title)`
+    fn members_page(members: &[(&str, &str, Option<&str>)], next: Option<&str>) -> String {
+        let data: Vec<_> = members
+            .iter()
+            .map(|(id, name, tier)| {
+                let tiers: Vec<_> = tier
+                    .iter()
+                    .map(|t| serde_json::json!({ "type": "tier", "id": format!("tier-{t}") }))
+                    .collect();
+                serde_json::json!({
+                    "type": "member",
+                    "id": format!("member-{id}"),
+                    "attributes": { "full_name": name },
+                    "relationships": {
+                        "currently_entitled_tiers": { "data": tiers },
+                        "user": { "data": { "id": id } },
+                    },
+                })
+            })
+            .collect();
+        let included: Vec<_> = members
+            .iter()
+            .flat_map(|(id, _, tier)| {
+                let user = serde_json::json!({
+                    "type": "user",
+                    "id": id,
+                    "attributes": { "thumb_url": format!("https://example.org/{id}.png") },
+                });
+                let tier = tier.map(|t| {
+                    serde_json::json!({
+                        "type": "tier",
+                        "id": format!("tier-{t}"),
+                        "attributes": { "title": t },
+                    })
+                });
+                std::iter::once(user).chain(tier)
+            })
+            .collect();
+        serde_json::json!({
+            "data": data,
+            "included": included,
+            "links": next.map(|next| serde_json::json!({ "next": next })),
+        })
+        .to_string()
+    }
+
+    /// Hands out canned pages, and records what was fetched and how long we slept
+    #[derive(Default)]
+    struct ScriptedSource {
+        pages: std::collections::VecDeque<RawPage>,
+        fetched: Vec<String>,
+        slept: Vec<std::time::Duration>,
+    }
+
+    impl ScriptedSource {
+        fn push(&mut self, status: StatusCode, retry_after: Option<u64>, body: String) {
+            let body = if status.is_success() {
+                Ok(body)
+            } else {
+                Err(error_from_body(status, body.as_bytes()))
+            };
+            self.pages.push_back(RawPage {
+                status,
+                retry_after: retry_after.map(std::time::Duration::from_secs),
+                body,
+            });
+        }
+    }
+
+    impl MembersSource for ScriptedSource {
+        fn fetch<'a>(&'a mut self, uri: &'a Uri) -> BoxFuture<'a, Result<RawPage>> {
+            self.fetched.push(uri.to_string());
+            let page = self.pages.pop_front();
+            Box::pin(async move { page.ok_or_else(|| eyre::eyre!("script ran out of pages")) })
+        }
+
+        fn sleep(&mut self, duration: std::time::Duration) -> BoxFuture<'_, ()> {
+            self.slept.push(duration);
+            Box::pin(async {})
+        }
+    }
+
+    const PAGE_1: &str =
+        "https://www.patreon.com/api/oauth2/v2/campaigns/a/members?page%5Bcursor%5D=1";
+    const PAGE_2: &str =
+        "https://www.patreon.com/api/oauth2/v2/campaigns/a/members?page%5Bcursor%5D=2";
+
+    #[tokio::test]
+    async fn test_retries_after_429() {
+        let mut source = ScriptedSource::default();
+        source.push(StatusCode::TOO_MANY_REQUESTS, Some(3), "slow down".into());
+        source.push(StatusCode::TOO_MANY_REQUESTS, None, "slow down".into());
+        source.push(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(3600),
+            "slow down".into(),
+        );
+        source.push(
+            StatusCode::OK,
+            None,
+            members_page(&[("1", "Alice", Some("Gold"))], Some(PAGE_2)),
+        );
+        source.push(
+            StatusCode::OK,
+            None,
+            members_page(&[("2", "Bob", None)], None),
+        );
+
+        let sponsors = paginate_members(
+            &mut source,
+            "a",
+            PAGE_1.parse().unwrap(),
+            &mut TierTitles::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sponsors.len(), 2);
+        assert_eq!(source.fetched, vec![PAGE_1, PAGE_1, PAGE_1, PAGE_1, PAGE_2]);
+        // Retry-After wins, then our own backoff, and we cap absurd waits
+        assert_eq!(
+            source.slept,
+            vec![
+                std::time::Duration::from_secs(3),
+                std::time::Duration::from_secs(4),
+                MAX_RATE_LIMIT_BACKOFF,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_too_many_429s() {
+        let mut source = ScriptedSource::default();
+        for _ in 0..=MAX_RATE_LIMIT_RETRIES {
+            source.push(StatusCode::TOO_MANY_REQUESTS, Some(1), "slow down".into());
+        }
+
+        let res = paginate_members(
+            &mut source,
+            "a",
+            PAGE_1.parse().unwrap(),
+            &mut TierTitles::default(),
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(source.slept.len(), MAX_RATE_LIMIT_RETRIES as usize);
+    }
+
+    #[tokio::test]
+    async fn test_self_referential_next_link() {
+        let mut source = ScriptedSource::default();
+        source.push(
+            StatusCode::OK,
+            None,
+            members_page(&[("1", "Alice", Some("Gold"))], Some(PAGE_2)),
+        );
+        source.push(
+            StatusCode::OK,
+            None,
+            members_page(&[("2", "Bob", None)], Some(PAGE_2)),
+        );
+
+        // we stop, but keep the sponsors we got
+        let sponsors = paginate_members(
+            &mut source,
+            "a",
+            PAGE_1.parse().unwrap(),
+            &mut TierTitles::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sponsors.len(), 2);
+        assert_eq!(source.fetched, vec![PAGE_1, PAGE_2]);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_page_is_refused() {
+        let page = members_page(&[("1", "Alice", Some("Gold"))], None);
+        let page_len = page.len();
+        let server = TestServer::always(Reply::new("200 OK").body(page)).await;
+
+        let tc = TenantConfig::new(config_types::TenantDomain::new("example.org".to_string()));
+        let client = libhttpclient::load().client();
+        let uri = server.uri("/api/oauth2/v2/campaigns/a/members");
+        let source = |max_page_size| PatreonMembersSource {
+            tc: &tc,
+            client: client.as_ref(),
+            access_token: "access",
+            max_page_size,
+        };
+
+        let sponsors = paginate_members(
+            &mut source(page_len),
+            "a",
+            uri.clone(),
+            &mut TierTitles::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sponsors.len(), 1);
+
+        let err = paginate_members(
+            &mut source(page_len - 1),
+            "a",
+            uri,
+            &mut TierTitles::default(),
+        )
+        .await
+        .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("reading Patreon members page"), "{err}");
+        assert!(
+            err.contains(&format!("over the {} bytes limit", page_len - 1)),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(
+            parse_retry_after("120"),
+            Some(std::time::Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after(" 5 "),
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[tokio::test]
+    async fn test_tier_titles_shared_across_pages() {
+        const TIERS: [&str; 5] = ["Bronze", "Silver", "Gold", "Platinum", "Diamond"];
+        const PAGES: usize = 40;
+
+        // 50 members per page, each page linking to the next
+        let server = TestServer::start(|req| {
+            let page: usize = req
+                .target()
+                .rsplit_once("page=")
+                .map_or(0, |(_, page)| page.parse().unwrap());
+            let ids: Vec<_> = (0..50).map(|i| (page * 50 + i).to_string()).collect();
+            let members: Vec<_> = ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.as_str(), "Someone", Some(TIERS[i % TIERS.len()])))
+                .collect();
+            let next = (page + 1 < PAGES).then(|| {
+                let host = req.header("host").unwrap();
+                format!("http://{host}/members?page={}", page + 1)
+            });
+            Reply::new("200 OK").body(members_page(&members, next.as_deref()))
+        })
+        .await;
+
+        let tc = TenantConfig::new(config_types::TenantDomain::new("example.org".to_string()));
+        let client = libhttpclient::load().client();
+        let mut source = PatreonMembersSource {
+            tc: &tc,
+            client: client.as_ref(),
+            access_token: "access",
+            max_page_size: usize::MAX,
+        };
+        let mut tier_titles = TierTitles::default();
+        let sponsors = paginate_members(&mut source, "a", server.uri("/members"), &mut tier_titles)
+            .await
+            .unwrap();
+
+        assert_eq!(sponsors.len(), PAGES * 50);
+        assert!(sponsors.iter().all(|s| s.tier.is_some()));
+        assert_eq!(sponsors[7].tier.as_deref(), Some("Gold"));
+        // one request per page, nothing more
+        let targets: Vec<_> = server
+            .requests()
+            .iter()
+            .map(|req| req.target().to_string())
+            .collect();
+        let expected: Vec<_> = std::iter::once("/members".to_string())
+            .chain((1..PAGES).map(|page| format!("/members?page={page}")))
+            .collect();
+        assert_eq!(targets, expected);
+        // 2000 tier references, but only 5 distinct tiers
+        assert_eq!(tier_titles.deserialized, TIERS.len());
+    }
+
+    #[test]
+    fn test_tier_titles_from_jsonapi_resources() {
+        let doc = Document::parse(
+            br#"{
+                "data": { "type": "user", "id": "1", "attributes": {} },
+                "included": [
+                    { "type": "tier", "id": "10", "attributes": { "title": "Gold" } },
+                    { "type": "tier", "id": "11", "attributes": { "title": "Silver" } }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mut tier_titles = TierTitles::default();
+        for _ in 0..100 {
+            for tier in &doc.included {
+                tier_titles.title_of(tier).unwrap();
+            }
+        }
+        assert_eq!(tier_titles.deserialized, 2);
+        assert_eq!(tier_titles.get("10"), Some("Gold"));
+        assert_eq!(tier_titles.get("11"), Some("Silver"));
+    }
+
+    fn with_fixture(data: &str) -> String {
+        format!(
+            r#"{{ "data": {data}, "included": [{}] }}"#,
+            jsonapi_doc::FIXTURE_INCLUDED
+        )
+    }
+
+    #[test]
+    fn test_profile_from_identity_fixture() {
+        let identity = with_fixture(
+            r#"{
+                "type": "user",
+                "id": "42",
+                "attributes": {
+                    "full_name": "Alice Example",
+                    "thumb_url": "https://example.org/42.png",
+                    "email": "alice@example.org"
+                },
+                "relationships": {
+                    "memberships": { "data": [
+                        { "type": "member", "id": "member-elsewhere" },
+                        { "type": "member", "id": "member-42" }
+                    ] }
+                }
+            }"#,
+        );
+
+        let rc = RevisionConfig {
+            patreon_campaign_ids: vec!["12345".to_string()],
+            ..Default::default()
+        };
+        let mut tier_titles = TierTitles::default();
+        let profile = profile_from_identity(&identity, &rc, &mut tier_titles).unwrap();
+        assert_eq!(profile.id.as_str(), "42");
+        // the membership of the other campaign doesn't count
+        assert_eq!(profile.tier.as_deref(), Some("Silver"));
+        assert_eq!(profile.email.as_deref(), Some("alice@example.org"));
+        assert_eq!(tier_titles.deserialized, 1);
+
+        // not one of our campaigns, no tier
+        let profile =
+            profile_from_identity(&identity, &RevisionConfig::default(), &mut tier_titles).unwrap();
+        assert_eq!(profile.tier, None);
+
+        assert!(
+            profile_from_identity(&with_fixture("[]"), &rc, &mut TierTitles::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_members_page_fixture() {
+        let page = with_fixture(
+            r#"[
+                {
+                    "type": "member",
+                    "id": "member-42",
+                    "attributes": { "full_name": "Alice Example ", "currently_entitled_amount_cents": 1000 },
+                    "relationships": {
+                        "currently_entitled_tiers": { "data": [{ "type": "tier", "id": "777" }] },
+                        "user": { "data": { "type": "user", "id": "42" } }
+                    }
+                },
+                {
+                    "type": "member",
+                    "id": "member-43",
+                    "attributes": { "full_name": "Bob" },
+                    "relationships": {
+                        "currently_entitled_tiers": { "data": [] },
+                        "user": { "data": { "id": "43" } }
+                    }
+                },
+                {
+                    "type": "member",
+                    "id": "member-anonymous",
+                    "attributes": { "full_name": null },
+                    "relationships": {
+                        "user": { "data": { "type": "user", "id": "44" } }
+                    }
+                }
+            ]"#,
+        );
+
+        let mut tier_titles = TierTitles::default();
+        let (members, next) = parse_members_page(&page, &mut tier_titles).unwrap();
+        assert!(next.is_none());
+        let summary: Vec<_> = members
+            .iter()
+            .map(|p| {
+                (
+                    p.id.as_str(),
+                    p.full_name.as_str(),
+                    p.tier.as_deref(),
+                    p.avatar_url.as_deref(),
+                    p.entitled_cents,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "42",
+                    "Alice Example",
+                    Some("Silver"),
+                    Some("https://example.org/42.png"),
+                    Some(1000)
+                ),
+                ("43", "Bob", None, Some("https://example.org/43.png"), None),
+            ]
+        );
+        // every included tier, whether a member on this page has it or not
+        assert_eq!(tier_titles.deserialized, 2);
+    }
+
+    #[test]
+    fn test_two_campaigns_with_overlapping_sponsors() {
+        let mut tier_titles = TierTitles::default();
+        let (first, next) = parse_members_page(
+            &members_page(
+                &[("1", "Alice", Some("Gold")), ("2", " Bob ", None)],
+                Some("https://www.patreon.com/api/oauth2/v2/campaigns/a/members?page[cursor]=x"),
+            ),
+            &mut tier_titles,
+        )
+        .unwrap();
+        assert!(next.is_some());
+        assert_eq!(first[1].full_name, "Bob");
+        assert_eq!(
+            first[0].avatar_url.as_deref(),
+            Some("https://example.org/1.png")
+        );
+
+        let (second, next) = parse_members_page(
+            &members_page(
+                &[
+                    ("1", "Alice", Some("Silver")),
+                    ("2", "Bob", Some("Bronze")),
+                    ("3", "Carol", None),
+                ],
+                None,
+            ),
+            &mut tier_titles,
+        )
+        .unwrap();
+        assert!(next.is_none());
+
+        let merged = merge_sponsors(vec![first, second]);
+        let summary: Vec<_> = merged
+            .iter()
+            .map(|p| (p.id.as_str(), p.full_name.as_str(), p.tier.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("1", "Alice", Some("Gold")),
+                ("2", "Bob", Some("Bronze")),
+                ("3", "Carol", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_members_page_with_entitled_amounts() {
+        let page = r#"{
+            "data": [
+                {
+                    "type": "member",
+                    "id": "member-1",
+                    "attributes": { "full_name": "Alice", "currently_entitled_amount_cents": 1500 },
+                    "relationships": {
+                        "currently_entitled_tiers": { "data": [{ "type": "tier", "id": "tier-gold" }] },
+                        "user": { "data": { "id": "1" } }
+                    }
+                },
+                {
+                    "type": "member",
+                    "id": "member-2",
+                    "attributes": { "full_name": "Bob", "currently_entitled_amount_cents": null },
+                    "relationships": {
+                        "currently_entitled_tiers": { "data": [] },
+                        "user": { "data": { "id": "2" } }
+                    }
+                },
+                {
+                    "type": "member",
+                    "id": "member-3",
+                    "attributes": { "full_name": "Carol" },
+                    "relationships": {
+                        "currently_entitled_tiers": { "data": [] },
+                        "user": { "data": { "id": "3" } }
+                    }
+                }
+            ],
+            "included": [
+                { "type": "tier", "id": "tier-gold", "attributes": { "title": "Gold" } }
+            ]
+        }"#;
+
+        let (members, next) = parse_members_page(page, &mut TierTitles::default()).unwrap();
+        assert!(next.is_none());
+        let summary: Vec<_> = members
+            .iter()
+            .map(|p| (p.id.as_str(), p.tier.as_deref(), p.entitled_cents))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("1", Some("Gold"), Some(1500)),
+                ("2", None, None),
+                ("3", None, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use credentials::PatreonProfile;
+use eyre::Result;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+use super::{Document, IncludedItems, MemberAttributes, TierTitles};
+
+/// Name of the header Patreon puts the webhook signature in
+pub const PATREON_SIGNATURE_HEADER: &str = "X-Patreon-Signature";
+
+/// Name of the header Patreon puts the webhook trigger in
+pub const PATREON_EVENT_HEADER: &str = "X-Patreon-Event";
+
+/// Checks that `body` was signed by Patreon: `signature` is the hex-encoded
+/// HMAC-MD5 of the raw body, keyed with the webhook secret.
+pub fn verify_webhook(secret: &str, signature: &str, body: &[u8]) -> Result<()> {
+    let signature = hex::decode(signature.trim())
+        .map_err(|e| eyre::eyre!("Patreon webhook signature is not valid hex: {e}"))?;
+
+    let mut mac =
+        Hmac::<Md5>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| eyre::eyre!("Patreon webhook signature mismatch"))
+}
+
+/// What happened to a pledge, from the `X-Patreon-Event` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatreonWebhookTrigger {
+    /// `members:pledge:create`
+    Create,
+    /// `members:pledge:update`
+    Update,
+    /// `members:pledge:delete`
+    Delete,
+}
+
+impl PatreonWebhookTrigger {
+    pub fn parse(trigger: &str) -> Result<Self> {
+        match trigger.trim() {
+            "members:pledge:create" => Ok(Self::Create),
+            "members:pledge:update" => Ok(Self::Update),
+            "members:pledge:delete" => Ok(Self::Delete),
+            other => Err(eyre::eyre!("Unsupported Patreon webhook trigger: {other}")),
+        }
+    }
+}
+
+/// A membership change, as delivered by a Patreon webhook
+#[derive(Debug, Clone)]
+pub struct PatreonWebhookEvent {
+    pub trigger: PatreonWebhookTrigger,
+
+    /// The campaign the member belongs to, if Patreon told us
+    pub campaign_id: Option<String>,
+
+    /// The member's profile, with the tier they're currently entitled to
+    pub profile: PatreonProfile,
+
+    /// `active_patron`, `declined_patron`, `former_patron`, or None
+    pub patron_status: Option<String>,
+}
+
+impl PatreonWebhookEvent {
+    /// Whether this member should currently get sponsor perks
+    pub fn is_active(&self) -> bool {
+        self.trigger != PatreonWebhookTrigger::Delete
+            && self.patron_status.as_deref() == Some("active_patron")
+    }
+}
+
+/// Parses a webhook body, given the value of its `X-Patreon-Event` header.
+/// Only call this after [`verify_webhook`].
+pub fn parse_webhook_event(trigger: &str, body: &[u8]) -> Result<PatreonWebhookEvent> {
+    let trigger = PatreonWebhookTrigger::parse(trigger)?;
+    let doc = Document::parse(body)?;
+
+    let member = doc.single()?;
+    if member.kind != "member" {
+        return Err(eyre::eyre!("Patreon webhook payload is not about a member"));
+    }
+    let mut tier_titles = TierTitles::default();
+    let profile = IncludedItems::new(&doc.included, &mut tier_titles)?
+        .member_profile(member)?
+        .ok_or_else(|| eyre::eyre!("Patreon webhook member has no name or user"))?;
+    let attrs: MemberAttributes = member.attributes()?;
+
+    Ok(PatreonWebhookEvent {
+        trigger,
+        campaign_id: member.related_id("campaign").map(|c| c.id.clone()),
+        profile,
+        patron_status: attrs.patron_status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_PLEDGE: &str = r#"{
+        "data": {
+            "type": "member",
+            "id": "0c5e4a3e-1f2b-4c3d-9e8f-123456789abc",
+            "attributes": {
+                "full_name": "Alice Example ",
+                "patron_status": "active_patron",
+                "currently_entitled_amount_cents": 1000
+            },
+            "relationships": {
+                "campaign": { "data": { "type": "campaign", "id": "12345" } },
+                "currently_entitled_tiers": { "data": [{ "type": "tier", "id": "777" }] },
+                "user": { "data": { "type": "user", "id": "42" } }
+            }
+        },
+        "included": [
+            { "type": "campaign", "id": "12345", "attributes": { "vanity": "fasterthanlime" } },
+            { "type": "tier", "id": "777", "attributes": { "title": "Silver" } },
+            { "type": "user", "id": "42", "attributes": { "thumb_url": "https://example.org/42.png" } }
+        ]
+    }"#;
+
+    #[test]
+    fn test_verify_webhook_known_signature() {
+        // RFC 2104-style test vector for HMAC-MD5
+        let body = b"The quick brown fox jumps over the lazy dog";
+        let signature = "80070713463e7749b90c2dc24911e275";
+        verify_webhook("key", signature, body).unwrap();
+        verify_webhook("key", &signature.to_uppercase(), body).unwrap();
+
+        assert!(verify_webhook("not-the-key", signature, body).is_err());
+        assert!(verify_webhook("key", signature, b"The quick brown fox").is_err());
+        assert!(verify_webhook("key", "80070713463e7749", body).is_err());
+        assert!(verify_webhook("key", "not hex at all", body).is_err());
+    }
+
+    #[test]
+    fn test_parse_sample_pledge_event() {
+        let event = parse_webhook_event("members:pledge:create", SAMPLE_PLEDGE.as_bytes()).unwrap();
+        assert_eq!(event.trigger, PatreonWebhookTrigger::Create);
+        assert_eq!(event.campaign_id.as_deref(), Some("12345"));
+        assert_eq!(event.profile.id.as_str(), "42");
+        assert_eq!(event.profile.full_name, "Alice Example");
+        assert_eq!(event.profile.tier.as_deref(), Some("Silver"));
+        assert_eq!(
+            event.profile.avatar_url.as_deref(),
+            Some("https://example.org/42.png")
+        );
+        assert!(event.is_active());
+
+        let event = parse_webhook_event("members:pledge:delete", SAMPLE_PLEDGE.as_bytes()).unwrap();
+        assert!(!event.is_active());
+
+        assert!(parse_webhook_event("posts:publish", SAMPLE_PLEDGE.as_bytes()).is_err());
+        assert!(parse_webhook_event("members:pledge:create", b"{}").is_err());
+    }
+}
@@ -214,6 +214,8 @@ pub async fn load_pak(
                 for (dst_container, dst_vc, dst_ac) in [
                     // AV1 needs to be first to be the default
                     (VContainer::MP4, VCodec::AV1, ACodec::Opus),
+                    // before AVC: browsers that can play HEVC get the smaller file
+                    (VContainer::MP4, VCodec::HEVC, ACodec::Aac),
                     (VContainer::MP4, VCodec::AVC, ACodec::Aac),
                     (VContainer::WebM, VCodec::VP9, ACodec::Opus),
                 ] {
@@ -370,9 +372,14 @@ pub async fn load_pak(
     let before_calculate_deps = Instant::now();
     for path in input_pages {
         let page = rev.pak.pages.get(path).unwrap();
-        let route_path = path.to_route_path();
-        rev.page_routes
-            .insert(route_path.to_owned(), page.path.clone());
+        let route_path = match path.to_route_path().canonicalize() {
+            Ok(route_path) => route_path,
+            Err(e) => {
+                warn!("Skipping page {path}: invalid route: {e}");
+                continue;
+            }
+        };
+        rev.page_routes.insert(route_path, page.path.clone());
 
         let prev_page = {
             if let Some(prev) = prev_rev {
@@ -511,7 +518,14 @@ pub async fn load_pak(
     let aliases_start = Instant::now();
     for page in rev.pages.values() {
         for alias in &page.aliases {
-            rev.page_routes.insert(alias.clone(), page.path.clone());
+            match alias.canonicalize() {
+                Ok(alias) => {
+                    rev.page_routes.insert(alias, page.path.clone());
+                }
+                Err(e) => {
+                    warn!("Ignoring alias {alias:?} of page {}: {e}", page.path);
+                }
+            }
         }
     }
     log::debug!(
@@ -598,7 +612,9 @@ pub async fn load_pak(
     {
         let all_input_paths = rev.pages.keys().cloned().collect::<Vec<_>>();
         for page_path in all_input_paths {
-            let page_route = page_path.to_route_path();
+            let Ok(page_route) = page_path.to_route_path().canonicalize() else {
+                continue;
+            };
             if let Some(parent_route) = page_route.parent() {
                 if let Some(parent_path) = rev.page_routes.get(parent_route) {
                     // Arc::get_mut is _bad_
@@ -0,0 +1,233 @@
+use autotrait::autotrait;
+use config_types::TenantConfig;
+use eyre::Result;
+use facet::Facet;
+use futures_core::future::BoxFuture;
+use libhttpclient::{
+    HeaderName, HeaderValue, HttpClient, RetryPolicy, Uri, decode_error_response, form_urlencoded,
+};
+
+struct ModImpl;
+
+pub fn load() -> &'static dyn Mod {
+    &ModImpl
+}
+
+#[autotrait]
+impl Mod for ModImpl {
+    /// Creates a hosted Checkout Session subscribing to `params.price_id`,
+    /// with the tenant's Stripe key, and returns the URL to send the
+    /// customer to.
+    fn create_checkout_session<'fut>(
+        &'fut self,
+        tc: &'fut TenantConfig,
+        client: &'fut dyn HttpClient,
+        params: &'fut CheckoutSessionParams,
+    ) -> BoxFuture<'fut, Result<String>> {
+        Box::pin(create_checkout_session(client, STRIPE_API_BASE, tc, params))
+    }
+}
+
+/// What a new subscriber signs up for, and where Stripe sends them after
+pub struct CheckoutSessionParams {
+    pub price_id: String,
+    /// Where Stripe sends the customer once they've paid
+    pub success_url: String,
+    /// Where Stripe sends the customer if they back out
+    pub cancel_url: String,
+    /// Prefills the email field on the checkout page
+    pub customer_email: Option<String>,
+}
+
+const STRIPE_API_BASE: &str = "https://api.stripe.com";
+
+async fn create_checkout_session(
+    client: &dyn HttpClient,
+    api_base: &str,
+    tc: &TenantConfig,
+    params: &CheckoutSessionParams,
+) -> Result<String> {
+    #[derive(Facet)]
+    struct CheckoutSession {
+        id: String,
+        /// Only missing for embedded checkouts, which we don't make
+        #[facet(default)]
+        url: Option<String>,
+    }
+
+    let stripe = tc.stripe_secrets()?;
+    let uri = Uri::try_from(format!("{api_base}/v1/checkout/sessions"))?;
+    // retries carry the same key, so Stripe answers them with the session it
+    // already created rather than making another one
+    let idempotency_key = format!("{:032x}", rand::random::<u128>());
+    let res = client
+        .post(uri)
+        .form(checkout_session_form(params))
+        .bearer_auth(&stripe.secret_key)
+        .header(
+            HeaderName::from_static("idempotency-key"),
+            HeaderValue::from_str(&idempotency_key)?,
+        )
+        .retry(RetryPolicy::QUICK)
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(decode_error_response(res).await);
+    }
+
+    let session = res.json::<CheckoutSession>().await?;
+    log::info!(
+        "Created Stripe checkout session {} for price {}",
+        session.id,
+        params.price_id
+    );
+    session
+        .url
+        .ok_or_else(|| eyre::eyre!("Stripe checkout session {} has no URL", session.id))
+}
+
+/// The form-encoded body of a `POST /v1/checkout/sessions`
+fn checkout_session_form(params: &CheckoutSessionParams) -> String {
+    let mut form = form_urlencoded::Serializer::new(String::new());
+    form.append_pair("mode", "subscription")
+        .append_pair("line_items[0][price]", &params.price_id)
+        .append_pair("line_items[0][quantity]", "1")
+        .append_pair("success_url", &params.success_url)
+        .append_pair("cancel_url", &params.cancel_url);
+    if let Some(email) = &params.customer_email {
+        form.append_pair("customer_email", email);
+    }
+    form.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config_types::{AwsSecrets, StripeSecrets, StripeTierMapping, TenantDomain, TenantSecrets};
+    use libhttpclient::test_server::{Reply, TestServer};
+
+    fn params(customer_email: Option<&str>) -> CheckoutSessionParams {
+        CheckoutSessionParams {
+            price_id: "price_123".to_string(),
+            success_url: "https://example.org/thanks?session={CHECKOUT_SESSION_ID}".to_string(),
+            cancel_url: "https://example.org/".to_string(),
+            customer_email: customer_email.map(String::from),
+        }
+    }
+
+    fn pairs(form: &str) -> Vec<(String, String)> {
+        form_urlencoded::parse(form.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    #[test]
+    fn test_checkout_session_form() {
+        let expected = [
+            ("mode", "subscription"),
+            ("line_items[0][price]", "price_123"),
+            ("line_items[0][quantity]", "1"),
+            (
+                "success_url",
+                "https://example.org/thanks?session={CHECKOUT_SESSION_ID}",
+            ),
+            ("cancel_url", "https://example.org/"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        assert_eq!(pairs(&checkout_session_form(&params(None))), expected);
+
+        let form = pairs(&checkout_session_form(&params(Some("amos@example.org"))));
+        assert_eq!(form[..expected.len()], expected);
+        assert_eq!(
+            form[expected.len()..],
+            [("customer_email".to_string(), "amos@example.org".to_string())]
+        );
+    }
+
+    fn tenant_config() -> TenantConfig {
+        let mut tc = TenantConfig::new(TenantDomain::new("example.org".to_string()));
+        tc.secrets = Some(TenantSecrets {
+            aws: AwsSecrets {
+                access_key_id: "AKIA".to_string(),
+                secret_access_key: "sekrit".to_string(),
+            },
+            patreon: None,
+            github: None,
+            discord: None,
+            stripe: Some(StripeSecrets {
+                secret_key: "sk_test_123".to_string(),
+                tier_mapping: StripeTierMapping {
+                    bronze_ids: vec![],
+                    silver_ids: vec![],
+                    gold_ids: vec![],
+                },
+            }),
+            git: None,
+            cookie_sauce: None,
+        });
+        tc
+    }
+
+    /// A fake Stripe API: answers with a checkout session
+    async fn fake_stripe() -> TestServer {
+        TestServer::always(
+            Reply::new("200 OK")
+                .header("content-type", "application/json")
+                .body(r#"{"id":"cs_test_a1","object":"checkout.session","mode":"subscription","status":"open","url":"https://checkout.stripe.com/c/pay/cs_test_a1"}"#),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_create_checkout_session() {
+        let server = fake_stripe().await;
+        let client = libhttpclient::load().client();
+        let params = params(Some("amos@example.org"));
+
+        let url = create_checkout_session(
+            client.as_ref(),
+            &server.base_url(),
+            &tenant_config(),
+            &params,
+        )
+        .await
+        .unwrap();
+        assert_eq!(url, "https://checkout.stripe.com/c/pay/cs_test_a1");
+
+        let requests = server.requests();
+        let [request] = &requests[..] else {
+            panic!("expected one request, got {requests:?}");
+        };
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.target(), "/v1/checkout/sessions");
+        assert_eq!(
+            request.header("authorization"),
+            Some("Bearer sk_test_123"),
+            "{}",
+            request.head
+        );
+        let idempotency_key = request.header("idempotency-key").unwrap();
+        assert_eq!(idempotency_key.len(), 32, "{}", request.head);
+        assert_eq!(
+            request.header("content-type"),
+            Some("application/x-www-form-urlencoded"),
+            "{}",
+            request.head
+        );
+        let form = String::from_utf8_lossy(&request.body);
+        assert_eq!(pairs(&form), pairs(&checkout_session_form(&params)));
+    }
+
+    #[tokio::test]
+    async fn test_create_checkout_session_needs_stripe_secrets() {
+        let client = libhttpclient::load().client();
+        let tc = TenantConfig::new(TenantDomain::new("example.org".to_string()));
+        let err =
+            create_checkout_session(client.as_ref(), "http://127.0.0.1:1", &tc, &params(None))
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("example.org"), "{err}");
+    }
+}
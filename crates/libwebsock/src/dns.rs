@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use eyre::eyre;
+use futures_core::future::BoxFuture;
+use parking_lot::Mutex;
+
+/// How long a resolved address is reused for
+pub const DNS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+static BYPASS: AtomicBool = AtomicBool::new(false);
+
+/// When set, every connection attempt resolves the host again, as if there
+/// was no cache. Meant for tests.
+pub fn set_dns_cache_bypass(bypass: bool) {
+    BYPASS.store(bypass, Ordering::Relaxed);
+}
+
+/// Turns a host into the (IPv4) address we'll connect to
+pub(crate) trait Resolver: Send + Sync {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, eyre::Result<IpAddr>>;
+}
+
+/// Asks the system resolver
+pub(crate) struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, eyre::Result<IpAddr>> {
+        Box::pin(async move {
+            let mut addrs = tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| eyre!("Failed to resolve host: {e}"))?
+                .filter_map(|sa| match sa {
+                    SocketAddr::V4(addr) => Some(IpAddr::V4(*addr.ip())),
+                    SocketAddr::V6(addr) => {
+                        // If it's ::1 (IPv6 localhost), return 127.0.0.1 (IPv4 localhost) instead.
+                        if addr.ip() == &Ipv6Addr::LOCALHOST {
+                            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+                        } else {
+                            None
+                        }
+                    }
+                });
+
+            addrs
+                .next()
+                .ok_or_else(|| eyre!("Failed to resolve host (no IPv4 addresses found)"))
+        })
+    }
+}
+
+/// Remembers what hosts resolved to for a little while, so that reconnect
+/// loops hammering the same host during an outage don't also hammer DNS.
+/// Failed lookups aren't remembered.
+pub(crate) struct DnsCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<(String, u16), (IpAddr, Instant)>>,
+}
+
+impl DnsCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    pub(crate) async fn resolve(
+        &self,
+        resolver: &dyn Resolver,
+        host: &str,
+        port: u16,
+    ) -> eyre::Result<IpAddr> {
+        if BYPASS.load(Ordering::Relaxed) {
+            return resolver.resolve(host, port).await;
+        }
+
+        let key = (host.to_string(), port);
+        if let Some(ip) = self.get(&key, Instant::now()) {
+            log::debug!("Using cached address {ip} for {host}:{port}");
+            return Ok(ip);
+        }
+
+        let ip = resolver.resolve(host, port).await?;
+        self.entries.lock().insert(key, (ip, Instant::now()));
+        Ok(ip)
+    }
+
+    fn get(&self, key: &(String, u16), now: Instant) -> Option<IpAddr> {
+        let mut entries = self.entries.lock();
+        let (ip, resolved_at) = *entries.get(key)?;
+        if now.saturating_duration_since(resolved_at) >= self.ttl {
+            entries.remove(key);
+            return None;
+        }
+        Some(ip)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, atomic::AtomicUsize};
+
+    /// Resolves everything to localhost, and counts how many times it did
+    #[derive(Clone, Default)]
+    pub(crate) struct CountingResolver(pub(crate) Arc<AtomicUsize>);
+
+    impl CountingResolver {
+        pub(crate) fn count(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Resolver for CountingResolver {
+        fn resolve<'a>(
+            &'a self,
+            _host: &'a str,
+            _port: u16,
+        ) -> BoxFuture<'a, eyre::Result<IpAddr>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() {
+        let resolver = CountingResolver::default();
+        let cache = DnsCache::new(Duration::from_secs(30));
+
+        cache.resolve(&resolver, "mom.local", 1118).await.unwrap();
+        cache.resolve(&resolver, "mom.local", 1118).await.unwrap();
+        assert_eq!(resolver.count(), 1);
+
+        // other ports are other entries
+        cache.resolve(&resolver, "mom.local", 443).await.unwrap();
+        assert_eq!(resolver.count(), 2);
+
+        let key = ("mom.local".to_string(), 1118);
+        let later = Instant::now() + Duration::from_secs(31);
+        assert_eq!(cache.get(&key, later), None);
+        // ...and the stale entry is gone
+        assert_eq!(cache.get(&key, Instant::now()), None);
+        cache.resolve(&resolver, "mom.local", 1118).await.unwrap();
+        assert_eq!(resolver.count(), 3);
+    }
+}
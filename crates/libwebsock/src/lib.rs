@@ -1,8 +1,11 @@
 use autotrait::autotrait;
 use eyre::eyre;
 use libhttpclient::Bytes;
-use std::net::IpAddr;
-pub use tokio_tungstenite::tungstenite::{Message, protocol::frame::CloseFrame};
+use std::{net::IpAddr, sync::OnceLock};
+pub use tokio_tungstenite::tungstenite::{
+    Message,
+    protocol::frame::{CloseFrame, coding::CloseCode},
+};
 
 use http::{HeaderMap, Uri};
 use rubicon as _;
@@ -11,11 +14,25 @@ use rustls as _;
 
 use futures_core::future::BoxFuture;
 
-struct ModImpl;
+mod reconnect;
+pub use reconnect::{ConnectFn, ConnectionState, ReconnectingStream, StateCallback, reconnect};
+
+mod dns;
+pub use dns::{DNS_CACHE_TTL, set_dns_cache_bypass};
+use dns::{DnsCache, Resolver, SystemResolver};
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_ws;
+
+struct ModImpl {
+    dns: DnsCache,
+}
 
 pub fn load() -> &'static dyn Mod {
-    static MOD: ModImpl = ModImpl;
-    &MOD
+    static MOD: OnceLock<ModImpl> = OnceLock::new();
+    MOD.get_or_init(|| ModImpl {
+        dns: DnsCache::new(DNS_CACHE_TTL),
+    })
 }
 
 #[autotrait]
@@ -25,89 +42,79 @@ impl Mod for ModImpl {
         uri: Uri,
         headers: HeaderMap,
     ) -> BoxFuture<'_, eyre::Result<Box<dyn WebSocketStream>>> {
-        Box::pin(async move {
-            use std::time::Instant;
-
-            let mut request = uri.clone().into_client_request()?;
-            request.headers_mut().extend(headers);
-
-            let host = uri.host().ok_or_else(|| eyre!("Missing host"))?;
-            let scheme = uri.scheme_str().ok_or_else(|| eyre!("Missing scheme"))?;
-            let port = uri
-                .port_u16()
-                .unwrap_or(if scheme == "wss" || scheme == "https" {
-                    443
-                } else {
-                    80
-                });
-            let host_and_port = format!("{host}:{port}");
-            log::debug!("Resolving {host_and_port}");
-
-            let before_dns = Instant::now();
-            let ip: IpAddr = if let Ok(ipv4) = host.parse::<std::net::Ipv4Addr>() {
-                ipv4.into()
-            } else if let Ok(_ipv6) = host.parse::<std::net::Ipv6Addr>() {
-                // If a literal IPv6 address was given, skip it (since we only want IPv4)
-                return Err(eyre!("IPv6 addresses not supported, only IPv4"));
-            } else {
-                let mut addrs = tokio::net::lookup_host((host, port))
-                    .await
-                    .map_err(|e| eyre!("Failed to resolve host: {e}"))?
-                    .filter_map(|sa| match sa {
-                        std::net::SocketAddr::V4(addr) => Some(IpAddr::V4(*addr.ip())),
-                        std::net::SocketAddr::V6(addr) => {
-                            // If it's ::1 (IPv6 localhost), return 127.0.0.1 (IPv4 localhost) instead.
-                            if addr.ip() == &std::net::Ipv6Addr::LOCALHOST {
-                                Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST))
-                            } else {
-                                None
-                            }
-                        }
-                    });
-
-                addrs
-                    .next()
-                    .ok_or_else(|| eyre!("Failed to resolve host (no IPv4 addresses found)"))?
-            };
-            let dns_elapsed = before_dns.elapsed();
-
-            log::debug!("Resolved {host_and_port} to {ip} in {dns_elapsed:?}");
-
-            log::debug!("Connecting to {ip}:{port}...");
-            let before_tcp = Instant::now();
-            let stream = tokio::net::TcpStream::connect((ip, port))
-                .await
-                .map_err(|e| eyre!("Failed to establish TCP connection: {e}"))?;
-            let tcp_elapsed = before_tcp.elapsed();
-
-            stream
-                .set_nodelay(true)
-                .map_err(|e| eyre!("Failed to set TCP_NODELAY: {e}"))?;
-
-            log::debug!("TCP connection established in {tcp_elapsed:?}");
-            log::debug!("Doing websocket handshake...");
-
-            let before_handshake = Instant::now();
-            let (ws_stream, _) = tokio_tungstenite::client_async_tls_with_config(
-                request,
-                stream,
-                Some(WebSocketConfig::default()),
-                None,
-            )
-            .await
-            .map_err(|e| {
-                log::warn!("WebSocket handshake failed: {e}");
-                eyre!("Failed to complete WebSocket handshake: {e}")
-            })?;
-            let handshake_elapsed = before_handshake.elapsed();
-
-            log::debug!("WebSocket handshake completed in {handshake_elapsed:?}");
-
-            Ok(Box::new(WebSocketStreamImpl::new(ws_stream)) as Box<dyn WebSocketStream>)
-        })
+        Box::pin(connect(uri, headers, &self.dns, &SystemResolver))
     }
 }
 
+async fn connect(
+    uri: Uri,
+    headers: HeaderMap,
+    dns: &DnsCache,
+    resolver: &dyn Resolver,
+) -> eyre::Result<Box<dyn WebSocketStream>> {
+    use std::time::Instant;
+
+    let mut request = uri.clone().into_client_request()?;
+    request.headers_mut().extend(headers);
+
+    let host = uri.host().ok_or_else(|| eyre!("Missing host"))?;
+    let scheme = uri.scheme_str().ok_or_else(|| eyre!("Missing scheme"))?;
+    let port = uri
+        .port_u16()
+        .unwrap_or(if scheme == "wss" || scheme == "https" {
+            443
+        } else {
+            80
+        });
+    let host_and_port = format!("{host}:{port}");
+    log::debug!("Resolving {host_and_port}");
+
+    let before_dns = Instant::now();
+    let ip: IpAddr = if let Ok(ipv4) = host.parse::<std::net::Ipv4Addr>() {
+        ipv4.into()
+    } else if let Ok(_ipv6) = host.parse::<std::net::Ipv6Addr>() {
+        // If a literal IPv6 address was given, skip it (since we only want IPv4)
+        return Err(eyre!("IPv6 addresses not supported, only IPv4"));
+    } else {
+        dns.resolve(resolver, host, port).await?
+    };
+    let dns_elapsed = before_dns.elapsed();
+
+    log::debug!("Resolved {host_and_port} to {ip} in {dns_elapsed:?}");
+
+    log::debug!("Connecting to {ip}:{port}...");
+    let before_tcp = Instant::now();
+    let stream = tokio::net::TcpStream::connect((ip, port))
+        .await
+        .map_err(|e| eyre!("Failed to establish TCP connection: {e}"))?;
+    let tcp_elapsed = before_tcp.elapsed();
+
+    stream
+        .set_nodelay(true)
+        .map_err(|e| eyre!("Failed to set TCP_NODELAY: {e}"))?;
+
+    log::debug!("TCP connection established in {tcp_elapsed:?}");
+    log::debug!("Doing websocket handshake...");
+
+    let before_handshake = Instant::now();
+    let (ws_stream, _) = tokio_tungstenite::client_async_tls_with_config(
+        request,
+        stream,
+        Some(WebSocketConfig::default()),
+        None,
+    )
+    .await
+    .map_err(|e| {
+        log::warn!("WebSocket handshake failed: {e}");
+        eyre!("Failed to complete WebSocket handshake: {e}")
+    })?;
+    let handshake_elapsed = before_handshake.elapsed();
+
+    log::debug!("WebSocket handshake completed in {handshake_elapsed:?}");
+
+    Ok(Box::new(WebSocketStreamImpl::new(ws_stream)) as Box<dyn WebSocketStream>)
+}
+
 use tokio_tungstenite::{
     MaybeTlsStream,
     tungstenite::{client::IntoClientRequest, protocol::WebSocketConfig},
@@ -157,3 +164,35 @@ impl WebSocketStream for WebSocketStreamImpl {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::tests::CountingResolver;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_reconnect_within_ttl_skips_resolution() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    while ws.next().await.is_some() {}
+                });
+            }
+        });
+
+        let resolver = CountingResolver::default();
+        let dns = DnsCache::new(DNS_CACHE_TTL);
+        let uri: Uri = format!("ws://mom.test:{port}/events").parse().unwrap();
+        for _ in 0..2 {
+            connect(uri.clone(), HeaderMap::new(), &dns, &resolver)
+                .await
+                .unwrap();
+        }
+        assert_eq!(resolver.count(), 1);
+    }
+}
@@ -0,0 +1,335 @@
+use std::{sync::Arc, time::Duration};
+
+use backoff::{Backoff, RetryPolicy};
+use eyre::eyre;
+use futures_core::future::BoxFuture;
+use libhttpclient::Bytes;
+
+use crate::{Message, WebSocketStream};
+
+/// Opens a fresh connection, called every time we need to (re)connect
+pub type ConnectFn =
+    Box<dyn FnMut() -> BoxFuture<'static, eyre::Result<Box<dyn WebSocketStream>>> + Send>;
+
+/// Called whenever the connection state of a [`ReconnectingStream`] changes
+pub type StateCallback = Arc<dyn Fn(&ConnectionState) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// About to try connecting, after waiting `delay`
+    Connecting { attempt: u32, delay: Duration },
+    /// Connection established, messages flow again
+    Connected,
+    /// The connection dropped (or couldn't be established)
+    Disconnected { reason: String },
+}
+
+/// A websocket that transparently reconnects (with backoff) when the
+/// connection drops. Close frames from the peer count as drops, so `receive`
+/// only returns `None` once the policy gives up. Messages are never resent:
+/// a `send` on a connection that fails is an error.
+///
+/// The backoff only starts over once a message comes in: a peer that accepts
+/// connections only to drop them right away gets waited on longer and longer.
+pub struct ReconnectingStream {
+    connect: ConnectFn,
+    backoff: Backoff,
+    on_state: Option<StateCallback>,
+    current: Option<Box<dyn WebSocketStream>>,
+    /// how long to wait before the next attempt: the very first one doesn't
+    next_delay: Duration,
+    gave_up: bool,
+}
+
+/// Wraps `connect` so that the resulting stream reconnects according to `policy`
+pub fn reconnect(connect: ConnectFn, policy: RetryPolicy) -> ReconnectingStream {
+    ReconnectingStream {
+        connect,
+        backoff: policy.backoff(),
+        on_state: None,
+        current: None,
+        next_delay: Duration::ZERO,
+        gave_up: false,
+    }
+}
+
+impl ReconnectingStream {
+    /// Registers a callback for connection state changes
+    pub fn on_state_change(mut self, on_state: StateCallback) -> Self {
+        self.on_state = Some(on_state);
+        self
+    }
+
+    fn notify(&self, state: ConnectionState) {
+        if let Some(on_state) = self.on_state.as_ref() {
+            on_state(&state);
+        }
+    }
+
+    fn disconnect(&mut self, reason: String) {
+        self.current = None;
+        self.notify(ConnectionState::Disconnected { reason });
+        self.schedule_retry();
+    }
+
+    /// Counts a failure, returns false if the policy says to give up
+    fn schedule_retry(&mut self) -> bool {
+        match self.backoff.next_delay() {
+            Some(delay) => {
+                self.next_delay = delay;
+                true
+            }
+            None => {
+                self.gave_up = true;
+                false
+            }
+        }
+    }
+
+    /// Returns the live connection, connecting (and waiting) as needed
+    async fn ensure_connected(&mut self) -> eyre::Result<&mut Box<dyn WebSocketStream>> {
+        if self.gave_up {
+            return Err(eyre!("gave up reconnecting"));
+        }
+
+        if self.current.is_none() {
+            let mut attempt = 1;
+            loop {
+                let delay = std::mem::take(&mut self.next_delay);
+                self.notify(ConnectionState::Connecting { attempt, delay });
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+
+                match (self.connect)().await {
+                    Ok(ws) => {
+                        self.current = Some(ws);
+                        self.notify(ConnectionState::Connected);
+                        break;
+                    }
+                    Err(e) => {
+                        self.notify(ConnectionState::Disconnected {
+                            reason: e.to_string(),
+                        });
+                        if !self.schedule_retry() {
+                            return Err(
+                                e.wrap_err(format!("could not connect after {attempt} attempts"))
+                            );
+                        }
+                        attempt += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(self.current.as_mut().unwrap())
+    }
+
+    /// Connects (retrying according to the policy), then hands out the
+    /// connection itself, which won't reconnect if it drops. For sessions
+    /// that can't survive a reconnect, like uploads.
+    pub async fn into_connected(mut self) -> eyre::Result<Box<dyn WebSocketStream>> {
+        self.ensure_connected().await?;
+        Ok(self.current.take().unwrap())
+    }
+
+    async fn send_inner(&mut self, frame: Message) -> eyre::Result<()> {
+        let res = self.ensure_connected().await?.send(frame).await;
+        if let Err(e) = &res {
+            self.disconnect(e.to_string());
+        }
+        res
+    }
+}
+
+impl WebSocketStream for ReconnectingStream {
+    fn send(&mut self, frame: Message) -> BoxFuture<'_, eyre::Result<()>> {
+        Box::pin(self.send_inner(frame))
+    }
+
+    fn send_binary(&mut self, msg: Bytes) -> BoxFuture<'_, eyre::Result<()>> {
+        self.send(Message::Binary(msg))
+    }
+
+    fn send_text(&mut self, msg: String) -> BoxFuture<'_, eyre::Result<()>> {
+        self.send(Message::Text(msg.into()))
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, Option<eyre::Result<Message>>> {
+        Box::pin(async move {
+            loop {
+                let ws = match self.ensure_connected().await {
+                    Ok(ws) => ws,
+                    Err(e) => {
+                        log::warn!("Giving up on websocket: {e:#}");
+                        return None;
+                    }
+                };
+                match ws.receive().await {
+                    Some(Ok(Message::Close(frame))) => {
+                        let reason = match frame {
+                            Some(frame) => format!("closed by peer: {}", frame.reason),
+                            None => "closed by peer".to_string(),
+                        };
+                        self.disconnect(reason);
+                    }
+                    Some(Ok(msg)) => {
+                        // the connection is healthy: the next drop starts
+                        // the backoff over
+                        self.backoff.reset();
+                        return Some(Ok(msg));
+                    }
+                    Some(Err(e)) => self.disconnect(e.to_string()),
+                    None => self.disconnect("connection closed".to_string()),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use super::*;
+    use crate::test_ws::channel_ws;
+
+    /// Each entry is one connection attempt: `None` fails, `Some` connects
+    /// and delivers those messages before dropping.
+    fn scripted(attempts: Vec<Option<Vec<&'static str>>>) -> ConnectFn {
+        let attempts = Arc::new(Mutex::new(VecDeque::from(attempts)));
+        Box::new(
+            move || -> BoxFuture<'static, eyre::Result<Box<dyn WebSocketStream>>> {
+                let next = attempts.lock().unwrap().pop_front();
+                Box::pin(async move {
+                    match next {
+                        Some(Some(messages)) => {
+                            let (ws, peer) = channel_ws();
+                            for m in messages {
+                                peer.incoming.send(Message::Text(m.into())).unwrap();
+                            }
+                            Ok(Box::new(ws) as Box<dyn WebSocketStream>)
+                        }
+                        _ => Err(eyre!("connection refused")),
+                    }
+                })
+            },
+        )
+    }
+
+    const TEST_POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: Some(5),
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(4),
+        jitter: Duration::ZERO,
+        max_total: None,
+    };
+
+    fn recording(ws: ReconnectingStream) -> (ReconnectingStream, Arc<Mutex<Vec<ConnectionState>>>) {
+        let states: Arc<Mutex<Vec<ConnectionState>>> = Default::default();
+        let ws = ws.on_state_change({
+            let states = states.clone();
+            Arc::new(move |state| states.lock().unwrap().push(state.clone()))
+        });
+        (ws, states)
+    }
+
+    async fn text(ws: &mut ReconnectingStream) -> String {
+        match ws.receive().await.unwrap().unwrap() {
+            Message::Text(t) => t.as_str().to_string(),
+            other => panic!("expected text, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_resume_after_drop() {
+        let connect = scripted(vec![Some(vec!["a", "b"]), Some(vec!["c"])]);
+        let (mut ws, states) = recording(reconnect(connect, TEST_POLICY));
+
+        assert_eq!(text(&mut ws).await, "a");
+        assert_eq!(text(&mut ws).await, "b");
+        // first connection drops here, the second one picks up
+        assert_eq!(text(&mut ws).await, "c");
+
+        let states = states.lock().unwrap().clone();
+        assert_eq!(
+            states,
+            vec![
+                ConnectionState::Connecting {
+                    attempt: 1,
+                    delay: Duration::ZERO
+                },
+                ConnectionState::Connected,
+                ConnectionState::Disconnected {
+                    reason: "connection closed".to_string()
+                },
+                ConnectionState::Connecting {
+                    attempt: 1,
+                    delay: Duration::from_millis(1)
+                },
+                ConnectionState::Connected,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backoff_sequence_and_giving_up() {
+        let connect = scripted(vec![Some(vec!["a"])]);
+        let (mut ws, states) = recording(reconnect(connect, TEST_POLICY));
+
+        assert_eq!(text(&mut ws).await, "a");
+        // every reconnection attempt fails from now on: the drop counts as
+        // the first of the 5 failures we put up with
+        assert!(ws.receive().await.is_none());
+        assert!(ws.receive().await.is_none());
+
+        assert_eq!(connecting_delays(&states), vec![0, 1, 2, 4, 4]);
+    }
+
+    fn connecting_delays(states: &Mutex<Vec<ConnectionState>>) -> Vec<u128> {
+        states
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|state| match state {
+                ConnectionState::Connecting { delay, .. } => Some(delay.as_millis()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_backoff_keeps_growing_while_connections_flap() {
+        // the first two connections drop before delivering anything
+        let connect = scripted(vec![Some(vec![]), Some(vec![]), Some(vec!["a"])]);
+        let (mut ws, states) = recording(reconnect(connect, TEST_POLICY));
+
+        assert_eq!(text(&mut ws).await, "a");
+        assert_eq!(connecting_delays(&states), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_into_connected_retries_then_hands_out_connection() {
+        let connect = scripted(vec![None, None, Some(vec!["a"])]);
+        let (ws, states) = recording(reconnect(connect, TEST_POLICY));
+
+        let mut ws = ws.into_connected().await.unwrap();
+        assert!(matches!(ws.receive().await, Some(Ok(Message::Text(t))) if t.as_str() == "a"));
+        // that connection doesn't reconnect by itself
+        assert!(ws.receive().await.is_none());
+        assert_eq!(connecting_delays(&states), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_into_connected_gives_up() {
+        let connect = scripted(vec![]);
+        let err = reconnect(connect, TEST_POLICY)
+            .into_connected()
+            .await
+            .err()
+            .unwrap();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("after 5 attempts"), "{msg}");
+        assert!(msg.contains("connection refused"), "{msg}");
+    }
+}
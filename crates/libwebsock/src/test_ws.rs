@@ -0,0 +1,59 @@
+//! A fake websocket peer for tests, backed by channels. Enabled by the
+//! `test-util` feature.
+
+use futures_core::future::BoxFuture;
+use libhttpclient::Bytes;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use crate::{Message, WebSocketStream};
+
+/// A websocket whose other end is a [`Peer`]: what gets sent through it ends
+/// up in [`Peer::sent`], what's pushed into [`Peer::incoming`] is what it
+/// receives. Once the peer is dropped (and its messages drained), `receive`
+/// returns `None` and `send` fails, like a closed connection.
+pub struct ChannelWs {
+    sent: UnboundedSender<Message>,
+    incoming: UnboundedReceiver<Message>,
+}
+
+/// The test's end of a [`ChannelWs`]
+pub struct Peer {
+    pub sent: UnboundedReceiver<Message>,
+    pub incoming: UnboundedSender<Message>,
+}
+
+pub fn channel_ws() -> (ChannelWs, Peer) {
+    let (sent_tx, sent_rx) = unbounded_channel();
+    let (incoming_tx, incoming_rx) = unbounded_channel();
+    let ws = ChannelWs {
+        sent: sent_tx,
+        incoming: incoming_rx,
+    };
+    let peer = Peer {
+        sent: sent_rx,
+        incoming: incoming_tx,
+    };
+    (ws, peer)
+}
+
+impl WebSocketStream for ChannelWs {
+    fn send(&mut self, frame: Message) -> BoxFuture<'_, eyre::Result<()>> {
+        let res = self
+            .sent
+            .send(frame)
+            .map_err(|_| eyre::eyre!("peer went away"));
+        Box::pin(async move { res })
+    }
+
+    fn send_binary(&mut self, msg: Bytes) -> BoxFuture<'_, eyre::Result<()>> {
+        self.send(Message::Binary(msg))
+    }
+
+    fn send_text(&mut self, msg: String) -> BoxFuture<'_, eyre::Result<()>> {
+        self.send(Message::Text(msg.into()))
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, Option<eyre::Result<Message>>> {
+        Box::pin(async move { self.incoming.recv().await.map(Ok) })
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+use parking_lot::Mutex;
+
+/// A least-recently-used cache, bounded by the total weight of its values:
+/// their size in bytes, or just 1 if all that matters is how many there are.
+/// Both lookups and evictions are `O(log n)`.
+pub struct LruCache<K, V> {
+    weigh: fn(&V) -> u64,
+    inner: Mutex<Inner<K, V>>,
+}
+
+struct Inner<K, V> {
+    capacity: u64,
+    entries: HashMap<K, Entry<V>>,
+    /// last use → key, oldest first
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+    weight: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    weight: u64,
+    last_used: u64,
+}
+
+impl<K, V> LruCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// A capacity of zero disables the cache
+    pub fn new(capacity: u64, weigh: fn(&V) -> u64) -> Self {
+        Self {
+            weigh,
+            inner: Mutex::new(Inner {
+                capacity,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                weight: 0,
+            }),
+        }
+    }
+
+    /// Returns the value for `key` (if any), marking it as the most recently
+    /// used.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        let entry = inner.entries.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.last_used, tick);
+        let value = entry.value.clone();
+        inner.recency.remove(&previous);
+        inner.recency.insert(tick, key.clone());
+        Some(value)
+    }
+
+    /// Evicts as many of the least recently used values as it takes to make
+    /// room for `value`. Values heavier than the whole cache are not kept.
+    pub fn insert(&self, key: K, value: V) {
+        let weight = (self.weigh)(&value);
+        let mut inner = self.inner.lock();
+        inner.remove(&key);
+        if weight > inner.capacity {
+            return;
+        }
+        inner.evict_down_to(inner.capacity - weight);
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.weight += weight;
+        inner.recency.insert(tick, key.clone());
+        inner.entries.insert(
+            key,
+            Entry {
+                value,
+                weight,
+                last_used: tick,
+            },
+        );
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.inner.lock().remove(key)
+    }
+
+    /// The total weight of the values in the cache
+    pub fn weight(&self) -> u64 {
+        self.inner.lock().weight
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.inner.lock().capacity
+    }
+
+    /// Evicts the least recently used values until the rest fit in
+    /// `capacity`, if it's smaller than the old one.
+    pub fn set_capacity(&self, capacity: u64) {
+        let mut inner = self.inner.lock();
+        inner.capacity = capacity;
+        inner.evict_down_to(capacity);
+    }
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Eq + Hash,
+{
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        self.weight -= entry.weight;
+        Some(entry.value)
+    }
+
+    fn evict_down_to(&mut self, weight: u64) {
+        while self.weight > weight {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.weight -= evicted.weight;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache bounded by the total length of its values
+    fn by_len(capacity: u64) -> LruCache<&'static str, Vec<u8>> {
+        LruCache::new(capacity, |v| v.len() as u64)
+    }
+
+    fn bytes(len: usize) -> Vec<u8> {
+        vec![0u8; len]
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = by_len(100);
+        assert!(cache.get(&"derivations/a").is_none());
+
+        cache.insert("derivations/a", b"hello".to_vec());
+        assert_eq!(cache.get(&"derivations/a").unwrap(), b"hello");
+        assert!(cache.get(&"derivations/b").is_none());
+
+        // replacing a value doesn't count it twice
+        cache.insert("derivations/a", b"hi".to_vec());
+        assert_eq!(cache.weight(), 2);
+
+        assert_eq!(cache.remove(&"derivations/a").unwrap(), b"hi");
+        assert_eq!(cache.weight(), 0);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = by_len(30);
+        cache.insert("a", bytes(10));
+        cache.insert("b", bytes(10));
+        cache.insert("c", bytes(10));
+
+        // "a" is now the most recently used, so "b" goes first
+        assert!(cache.get(&"a").is_some());
+        cache.insert("d", bytes(10));
+        assert!(cache.get(&"b").is_none());
+        assert!(cache.get(&"a").is_some());
+        assert!(cache.get(&"c").is_some());
+        assert!(cache.get(&"d").is_some());
+        assert_eq!(cache.weight(), 30);
+
+        // a big value pushes out as many entries as it needs to
+        cache.insert("e", bytes(25));
+        assert_eq!(cache.weight(), 25);
+        assert!(cache.get(&"e").is_some());
+        assert!(cache.get(&"a").is_none());
+        assert!(cache.get(&"c").is_none());
+        assert!(cache.get(&"d").is_none());
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let cache = by_len(30);
+        cache.insert("a", bytes(10));
+        cache.insert("b", bytes(10));
+        cache.insert("c", bytes(10));
+        assert!(cache.get(&"a").is_some());
+
+        cache.set_capacity(20);
+        assert_eq!(cache.capacity(), 20);
+        assert_eq!(cache.weight(), 20);
+        assert!(cache.get(&"b").is_none());
+        assert!(cache.get(&"a").is_some());
+        assert!(cache.get(&"c").is_some());
+
+        // growing it back doesn't bring anything back, but makes room
+        cache.set_capacity(40);
+        cache.insert("d", bytes(20));
+        assert_eq!(cache.weight(), 40);
+        assert!(cache.get(&"a").is_some());
+    }
+
+    #[test]
+    fn test_counting_entries() {
+        let cache = LruCache::new(2, |_: &u32| 1);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+    }
+
+    #[test]
+    fn test_oversized_values_and_disabled_cache() {
+        let cache = by_len(10);
+        cache.insert("a", bytes(5));
+        cache.insert("huge", bytes(11));
+        assert!(cache.get(&"huge").is_none());
+        assert!(cache.get(&"a").is_some());
+
+        let cache = by_len(0);
+        cache.insert("a", bytes(1));
+        assert!(cache.get(&"a").is_none());
+    }
+}
@@ -5,7 +5,11 @@ use derivations::DerivationInfo;
 use facet::Facet;
 use media_types::{TargetFormat, TranscodingProgress};
 use objectstore_types::ObjectStoreKey;
-use std::{collections::HashMap, sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use config_types::{MomConfig, TenantConfig, TenantDomain, TenantInfo, WebConfig};
 
@@ -26,6 +30,30 @@ pub struct TranscodeJobInfo {
     pub last_progress: Option<TranscodingProgress>,
 }
 
+impl TranscodeJobInfo {
+    pub fn status(&self) -> TranscodeJobStatus {
+        TranscodeJobStatus {
+            running_for_ms: self.started.elapsed().as_millis() as u64,
+            last_ping_ms_ago: self.last_ping.elapsed().as_millis() as u64,
+            last_progress: self.last_progress.clone(),
+        }
+    }
+}
+
+/// Where a running transcode job is at, for polling it without starting one
+#[derive(Debug, Clone, Facet)]
+pub struct TranscodeJobStatus {
+    /// how long ago the job started
+    pub running_for_ms: u64,
+
+    /// how long ago ffmpeg last reported progress (or the job started)
+    pub last_ping_ms_ago: u64,
+
+    /// `None` until ffmpeg reports progress
+    #[facet(default)]
+    pub last_progress: Option<TranscodingProgress>,
+}
+
 // Note: this is tenant-specific, the video data etc. is per-tenant.
 #[derive(PartialEq, Eq, Debug, Clone, Hash, Facet)]
 pub struct TranscodeParams {
@@ -122,6 +150,47 @@ pub struct DeriveResponseAlreadyInProgress {
 #[derive(Facet)]
 pub struct DeriveResponseTooManyRequests {}
 
+/// Messages exchanged over the `derive/stream` websocket: the client sends
+/// `Params` once, then mom sends any number of `TranscodingEvent`s and
+/// finishes with a `Response` (or an `Error`).
+#[derive(Facet)]
+#[repr(u8)]
+pub enum DeriveStreamMessage {
+    Params(DeriveParams),
+    TranscodingEvent(media_types::TranscodeEvent),
+    Response(DeriveResponse),
+    Error(String),
+}
+
+/// Messages exchanged over the `derive/batch` websocket: the client sends
+/// `Params` once, then mom sends an `Item` as each derivation finishes (in no
+/// particular order) and `Done` once they all have. `Error` means the batch
+/// as a whole failed.
+#[derive(Facet)]
+#[repr(u8)]
+pub enum DeriveBatchMessage {
+    Params(Vec<DeriveParams>),
+    Item(DeriveBatchItem),
+    Done,
+    Error(String),
+}
+
+/// How one derivation of a batch went
+#[derive(Facet)]
+pub struct DeriveBatchItem {
+    /// Position of the derivation in the `Params` list
+    pub index: usize,
+    pub outcome: DeriveBatchOutcome,
+}
+
+#[derive(Facet)]
+#[repr(u8)]
+pub enum DeriveBatchOutcome {
+    Response(DeriveResponse),
+    /// The derivation failed, the rest of the batch carries on
+    Error(String),
+}
+
 pub mod media_types {
     use conflux::{MediaProps, VCodec};
     use facet::Facet;
@@ -132,49 +201,95 @@ pub mod media_types {
     pub enum TargetFormat {
         AV1,
         AVC,
+        HEVC,
         VP9,
         ThumbJXL,
         ThumbAVIF,
         ThumbWEBP,
     }
 
+    /// How a thumbnail format is produced: ffmpeg grabs a frame as
+    /// `ffmpeg_ic`, which is then converted to `final_ic` when they differ.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ThumbChain {
+        pub format: TargetFormat,
+        pub ffmpeg_ic: ICodec,
+        pub final_ic: ICodec,
+    }
+
+    impl ThumbChain {
+        pub fn postprocess(&self) -> Option<PostProcess> {
+            (self.ffmpeg_ic != self.final_ic).then_some(PostProcess {
+                src_ic: self.ffmpeg_ic,
+                dst_ic: self.final_ic,
+            })
+        }
+    }
+
+    /// Every thumbnail format. AVIF and WEBP go through a JXL intermediate:
+    /// add a row to support a new format, or change `ffmpeg_ic` to have
+    /// ffmpeg produce the final format directly.
+    pub const THUMB_CHAINS: &[ThumbChain] = &[
+        ThumbChain {
+            format: TargetFormat::ThumbJXL,
+            ffmpeg_ic: ICodec::JXL,
+            final_ic: ICodec::JXL,
+        },
+        ThumbChain {
+            format: TargetFormat::ThumbAVIF,
+            ffmpeg_ic: ICodec::JXL,
+            final_ic: ICodec::AVIF,
+        },
+        ThumbChain {
+            format: TargetFormat::ThumbWEBP,
+            ffmpeg_ic: ICodec::JXL,
+            final_ic: ICodec::WEBP,
+        },
+    ];
+
     impl TargetFormat {
+        /// The row of [`THUMB_CHAINS`] for this format, if it's a thumbnail
+        pub fn thumb_chain(&self) -> Option<&'static ThumbChain> {
+            THUMB_CHAINS.iter().find(|chain| chain.format == *self)
+        }
+
         pub fn as_thumb_format(&self) -> Option<ICodec> {
-            match self {
-                TargetFormat::ThumbJXL => Some(ICodec::JXL),
-                TargetFormat::ThumbAVIF => Some(ICodec::AVIF),
-                TargetFormat::ThumbWEBP => Some(ICodec::WEBP),
-                _ => None,
-            }
+            self.thumb_chain().map(|chain| chain.final_ic)
         }
 
         pub fn postprocess(&self) -> Option<PostProcess> {
-            match self {
-                TargetFormat::ThumbAVIF => Some(PostProcess {
-                    src_ic: ICodec::JXL,
-                    dst_ic: ICodec::AVIF,
-                }),
-                TargetFormat::ThumbWEBP => Some(PostProcess {
-                    src_ic: ICodec::JXL,
-                    dst_ic: ICodec::WEBP,
-                }),
-                _ => None,
+            self.thumb_chain().and_then(ThumbChain::postprocess)
+        }
+
+        /// Extension of the file ffmpeg writes. For formats that need a
+        /// [`postprocess`](Self::postprocess) step, that's the intermediate format.
+        /// `None` for a thumbnail format missing from [`THUMB_CHAINS`].
+        pub fn ffmpeg_output_ext(&self) -> Option<&'static str> {
+            match self.thumb_chain() {
+                Some(chain) => Some(chain.ffmpeg_ic.ext()),
+                None => self.video_ext(),
+            }
+        }
+
+        /// Extension of the final output, after any postprocessing
+        pub fn final_output_ext(&self) -> Option<&'static str> {
+            match self.thumb_chain() {
+                Some(chain) => Some(chain.final_ic.ext()),
+                None => self.video_ext(),
             }
         }
 
-        pub fn ffmpeg_output_ext(&self) -> &'static str {
+        /// `None` for thumbnails: those are described by [`THUMB_CHAINS`]
+        fn video_ext(&self) -> Option<&'static str> {
             match self {
-                TargetFormat::AV1 => "mp4",
-                TargetFormat::AVC => "mp4",
-                TargetFormat::VP9 => "webm",
-                TargetFormat::ThumbJXL => "jxl",
-                TargetFormat::ThumbAVIF => "jxl",
-                TargetFormat::ThumbWEBP => "jxl",
+                TargetFormat::AV1 | TargetFormat::AVC | TargetFormat::HEVC => Some("mp4"),
+                TargetFormat::VP9 => Some("webm"),
+                TargetFormat::ThumbJXL | TargetFormat::ThumbAVIF | TargetFormat::ThumbWEBP => None,
             }
         }
     }
 
-    #[derive(Facet)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Facet)]
     pub struct PostProcess {
         pub src_ic: ICodec,
         pub dst_ic: ICodec,
@@ -187,6 +302,7 @@ pub mod media_types {
             match value {
                 VCodec::VP9 => Ok(TargetFormat::VP9),
                 VCodec::AV1 => Ok(TargetFormat::AV1),
+                VCodec::HEVC => Ok(TargetFormat::HEVC),
                 format => eyre::bail!("Refusing to encode to {format:?}"),
             }
         }
@@ -196,11 +312,9 @@ pub mod media_types {
         type Error = eyre::Report;
 
         fn try_from(value: ICodec) -> Result<Self, Self::Error> {
-            match value {
-                ICodec::AVIF => Ok(TargetFormat::ThumbAVIF),
-                ICodec::WEBP => Ok(TargetFormat::ThumbWEBP),
-                ICodec::JXL => Ok(TargetFormat::ThumbJXL),
-                format => eyre::bail!("Refusing to grab thumbnail in format {format:?}"),
+            match THUMB_CHAINS.iter().find(|chain| chain.final_ic == value) {
+                Some(chain) => Ok(chain.format),
+                None => eyre::bail!("Refusing to grab thumbnail in format {value:?}"),
             }
         }
     }
@@ -213,6 +327,10 @@ pub mod media_types {
         TranscodingEvent(TranscodeEvent),
         TranscodingComplete(TranscodingCompleteMessage),
         Error(String),
+        ResumeDownload(ResumeDownloadMessage),
+        /// Sent by the uploader right before closing the connection: mom
+        /// stops whatever it's doing for it, transcode included.
+        Cancel,
     }
 
     #[derive(Debug, Facet)]
@@ -230,6 +348,19 @@ pub mod media_types {
     #[derive(Debug, Facet)]
     pub struct TranscodingCompleteMessage {
         pub output_size: usize,
+
+        /// If set, the output can be downloaded again for a little while, by
+        /// sending a `ResumeDownload` with this token on a fresh connection.
+        #[facet(default)]
+        pub resume_token: Option<String>,
+    }
+
+    /// Sent as the first message of a fresh connection, instead of `Headers`,
+    /// to get the output of a previous transcode starting at `offset`.
+    #[derive(Debug, Facet)]
+    pub struct ResumeDownloadMessage {
+        pub resume_token: String,
+        pub offset: usize,
     }
 
     #[derive(Debug, Clone, Facet)]
@@ -289,6 +420,44 @@ pub struct ListMissingResponse {
     pub missing: HashMap<ObjectStoreKey, InputPath>,
 }
 
+/// Which tenants the API key used for the request can access. Also what to
+/// look at when mom answers 403.
+#[derive(Debug, Clone, PartialEq, Facet)]
+pub struct ListTenantsResponse {
+    /// true for the readonly key, which isn't scoped to any tenant
+    pub all_tenants: bool,
+    /// tenants mom knows about that the key can access, sorted
+    pub tenants: Vec<TenantDomain>,
+    /// for scoped keys, the tenants the key lists that mom doesn't host
+    /// (typos, or tenants that were removed), sorted
+    #[facet(default)]
+    pub unknown_tenants: Vec<TenantDomain>,
+}
+
+impl std::fmt::Display for ListTenantsResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.all_tenants {
+            return write!(f, "global key (all tenants)");
+        }
+        let list = |tenants: &[TenantDomain]| {
+            tenants
+                .iter()
+                .map(|tn| tn.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "scoped key for [{}]", list(&self.tenants))?;
+        if !self.unknown_tenants.is_empty() {
+            write!(
+                f,
+                ", also lists unknown tenants [{}]",
+                list(&self.unknown_tenants)
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Facet)]
 #[repr(u8)]
 pub enum MomEvent {
@@ -307,14 +476,157 @@ pub struct AllUsers {
     pub users: HashMap<UserId, UserInfo>,
 }
 
+impl AllUsers {
+    /// Computes what changed between `self` (what the other side already has)
+    /// and `next`. `fetched_at` is bumped on every refresh, so it alone doesn't
+    /// make a user "changed".
+    pub fn delta_to(&self, next: &AllUsers) -> UsersDelta {
+        let mut delta = UsersDelta::default();
+
+        for (id, next_info) in &next.users {
+            match self.users.get(id) {
+                Some(prev_info) if same_user_data(prev_info, next_info) => {}
+                _ => {
+                    delta.upserted.insert(id.clone(), next_info.clone());
+                }
+            }
+        }
+
+        for id in self.users.keys() {
+            if !next.users.contains_key(id) {
+                delta.removed.push(id.clone());
+            }
+        }
+
+        delta
+    }
+
+    /// Applies a delta computed with [`AllUsers::delta_to`]
+    pub fn apply_delta(&mut self, delta: UsersDelta) {
+        for id in &delta.removed {
+            self.users.remove(id);
+        }
+        self.users.extend(delta.upserted);
+    }
+
+    /// What cubs get to see: the same users, minus their emails (see
+    /// [`UserInfo::strip_emails`]). Everything mom sends to cubs (good
+    /// mornings, broadcasts, `/users`) goes through this.
+    pub fn for_cubs(&self) -> AllUsers {
+        let mut users = self.clone();
+        for info in users.users.values_mut() {
+            info.strip_emails();
+        }
+        users
+    }
+}
+
+/// Whether two user infos only differ by when they were fetched. Destructured
+/// so that a field added to [`UserInfo`] can't be left out of deltas.
+fn same_user_data(a: &UserInfo, b: &UserInfo) -> bool {
+    let UserInfo {
+        id,
+        fetched_at: _,
+        patreon,
+        github,
+        discord,
+        in_discord,
+        gifted_tier,
+    } = a;
+    *id == b.id
+        && *patreon == b.patreon
+        && *github == b.github
+        && *discord == b.discord
+        && *in_discord == b.in_discord
+        && *gifted_tier == b.gifted_tier
+}
+
+/// Users that were added, changed or removed since the last broadcast
+#[derive(Facet, Clone, Default)]
+pub struct UsersDelta {
+    /// users that were added or whose info changed
+    pub upserted: HashMap<UserId, UserInfo>,
+
+    /// users that are gone
+    pub removed: Vec<UserId>,
+}
+
+impl UsersDelta {
+    pub fn is_empty(&self) -> bool {
+        self.upserted.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Decides whether to broadcast a full users snapshot or a delta. Deltas are
+/// relative to the last thing we broadcast; every so often we send a full
+/// snapshot anyway, so a cub that missed an event (or got a stale good morning)
+/// eventually converges, even if users stopped changing.
+#[derive(Default)]
+pub struct UsersBroadcastState {
+    last_sent: Option<Arc<AllUsers>>,
+    deltas_since_snapshot: u32,
+    last_snapshot_at: Option<Instant>,
+}
+
+impl UsersBroadcastState {
+    /// How many deltas we send before forcing a full snapshot
+    pub const DELTAS_PER_SNAPSHOT: u32 = 10;
+
+    /// How long we go without a full snapshot, changes or not
+    pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    /// Returns the payload to broadcast for this new set of users, or `None`
+    /// if nothing changed since the last broadcast (and no snapshot is due).
+    pub fn next_payload(&mut self, users: Arc<AllUsers>) -> Option<TenantEventPayload> {
+        self.next_payload_at(users, Instant::now())
+    }
+
+    fn next_payload_at(
+        &mut self,
+        users: Arc<AllUsers>,
+        now: Instant,
+    ) -> Option<TenantEventPayload> {
+        let snapshot_due = self
+            .last_snapshot_at
+            .is_none_or(|at| now.duration_since(at) >= Self::SNAPSHOT_INTERVAL);
+        let prev = match self.last_sent.replace(users.clone()) {
+            Some(prev)
+                if !snapshot_due && self.deltas_since_snapshot < Self::DELTAS_PER_SNAPSHOT =>
+            {
+                prev
+            }
+            _ => {
+                self.deltas_since_snapshot = 0;
+                self.last_snapshot_at = Some(now);
+                return Some(TenantEventPayload::UsersUpdated(users));
+            }
+        };
+
+        let delta = prev.delta_to(&users);
+        if delta.is_empty() {
+            return None;
+        }
+        self.deltas_since_snapshot += 1;
+        Some(TenantEventPayload::UsersDelta(delta))
+    }
+}
+
 #[derive(Facet)]
 #[repr(u8)]
 pub enum TenantEventPayload {
     RevisionChanged(Box<Pak>),
 
-    /// cubs derive sponsors to show from user info themselves. there's no delta
-    /// going on here — we just send them all user info every so often
+    /// cubs derive sponsors to show from user info themselves. this is a full
+    /// snapshot of all user info, sent periodically so cubs can resync.
     UsersUpdated(Arc<AllUsers>),
+
+    /// only the users that changed since the last broadcast, see
+    /// [`UsersBroadcastState`]
+    UsersDelta(UsersDelta),
+
+    /// the tenant's cookie sauce was rotated: this is the new one. cubs
+    /// switch to it right away, which logs everyone out.
+    CookieSauceRotated(String),
 }
 
 impl std::fmt::Debug for TenantEventPayload {
@@ -332,6 +644,15 @@ impl std::fmt::Debug for TenantEventPayload {
             TenantEventPayload::UsersUpdated(users) => {
                 write!(f, "TenantEvent::UsersUpdated({} users)", users.users.len())
             }
+            TenantEventPayload::UsersDelta(delta) => write!(
+                f,
+                "TenantEvent::UsersDelta({} upserted, {} removed)",
+                delta.upserted.len(),
+                delta.removed.len()
+            ),
+            TenantEventPayload::CookieSauceRotated(_) => {
+                write!(f, "TenantEvent::CookieSauceRotated(<redacted>)")
+            }
         }
     }
 }
@@ -371,7 +692,15 @@ pub struct MomServeArgs {
 
 /// Returns a 64-character hex string that's deterministic and unique per tenant
 /// Uses HMAC to be secure even if tenant names become user-controlled in the future
-pub fn derive_cookie_sauce(global_sauce: &str, tenant_name: &TenantDomain) -> String {
+///
+/// `generation` is bumped every time the tenant's sauce is rotated. Generation 0
+/// is what tenants start with, and derives the same value as before rotation
+/// existed, so existing cookies stay valid until the first rotation.
+pub fn derive_cookie_sauce(
+    global_sauce: &str,
+    tenant_name: &TenantDomain,
+    generation: u64,
+) -> String {
     use hmac::{Hmac, Mac};
     use sha2::Sha256;
 
@@ -380,10 +709,38 @@ pub fn derive_cookie_sauce(global_sauce: &str, tenant_name: &TenantDomain) -> St
     let mut mac =
         HmacSha256::new_from_slice(global_sauce.as_bytes()).expect("HMAC can take key of any size");
     mac.update(tenant_name.as_str().as_bytes());
+    if generation > 0 {
+        // tenant names can't contain a NUL, so this can't collide with another tenant
+        mac.update(b"\0");
+        mac.update(generation.to_string().as_bytes());
+    }
     let result = mac.finalize();
     hex::encode(result.into_bytes())
 }
 
+/// Header carrying the hex-encoded SHA-256 of an upload's body. Clients send it
+/// with uploads, mom checks it and echoes it back once the body is verified.
+pub const CONTENT_SHA256_HEADER: &str = "x-content-sha256";
+
+/// Hex-encoded SHA-256 of `payload`, for [`CONTENT_SHA256_HEADER`]
+pub fn content_sha256(payload: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    hex::encode(Sha256::digest(payload))
+}
+
+/// Checks `payload` against the value of a [`CONTENT_SHA256_HEADER`]
+pub fn verify_content_sha256(payload: &[u8], expected: &str) -> eyre::Result<()> {
+    let actual = content_sha256(payload);
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        eyre::bail!(
+            "content hash mismatch: expected {expected}, got {actual} for {} bytes",
+            payload.len()
+        );
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Facet)]
 pub struct PatreonCallbackResponse {
     pub user_info: UserInfo,
@@ -420,6 +777,12 @@ pub struct MakeApiKeyResponse {
     pub api_key: UserApiKey,
 }
 
+#[derive(Debug, Facet)]
+pub struct RotateCookieSauceResponse {
+    /// how many times the tenant's cookie sauce has been rotated, now
+    pub generation: u64,
+}
+
 #[derive(Facet)]
 pub struct VerifyApiKeyArgs {
     /// the API key to verify
@@ -442,3 +805,349 @@ pub struct MomStructuredError {
     /// backtrace frame lines (formatted with ANSI escape codes)
     pub frames: Vec<String>,
 }
+
+#[cfg(test)]
+mod target_format_tests {
+    use super::media_types::TargetFormat;
+
+    #[test]
+    fn test_output_extensions() {
+        let cases = [
+            (TargetFormat::AV1, "mp4", "mp4"),
+            (TargetFormat::AVC, "mp4", "mp4"),
+            (TargetFormat::HEVC, "mp4", "mp4"),
+            (TargetFormat::VP9, "webm", "webm"),
+            (TargetFormat::ThumbJXL, "jxl", "jxl"),
+            (TargetFormat::ThumbAVIF, "jxl", "avif"),
+            (TargetFormat::ThumbWEBP, "jxl", "webp"),
+        ];
+        for (format, ffmpeg_ext, final_ext) in cases {
+            assert_eq!(format.ffmpeg_output_ext(), Some(ffmpeg_ext), "{format:?}");
+            assert_eq!(format.final_output_ext(), Some(final_ext), "{format:?}");
+            // only postprocessed formats go through an intermediate
+            assert_eq!(
+                format.postprocess().is_some(),
+                ffmpeg_ext != final_ext,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_thumb_chains() {
+        use super::media_types::{PostProcess, THUMB_CHAINS};
+        use image_types::ICodec;
+
+        let cases = [
+            (TargetFormat::ThumbJXL, ICodec::JXL, None),
+            (
+                TargetFormat::ThumbAVIF,
+                ICodec::AVIF,
+                Some((ICodec::JXL, ICodec::AVIF)),
+            ),
+            (
+                TargetFormat::ThumbWEBP,
+                ICodec::WEBP,
+                Some((ICodec::JXL, ICodec::WEBP)),
+            ),
+        ];
+        assert_eq!(THUMB_CHAINS.len(), cases.len());
+        for (format, ic, postprocess) in cases {
+            assert_eq!(format.as_thumb_format(), Some(ic), "{format:?}");
+            assert_eq!(
+                format.postprocess(),
+                postprocess.map(|(src_ic, dst_ic)| PostProcess { src_ic, dst_ic }),
+                "{format:?}"
+            );
+            assert_eq!(TargetFormat::try_from(ic).unwrap(), format);
+        }
+
+        for format in [
+            TargetFormat::AV1,
+            TargetFormat::AVC,
+            TargetFormat::HEVC,
+            TargetFormat::VP9,
+        ] {
+            assert!(format.thumb_chain().is_none(), "{format:?}");
+            assert_eq!(format.as_thumb_format(), None);
+            assert_eq!(format.postprocess(), None);
+        }
+        assert!(TargetFormat::try_from(ICodec::PNG).is_err());
+    }
+
+    #[test]
+    fn test_try_from_vcodec() {
+        use conflux::VCodec;
+
+        assert_eq!(
+            TargetFormat::try_from(VCodec::HEVC).unwrap(),
+            TargetFormat::HEVC
+        );
+        assert_eq!(
+            TargetFormat::try_from(VCodec::AV1).unwrap(),
+            TargetFormat::AV1
+        );
+        assert_eq!(
+            TargetFormat::try_from(VCodec::VP9).unwrap(),
+            TargetFormat::VP9
+        );
+        assert!(TargetFormat::try_from(VCodec::AVC).is_err());
+    }
+
+    #[test]
+    fn test_transcode_params_distinguish_hevc() {
+        use super::TranscodeParams;
+        use objectstore_types::ObjectStoreKey;
+        use std::collections::HashSet;
+
+        let params = |target_format| TranscodeParams {
+            input: ObjectStoreKey::new("uploads/in.mov".to_string()),
+            target_format,
+            output: ObjectStoreKey::new("uploads/out.mp4".to_string()),
+        };
+        assert_eq!(params(TargetFormat::HEVC), params(TargetFormat::HEVC));
+        assert_ne!(params(TargetFormat::HEVC), params(TargetFormat::AVC));
+
+        let jobs: HashSet<TranscodeParams> = [
+            params(TargetFormat::AV1),
+            params(TargetFormat::AVC),
+            params(TargetFormat::HEVC),
+            params(TargetFormat::HEVC),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(jobs.len(), 3);
+        assert!(jobs.contains(&params(TargetFormat::HEVC)));
+    }
+}
+
+#[cfg(test)]
+mod content_sha256_tests {
+    use super::*;
+
+    #[test]
+    fn test_content_sha256() {
+        // sha256 of the empty string
+        assert_eq!(
+            content_sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let hash = content_sha256(b"hello");
+        verify_content_sha256(b"hello", &hash).unwrap();
+        verify_content_sha256(b"hello", &hash.to_uppercase()).unwrap();
+        assert!(verify_content_sha256(b"hellp", &hash).is_err());
+    }
+}
+
+#[cfg(test)]
+mod cookie_sauce_tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_changes_derived_sauce() {
+        let tn = TenantDomain::new("fasterthanli.me".to_string());
+
+        // generation 0 is the plain HMAC of the tenant name, as it always was
+        assert_eq!(
+            derive_cookie_sauce("global", &tn, 0),
+            "a370334034bf331ce6b9b2196e5f025094e5a94c992f8a03cead99eda8afc975"
+        );
+
+        let gen1 = derive_cookie_sauce("global", &tn, 1);
+        let gen2 = derive_cookie_sauce("global", &tn, 2);
+        assert_eq!(gen1.len(), 64);
+        assert_ne!(gen1, derive_cookie_sauce("global", &tn, 0));
+        assert_ne!(gen1, gen2);
+
+        // deterministic, so every mom and cub agree on it
+        assert_eq!(gen1, derive_cookie_sauce("global", &tn, 1));
+
+        // and still unique per tenant
+        let other = TenantDomain::new("example.org".to_string());
+        assert_ne!(gen1, derive_cookie_sauce("global", &other, 1));
+    }
+}
+
+#[cfg(test)]
+mod users_delta_tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    fn user(id: &str, gifted_tier: Option<&str>) -> UserInfo {
+        UserInfo {
+            id: UserId::new(id.to_string()),
+            fetched_at: OffsetDateTime::now_utc(),
+            patreon: None,
+            github: None,
+            discord: None,
+            in_discord: false,
+            gifted_tier: gifted_tier.map(|s| s.to_string()),
+        }
+    }
+
+    fn all_users(users: &[UserInfo]) -> AllUsers {
+        AllUsers {
+            users: users.iter().map(|u| (u.id.clone(), u.clone())).collect(),
+        }
+    }
+
+    fn tier_of(users: &AllUsers, id: &str) -> Option<String> {
+        users.users[&UserId::new(id.to_string())]
+            .gifted_tier
+            .clone()
+    }
+
+    #[test]
+    fn test_apply_add_update_remove() {
+        let prev = all_users(&[user("1", None), user("2", None), user("3", None)]);
+        let next = all_users(&[user("1", None), user("2", Some("gold")), user("4", None)]);
+
+        let delta = prev.delta_to(&next);
+        assert_eq!(delta.upserted.len(), 2);
+        assert_eq!(delta.removed, vec![UserId::new("3".to_string())]);
+
+        let mut cub_side = prev.clone();
+        cub_side.apply_delta(delta);
+        assert_eq!(cub_side.users.len(), 3);
+        assert_eq!(tier_of(&cub_side, "2").as_deref(), Some("gold"));
+        assert!(cub_side.users.contains_key(&UserId::new("4".to_string())));
+        assert!(!cub_side.users.contains_key(&UserId::new("3".to_string())));
+    }
+
+    #[test]
+    fn test_fetched_at_alone_is_not_a_change() {
+        let prev = all_users(&[user("1", None)]);
+        let mut next = prev.clone();
+        for info in next.users.values_mut() {
+            info.fetched_at += time::Duration::seconds(120);
+        }
+        assert!(prev.delta_to(&next).is_empty());
+    }
+
+    #[test]
+    fn test_for_cubs_strips_emails() {
+        let mut info = user("1", Some("gold"));
+        info.github = Some(credentials::GithubProfile {
+            id: credentials::GithubUserId::new("12345".to_string()),
+            monthly_usd: Some(10),
+            sponsorship_privacy_level: None,
+            name: None,
+            login: "someone".to_string(),
+            avatar_url: None,
+            email: Some("someone@example.org".to_string()),
+        });
+        let users = all_users(&[info]);
+
+        let for_cubs = users.for_cubs();
+        let info = &for_cubs.users[&UserId::new("1".to_string())];
+        let github = info.github.as_ref().unwrap();
+        assert_eq!(github.email, None);
+        assert_eq!(github.login, "someone");
+        assert_eq!(info.gifted_tier.as_deref(), Some("gold"));
+
+        // mom keeps them
+        assert!(
+            users
+                .users
+                .values()
+                .all(|u| u.github.as_ref().unwrap().email.is_some())
+        );
+    }
+
+    #[test]
+    fn test_broadcast_state_sends_deltas_then_snapshot() {
+        let mut state = UsersBroadcastState::default();
+
+        // first broadcast is always a snapshot
+        let users = Arc::new(all_users(&[user("1", None)]));
+        assert!(matches!(
+            state.next_payload(users.clone()),
+            Some(TenantEventPayload::UsersUpdated(_))
+        ));
+
+        // nothing changed: nothing to send
+        assert!(state.next_payload(users).is_none());
+
+        for i in 0..UsersBroadcastState::DELTAS_PER_SNAPSHOT {
+            let users = Arc::new(all_users(&[user("1", Some(&format!("tier{i}")))]));
+            assert!(matches!(
+                state.next_payload(users),
+                Some(TenantEventPayload::UsersDelta(_))
+            ));
+        }
+
+        let users = Arc::new(all_users(&[user("1", Some("gold"))]));
+        assert!(matches!(
+            state.next_payload(users),
+            Some(TenantEventPayload::UsersUpdated(_))
+        ));
+    }
+
+    #[test]
+    fn test_broadcast_state_resyncs_after_interval() {
+        let mut state = UsersBroadcastState::default();
+        let start = Instant::now();
+
+        let users = Arc::new(all_users(&[user("1", None)]));
+        assert!(matches!(
+            state.next_payload_at(users.clone(), start),
+            Some(TenantEventPayload::UsersUpdated(_))
+        ));
+        assert!(state.next_payload_at(users.clone(), start).is_none());
+
+        // even if nothing changed, a cub that missed an event gets a snapshot
+        let later = start + UsersBroadcastState::SNAPSHOT_INTERVAL;
+        assert!(matches!(
+            state.next_payload_at(users.clone(), later),
+            Some(TenantEventPayload::UsersUpdated(_))
+        ));
+        assert!(state.next_payload_at(users, later).is_none());
+    }
+
+    fn apply(cub_side: &mut AllUsers, payload: TenantEventPayload) {
+        match payload {
+            TenantEventPayload::UsersUpdated(users) => *cub_side = AllUsers::clone(&users),
+            TenantEventPayload::UsersDelta(delta) => cub_side.apply_delta(delta),
+            TenantEventPayload::RevisionChanged(_) | TenantEventPayload::CookieSauceRotated(_) => {
+                unreachable!()
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_resyncs_after_missed_delta() {
+        let mut state = UsersBroadcastState::default();
+        let mut cub_side = AllUsers::default();
+
+        let v1 = Arc::new(all_users(&[user("1", None)]));
+        apply(&mut cub_side, state.next_payload(v1).unwrap());
+
+        // the cub misses this delta
+        let v2 = Arc::new(all_users(&[user("1", None), user("2", None)]));
+        let _missed = state.next_payload(v2).unwrap();
+
+        // ...applies subsequent deltas, but is still missing user 2
+        let mut latest = Arc::new(all_users(&[user("1", Some("gold")), user("2", None)]));
+        apply(&mut cub_side, state.next_payload(latest.clone()).unwrap());
+        assert_eq!(cub_side.users.len(), 1);
+
+        // until the next full snapshot comes around
+        let mut i = 0;
+        loop {
+            i += 1;
+            latest = Arc::new(all_users(&[
+                user("1", Some(&format!("tier{i}"))),
+                user("2", None),
+            ]));
+            let payload = state.next_payload(latest.clone()).unwrap();
+            let is_snapshot = matches!(payload, TenantEventPayload::UsersUpdated(_));
+            apply(&mut cub_side, payload);
+            if is_snapshot {
+                break;
+            }
+        }
+        assert!(latest.delta_to(&cub_side).is_empty());
+        assert_eq!(cub_side.users.len(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use log::Record;
+
+/// Past this, a request is logging way too much to be useful in an error page
+const MAX_CAPTURED_LINES: usize = 1000;
+
+tokio::task_local! {
+    static LOG_CAPTURE: LogCapture;
+}
+
+/// Log lines recorded while running a future, see [`capture_logs`]
+#[derive(Clone, Default)]
+pub struct LogCapture {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl LogCapture {
+    /// Everything captured so far, oldest first, without colors
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+
+    fn push(&self, record: &Record) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() < MAX_CAPTURED_LINES {
+            lines.push(format!(
+                "{} - {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+    }
+}
+
+/// Runs `fut`, recording every line skelly's logger prints from within it.
+/// Logs from tasks spawned by `fut` are not captured.
+pub async fn capture_logs<F: Future>(capture: LogCapture, fut: F) -> F::Output {
+    LOG_CAPTURE.scope(capture, fut).await
+}
+
+/// The capture for the current task, if it's running under [`capture_logs`]
+pub fn current_log_capture() -> Option<LogCapture> {
+    LOG_CAPTURE.try_with(|capture| capture.clone()).ok()
+}
+
+/// Called by the logger for every record it prints
+pub(crate) fn capture_record(record: &Record) {
+    let _ = LOG_CAPTURE.try_with(|capture| capture.push(record));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    // goes straight to `capture_record`, so we don't depend on which logger
+    // (and max level) other tests installed
+    fn emit(level: Level, msg: &str) {
+        capture_record(
+            &Record::builder()
+                .args(format_args!("{msg}"))
+                .level(level)
+                .target("skelly::tests")
+                .build(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_capture_is_scoped_to_the_future() {
+        assert!(current_log_capture().is_none());
+
+        let capture = LogCapture::default();
+        capture_logs(capture.clone(), async {
+            assert!(current_log_capture().is_some());
+            emit(Level::Info, "inside the request");
+            tokio::task::yield_now().await;
+            emit(Level::Warn, "still inside");
+        })
+        .await;
+        emit(Level::Info, "outside the request");
+
+        assert_eq!(
+            capture.lines(),
+            vec![
+                "INFO - skelly::tests: inside the request",
+                "WARN - skelly::tests: still inside",
+            ]
+        );
+    }
+}
@@ -6,21 +6,54 @@ pub use owo_colors;
 
 use log::{Level, LevelFilter, Log, Metadata, Record};
 use owo_colors::{OwoColorize, Style};
-use std::{io::Write, time::Duration};
+use std::{
+    io::Write,
+    sync::{Once, OnceLock, RwLock},
+    time::Duration,
+};
 use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
 
-struct SimpleLogger;
+mod capture;
+pub use capture::*;
+
+struct SimpleLogger {
+    /// Behind a lock so [`set_log_directives`] can swap it
+    filter: RwLock<env_filter::Filter>,
+}
+
+/// The logger installed by [`setup`], if it was installed
+static INSTALLED_LOGGER: OnceLock<&'static SimpleLogger> = OnceLock::new();
+
+impl SimpleLogger {
+    /// Honors the full `RUST_LOG` directive syntax (e.g. `info,libcub=debug,reqwest=warn`),
+    /// using `default` if it's not set.
+    fn from_env(default: &str) -> Self {
+        Self::new(std::env::var("RUST_LOG").ok().as_deref(), default)
+    }
+
+    fn new(directives: Option<&str>, default: &str) -> Self {
+        Self {
+            filter: RwLock::new(parse_filter(directives, default)),
+        }
+    }
+
+    /// The most verbose level any directive allows, for `log::set_max_level`
+    fn max_level(&self) -> LevelFilter {
+        self.filter.read().unwrap().filter()
+    }
+}
 
 impl Log for SimpleLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        // Only log entries at or above the max level filter from log.
-        metadata.level() <= log::max_level()
+        // the global max level can be lowered after the fact (see `TestGuard`)
+        metadata.level() <= log::max_level() && self.filter.read().unwrap().enabled(metadata)
     }
 
     fn log(&self, record: &Record) {
-        if !self.enabled(record.metadata()) {
+        if !self.enabled(record.metadata()) || !self.filter.read().unwrap().matches(record) {
             return;
         }
+        capture_record(record);
 
         // Create style based on log level
         let level_style = match record.level() {
@@ -47,8 +80,171 @@ impl Log for SimpleLogger {
     }
 }
 
+fn parse_filter(directives: Option<&str>, default: &str) -> env_filter::Filter {
+    let directives = directives
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .unwrap_or(default);
+    env_filter::Builder::new().parse(directives).build()
+}
+
+/// Replaces the filter of the logger installed by [`setup`] with `RUST_LOG`-style
+/// directives (e.g. `info,libcub=debug`), falling back to `RUST_LOG` if they're
+/// empty. Returns false if skelly's logger isn't the one installed.
+pub fn set_log_directives(directives: &str) -> bool {
+    let Some(logger) = INSTALLED_LOGGER.get() else {
+        return false;
+    };
+    let env = std::env::var("RUST_LOG").ok();
+    let directives = Some(directives)
+        .filter(|d| !d.trim().is_empty())
+        .or(env.as_deref());
+    *logger.filter.write().unwrap() = parse_filter(directives, "info");
+    log::set_max_level(logger.max_level());
+    true
+}
+
+/// What to do when the parent process (see [`spawn`]) goes away
+#[derive(Default)]
+pub enum OnParentExit {
+    /// Exit right away, with status 1
+    #[default]
+    Exit,
+
+    /// Call `trigger` (which should start a graceful shutdown), then exit
+    /// anyway if we're still running `grace` later
+    Graceful {
+        trigger: Box<dyn FnOnce() + Send>,
+        grace: Duration,
+    },
+}
+
+impl OnParentExit {
+    /// Sends ourselves SIGTERM, which is what cub and mom's graceful shutdown
+    /// listens for, so in-flight requests get to drain.
+    #[cfg(unix)]
+    pub fn sigterm(grace: Duration) -> Self {
+        OnParentExit::Graceful {
+            trigger: Box::new(|| unsafe {
+                libc::raise(libc::SIGTERM);
+            }),
+            grace,
+        }
+    }
+}
+
+/// Options for [`setup_with`]
+#[derive(Default)]
+pub struct SetupOptions {
+    pub on_parent_exit: OnParentExit,
+}
+
 /// Installs color-backtrace (except on miri), and sets up a simple logger.
+/// Only the first call does anything, so it's fine to call it more than once.
 pub fn setup() {
+    setup_with(SetupOptions::default())
+}
+
+/// Like [`setup`], with options. Options passed to later calls are ignored.
+pub fn setup_with(opts: SetupOptions) {
+    static SETUP: Once = Once::new();
+
+    let mut opts = Some(opts);
+    SETUP.call_once(|| setup_inner(opts.take().unwrap()));
+    if opts.is_some() {
+        log::debug!("skelly is already set up, ignoring");
+    }
+}
+
+/// For tests: logs to stderr (per `RUST_LOG`, `debug` by default),
+/// without installing panic/error hooks, sentry, or the parent watcher.
+/// Safe to call from every test. The log level is restored when the
+/// returned guard is dropped.
+#[must_use]
+pub fn setup_for_tests() -> TestGuard {
+    let logger = SimpleLogger::from_env("debug");
+    let max_level = logger.max_level();
+    // fails if a logger is already installed, which is fine
+    let _ = log::set_boxed_logger(Box::new(logger));
+
+    let guard = TestGuard {
+        previous_level: log::max_level(),
+    };
+    log::set_max_level(max_level);
+    guard
+}
+
+/// Returned by [`setup_for_tests`]
+pub struct TestGuard {
+    previous_level: LevelFilter,
+}
+
+impl Drop for TestGuard {
+    fn drop(&mut self) {
+        log::set_max_level(self.previous_level);
+    }
+}
+
+fn setup_inner(opts: SetupOptions) {
+    // the logger goes first, so we can complain about the rest
+    // Respect RUST_LOG directives, fallback to Info if not set
+    let logger: &'static SimpleLogger = Box::leak(Box::new(SimpleLogger::from_env("info")));
+    let max_level = logger.max_level();
+    let sentry_logger = sentry::integrations::log::SentryLogger::with_dest(logger);
+    match log::set_boxed_logger(Box::new(sentry_logger)) {
+        Ok(()) => {
+            let _ = INSTALLED_LOGGER.set(logger);
+        }
+        Err(e) => log::warn!("Not installing skelly's logger: {e}"),
+    }
+    log::set_max_level(max_level);
+
+    if install_hooks() {
+        install_backtrace_printer();
+    }
+
+    // Watch parent process if SKELLY_PARENT_PID is set
+    if let Ok(parent_pid_str) = std::env::var("SKELLY_PARENT_PID") {
+        if let Ok(parent_pid) = parent_pid_str.parse::<usize>() {
+            log::debug!("Watching parent process with PID {parent_pid}");
+
+            let on_exit = opts.on_parent_exit;
+            std::thread::spawn(move || {
+                wait_for_parent_exit(parent_pid);
+                handle_parent_exit(parent_pid, on_exit, std::thread::sleep, |code| {
+                    std::process::exit(code)
+                });
+            });
+        } else {
+            log::warn!("Invalid SKELLY_PARENT_PID value: {parent_pid_str}");
+        }
+    }
+}
+
+/// How often we check on the parent process when we can't get notified of its exit
+const DEFAULT_PARENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Don't let `SKELLY_PARENT_POLL_MS` turn the watcher into a busy loop
+const MIN_PARENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Picks the parent poll interval from the value of `SKELLY_PARENT_POLL_MS`
+fn parent_poll_interval(raw: Option<&str>) -> Duration {
+    let Some(raw) = raw else {
+        return DEFAULT_PARENT_POLL_INTERVAL;
+    };
+    match raw.trim().parse::<u64>() {
+        Ok(ms) => Duration::from_millis(ms).max(MIN_PARENT_POLL_INTERVAL),
+        Err(_) => {
+            log::warn!("Invalid SKELLY_PARENT_POLL_MS value: {raw}, using default");
+            DEFAULT_PARENT_POLL_INTERVAL
+        }
+    }
+}
+
+/// Installs color-eyre's error and panic hooks. Returns false if someone
+/// else already installed an eyre hook, in which case we leave error and
+/// panic reporting to them.
+fn install_hooks() -> bool {
     use color_eyre::config::HookBuilder;
 
     // color-eyre filter
@@ -65,62 +261,52 @@ pub fn setup() {
         }
     };
 
-    HookBuilder::default()
+    match HookBuilder::default()
         .add_frame_filter(Box::new(eyre_filter))
         .install()
-        .expect("Failed to set up color-eyre");
-
-    // color-backtrace filter
     {
-        use color_backtrace::{BacktracePrinter, Frame};
-
-        // The frame filter must be Fn(&mut Vec<&Frame>)
-        let filter = move |frames: &mut Vec<&Frame>| {
-            // eprintln!("[skelly] color-backtrace filter called");
-            frames.retain(|frame| {
-                frame
-                    .name
-                    .as_ref()
-                    .map(should_include_frame_name)
-                    .unwrap_or(true)
-            });
-        };
-
-        // Build and install custom BacktracePrinter with our filter.
-        // Use StandardStream to provide a WriteColor.
-        let stderr = color_backtrace::termcolor::StandardStream::stderr(
-            color_backtrace::termcolor::ColorChoice::Auto,
-        );
-        let printer = BacktracePrinter::new().add_frame_filter(Box::new(filter));
-        printer.install(Box::new(stderr));
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("Not installing color-eyre hooks: {e}");
+            false
+        }
     }
+}
 
-    let logger = sentry::integrations::log::SentryLogger::with_dest(SimpleLogger);
-    log::set_boxed_logger(Box::new(logger)).unwrap();
-
-    // Respect RUST_LOG, fallback to Trace if not set or invalid
-    let level = std::env::var("RUST_LOG")
-        .ok()
-        .and_then(|val| val.parse::<LevelFilter>().ok())
-        .unwrap_or(LevelFilter::Info);
+fn install_backtrace_printer() {
+    use color_backtrace::{BacktracePrinter, Frame};
 
-    log::set_max_level(level);
+    // The frame filter must be Fn(&mut Vec<&Frame>)
+    let filter = move |frames: &mut Vec<&Frame>| {
+        // eprintln!("[skelly] color-backtrace filter called");
+        frames.retain(|frame| {
+            frame
+                .name
+                .as_ref()
+                .map(should_include_frame_name)
+                .unwrap_or(true)
+        });
+    };
 
-    // Watch parent process if SKELLY_PARENT_PID is set
-    if let Ok(parent_pid_str) = std::env::var("SKELLY_PARENT_PID") {
-        if let Ok(parent_pid) = parent_pid_str.parse::<usize>() {
-            log::debug!("Watching parent process with PID {parent_pid}");
+    // Build and install custom BacktracePrinter with our filter.
+    // Use StandardStream to provide a WriteColor.
+    let stderr = color_backtrace::termcolor::StandardStream::stderr(
+        color_backtrace::termcolor::ColorChoice::Auto,
+    );
+    let printer = BacktracePrinter::new().add_frame_filter(Box::new(filter));
+    printer.install(Box::new(stderr));
+}
 
-            std::thread::spawn(move || {
-                watch_parent_process(parent_pid);
-            });
-        } else {
-            log::warn!("Invalid SKELLY_PARENT_PID value: {parent_pid_str}");
-        }
+/// Blocks until the parent process is gone
+fn wait_for_parent_exit(parent_pid: usize) {
+    #[cfg(target_os = "linux")]
+    if wait_for_parent_exit_pidfd(parent_pid) {
+        return;
     }
-}
 
-fn watch_parent_process(parent_pid: usize) {
+    let interval = parent_poll_interval(std::env::var("SKELLY_PARENT_POLL_MS").ok().as_deref());
+    log::debug!("Polling parent process every {interval:?}");
+
     let pid = Pid::from(parent_pid);
     let mut system = System::new();
 
@@ -132,12 +318,111 @@ fn watch_parent_process(parent_pid: usize) {
         );
 
         if system.process(pid).is_none() {
+            return;
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+/// Acts on the parent's exit: `sleep` and `exit` are only parameters so
+/// tests don't actually exit.
+fn handle_parent_exit(
+    parent_pid: usize,
+    on_exit: OnParentExit,
+    sleep: impl FnOnce(Duration),
+    exit: impl FnOnce(i32),
+) {
+    match on_exit {
+        OnParentExit::Exit => {
             log::warn!("Parent process (PID {parent_pid}) has exited, terminating");
-            std::process::exit(1);
+            exit(1);
         }
+        OnParentExit::Graceful { trigger, grace } => {
+            log::warn!("Parent process (PID {parent_pid}) has exited, shutting down gracefully");
+            trigger();
+            sleep(grace);
+            log::error!("Still running {grace:?} after starting graceful shutdown, terminating");
+            exit(1);
+        }
+    }
+}
+
+/// Blocks until the parent process exits, using a pidfd. Returns false if
+/// pidfds aren't available (old kernel, seccomp, etc.), in which case the
+/// caller should fall back to polling.
+#[cfg(target_os = "linux")]
+fn wait_for_parent_exit_pidfd(parent_pid: usize) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(parent_pid) else {
+        return false;
+    };
+
+    let fd = match pidfd_open(pid) {
+        PidFd::Open(fd) => fd,
+        PidFd::Gone => return true,
+        PidFd::Unavailable => return false,
+    };
+
+    // if the parent exited before we opened it, its pid may already belong to
+    // another process, which we'd then wait on instead. We've been reparented
+    // by then, since `spawn` makes that process our direct parent.
+    if unsafe { libc::getppid() } != pid {
+        unsafe { libc::close(fd) };
+        return true;
+    }
+
+    wait_for_pidfd(fd)
+}
+
+#[cfg(target_os = "linux")]
+enum PidFd {
+    Open(libc::c_int),
+    /// there's no process with that pid
+    Gone,
+    /// pidfds aren't available
+    Unavailable,
+}
+
+#[cfg(target_os = "linux")]
+fn pidfd_open(pid: libc::pid_t) -> PidFd {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd >= 0 {
+        return PidFd::Open(fd as libc::c_int);
+    }
+
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::ESRCH) {
+        return PidFd::Gone;
+    }
+    log::debug!("pidfd_open failed ({err}), falling back to polling");
+    PidFd::Unavailable
+}
 
-        std::thread::sleep(Duration::from_secs(1));
+/// Blocks until the process behind `fd` exits, and closes `fd`. Returns false
+/// if we can't poll it.
+#[cfg(target_os = "linux")]
+fn wait_for_pidfd(fd: libc::c_int) -> bool {
+    // a pidfd becomes readable when the process exits
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        let ret = unsafe { libc::poll(&mut pollfd, 1, -1) };
+        if ret > 0 {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if ret < 0 && err.kind() != std::io::ErrorKind::Interrupted {
+            log::debug!("poll on pidfd failed ({err}), falling back to polling");
+            unsafe { libc::close(fd) };
+            return false;
+        }
     }
+
+    unsafe { libc::close(fd) };
+    true
 }
 
 /// Spawn a child process with SKELLY_PARENT_PID set to the current process ID
@@ -146,3 +431,174 @@ pub fn spawn(mut cmd: tokio::process::Command) -> tokio::process::Command {
     cmd.env("SKELLY_PARENT_PID", current_pid.to_string());
     cmd
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_twice_does_not_panic() {
+        setup();
+        setup();
+        setup_with(SetupOptions::default());
+
+        let guard = setup_for_tests();
+        let level = log::max_level();
+        drop(guard);
+        let _guard = setup_for_tests();
+        assert_eq!(log::max_level(), level);
+        log::debug!("still alive");
+    }
+
+    #[test]
+    fn test_install_hooks_tolerates_existing_hooks() {
+        // whether this call or another test installed them, they're there now
+        install_hooks();
+        assert!(!install_hooks());
+
+        // eyre still works
+        let report = eyre::eyre!("still reporting");
+        assert!(format!("{report:?}").contains("still reporting"));
+    }
+
+    fn enabled(logger: &SimpleLogger, target: &str, level: Level) -> bool {
+        logger
+            .filter
+            .read()
+            .unwrap()
+            .enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn test_logger_honors_per_module_directives() {
+        let logger = SimpleLogger::new(Some("info,libcub=debug,reqwest=warn"), "info");
+        assert_eq!(logger.max_level(), LevelFilter::Debug);
+
+        assert!(enabled(&logger, "libcub", Level::Debug));
+        assert!(enabled(&logger, "libcub::impls::health", Level::Debug));
+        assert!(!enabled(&logger, "libcub", Level::Trace));
+
+        assert!(enabled(&logger, "reqwest", Level::Warn));
+        assert!(!enabled(&logger, "reqwest::connect", Level::Info));
+
+        assert!(enabled(&logger, "libmom", Level::Info));
+        assert!(!enabled(&logger, "libmom", Level::Debug));
+    }
+
+    #[test]
+    fn test_logger_most_specific_directive_wins() {
+        // quiet by default, one crate verbose, one of its modules silenced
+        let logger = SimpleLogger::new(
+            Some("warn,libcub=trace,libcub::impls::vite=off,hyper=error"),
+            "info",
+        );
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+
+        assert!(enabled(&logger, "libcub::impls::health", Level::Trace));
+        assert!(!enabled(&logger, "libcub::impls::vite", Level::Error));
+        assert!(!enabled(
+            &logger,
+            "libcub::impls::vite::proxy",
+            Level::Error
+        ));
+        assert!(enabled(&logger, "hyper::proto", Level::Error));
+        assert!(!enabled(&logger, "hyper::proto", Level::Warn));
+        assert!(enabled(&logger, "libmom", Level::Warn));
+        assert!(!enabled(&logger, "libmom", Level::Info));
+    }
+
+    #[test]
+    fn test_logger_default_directives() {
+        for directives in [None, Some(""), Some("  ")] {
+            let logger = SimpleLogger::new(directives, "info");
+            assert_eq!(logger.max_level(), LevelFilter::Info);
+            assert!(enabled(&logger, "libcub", Level::Info));
+            assert!(!enabled(&logger, "libcub", Level::Debug));
+        }
+
+        // a bare level still works like it used to
+        let logger = SimpleLogger::new(Some("trace"), "info");
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+        assert!(enabled(&logger, "anything", Level::Trace));
+    }
+
+    #[test]
+    fn test_parent_poll_interval() {
+        assert_eq!(parent_poll_interval(None), Duration::from_secs(1));
+        assert_eq!(
+            parent_poll_interval(Some("250")),
+            Duration::from_millis(250)
+        );
+        assert_eq!(parent_poll_interval(Some(" 5000 ")), Duration::from_secs(5));
+        // clamped, so we don't spin
+        assert_eq!(parent_poll_interval(Some("0")), Duration::from_millis(50));
+        assert_eq!(parent_poll_interval(Some("10")), Duration::from_millis(50));
+        // garbage falls back to the default
+        assert_eq!(parent_poll_interval(Some("")), Duration::from_secs(1));
+        assert_eq!(parent_poll_interval(Some("1s")), Duration::from_secs(1));
+        assert_eq!(parent_poll_interval(Some("-5")), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_parent_exit_hard_vs_graceful() {
+        use std::sync::{Arc, Mutex};
+
+        fn run(on_exit: OnParentExit, events: &Arc<Mutex<Vec<String>>>) {
+            let (sleep_events, exit_events) = (events.clone(), events.clone());
+            handle_parent_exit(
+                1234,
+                on_exit,
+                move |d| sleep_events.lock().unwrap().push(format!("sleep {d:?}")),
+                move |code| exit_events.lock().unwrap().push(format!("exit {code}")),
+            );
+        }
+
+        // the default is a hard exit, nothing else
+        let events = Arc::new(Mutex::new(Vec::new()));
+        run(OnParentExit::default(), &events);
+        assert_eq!(*events.lock().unwrap(), vec!["exit 1"]);
+
+        // graceful: trigger first, give it time, then exit anyway
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let trigger_events = events.clone();
+        run(
+            OnParentExit::Graceful {
+                trigger: Box::new(move || trigger_events.lock().unwrap().push("trigger".into())),
+                grace: Duration::from_secs(10),
+            },
+            &events,
+        );
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["trigger", "sleep 10s", "exit 1"]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pidfd_wait_returns_when_process_exits() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.1")
+            .spawn()
+            .unwrap();
+        let PidFd::Open(fd) = pidfd_open(child.id() as libc::pid_t) else {
+            panic!("could not open a pidfd for our own child");
+        };
+        // blocks until `sleep` exits (it's a zombie until we reap it below)
+        assert!(wait_for_pidfd(fd));
+        child.wait().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pidfd_wait_does_not_wait_on_a_reused_pid() {
+        // stands in for a process that got our exited parent's pid
+        let mut other = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        assert!(wait_for_parent_exit_pidfd(other.id() as usize));
+        other.kill().unwrap();
+        other.wait().unwrap();
+    }
+}